@@ -0,0 +1,109 @@
+//! Throughput of contract calls executed on a single runtime, compared with the same calls
+//! spread over runtimes forked from it, each running on its own thread.
+//!
+//! Requires `CARGO_TARGET_DIR` to be set, so the test contract can be compiled.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Arc,
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use freenet::dev_tool::{
+    ContractRuntimeInterface, ContractStore, DelegateStore, Runtime, SecretsStore,
+};
+use freenet_stdlib::prelude::*;
+
+const TEST_CONTRACT: &str = "test_contract_metering";
+
+/// Loop iterations executed by the contract on each call.
+const ITERATIONS: u64 = 10_000;
+
+/// Calls executed on each benchmark iteration, split between the runtimes.
+const CALLS: usize = 64;
+
+fn compile_test_contract() -> Vec<u8> {
+    let contract_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .ancestors()
+        .nth(2)
+        .unwrap()
+        .join("tests")
+        .join(TEST_CONTRACT.replace('_', "-"));
+    let target = std::env::var("CARGO_TARGET_DIR").expect("CARGO_TARGET_DIR should be set");
+    const WASM_TARGET: &str = "wasm32-unknown-unknown";
+    let status = Command::new("cargo")
+        .args(["build", "--target", WASM_TARGET])
+        .current_dir(&contract_dir)
+        .status()
+        .unwrap();
+    assert!(status.success(), "failed compiling the test contract");
+    let output_file = Path::new(&target)
+        .join(WASM_TARGET)
+        .join("debug")
+        .join(TEST_CONTRACT)
+        .with_extension("wasm");
+    std::fs::read(output_file).unwrap()
+}
+
+fn validate(runtime: &mut Runtime, key: &ContractKey, state: &WrappedState, calls: usize) {
+    for _ in 0..calls {
+        let result = runtime
+            .validate_state(
+                key,
+                &Parameters::from([].as_ref()),
+                state,
+                &Default::default(),
+            )
+            .unwrap();
+        assert_eq!(result, ValidateResult::Valid);
+    }
+}
+
+fn runtime_pool(c: &mut Criterion) {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let mut contract_store = ContractStore::new(temp_dir.path().join("contract"), 10_000).unwrap();
+    let delegate_store = DelegateStore::new(temp_dir.path().join("delegate"), 10_000).unwrap();
+    let secrets_store =
+        SecretsStore::new(temp_dir.path().join("secrets"), Default::default()).unwrap();
+    let contract = ContractContainer::Wasm(ContractWasmAPIVersion::V1(WrappedContract::new(
+        Arc::new(ContractCode::from(compile_test_contract())),
+        vec![].into(),
+    )));
+    let key = contract.key();
+    contract_store.store_contract(contract).unwrap();
+
+    let mut runtime = Runtime::build(contract_store, delegate_store, secrets_store, false).unwrap();
+    let state = WrappedState::new(
+        serde_json::to_vec(&serde_json::json!({ "iterations": ITERATIONS })).unwrap(),
+    );
+    // compile the module before measuring
+    validate(&mut runtime, &key, &state, 1);
+
+    let mut group = c.benchmark_group("validate_state");
+    group.throughput(Throughput::Elements(CALLS as u64));
+    group.bench_function(BenchmarkId::new("runtimes", 1), |b| {
+        b.iter(|| validate(&mut runtime, &key, &state, CALLS))
+    });
+    let max_workers = std::thread::available_parallelism().map_or(1, |n| n.get());
+    for workers in [2, 4, 8].into_iter().filter(|w| *w <= max_workers) {
+        let mut pool = (0..workers)
+            .map(|_| runtime.fork())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        group.bench_function(BenchmarkId::new("runtimes", workers), |b| {
+            b.iter(|| {
+                std::thread::scope(|s| {
+                    for runtime in &mut pool {
+                        let (key, state) = (&key, &state);
+                        s.spawn(move || validate(runtime, key, state, CALLS / workers));
+                    }
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, runtime_pool);
+criterion_main!(benches);
@@ -109,6 +109,8 @@ pub struct OpenRequest<'a> {
     pub request: Box<ClientRequest<'a>>,
     pub notification_channel: Option<UnboundedSender<HostResult>>,
     pub token: Option<AuthToken>,
+    /// Contract instance attested for the token used by the client, if any.
+    pub attested_contract: Option<ContractInstanceId>,
 }
 
 impl Display for OpenRequest<'_> {
@@ -135,6 +137,7 @@ impl<'a> OpenRequest<'a> {
             request,
             notification_channel: None,
             token: None,
+            attested_contract: None,
         }
     }
 
@@ -147,6 +150,11 @@ impl<'a> OpenRequest<'a> {
         self.token = token;
         self
     }
+
+    pub fn with_attested_contract(mut self, contract: Option<ContractInstanceId>) -> Self {
+        self.attested_contract = contract;
+        self
+    }
 }
 
 pub trait ClientEventsProxy {
@@ -241,6 +249,7 @@ where
                                     contract,
                                 }))
                             }
+                            QueryResult::DelegateResult(response) => Ok(response),
                         };
                         if let Err(err) = client_events.send(cli_id, res).await {
                             tracing::debug!("channel closed: {err}");
@@ -520,8 +529,29 @@ async fn process_open_request(
                     }
                 }
             }
-            ClientRequest::DelegateOp(_op) => {
-                todo!("FIXME: delegate op");
+            ClientRequest::DelegateOp(op) => {
+                let response = match op_manager
+                    .notify_contract_handler(ContractHandlerEvent::DelegateRequest {
+                        data: op,
+                        attested_contract: request.attested_contract,
+                    })
+                    .await
+                {
+                    Ok(ContractHandlerEvent::DelegateResponse(Ok(response))) => response,
+                    Ok(ContractHandlerEvent::DelegateResponse(Err(err))) => {
+                        tracing::error!("delegate request failed: {}", err);
+                        return Err(Error::Executor(err));
+                    }
+                    Err(err) => {
+                        tracing::error!("delegate request failed: {}", err);
+                        return Err(Error::Contract(err));
+                    }
+                    Ok(_) => {
+                        tracing::error!("delegate request failed: UnexpectedOpState");
+                        return Err(Error::Op(OpError::UnexpectedOpState));
+                    }
+                };
+                return Ok(Some(Either::Left(QueryResult::DelegateResult(response))));
             }
             ClientRequest::Disconnect { .. } => {
                 unreachable!();
@@ -930,10 +960,13 @@ pub(crate) mod test {
                         }
                     }
                     val if (35..80).contains(&val) => {
-                        let new_state = UpdateData::State(State::from(self.random_byte_vec()));
+                        // the mock runtime treats deltas as whole states
+                        let new_state = if self.gen_range(0..2) == 0 {
+                            UpdateData::State(State::from(self.random_byte_vec()))
+                        } else {
+                            UpdateData::Delta(StateDelta::from(self.random_byte_vec()))
+                        };
                         if let Some(contract) = self.choose(&state.existing_contracts) {
-                            // TODO: It will be used when the delta updates are available
-                            // let delta = UpdateData::Delta(StateDelta::from(self.random_byte_vec()));
                             if !for_this_peer {
                                 continue;
                             }
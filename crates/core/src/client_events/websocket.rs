@@ -25,7 +25,7 @@ use tokio::sync::{mpsc, Mutex};
 
 use crate::{
     client_events::AuthToken,
-    server::{ClientConnection, HostCallbackResult},
+    server::{AttestedContractMap, ClientConnection, HostCallbackResult},
     util::EncodingProtocol,
 };
 
@@ -47,13 +47,17 @@ impl std::ops::Deref for WebSocketRequest {
 pub(crate) struct WebSocketProxy {
     proxy_server_request: mpsc::Receiver<ClientConnection>,
     response_channels: HashMap<ClientId, mpsc::UnboundedSender<HostCallbackResult>>,
+    attested_contracts: AttestedContractMap,
 }
 
 const PARALLELISM: usize = 10; // TODO: get this from config, or whatever optimal way
 
 impl WebSocketProxy {
-    pub fn as_router(server_routing: Router) -> (Self, Router) {
-        WebSocketProxy::as_router_v1(server_routing)
+    pub fn as_router(
+        server_routing: Router,
+        attested_contracts: AttestedContractMap,
+    ) -> (Self, Router) {
+        WebSocketProxy::as_router_v1(server_routing, attested_contracts)
     }
 
     async fn internal_proxy_recv(
@@ -75,6 +79,9 @@ impl WebSocketProxy {
                 req,
                 auth_token,
             } => {
+                let attested_contract = auth_token
+                    .as_ref()
+                    .and_then(|token| self.attested_contracts.get(token).map(|entry| entry.0));
                 let open_req = match &*req {
                     ClientRequest::ContractOp(ContractRequest::Subscribe { key, .. }) => {
                         // intercept subscription messages because they require a callback subscription channel
@@ -99,7 +106,7 @@ impl WebSocketProxy {
                         OpenRequest::new(client_id, req).with_token(auth_token)
                     }
                 };
-                Ok(Some(open_req))
+                Ok(Some(open_req.with_attested_contract(attested_contract)))
             }
         }
     }
@@ -1,7 +1,10 @@
 use super::*;
 
 impl WebSocketProxy {
-    pub fn as_router_v1(server_routing: Router) -> (Self, Router) {
+    pub fn as_router_v1(
+        server_routing: Router,
+        attested_contracts: AttestedContractMap,
+    ) -> (Self, Router) {
         let (proxy_request_sender, proxy_server_request) = mpsc::channel(PARALLELISM);
 
         let router = server_routing
@@ -12,6 +15,7 @@ impl WebSocketProxy {
             WebSocketProxy {
                 proxy_server_request,
                 response_channels: HashMap::new(),
+                attested_contracts,
             },
             router,
         )
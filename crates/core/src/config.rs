@@ -36,6 +36,10 @@ pub const DEFAULT_RANDOM_PEER_CONN_THRESHOLD: usize = 7;
 /// Default maximum number of hops to live for any operation
 /// (if it applies, e.g. connect requests).
 pub const DEFAULT_MAX_HOPS_TO_LIVE: usize = 10;
+/// Default cap on the upload bandwidth of the node, in bytes per second.
+pub const DEFAULT_MAX_UPSTREAM_BANDWIDTH: u64 = 10 * 1024 * 1024;
+/// Default cap on the download bandwidth of the node, in bytes per second.
+pub const DEFAULT_MAX_DOWNSTREAM_BANDWIDTH: u64 = 10 * 1024 * 1024;
 pub(crate) const OPERATION_TTL: Duration = Duration::from_secs(60);
 
 // Initialize the executor once.
@@ -65,6 +69,22 @@ pub struct ConfigArgs {
     #[clap(long, env = "LOG_LEVEL")]
     pub log_level: Option<tracing::log::LevelFilter>,
 
+    /// Maximum number of bytes used to store contracts and delegates, once exceeded the least
+    /// recently used contracts which aren't seeded or subscribed to are evicted. Unbounded by default.
+    #[clap(long, env = "MAX_STORAGE_BYTES")]
+    pub max_storage_bytes: Option<u64>,
+
+    /// Number of replaced states kept for each contract, so they can be inspected and restored
+    /// with `fdev`. Disabled by default.
+    #[clap(long, env = "STATE_HISTORY")]
+    pub state_history: Option<usize>,
+
+    /// Store snapshot exported with `fdev`, imported into the stores on startup replacing any
+    /// entries with the same keys. Only applies to the run it is passed to, it isn't persisted
+    /// in the configuration file.
+    #[clap(long, env = "LOAD_SNAPSHOT")]
+    pub load_snapshot: Option<PathBuf>,
+
     #[clap(flatten)]
     config_paths: ConfigPathsArgs,
 
@@ -85,6 +105,8 @@ impl Default for ConfigArgs {
                 is_gateway: false,
                 skip_load_from_network: true,
                 ignore_protocol_checking: false,
+                max_upstream_bandwidth: None,
+                max_downstream_bandwidth: None,
             },
             ws_api: WebsocketApiArgs {
                 address: Some(default_listening_address()),
@@ -92,6 +114,9 @@ impl Default for ConfigArgs {
             },
             secrets: Default::default(),
             log_level: Some(tracing::log::LevelFilter::Info),
+            max_storage_bytes: None,
+            state_history: None,
+            load_snapshot: None,
             config_paths: Default::default(),
             id: None,
         }
@@ -214,6 +239,18 @@ impl ConfigArgs {
             self.ws_api.address.get_or_insert(cfg.ws_api.address);
             self.ws_api.ws_api_port.get_or_insert(cfg.ws_api.port);
             self.log_level.get_or_insert(cfg.log_level);
+            self.network_api
+                .max_upstream_bandwidth
+                .get_or_insert(cfg.network_api.max_upstream_bandwidth);
+            self.network_api
+                .max_downstream_bandwidth
+                .get_or_insert(cfg.network_api.max_downstream_bandwidth);
+            if let Some(max_storage_bytes) = cfg.max_storage_bytes {
+                self.max_storage_bytes.get_or_insert(max_storage_bytes);
+            }
+            if let Some(state_history) = cfg.state_history {
+                self.state_history.get_or_insert(state_history);
+            }
             self.config_paths.merge(cfg.config_paths.as_ref().clone());
         }
 
@@ -294,6 +331,14 @@ impl ConfigArgs {
                 public_address: self.network_api.public_address,
                 public_port: self.network_api.public_port,
                 ignore_protocol: self.network_api.ignore_protocol_checking,
+                max_upstream_bandwidth: self
+                    .network_api
+                    .max_upstream_bandwidth
+                    .unwrap_or(DEFAULT_MAX_UPSTREAM_BANDWIDTH),
+                max_downstream_bandwidth: self
+                    .network_api
+                    .max_downstream_bandwidth
+                    .unwrap_or(DEFAULT_MAX_DOWNSTREAM_BANDWIDTH),
             },
             ws_api: WebsocketApiConfig {
                 address: self.ws_api.address.unwrap_or_else(|| match mode {
@@ -307,6 +352,9 @@ impl ConfigArgs {
             },
             secrets,
             log_level: self.log_level.unwrap_or(tracing::log::LevelFilter::Info),
+            max_storage_bytes: self.max_storage_bytes,
+            state_history: self.state_history,
+            load_snapshot: self.load_snapshot,
             config_paths: Arc::new(config_paths),
             gateways: gateways.gateways.clone(),
             is_gateway: self.network_api.is_gateway,
@@ -385,6 +433,16 @@ pub struct Config {
     pub secrets: Secrets,
     #[serde(with = "serde_log_level_filter")]
     pub log_level: tracing::log::LevelFilter,
+    /// Disk budget for contracts and delegates, unbounded if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_storage_bytes: Option<u64>,
+    /// Number of replaced states kept for each contract, disabled if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_history: Option<usize>,
+    /// Store snapshot imported on startup, if any. Not persisted, otherwise it would be
+    /// imported again on every restart, rolling back any newer states.
+    #[serde(skip)]
+    pub load_snapshot: Option<PathBuf>,
     #[serde(flatten)]
     config_paths: Arc<ConfigPaths>,
     #[serde(skip)]
@@ -448,6 +506,22 @@ pub struct NetworkArgs {
     /// Ignores protocol version failures, continuing to run the node if there is a mismatch with the gateway.
     #[arg(long)]
     pub ignore_protocol_checking: bool,
+
+    /// Maximum upload bandwidth used by the node in bytes per second, default is 10 MiB/s.
+    #[arg(long, env = "MAX_UPSTREAM_BANDWIDTH")]
+    #[serde(
+        rename = "max-upstream-bandwidth",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_upstream_bandwidth: Option<u64>,
+
+    /// Maximum download bandwidth used by the node in bytes per second, default is 10 MiB/s.
+    #[arg(long, env = "MAX_DOWNSTREAM_BANDWIDTH")]
+    #[serde(
+        rename = "max-downstream-bandwidth",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_downstream_bandwidth: Option<u64>,
 }
 
 impl NetworkArgs {
@@ -490,6 +564,20 @@ pub struct NetworkApiConfig {
 
     #[serde(skip)]
     pub ignore_protocol: bool,
+
+    /// Cap on the upload bandwidth of the node, in bytes per second.
+    #[serde(
+        default = "default_max_upstream_bandwidth",
+        rename = "max-upstream-bandwidth"
+    )]
+    pub max_upstream_bandwidth: u64,
+
+    /// Cap on the download bandwidth of the node, in bytes per second.
+    #[serde(
+        default = "default_max_downstream_bandwidth",
+        rename = "max-downstream-bandwidth"
+    )]
+    pub max_downstream_bandwidth: u64,
 }
 
 mod port_allocation;
@@ -499,6 +587,14 @@ pub fn default_network_api_port() -> u16 {
     find_available_port().unwrap_or(31337) // Fallback to 31337 if we can't find a random port
 }
 
+fn default_max_upstream_bandwidth() -> u64 {
+    DEFAULT_MAX_UPSTREAM_BANDWIDTH
+}
+
+fn default_max_downstream_bandwidth() -> u64 {
+    DEFAULT_MAX_DOWNSTREAM_BANDWIDTH
+}
+
 #[derive(clap::Parser, Debug, Default, Copy, Clone, Serialize, Deserialize)]
 pub struct WebsocketApiArgs {
     /// Address to bind to for the websocket API, default is 0.0.0.0
@@ -40,11 +40,12 @@ impl ConfigArgs {
             nonce_path: path_to_nonce,
             cipher,
             cipher_path: path_to_cipher,
+            passphrase: None,
         })
     }
 }
 
-#[derive(Debug, Default, Clone, clap::Parser, serde::Serialize, serde::Deserialize)]
+#[derive(Default, Clone, clap::Parser, serde::Serialize, serde::Deserialize)]
 pub struct SecretArgs {
     /// Path to the RSA private key for the transport layer.
     #[clap(long, value_parser, default_value=None, env = "TRANSPORT_KEYPAIR")]
@@ -57,10 +58,30 @@ pub struct SecretArgs {
     /// Path to the cipher file.
     #[clap(long, value_parser, default_value=None, env = "CIPHER")]
     pub cipher: Option<PathBuf>,
+
+    /// Passphrase the master key encrypting the delegate secrets is derived from, instead of
+    /// the cipher. Never persisted, so it must be provided on every start.
+    #[clap(long, env = "SECRETS_PASSPHRASE", hide_env_values = true)]
+    #[serde(skip)]
+    pub secrets_passphrase: Option<String>,
+}
+
+impl std::fmt::Debug for SecretArgs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SecretArgs")
+            .field("transport_keypair", &self.transport_keypair)
+            .field("nonce", &self.nonce)
+            .field("cipher", &self.cipher)
+            .field(
+                "secrets_passphrase",
+                &self.secrets_passphrase.as_ref().map(|_| "<redacted>"),
+            )
+            .finish()
+    }
 }
 
 impl SecretArgs {
-    pub(super) fn build(self) -> std::io::Result<Secrets> {
+    pub fn build(self) -> std::io::Result<Secrets> {
         let transport_key = self
             .transport_keypair
             .as_ref()
@@ -95,6 +116,7 @@ impl SecretArgs {
             nonce_path,
             cipher,
             cipher_path,
+            passphrase: self.secrets_passphrase,
         })
     }
 
@@ -113,7 +135,7 @@ impl SecretArgs {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Secrets {
     #[serde(skip)]
     pub transport_keypair: TransportKeypair,
@@ -127,6 +149,8 @@ pub struct Secrets {
     pub cipher: [u8; 32],
     #[serde(rename = "cipher", skip_serializing_if = "Option::is_none")]
     pub cipher_path: Option<PathBuf>,
+    #[serde(skip)]
+    pub passphrase: Option<String>,
 }
 
 // Only used in tests
@@ -144,10 +168,28 @@ impl Default for Secrets {
             nonce_path: None,
             cipher,
             cipher_path: None,
+            passphrase: None,
         }
     }
 }
 
+impl std::fmt::Debug for Secrets {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Secrets")
+            .field("transport_keypair", &self.transport_keypair)
+            .field("transport_keypair_path", &self.transport_keypair_path)
+            .field("nonce", &self.nonce)
+            .field("nonce_path", &self.nonce_path)
+            .field("cipher", &self.cipher)
+            .field("cipher_path", &self.cipher_path)
+            .field(
+                "passphrase",
+                &self.passphrase.as_ref().map(|_| "<redacted>"),
+            )
+            .finish()
+    }
+}
+
 impl Secrets {
     #[inline]
     pub fn nonce(&self) -> XNonce {
@@ -163,6 +205,23 @@ impl Secrets {
     pub fn transport_keypair(&self) -> &TransportKeypair {
         &self.transport_keypair
     }
+
+    /// What the master key of the secrets store is derived from.
+    pub fn master_key_source(&self) -> MasterKeySource {
+        match &self.passphrase {
+            Some(passphrase) => MasterKeySource::Passphrase(passphrase.clone()),
+            None => MasterKeySource::Cipher(self.cipher),
+        }
+    }
+}
+
+/// What the master key encrypting the delegate secrets is derived from.
+#[derive(Clone)]
+pub enum MasterKeySource {
+    /// The key is used as is.
+    Cipher([u8; CIPHER_SIZE]),
+    /// The key is derived from the passphrase with a salt kept in the secrets directory.
+    Passphrase(String),
 }
 
 fn read_nonce(path_to_nonce: impl AsRef<Path>) -> std::io::Result<[u8; NONCE_SIZE]> {
@@ -263,18 +322,31 @@ mod tests {
             nonce_path: Some(nonce_file.path().to_path_buf()),
             cipher,
             cipher_path: Some(cipher_file.path().to_path_buf()),
+            passphrase: None,
         };
 
         let secret_args = SecretArgs {
             transport_keypair: Some(transport_keypair_file.path().to_path_buf()),
             nonce: Some(nonce_file.path().to_path_buf()),
             cipher: Some(cipher_file.path().to_path_buf()),
+            secrets_passphrase: None,
         };
 
         let loaded_secrets = secret_args.build().unwrap();
         assert_eq!(secrets, loaded_secrets);
     }
 
+    #[test]
+    fn test_redact_passphrase() {
+        let secrets = Secrets {
+            passphrase: Some("correct horse".into()),
+            ..Default::default()
+        };
+        let debug = format!("{secrets:?}");
+        assert!(!debug.contains("correct horse"));
+        assert!(debug.contains("<redacted>"));
+    }
+
     #[test]
     fn test_load_default() {
         let secret_args = SecretArgs::default();
@@ -10,8 +10,8 @@ mod handler;
 pub mod storages;
 
 pub(crate) use executor::{
-    executor_channel, mock_runtime::MockRuntime, Callback, ExecutorToEventLoopChannel,
-    NetworkEventListenerHalve, UpsertResult,
+    executor_channel, executor_channels, mock_runtime::MockRuntime, Callback,
+    ExecutorToEventLoopChannel, NetworkEventListenerHalve, UpsertResult,
 };
 pub(crate) use handler::{
     client_responses_channel, contract_handler_channel, in_memory::MemoryContractHandler,
@@ -20,177 +20,638 @@ pub(crate) use handler::{
     WaitingTransaction,
 };
 
-pub use executor::{Executor, ExecutorError, OperationMode};
+pub use executor::{upgraded_to, Executor, ExecutorError, OperationMode};
 
-use executor::ContractExecutor;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use executor::{ContractExecutor, ExecutorHalve};
+use freenet_stdlib::client_api::{DelegateRequest, HostResponse, RequestError};
+use futures::stream::{FuturesUnordered, StreamExt};
+use handler::{ContractHandlerHalve, EventId, ShardRouter};
 use tracing::Instrument;
 
+use crate::config::GlobalExecutor;
+use crate::message::Transaction;
+
 pub(crate) async fn contract_handling<CH>(mut contract_handler: CH) -> Result<(), ContractError>
 where
     CH: ContractHandler + Send + 'static,
 {
+    let mut suspended = SuspendedRequests::default();
+    let mut supervisor = ExecutorSupervisor::default();
+    let mut check_expired = tokio::time::interval(SuspendedRequests::EXPIRATION_CHECK_INTERVAL);
     loop {
-        let (id, event) = contract_handler.channel().recv_from_sender().await?;
-        tracing::debug!(%event, "Got contract handling event");
-        match event {
-            ContractHandlerEvent::GetQuery {
-                key,
-                return_contract_code,
-            } => {
-                match contract_handler
-                    .executor()
-                    .fetch_contract(key, return_contract_code)
-                    .instrument(tracing::info_span!("fetch_contract", %key, %return_contract_code))
-                    .await
-                {
-                    Ok((state, contract)) => {
-                        tracing::debug!(with_contract_code = %return_contract_code, has_contract = %contract.is_some(), "Fetched contract {key}");
-                        contract_handler
-                            .channel()
-                            .send_to_sender(
-                                id,
-                                ContractHandlerEvent::GetResponse {
-                                    key,
-                                    response: Ok(StoreResponse { state, contract }),
-                                },
-                            )
-                            .await
-                            .map_err(|error| {
-                                tracing::debug!(%error, "shutting down contract handler");
-                                error
-                            })?;
+        let (channel, executor) = contract_handler.channel_and_executor();
+        tokio::select! {
+            event = channel.recv_from_sender() => {
+                let (id, event) = event?;
+                tracing::debug!(%event, "Got contract handling event");
+                // requests for a contract with a suspended request wait for it to complete
+                let Some((id, event)) = suspended.hold(id, event) else {
+                    continue;
+                };
+                process_event(&mut contract_handler, &mut suspended, &mut supervisor, id, event)
+                    .await?;
+            }
+            tx = executor.op_result_ready() => {
+                let tx = tx.map_err(|error| {
+                    tracing::debug!(%error, "shutting down contract handler");
+                    ContractError::ExecutorChannelClosed
+                })?;
+                for (id, event) in suspended.resume(&tx) {
+                    tracing::debug!(%event, %tx, "Resuming contract handling event");
+                    let contract = event.contract();
+                    process_event(&mut contract_handler, &mut suspended, &mut supervisor, id, event)
+                        .await?;
+                    release_held(&mut contract_handler, &mut suspended, &mut supervisor, contract)
+                        .await?;
+                }
+                // the result is stale if none of the resumed requests claimed it, e.g. on error
+                contract_handler.executor().forget_op(&tx);
+            }
+            _ = check_expired.tick() => {
+                for (tx, id, event) in suspended.expired() {
+                    tracing::warn!(%event, %tx, "Timed out waiting for network result");
+                    contract_handler.executor().forget_op(&tx);
+                    let contract = event.contract();
+                    let response = failed_response(event, RequestError::Timeout.into());
+                    contract_handler
+                        .channel()
+                        .send_to_sender(id, response)
+                        .await
+                        .map_err(|error| {
+                            tracing::debug!(%error, "shutting down contract handler");
+                            error
+                        })?;
+                    release_held(&mut contract_handler, &mut suspended, &mut supervisor, contract)
+                        .await?;
+                }
+            }
+        }
+    }
+}
+
+/// A pool of contract handlers, each running on its own task with its own executor.
+///
+/// Events are sharded by contract, so the events for a given contract are processed in order
+/// while events for different contracts are processed concurrently, and a contract which is
+/// slow to execute only stalls the contracts in its own shard.
+pub(crate) struct ContractHandlerPool<CH> {
+    router: ShardRouter,
+    handlers: Vec<CH>,
+}
+
+impl<CH> ContractHandlerPool<CH>
+where
+    CH: ContractHandler + Send + 'static,
+{
+    const MAX_SIZE: usize = 8;
+
+    /// Number of handlers to use by default, based on the available parallelism.
+    pub fn default_size() -> usize {
+        std::thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(Self::MAX_SIZE)
+    }
+
+    /// Builds a handler for each of the executor channels, the first one from the builder
+    /// and the rest forked from it.
+    pub async fn build(
+        channel: ContractHandlerChannel<ContractHandlerHalve>,
+        executor_request_senders: Vec<ExecutorToEventLoopChannel<ExecutorHalve>>,
+        builder: CH::Builder,
+    ) -> anyhow::Result<Self> {
+        let (router, channels) = channel.into_shards(executor_request_senders.len());
+        let mut shards = channels.into_iter().zip(executor_request_senders);
+        let Some((channel, executor_request_sender)) = shards.next() else {
+            anyhow::bail!("contract handler pool must have at least one handler");
+        };
+        let first = CH::build(channel, executor_request_sender, builder).await?;
+        let mut handlers = Vec::with_capacity(shards.len() + 1);
+        for (channel, executor_request_sender) in shards {
+            handlers.push(first.fork(channel, executor_request_sender).await?);
+        }
+        handlers.push(first);
+        Ok(Self { router, handlers })
+    }
+
+    /// Runs all the handlers in the pool, returns as soon as any of them stops.
+    pub async fn run(self) -> Result<(), ContractError> {
+        let mut handlers: FuturesUnordered<_> = self
+            .handlers
+            .into_iter()
+            .enumerate()
+            .map(|(shard, handler)| {
+                GlobalExecutor::spawn(
+                    contract_handling(handler)
+                        .instrument(tracing::debug_span!("contract_handler", shard)),
+                )
+            })
+            .collect();
+        tokio::select! {
+            result = self.router.run() => result,
+            Some(result) = handlers.next() => result.map_err(ContractError::HandlerFailed)?,
+        }
+    }
+}
+
+/// Handles an event until it either completes or is suspended waiting for the network.
+///
+/// If the executor fails with a fatal error it is reset and the event replayed once, unless
+/// the executor is crash looping; otherwise the error is reported back to the requester.
+async fn process_event<CH>(
+    contract_handler: &mut CH,
+    suspended: &mut SuspendedRequests,
+    supervisor: &mut ExecutorSupervisor,
+    id: EventId,
+    event: ContractHandlerEvent,
+) -> Result<(), ContractError>
+where
+    CH: ContractHandler + Send + 'static,
+{
+    let mut replayed = false;
+    let (mut id, mut event) = (id, event);
+    loop {
+        match handle_event(contract_handler, id, event).await? {
+            EventOutcome::Completed => return Ok(()),
+            EventOutcome::Suspended(tx, id, event) => {
+                suspended.park(tx, id, event);
+                return Ok(());
+            }
+            EventOutcome::Fatal(err, failed_id, failed_event) => {
+                tracing::error!(%err, event = %failed_event, "Fatal error in the contract executor");
+                let crash_loop = supervisor.restart(contract_handler.executor())?;
+                if replayed || crash_loop {
+                    contract_handler
+                        .channel()
+                        .send_to_sender(failed_id, failed_response(failed_event, err))
+                        .await
+                        .map_err(|error| {
+                            tracing::debug!(%error, "shutting down contract handler");
+                            error
+                        })?;
+                    return Ok(());
+                }
+                tracing::debug!(event = %failed_event, "Replaying event after executor reset");
+                replayed = true;
+                id = failed_id;
+                event = failed_event;
+            }
+        }
+    }
+}
+
+/// Processes the requests held for a contract, in the order they were received, until one
+/// of them is suspended or none are left.
+async fn release_held<CH>(
+    contract_handler: &mut CH,
+    suspended: &mut SuspendedRequests,
+    supervisor: &mut ExecutorSupervisor,
+    contract: Option<ContractKey>,
+) -> Result<(), ContractError>
+where
+    CH: ContractHandler + Send + 'static,
+{
+    let Some(contract) = contract else {
+        return Ok(());
+    };
+    while let Some((id, event)) = suspended.next_held(&contract) {
+        tracing::debug!(%event, "Processing held contract handling event");
+        process_event(contract_handler, suspended, supervisor, id, event).await?;
+    }
+    Ok(())
+}
+
+/// Keeps track of the executor resets performed after fatal errors.
+#[derive(Default)]
+struct ExecutorSupervisor {
+    restarts: usize,
+    recent_restarts: VecDeque<Instant>,
+}
+
+impl ExecutorSupervisor {
+    const CRASH_LOOP_WINDOW: Duration = Duration::from_secs(60);
+    const CRASH_LOOP_THRESHOLD: usize = 5;
+
+    /// Resets the executor, returns whether the executor is crash looping.
+    fn restart<E: ContractExecutor>(&mut self, executor: &mut E) -> Result<bool, ContractError> {
+        executor.reset().map_err(|err| {
+            tracing::error!(%err, "Failed to reset the contract executor");
+            ContractError::ExecutorReset(err)
+        })?;
+        self.restarts += 1;
+        let now = Instant::now();
+        self.recent_restarts.push_back(now);
+        while self
+            .recent_restarts
+            .front()
+            .is_some_and(|t| now.duration_since(*t) > Self::CRASH_LOOP_WINDOW)
+        {
+            self.recent_restarts.pop_front();
+        }
+        let crash_loop = self.recent_restarts.len() >= Self::CRASH_LOOP_THRESHOLD;
+        if crash_loop {
+            tracing::error!(
+                restarts = self.restarts,
+                recent = self.recent_restarts.len(),
+                "Contract executor is crash looping"
+            );
+        } else {
+            tracing::warn!(restarts = self.restarts, "Contract executor reset");
+        }
+        Ok(crash_loop)
+    }
+}
+
+/// Requests parked while waiting for the result of a network operation.
+///
+/// Instead of blocking the executor until a network operation issued on behalf of a request
+/// completes, the request is kept here as a continuation keyed by the operation transaction
+/// and replayed once the event loop delivers the result back to the executor.
+///
+/// Later requests for the same contract are held back until the suspended ones complete,
+/// so they don't overtake them.
+#[derive(Default)]
+struct SuspendedRequests {
+    waiting: HashMap<Transaction, Vec<(EventId, ContractHandlerEvent)>>,
+    /// Requests received for each contract with suspended requests, in order.
+    held: HashMap<ContractKey, VecDeque<(EventId, ContractHandlerEvent)>>,
+}
+
+impl SuspendedRequests {
+    const EXPIRATION_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+    fn park(&mut self, tx: Transaction, id: EventId, event: ContractHandlerEvent) {
+        tracing::debug!(%event, %tx, "Suspending contract handling event");
+        if let Some(contract) = event.contract() {
+            self.held.entry(contract).or_default();
+        }
+        self.waiting.entry(tx).or_default().push((id, event));
+    }
+
+    /// Holds the request if its contract has requests suspended or held, otherwise returns
+    /// it to be processed.
+    fn hold(
+        &mut self,
+        id: EventId,
+        event: ContractHandlerEvent,
+    ) -> Option<(EventId, ContractHandlerEvent)> {
+        match event
+            .contract()
+            .and_then(|contract| self.held.get_mut(&contract))
+        {
+            Some(held) => {
+                tracing::debug!(%event, "Holding contract handling event");
+                held.push_back((id, event));
+                None
+            }
+            None => Some((id, event)),
+        }
+    }
+
+    /// Returns the next request held for the contract, once none of its requests are
+    /// suspended anymore.
+    fn next_held(&mut self, contract: &ContractKey) -> Option<(EventId, ContractHandlerEvent)> {
+        let suspended = self
+            .waiting
+            .values()
+            .flatten()
+            .any(|(_, event)| event.contract().as_ref() == Some(contract));
+        if suspended {
+            return None;
+        }
+        let next = self.held.get_mut(contract)?.pop_front();
+        if next.is_none() {
+            self.held.remove(contract);
+        }
+        next
+    }
+
+    fn resume(&mut self, tx: &Transaction) -> Vec<(EventId, ContractHandlerEvent)> {
+        self.waiting.remove(tx).unwrap_or_default()
+    }
+
+    /// Removes all requests waiting on operations which already timed out.
+    fn expired(&mut self) -> Vec<(Transaction, EventId, ContractHandlerEvent)> {
+        let expired: Vec<_> = self
+            .waiting
+            .keys()
+            .filter(|tx| tx.timed_out())
+            .copied()
+            .collect();
+        expired
+            .into_iter()
+            .flat_map(|tx| {
+                self.waiting
+                    .remove(&tx)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(move |(id, event)| (tx, id, event))
+            })
+            .collect()
+    }
+}
+
+enum EventOutcome {
+    Completed,
+    /// The request is waiting for the network, the event is returned to be resumed later.
+    Suspended(Transaction, EventId, ContractHandlerEvent),
+    /// The executor failed with a fatal error, the event is returned to be replayed or rejected.
+    Fatal(ExecutorError, EventId, ContractHandlerEvent),
+}
+
+/// Handles a single event with the executor.
+async fn handle_event<CH>(
+    contract_handler: &mut CH,
+    id: EventId,
+    event: ContractHandlerEvent,
+) -> Result<EventOutcome, ContractError>
+where
+    CH: ContractHandler + Send + 'static,
+{
+    match event {
+        ContractHandlerEvent::GetQuery {
+            key,
+            return_contract_code,
+        } => {
+            match contract_handler
+                .executor()
+                .fetch_contract(key, return_contract_code)
+                .instrument(tracing::info_span!("fetch_contract", %key, %return_contract_code))
+                .await
+            {
+                Ok((state, contract)) => {
+                    tracing::debug!(with_contract_code = %return_contract_code, has_contract = %contract.is_some(), "Fetched contract {key}");
+                    contract_handler
+                        .channel()
+                        .send_to_sender(
+                            id,
+                            ContractHandlerEvent::GetResponse {
+                                key,
+                                response: Ok(StoreResponse { state, contract }),
+                            },
+                        )
+                        .await
+                        .map_err(|error| {
+                            tracing::debug!(%error, "shutting down contract handler");
+                            error
+                        })?;
+                }
+                Err(err) => {
+                    let event = ContractHandlerEvent::GetQuery {
+                        key,
+                        return_contract_code,
+                    };
+                    if let Some(tx) = err.suspended_on() {
+                        return Ok(EventOutcome::Suspended(tx, id, event));
                     }
-                    Err(err) => {
-                        tracing::warn!("Error while executing get contract query: {err}");
-                        if err.is_fatal() {
-                            todo!("Handle fatal error; reset executor");
-                        }
-                        contract_handler
-                            .channel()
-                            .send_to_sender(
-                                id,
-                                ContractHandlerEvent::GetResponse {
-                                    key,
-                                    response: Err(err),
-                                },
-                            )
-                            .await
-                            .map_err(|error| {
-                                tracing::debug!(%error, "shutting down contract handler");
-                                error
-                            })?;
+                    tracing::warn!("Error while executing get contract query: {err}");
+                    if err.is_fatal() {
+                        return Ok(EventOutcome::Fatal(err, id, event));
                     }
+                    contract_handler
+                        .channel()
+                        .send_to_sender(
+                            id,
+                            ContractHandlerEvent::GetResponse {
+                                key,
+                                response: Err(err),
+                            },
+                        )
+                        .await
+                        .map_err(|error| {
+                            tracing::debug!(%error, "shutting down contract handler");
+                            error
+                        })?;
                 }
             }
-            ContractHandlerEvent::PutQuery {
-                key,
-                state,
-                related_contracts,
-                contract,
-            } => {
-                let put_result = contract_handler
-                    .executor()
-                    .upsert_contract_state(
+        }
+        ContractHandlerEvent::PutQuery {
+            key,
+            state,
+            related_contracts,
+            contract,
+        } => {
+            let put_result = contract_handler
+                .executor()
+                .upsert_contract_state(
+                    key,
+                    Either::Left(state.clone()),
+                    related_contracts.clone(),
+                    contract.clone(),
+                )
+                .instrument(tracing::info_span!("upsert_contract_state", %key))
+                .await;
+
+            let event_result = match put_result {
+                Ok(UpsertResult::NoChange) => ContractHandlerEvent::PutResponse {
+                    new_value: Ok(state),
+                },
+                Ok(UpsertResult::Updated(state)) => ContractHandlerEvent::PutResponse {
+                    new_value: Ok(state),
+                },
+                Err(err) => {
+                    let event = ContractHandlerEvent::PutQuery {
                         key,
-                        Either::Left(state.clone()),
+                        state,
                         related_contracts,
                         contract,
-                    )
-                    .instrument(tracing::info_span!("upsert_contract_state", %key))
-                    .await;
-
-                let event_result = match put_result {
-                    Ok(UpsertResult::NoChange) => ContractHandlerEvent::PutResponse {
-                        new_value: Ok(state),
-                    },
-                    Ok(UpsertResult::Updated(state)) => ContractHandlerEvent::PutResponse {
-                        new_value: Ok(state),
-                    },
-                    Err(err) => {
-                        if err.is_fatal() {
-                            todo!("Handle fatal error; reset executor");
-                        }
-                        ContractHandlerEvent::PutResponse {
-                            new_value: Err(err),
-                        }
+                    };
+                    if let Some(tx) = err.suspended_on() {
+                        return Ok(EventOutcome::Suspended(tx, id, event));
                     }
-                };
+                    if err.is_fatal() {
+                        return Ok(EventOutcome::Fatal(err, id, event));
+                    }
+                    ContractHandlerEvent::PutResponse {
+                        new_value: Err(err),
+                    }
+                }
+            };
 
-                contract_handler
-                    .channel()
-                    .send_to_sender(id, event_result)
-                    .await
-                    .map_err(|error| {
-                        tracing::debug!(%error, "shutting down contract handler");
-                        error
-                    })?;
-            }
-            ContractHandlerEvent::UpdateQuery {
-                key,
-                data,
-                related_contracts,
-            } => {
-                let update_value: Either<WrappedState, StateDelta<'static>> = match data {
-                    freenet_stdlib::prelude::UpdateData::State(state) => {
-                        Either::Left(WrappedState::from(state.into_bytes()))
+            contract_handler
+                .channel()
+                .send_to_sender(id, event_result)
+                .await
+                .map_err(|error| {
+                    tracing::debug!(%error, "shutting down contract handler");
+                    error
+                })?;
+        }
+        ContractHandlerEvent::UpdateQuery {
+            key,
+            data,
+            related_contracts,
+        } => {
+            let update_value: Either<WrappedState, StateDelta<'static>> = match data.clone() {
+                freenet_stdlib::prelude::UpdateData::State(state) => {
+                    Either::Left(WrappedState::from(state.into_bytes()))
+                }
+                freenet_stdlib::prelude::UpdateData::Delta(delta) => Either::Right(delta),
+                _ => unreachable!(),
+            };
+            let update_result = contract_handler
+                .executor()
+                .upsert_contract_state(key, update_value, related_contracts.clone(), None)
+                .instrument(tracing::info_span!("upsert_contract_state", %key))
+                .await;
+
+            let event_result = match update_result {
+                Ok(UpsertResult::NoChange) => ContractHandlerEvent::UpdateNoChange { key },
+                Ok(UpsertResult::Updated(state)) => ContractHandlerEvent::UpdateResponse {
+                    new_value: Ok(state),
+                },
+                Err(err) => {
+                    let event = ContractHandlerEvent::UpdateQuery {
+                        key,
+                        data,
+                        related_contracts,
+                    };
+                    if let Some(tx) = err.suspended_on() {
+                        return Ok(EventOutcome::Suspended(tx, id, event));
                     }
-                    freenet_stdlib::prelude::UpdateData::Delta(delta) => Either::Right(delta),
-                    _ => unreachable!(),
-                };
-                let update_result = contract_handler
-                    .executor()
-                    .upsert_contract_state(key, update_value, related_contracts, None)
-                    .instrument(tracing::info_span!("upsert_contract_state", %key))
-                    .await;
-
-                let event_result = match update_result {
-                    Ok(UpsertResult::NoChange) => ContractHandlerEvent::UpdateNoChange { key },
-                    Ok(UpsertResult::Updated(state)) => ContractHandlerEvent::UpdateResponse {
-                        new_value: Ok(state),
-                    },
-                    Err(err) => {
-                        if err.is_fatal() {
-                            todo!("Handle fatal error; reset executor");
-                        }
-                        ContractHandlerEvent::UpdateResponse {
-                            new_value: Err(err),
-                        }
+                    if err.is_fatal() {
+                        return Ok(EventOutcome::Fatal(err, id, event));
                     }
-                };
+                    ContractHandlerEvent::UpdateResponse {
+                        new_value: Err(err),
+                    }
+                }
+            };
 
-                contract_handler
-                    .channel()
-                    .send_to_sender(id, event_result)
-                    .await
-                    .map_err(|error| {
-                        tracing::debug!(%error, "shutting down contract handler");
-                        error
-                    })?;
-            }
-            ContractHandlerEvent::RegisterSubscriberListener {
-                key,
-                client_id,
-                summary,
-                subscriber_listener,
-            } => {
-                let _ = contract_handler
-                    .executor()
-                    .register_contract_notifier(key, client_id, subscriber_listener, summary)
-                    .inspect_err(|err| {
-                        tracing::warn!("Error while registering subscriber listener: {err}");
-                    });
-
-                // FIXME: if there is an error senc actually an error back
-                contract_handler
-                    .channel()
-                    .send_to_sender(id, ContractHandlerEvent::RegisterSubscriberListenerResponse)
-                    .await
-                    .inspect_err(|error| {
-                        tracing::debug!(%error, "shutting down contract handler");
-                    })?;
-            }
-            _ => unreachable!(),
+            contract_handler
+                .channel()
+                .send_to_sender(id, event_result)
+                .await
+                .map_err(|error| {
+                    tracing::debug!(%error, "shutting down contract handler");
+                    error
+                })?;
+        }
+        ContractHandlerEvent::RegisterSubscriberListener {
+            key,
+            client_id,
+            summary,
+            subscriber_listener,
+        } => {
+            let _ = contract_handler
+                .executor()
+                .register_contract_notifier(key, client_id, subscriber_listener, summary)
+                .inspect_err(|err| {
+                    tracing::warn!("Error while registering subscriber listener: {err}");
+                });
+
+            // FIXME: if there is an error senc actually an error back
+            contract_handler
+                .channel()
+                .send_to_sender(id, ContractHandlerEvent::RegisterSubscriberListenerResponse)
+                .await
+                .inspect_err(|error| {
+                    tracing::debug!(%error, "shutting down contract handler");
+                })?;
         }
+        ContractHandlerEvent::SummarizeQuery { key } => {
+            let summary = contract_handler
+                .executor()
+                .summarize_contract_state(key)
+                .instrument(tracing::info_span!("summarize_contract_state", %key))
+                .await;
+            let summary = match summary {
+                Err(err) if err.is_fatal() => {
+                    let event = ContractHandlerEvent::SummarizeQuery { key };
+                    return Ok(EventOutcome::Fatal(err, id, event));
+                }
+                summary => summary,
+            };
+            contract_handler
+                .channel()
+                .send_to_sender(id, ContractHandlerEvent::SummarizeResponse { key, summary })
+                .await
+                .map_err(|error| {
+                    tracing::debug!(%error, "shutting down contract handler");
+                    error
+                })?;
+        }
+        ContractHandlerEvent::DeltaQuery { key, summary } => {
+            let delta = contract_handler
+                .executor()
+                .get_contract_state_delta(key, summary.clone())
+                .instrument(tracing::info_span!("get_contract_state_delta", %key))
+                .await;
+            let delta = match delta {
+                Err(err) if err.is_fatal() => {
+                    let event = ContractHandlerEvent::DeltaQuery { key, summary };
+                    return Ok(EventOutcome::Fatal(err, id, event));
+                }
+                delta => delta,
+            };
+            contract_handler
+                .channel()
+                .send_to_sender(id, ContractHandlerEvent::DeltaResponse { key, delta })
+                .await
+                .map_err(|error| {
+                    tracing::debug!(%error, "shutting down contract handler");
+                    error
+                })?;
+        }
+        ContractHandlerEvent::DelegateRequest {
+            data,
+            attested_contract,
+        } => {
+            let response = contract_handler
+                .executor()
+                .execute_delegate_request(data.clone(), attested_contract.as_ref());
+            let response = match response {
+                Err(err) if err.is_fatal() => {
+                    let event = ContractHandlerEvent::DelegateRequest {
+                        data,
+                        attested_contract,
+                    };
+                    return Ok(EventOutcome::Fatal(err, id, event));
+                }
+                response => response,
+            };
+
+            contract_handler
+                .channel()
+                .send_to_sender(id, ContractHandlerEvent::DelegateResponse(response))
+                .await
+                .map_err(|error| {
+                    tracing::debug!(%error, "shutting down contract handler");
+                    error
+                })?;
+        }
+        _ => unreachable!(),
+    }
+    Ok(EventOutcome::Completed)
+}
+
+/// Builds the failure response for a query event which could not be completed.
+fn failed_response(event: ContractHandlerEvent, err: ExecutorError) -> ContractHandlerEvent {
+    match event {
+        ContractHandlerEvent::GetQuery { key, .. } => ContractHandlerEvent::GetResponse {
+            key,
+            response: Err(err),
+        },
+        ContractHandlerEvent::PutQuery { .. } => ContractHandlerEvent::PutResponse {
+            new_value: Err(err),
+        },
+        ContractHandlerEvent::UpdateQuery { .. } => ContractHandlerEvent::UpdateResponse {
+            new_value: Err(err),
+        },
+        ContractHandlerEvent::SummarizeQuery { key } => ContractHandlerEvent::SummarizeResponse {
+            key,
+            summary: Err(err),
+        },
+        ContractHandlerEvent::DeltaQuery { key, .. } => ContractHandlerEvent::DeltaResponse {
+            key,
+            delta: Err(err),
+        },
+        ContractHandlerEvent::DelegateRequest { .. } => {
+            ContractHandlerEvent::DelegateResponse(Err(err))
+        }
+        _ => unreachable!(),
     }
 }
 
@@ -204,4 +665,699 @@ pub(crate) enum ContractError {
     IOError(#[from] std::io::Error),
     #[error("no response received from handler")]
     NoEvHandlerResponse,
+    #[error("executor channel to the event loop closed")]
+    ExecutorChannelClosed,
+    #[error("failed to reset the executor: {0}")]
+    ExecutorReset(ExecutorError),
+    #[error("contract handler task failed: {0}")]
+    HandlerFailed(tokio::task::JoinError),
+}
+
+#[cfg(test)]
+mod test {
+    use std::future::Future;
+    use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+    use std::sync::Arc;
+
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::client_events::{ClientId, HostResult};
+    use crate::operations::get::GetMsg;
+
+    fn missing_state(key: ContractKey) -> ExecutorError {
+        ExecutorError::other(anyhow::anyhow!("no state stored for contract {key}"))
+    }
+
+    /// Behaviour of a [`TestExecutor`], each test only hooks the events it exercises.
+    trait ExecutorHooks: Send + 'static {
+        fn upsert(
+            &mut self,
+            _key: ContractKey,
+            _update: Either<WrappedState, StateDelta<'static>>,
+        ) -> impl Future<Output = Result<UpsertResult, ExecutorError>> + Send {
+            async { unreachable!() }
+        }
+
+        fn op_result_ready(
+            &mut self,
+        ) -> impl Future<Output = Result<Transaction, ExecutorError>> + Send {
+            std::future::pending()
+        }
+
+        fn forget_op(&mut self, _transaction: &Transaction) {}
+
+        fn reset(&mut self) -> Result<(), ExecutorError> {
+            Ok(())
+        }
+
+        fn delegate_request(
+            &mut self,
+            _req: DelegateRequest<'_>,
+            _attested_contract: Option<&ContractInstanceId>,
+        ) -> Result<HostResponse, ExecutorError> {
+            unreachable!()
+        }
+    }
+
+    /// Executor forwarding the events under test to its hooks.
+    struct TestExecutor<H>(H);
+
+    impl<H: ExecutorHooks> ContractExecutor for TestExecutor<H> {
+        async fn fetch_contract(
+            &mut self,
+            _key: ContractKey,
+            _return_contract_code: bool,
+        ) -> Result<(Option<WrappedState>, Option<ContractContainer>), ExecutorError> {
+            unreachable!()
+        }
+
+        async fn upsert_contract_state(
+            &mut self,
+            key: ContractKey,
+            update: Either<WrappedState, StateDelta<'static>>,
+            _related_contracts: RelatedContracts<'static>,
+            _code: Option<ContractContainer>,
+        ) -> Result<UpsertResult, ExecutorError> {
+            self.0.upsert(key, update).await
+        }
+
+        fn register_contract_notifier(
+            &mut self,
+            _key: ContractKey,
+            _cli_id: ClientId,
+            _notification_ch: mpsc::UnboundedSender<HostResult>,
+            _summary: Option<StateSummary<'_>>,
+        ) -> Result<(), Box<RequestError>> {
+            Ok(())
+        }
+
+        async fn summarize_contract_state(
+            &mut self,
+            key: ContractKey,
+        ) -> Result<StateSummary<'static>, ExecutorError> {
+            Err(missing_state(key))
+        }
+
+        async fn get_contract_state_delta(
+            &mut self,
+            key: ContractKey,
+            _summary: StateSummary<'static>,
+        ) -> Result<StateDelta<'static>, ExecutorError> {
+            Err(missing_state(key))
+        }
+
+        async fn op_result_ready(&mut self) -> Result<Transaction, ExecutorError> {
+            self.0.op_result_ready().await
+        }
+
+        fn forget_op(&mut self, transaction: &Transaction) {
+            self.0.forget_op(transaction)
+        }
+
+        fn reset(&mut self) -> Result<(), ExecutorError> {
+            self.0.reset()
+        }
+
+        fn execute_delegate_request(
+            &mut self,
+            req: DelegateRequest<'_>,
+            attested_contract: Option<&ContractInstanceId>,
+        ) -> Result<HostResponse, ExecutorError> {
+            self.0.delegate_request(req, attested_contract)
+        }
+    }
+
+    /// Putting a contract requires the state of another contract, which has to be fetched
+    /// from the network if not available locally. Deltas are appended to the stored state.
+    struct DependentContracts {
+        depends_on: HashMap<ContractKey, ContractKey>,
+        stored: HashMap<ContractKey, WrappedState>,
+        in_flight: HashMap<ContractKey, Transaction>,
+        /// Fetch transactions are created already timed out.
+        expired_fetches: bool,
+        fetch_requests: mpsc::UnboundedSender<(Transaction, ContractKey)>,
+        fetch_results: mpsc::UnboundedReceiver<(Transaction, ContractKey, WrappedState)>,
+    }
+
+    impl ExecutorHooks for DependentContracts {
+        async fn upsert(
+            &mut self,
+            key: ContractKey,
+            update: Either<WrappedState, StateDelta<'static>>,
+        ) -> Result<UpsertResult, ExecutorError> {
+            let state = match update {
+                Either::Left(state) => state,
+                Either::Right(delta) => {
+                    let stored = self
+                        .stored
+                        .get_mut(&key)
+                        .ok_or_else(|| missing_state(key))?;
+                    let updated = [stored.as_ref(), delta.as_ref()].concat();
+                    *stored = WrappedState::from(updated);
+                    return Ok(UpsertResult::Updated(stored.clone()));
+                }
+            };
+            let related = self.depends_on[&key];
+            if !self.stored.contains_key(&related) {
+                let (fetch_requests, expired) = (&self.fetch_requests, self.expired_fetches);
+                let tx = *self.in_flight.entry(related).or_insert_with(|| {
+                    let tx = if expired {
+                        Transaction::ttl_transaction()
+                    } else {
+                        Transaction::new::<GetMsg>()
+                    };
+                    fetch_requests.send((tx, related)).unwrap();
+                    tx
+                });
+                return Err(ExecutorError::suspended(tx));
+            }
+            self.stored.insert(key, state.clone());
+            Ok(UpsertResult::Updated(state))
+        }
+
+        async fn op_result_ready(&mut self) -> Result<Transaction, ExecutorError> {
+            let (tx, key, state) = self
+                .fetch_results
+                .recv()
+                .await
+                .ok_or_else(|| ExecutorError::other(anyhow::anyhow!("channel closed")))?;
+            self.in_flight.remove(&key);
+            self.stored.insert(key, state);
+            Ok(tx)
+        }
+
+        fn forget_op(&mut self, transaction: &Transaction) {
+            self.in_flight.retain(|_, tx| tx != transaction);
+        }
+    }
+
+    /// Fails with a fatal error until the executor has been reset a number of times.
+    struct Crashing {
+        fails_until_reset: usize,
+        resets: Arc<AtomicUsize>,
+    }
+
+    impl ExecutorHooks for Crashing {
+        async fn upsert(
+            &mut self,
+            _key: ContractKey,
+            update: Either<WrappedState, StateDelta<'static>>,
+        ) -> Result<UpsertResult, ExecutorError> {
+            if self.resets.load(SeqCst) < self.fails_until_reset {
+                let err = crate::wasm_runtime::ContractError::from(
+                    crate::wasm_runtime::ContractExecError::MaxComputeTimeExceeded,
+                );
+                return Err(ExecutorError::execution(err, None));
+            }
+            let Either::Left(state) = update else {
+                unreachable!()
+            };
+            Ok(UpsertResult::Updated(state))
+        }
+
+        fn reset(&mut self) -> Result<(), ExecutorError> {
+            self.resets.fetch_add(1, SeqCst);
+            Ok(())
+        }
+    }
+
+    /// Doesn't finish upserting a given contract until the gate is opened.
+    struct Gated {
+        gated: ContractKey,
+        gate: Arc<tokio::sync::Notify>,
+    }
+
+    impl ExecutorHooks for Gated {
+        async fn upsert(
+            &mut self,
+            key: ContractKey,
+            update: Either<WrappedState, StateDelta<'static>>,
+        ) -> Result<UpsertResult, ExecutorError> {
+            if key == self.gated {
+                self.gate.notified().await;
+            }
+            let Either::Left(state) = update else {
+                unreachable!()
+            };
+            Ok(UpsertResult::Updated(state))
+        }
+    }
+
+    /// Records the delegate requests served, failing for a given delegate.
+    struct Delegates {
+        failing: DelegateKey,
+        served: mpsc::UnboundedSender<(DelegateKey, Option<ContractInstanceId>)>,
+    }
+
+    impl ExecutorHooks for Delegates {
+        fn delegate_request(
+            &mut self,
+            req: DelegateRequest<'_>,
+            attested_contract: Option<&ContractInstanceId>,
+        ) -> Result<HostResponse, ExecutorError> {
+            let key = req.key().clone();
+            self.served
+                .send((key.clone(), attested_contract.copied()))
+                .unwrap();
+            if key == self.failing {
+                return Err(ExecutorError::other(anyhow::anyhow!(
+                    "delegate {key} not found"
+                )));
+            }
+            Ok(HostResponse::Ok)
+        }
+    }
+
+    struct TestHandler<E> {
+        channel: ContractHandlerChannel<ContractHandlerHalve>,
+        executor: E,
+    }
+
+    impl<E: ContractExecutor> ContractHandler for TestHandler<E> {
+        type Builder = ();
+        type ContractExecutor = E;
+
+        async fn build(
+            _channel: ContractHandlerChannel<ContractHandlerHalve>,
+            _executor_request_sender: ExecutorToEventLoopChannel<ExecutorHalve>,
+            _builder: Self::Builder,
+        ) -> anyhow::Result<Self> {
+            unreachable!()
+        }
+
+        async fn fork(
+            &self,
+            _channel: ContractHandlerChannel<ContractHandlerHalve>,
+            _executor_request_sender: ExecutorToEventLoopChannel<ExecutorHalve>,
+        ) -> anyhow::Result<Self> {
+            unreachable!()
+        }
+
+        fn channel(&mut self) -> &mut ContractHandlerChannel<ContractHandlerHalve> {
+            &mut self.channel
+        }
+
+        fn executor(&mut self) -> &mut Self::ContractExecutor {
+            &mut self.executor
+        }
+
+        fn channel_and_executor(
+            &mut self,
+        ) -> (
+            &mut ContractHandlerChannel<ContractHandlerHalve>,
+            &mut Self::ContractExecutor,
+        ) {
+            (&mut self.channel, &mut self.executor)
+        }
+    }
+
+    fn test_contract(seed: u8) -> ContractContainer {
+        ContractContainer::Wasm(ContractWasmAPIVersion::V1(WrappedContract::new(
+            Arc::new(ContractCode::from(vec![seed])),
+            Parameters::from(vec![seed]),
+        )))
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn interleaved_requests_with_cross_dependencies() -> anyhow::Result<()> {
+        const TIMEOUT: Duration = Duration::from_secs(1);
+
+        let (send_halve, rcv_halve, _) = contract_handler_channel();
+        let send_halve = Arc::new(send_halve);
+        let (contract_a, contract_b) = (test_contract(1), test_contract(2));
+        let (key_a, key_b) = (contract_a.key(), contract_b.key());
+
+        let (fetch_requests, mut requested) = mpsc::unbounded_channel();
+        let (network, fetch_results) = mpsc::unbounded_channel();
+        let executor = TestExecutor(DependentContracts {
+            depends_on: HashMap::from([(key_a, key_b), (key_b, key_a)]),
+            stored: HashMap::new(),
+            in_flight: HashMap::new(),
+            expired_fetches: false,
+            fetch_requests,
+            fetch_results,
+        });
+        let handler = GlobalExecutor::spawn(contract_handling(TestHandler {
+            channel: rcv_halve,
+            executor,
+        }));
+
+        let put = |contract: ContractContainer, state: Vec<u8>| {
+            let send_halve = send_halve.clone();
+            GlobalExecutor::spawn(async move {
+                send_halve
+                    .send_to_handler(ContractHandlerEvent::PutQuery {
+                        key: contract.key(),
+                        state: state.into(),
+                        related_contracts: RelatedContracts::default(),
+                        contract: Some(contract),
+                    })
+                    .await
+            })
+        };
+
+        // A depends on B, which is fetched from the network, so A is suspended...
+        let put_a = put(contract_a, vec![1]);
+        let (tx_b, requested_key) = tokio::time::timeout(TIMEOUT, requested.recv())
+            .await?
+            .expect("fetch request");
+        assert_eq!(requested_key, key_b);
+
+        // ...and the handler keeps serving B, which in turn depends on A
+        let put_b = put(contract_b, vec![2]);
+        let (tx_a, requested_key) = tokio::time::timeout(TIMEOUT, requested.recv())
+            .await?
+            .expect("fetch request");
+        assert_eq!(requested_key, key_a);
+
+        // results are delivered in reverse order
+        network.send((tx_a, key_a, vec![1].into()))?;
+        let ContractHandlerEvent::PutResponse { new_value } =
+            tokio::time::timeout(TIMEOUT, put_b).await???
+        else {
+            anyhow::bail!("invalid event");
+        };
+        assert_eq!(new_value.map_err(|e| anyhow::anyhow!(e))?.as_ref(), &[2]);
+
+        network.send((tx_b, key_b, vec![2].into()))?;
+        let ContractHandlerEvent::PutResponse { new_value } =
+            tokio::time::timeout(TIMEOUT, put_a).await???
+        else {
+            anyhow::bail!("invalid event");
+        };
+        assert_eq!(new_value.map_err(|e| anyhow::anyhow!(e))?.as_ref(), &[1]);
+
+        handler.abort();
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn update_does_not_overtake_suspended_put() -> anyhow::Result<()> {
+        const TIMEOUT: Duration = Duration::from_secs(1);
+
+        let (send_halve, rcv_halve, _) = contract_handler_channel();
+        let send_halve = Arc::new(send_halve);
+        let (contract_a, contract_b) = (test_contract(1), test_contract(2));
+        let (key_a, key_b) = (contract_a.key(), contract_b.key());
+
+        let (fetch_requests, mut requested) = mpsc::unbounded_channel();
+        let (network, fetch_results) = mpsc::unbounded_channel();
+        let executor = TestExecutor(DependentContracts {
+            depends_on: HashMap::from([(key_a, key_b)]),
+            stored: HashMap::new(),
+            in_flight: HashMap::new(),
+            expired_fetches: false,
+            fetch_requests,
+            fetch_results,
+        });
+        let handler = GlobalExecutor::spawn(contract_handling(TestHandler {
+            channel: rcv_halve,
+            executor,
+        }));
+
+        // the put is suspended waiting for the related contract...
+        let put = {
+            let send_halve = send_halve.clone();
+            GlobalExecutor::spawn(async move {
+                send_halve
+                    .send_to_handler(ContractHandlerEvent::PutQuery {
+                        key: key_a,
+                        state: vec![1].into(),
+                        related_contracts: RelatedContracts::default(),
+                        contract: Some(contract_a),
+                    })
+                    .await
+            })
+        };
+        let (tx_b, _) = tokio::time::timeout(TIMEOUT, requested.recv())
+            .await?
+            .expect("fetch request");
+
+        // ...so a later update, which doesn't need it, waits for the put
+        let update = {
+            let send_halve = send_halve.clone();
+            GlobalExecutor::spawn(async move {
+                send_halve
+                    .send_to_handler(ContractHandlerEvent::UpdateQuery {
+                        key: key_a,
+                        data: UpdateData::Delta(StateDelta::from(vec![2])),
+                        related_contracts: RelatedContracts::default(),
+                    })
+                    .await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!update.is_finished());
+
+        network.send((tx_b, key_b, vec![3].into()))?;
+        let ContractHandlerEvent::PutResponse { new_value } =
+            tokio::time::timeout(TIMEOUT, put).await???
+        else {
+            anyhow::bail!("invalid event");
+        };
+        assert_eq!(new_value.map_err(|e| anyhow::anyhow!(e))?.as_ref(), &[1]);
+        let ContractHandlerEvent::UpdateResponse { new_value } =
+            tokio::time::timeout(TIMEOUT, update).await???
+        else {
+            anyhow::bail!("invalid event");
+        };
+        assert_eq!(new_value.map_err(|e| anyhow::anyhow!(e))?.as_ref(), &[1, 2]);
+
+        handler.abort();
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn timed_out_request_does_not_block_later_requests() -> anyhow::Result<()> {
+        const TIMEOUT: Duration = Duration::from_secs(1);
+
+        let (send_halve, rcv_halve, _) = contract_handler_channel();
+        let send_halve = Arc::new(send_halve);
+        let (contract_a, contract_b) = (test_contract(1), test_contract(2));
+        let key_b = contract_b.key();
+
+        let (fetch_requests, mut requested) = mpsc::unbounded_channel();
+        let (_network, fetch_results) = mpsc::unbounded_channel();
+        let executor = TestExecutor(DependentContracts {
+            depends_on: HashMap::from([(contract_a.key(), key_b)]),
+            stored: HashMap::new(),
+            in_flight: HashMap::new(),
+            expired_fetches: true,
+            fetch_requests,
+            fetch_results,
+        });
+        let handler = GlobalExecutor::spawn(contract_handling(TestHandler {
+            channel: rcv_halve,
+            executor,
+        }));
+
+        let put = |contract: ContractContainer| {
+            let send_halve = send_halve.clone();
+            GlobalExecutor::spawn(async move {
+                send_halve
+                    .send_to_handler(ContractHandlerEvent::PutQuery {
+                        key: contract.key(),
+                        state: vec![1].into(),
+                        related_contracts: RelatedContracts::default(),
+                        contract: Some(contract),
+                    })
+                    .await
+            })
+        };
+
+        // the fetch never completes, so the request times out on the next expiration check...
+        let first_put = put(contract_a.clone());
+        let (first_tx, _) = tokio::time::timeout(TIMEOUT, requested.recv())
+            .await?
+            .expect("fetch request");
+        let expiration = SuspendedRequests::EXPIRATION_CHECK_INTERVAL + TIMEOUT;
+        let ContractHandlerEvent::PutResponse { new_value } =
+            tokio::time::timeout(expiration, first_put).await???
+        else {
+            anyhow::bail!("invalid event");
+        };
+        assert!(new_value.is_err());
+
+        // ...and a later request for the same contract fetches it again instead of waiting on it
+        let _second_put = put(contract_a);
+        let (second_tx, requested_key) = tokio::time::timeout(TIMEOUT, requested.recv())
+            .await?
+            .expect("fetch request");
+        assert_eq!(requested_key, key_b);
+        assert_ne!(first_tx, second_tx);
+
+        handler.abort();
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn slow_contract_only_stalls_its_own_shard() -> anyhow::Result<()> {
+        const TIMEOUT: Duration = Duration::from_secs(1);
+        const SHARDS: usize = 2;
+
+        let contract_in_shard = |shard: usize| {
+            (0..=u8::MAX)
+                .map(test_contract)
+                .find(|c| ShardRouter::shard_of(c.key().id(), SHARDS) == shard)
+                .expect("contract for shard")
+        };
+        let (slow, fast) = (contract_in_shard(0), contract_in_shard(1));
+
+        let (send_halve, rcv_halve, _) = contract_handler_channel();
+        let send_halve = Arc::new(send_halve);
+        let (router, channels) = rcv_halve.into_shards(SHARDS);
+        let gate = Arc::new(tokio::sync::Notify::new());
+        let router = GlobalExecutor::spawn(router.run());
+        let handlers: Vec<_> = channels
+            .into_iter()
+            .map(|channel| {
+                GlobalExecutor::spawn(contract_handling(TestHandler {
+                    channel,
+                    executor: TestExecutor(Gated {
+                        gated: slow.key(),
+                        gate: gate.clone(),
+                    }),
+                }))
+            })
+            .collect();
+
+        let put = |contract: ContractContainer, state: Vec<u8>| {
+            let send_halve = send_halve.clone();
+            GlobalExecutor::spawn(async move {
+                send_halve
+                    .send_to_handler(ContractHandlerEvent::PutQuery {
+                        key: contract.key(),
+                        state: state.into(),
+                        related_contracts: RelatedContracts::default(),
+                        contract: Some(contract),
+                    })
+                    .await
+            })
+        };
+
+        // the slow contract holds its handler...
+        let put_slow = put(slow, vec![1]);
+        // ...while the contract in the other shard is served
+        let ContractHandlerEvent::PutResponse { new_value } =
+            tokio::time::timeout(TIMEOUT, put(fast, vec![2])).await???
+        else {
+            anyhow::bail!("invalid event");
+        };
+        assert_eq!(new_value.map_err(|e| anyhow::anyhow!(e))?.as_ref(), &[2]);
+        assert!(!put_slow.is_finished());
+
+        gate.notify_one();
+        let ContractHandlerEvent::PutResponse { new_value } =
+            tokio::time::timeout(TIMEOUT, put_slow).await???
+        else {
+            anyhow::bail!("invalid event");
+        };
+        assert_eq!(new_value.map_err(|e| anyhow::anyhow!(e))?.as_ref(), &[1]);
+
+        router.abort();
+        handlers.iter().for_each(|h| h.abort());
+        Ok(())
+    }
+
+    async fn put_with_crashing_executor(
+        fails_until_reset: usize,
+    ) -> anyhow::Result<(Result<WrappedState, ExecutorError>, usize)> {
+        let (send_halve, rcv_halve, _) = contract_handler_channel();
+        let resets = Arc::new(AtomicUsize::new(0));
+        let executor = TestExecutor(Crashing {
+            fails_until_reset,
+            resets: resets.clone(),
+        });
+        let handler = GlobalExecutor::spawn(contract_handling(TestHandler {
+            channel: rcv_halve,
+            executor,
+        }));
+
+        let contract = test_contract(1);
+        let response = tokio::time::timeout(
+            Duration::from_secs(1),
+            send_halve.send_to_handler(ContractHandlerEvent::PutQuery {
+                key: contract.key(),
+                state: vec![1].into(),
+                related_contracts: RelatedContracts::default(),
+                contract: Some(contract),
+            }),
+        )
+        .await??;
+        let ContractHandlerEvent::PutResponse { new_value } = response else {
+            anyhow::bail!("invalid event");
+        };
+
+        handler.abort();
+        Ok((new_value, resets.load(SeqCst)))
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn fatal_error_resets_executor_and_replays() -> anyhow::Result<()> {
+        let (new_value, resets) = put_with_crashing_executor(1).await?;
+        assert_eq!(new_value.map_err(|e| anyhow::anyhow!(e))?.as_ref(), &[1]);
+        assert_eq!(resets, 1);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn repeated_fatal_error_is_reported_to_requester() -> anyhow::Result<()> {
+        let (new_value, resets) = put_with_crashing_executor(usize::MAX).await?;
+        let err = new_value.expect_err("request should fail");
+        assert!(err.is_fatal());
+        assert_eq!(resets, 2);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn delegate_request_is_routed_with_attested_contract() -> anyhow::Result<()> {
+        const TIMEOUT: Duration = Duration::from_secs(1);
+
+        let (send_halve, rcv_halve, _) = contract_handler_channel();
+        let failing = DelegateKey::new([2; 32], CodeHash::new([2; 32]));
+        let (served, mut served_rx) = mpsc::unbounded_channel();
+        let handler = GlobalExecutor::spawn(contract_handling(TestHandler {
+            channel: rcv_halve,
+            executor: TestExecutor(Delegates {
+                failing: failing.clone(),
+                served,
+            }),
+        }));
+
+        // the response is sent back to the requester along with the contract attested for it...
+        let key = DelegateKey::new([1; 32], CodeHash::new([1; 32]));
+        let attested = ContractInstanceId::new([3; 32]);
+        let response = tokio::time::timeout(
+            TIMEOUT,
+            send_halve.send_to_handler(ContractHandlerEvent::DelegateRequest {
+                data: DelegateRequest::UnregisterDelegate(key.clone()),
+                attested_contract: Some(attested),
+            }),
+        )
+        .await??;
+        let ContractHandlerEvent::DelegateResponse(response) = response else {
+            anyhow::bail!("invalid event");
+        };
+        assert!(matches!(response, Ok(HostResponse::Ok)));
+        assert_eq!(served_rx.try_recv()?, (key, Some(attested)));
+
+        // ...and so are the executor errors
+        let response = tokio::time::timeout(
+            TIMEOUT,
+            send_halve.send_to_handler(ContractHandlerEvent::DelegateRequest {
+                data: DelegateRequest::UnregisterDelegate(failing.clone()),
+                attested_contract: None,
+            }),
+        )
+        .await??;
+        let ContractHandlerEvent::DelegateResponse(response) = response else {
+            anyhow::bail!("invalid event");
+        };
+        assert!(response.is_err());
+        assert_eq!(served_rx.try_recv()?, (failing, None));
+
+        handler.abort();
+        Ok(())
+    }
 }
@@ -1,5 +1,6 @@
 //! Contract executor.
 
+use std::any::TypeId;
 use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
 use std::future::Future;
@@ -8,6 +9,7 @@ use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use blake3::traits::digest::generic_array::GenericArray;
+use dashmap::DashMap;
 use either::Either;
 use freenet_stdlib::client_api::{
     ClientError as WsClientError, ClientRequest, ContractError as StdContractError,
@@ -17,17 +19,18 @@ use freenet_stdlib::client_api::{
 };
 use freenet_stdlib::prelude::*;
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc::{self};
+use tokio::sync::{broadcast, mpsc};
 
 use super::storages::Storage;
-use crate::config::Config;
+use crate::config::{Config, OPERATION_TTL};
 use crate::message::Transaction;
 use crate::node::OpManager;
 use crate::operations::get::GetResult;
 use crate::operations::{OpEnum, OpError};
 use crate::wasm_runtime::{
-    ContractExecError, ContractRuntimeInterface, ContractStore, DelegateRuntimeInterface,
-    DelegateStore, Runtime, SecretsStore, StateStore, StateStoreError,
+    verify_stores, ContractExecError, ContractRuntimeInterface, ContractStore,
+    DelegateRuntimeInterface, DelegateStore, Runtime, SecretsStore, StateBatch, StateStore,
+    StateStoreError, StoreSnapshot, VerifyOptions,
 };
 use crate::{
     client_events::{ClientId, HostResult},
@@ -36,11 +39,17 @@ use crate::{
 
 pub(super) mod mock_runtime;
 pub(super) mod runtime;
+pub(super) mod storage_quota;
+
+use storage_quota::{InFlight, StorageQuota};
 
 #[derive(Debug)]
 pub struct ExecutorError {
     inner: Either<Box<RequestError>, anyhow::Error>,
     fatal: bool,
+    /// Set when the request can't make progress until the result of a network operation
+    /// with this transaction is received.
+    suspended_on: Option<Transaction>,
 }
 
 enum InnerOpError {
@@ -55,6 +64,7 @@ impl ExecutorError {
         Self {
             inner: Either::Right(error.into()),
             fatal: false,
+            suspended_on: None,
         }
     }
 
@@ -63,6 +73,19 @@ impl ExecutorError {
         Self {
             inner: Either::Right(anyhow::anyhow!("internal error")),
             fatal: false,
+            suspended_on: None,
+        }
+    }
+
+    /// The request is waiting for the result of a network operation and must be resumed
+    /// once the result for the given transaction is available.
+    pub(super) fn suspended(transaction: Transaction) -> Self {
+        Self {
+            inner: Either::Right(anyhow::anyhow!(
+                "request suspended waiting for transaction {transaction}"
+            )),
+            fatal: false,
+            suspended_on: Some(transaction),
         }
     }
 
@@ -70,10 +93,11 @@ impl ExecutorError {
         Self {
             inner: Either::Left(Box::new(error.into())),
             fatal: false,
+            suspended_on: None,
         }
     }
 
-    fn execution(
+    pub(super) fn execution(
         outer_error: crate::wasm_runtime::ContractError,
         op: Option<InnerOpError>,
     ) -> Self {
@@ -146,6 +170,11 @@ impl ExecutorError {
         self.fatal
     }
 
+    /// If the request was suspended, returns the transaction it is waiting on.
+    pub(crate) fn suspended_on(&self) -> Option<Transaction> {
+        self.suspended_on
+    }
+
     pub fn unwrap_request(self) -> RequestError {
         match self.inner {
             Either::Left(err) => *err,
@@ -159,6 +188,7 @@ impl From<RequestError> for ExecutorError {
         Self {
             inner: Either::Left(Box::new(value)),
             fatal: false,
+            suspended_on: None,
         }
     }
 }
@@ -177,6 +207,7 @@ impl From<Box<RequestError>> for ExecutorError {
         Self {
             inner: Either::Left(value),
             fatal: false,
+            suspended_on: None,
         }
     }
 }
@@ -212,37 +243,52 @@ pub(crate) fn executor_channel(
     ExecutorToEventLoopChannel<NetworkEventListenerHalve>,
     ExecutorToEventLoopChannel<ExecutorHalve>,
 ) {
-    // todo: use sensible values for channel buf sizes based on number concurrent tasks running
-    // when we are able to suspend execution of a request while waiting for a callback
-    let (waiting_for_op_tx, waiting_for_op_rx) = mpsc::channel(1);
-    let (response_for_tx, response_for_rx) = mpsc::channel(1);
+    let (listener_halve, mut sender_halves) = executor_channels(op_manager, 1);
+    (listener_halve, sender_halves.pop().unwrap())
+}
+
+/// Like [`executor_channel`], but for a pool of executors sharing the same event loop.
+///
+/// The result of an operation is sent back to the executor which requested it.
+pub(crate) fn executor_channels(
+    op_manager: Arc<OpManager>,
+    executors: usize,
+) -> (
+    ExecutorToEventLoopChannel<NetworkEventListenerHalve>,
+    Vec<ExecutorToEventLoopChannel<ExecutorHalve>>,
+) {
+    // requests waiting on the network are suspended instead of blocking the executor,
+    // so there can be several operations in flight at the same time
+    let (waiting_for_op_tx, waiting_for_op_rx) = mpsc::channel(100);
+    let requested_by = Arc::new(DashMap::new());
+
+    let mut response_for_tx = Vec::with_capacity(executors);
+    let mut sender_halves = Vec::with_capacity(executors);
+    for executor in 0..executors {
+        let (tx, response_for_rx) = mpsc::channel(100);
+        response_for_tx.push(tx);
+        sender_halves.push(ExecutorToEventLoopChannel {
+            op_manager: op_manager.clone(),
+            end: ExecutorHalve {
+                executor,
+                waiting_for_op_tx: waiting_for_op_tx.clone(),
+                response_for_rx,
+                requested_by: requested_by.clone(),
+                completed: HashMap::default(),
+                detached: HashSet::default(),
+            },
+        });
+    }
 
     let listener_halve = ExecutorToEventLoopChannel {
-        op_manager: op_manager.clone(),
+        op_manager,
         end: NetworkEventListenerHalve {
             waiting_for_op_rx,
-            response_for_tx,
-        },
-    };
-    let sender_halve = ExecutorToEventLoopChannel {
-        op_manager: op_manager.clone(),
-        end: ExecutorHalve {
-            waiting_for_op_tx,
-            response_for_rx,
-            completed: HashMap::default(),
+            response_for_tx: response_for_tx.into(),
+            requested_by,
         },
     };
-    (listener_halve, sender_halve)
-}
-
-#[derive(thiserror::Error, Debug)]
-enum CallbackError {
-    #[error(transparent)]
-    Err(#[from] ExecutorError),
-    #[error(transparent)]
-    Conversion(#[from] OpError),
-    #[error("missing result")]
-    MissingResult,
+    (listener_halve, sender_halves)
 }
 
 impl ExecutorToEventLoopChannel<ExecutorHalve> {
@@ -253,6 +299,7 @@ impl ExecutorToEventLoopChannel<ExecutorHalve> {
     {
         let op = message.initiate_op(&self.op_manager);
         let tx = *op.id();
+        self.end.requested_by.insert(tx, self.end.executor);
         self.end.waiting_for_op_tx.send(tx).await.inspect_err(|_| {
             tracing::debug!("failed to send request to executor, channel closed");
         })?;
@@ -265,24 +312,37 @@ impl ExecutorToEventLoopChannel<ExecutorHalve> {
         Ok(tx)
     }
 
-    async fn receive_op_result<Op>(&mut self, transaction: Transaction) -> Result<Op, CallbackError>
+    /// Waits for the next operation result sent back by the event loop and returns
+    /// the transaction it belongs to.
+    ///
+    /// Results of detached operations are discarded, any other result is kept until it is
+    /// claimed by the resumed request.
+    async fn next_op_result(&mut self) -> Result<Transaction, ExecutorError> {
+        loop {
+            let op_result = self
+                .end
+                .response_for_rx
+                .recv()
+                .await
+                .ok_or_else(|| ExecutorError::other(anyhow::anyhow!("channel closed")))?;
+            let tx = *op_result.id();
+            if self.end.detached.remove(&tx) {
+                tracing::debug!(%tx, "discarding result of detached operation");
+                continue;
+            }
+            self.end.completed.insert(tx, op_result);
+            return Ok(tx);
+        }
+    }
+
+    fn take_op_result<Op>(&mut self, transaction: &Transaction) -> Option<Result<Op, OpError>>
     where
         Op: Operation + TryFrom<OpEnum, Error = OpError>,
     {
-        if let Some(result) = self.end.completed.remove(&transaction) {
-            return result.try_into().map_err(CallbackError::Conversion);
-        }
-        let op_result = self
-            .end
-            .response_for_rx
-            .recv()
-            .await
-            .ok_or_else(|| ExecutorError::other(anyhow::anyhow!("channel closed")))?;
-        if op_result.id() != &transaction {
-            self.end.completed.insert(*op_result.id(), op_result);
-            return Err(CallbackError::MissingResult);
-        }
-        op_result.try_into().map_err(CallbackError::Conversion)
+        self.end
+            .completed
+            .remove(transaction)
+            .map(|op_result| op_result.try_into())
     }
 }
 
@@ -302,6 +362,7 @@ impl ExecutorToEventLoopChannel<NetworkEventListenerHalve> {
             op_manager: self.op_manager.clone(),
             end: Callback {
                 response_for_tx: self.end.response_for_tx.clone(),
+                requested_by: self.end.requested_by.clone(),
             },
         }
     }
@@ -309,33 +370,51 @@ impl ExecutorToEventLoopChannel<NetworkEventListenerHalve> {
 
 impl ExecutorToEventLoopChannel<Callback> {
     pub async fn response(&mut self, result: OpEnum) {
-        if self.end.response_for_tx.send(result).await.is_err() {
+        let Some((_, executor)) = self.end.requested_by.remove(result.id()) else {
+            tracing::debug!(tx = %result.id(), "no executor waiting for the operation result");
+            return;
+        };
+        if self.end.response_for_tx[executor]
+            .send(result)
+            .await
+            .is_err()
+        {
             tracing::debug!("failed to send response to executor, channel closed");
         }
     }
 }
 
 pub(crate) struct Callback {
-    /// sends the callback response to the executor
-    response_for_tx: mpsc::Sender<OpEnum>,
+    /// sends the callback response to the executors
+    response_for_tx: Arc<[mpsc::Sender<OpEnum>]>,
+    /// executor which requested each operation
+    requested_by: Arc<DashMap<Transaction, usize>>,
 }
 
 pub(crate) struct NetworkEventListenerHalve {
     /// this is the receiver end of the Executor halve, which will be sent from the executor
     /// when a callback is expected for a given transaction
     waiting_for_op_rx: mpsc::Receiver<Transaction>,
-    /// this is the sender end of the Executor halve receiver, which will communicate
-    /// back responses to the executor, it's cloned each tiome a new callback halve is created
-    response_for_tx: mpsc::Sender<OpEnum>,
+    /// this is the sender end of the Executor halves receivers, which will communicate
+    /// back responses to the executors, it's cloned each tiome a new callback halve is created
+    response_for_tx: Arc<[mpsc::Sender<OpEnum>]>,
+    /// executor which requested each operation, indexing `response_for_tx`
+    requested_by: Arc<DashMap<Transaction, usize>>,
 }
 
 pub struct ExecutorHalve {
+    /// index of this executor in the pool sharing the event loop
+    executor: usize,
     /// communicates the executor is waiting for a callback for a given transaction
     waiting_for_op_tx: mpsc::Sender<Transaction>,
     /// receives the callback response from the `process_message` task after completion
     response_for_rx: mpsc::Receiver<OpEnum>,
+    /// executor which requested each operation, shared with the event loop
+    requested_by: Arc<DashMap<Transaction, usize>>,
     /// stores the completed operations if they haven't been asked for yet in the executor
     completed: HashMap<Transaction, OpEnum>,
+    /// operations for which nobody is waiting, their results are discarded on arrival
+    detached: HashSet<Transaction>,
 }
 
 mod sealed {
@@ -351,6 +430,9 @@ where
     Self: Sized,
     Op: Operation + Send + 'static,
 {
+    /// Contract this request is about, used to match a replayed request with its result.
+    fn contract(&self) -> ContractInstanceId;
+
     fn initiate_op(self, op_manager: &OpManager) -> Op;
 
     fn resume_op(
@@ -366,6 +448,10 @@ struct GetContract {
 }
 
 impl ComposeNetworkMessage<operations::get::GetOp> for GetContract {
+    fn contract(&self) -> ContractInstanceId {
+        *self.key.id()
+    }
+
     fn initiate_op(self, _op_manager: &OpManager) -> operations::get::GetOp {
         operations::get::start_op(self.key, self.return_contract_code)
     }
@@ -381,6 +467,10 @@ struct SubscribeContract {
 }
 
 impl ComposeNetworkMessage<operations::subscribe::SubscribeOp> for SubscribeContract {
+    fn contract(&self) -> ContractInstanceId {
+        *self.key.id()
+    }
+
     fn initiate_op(self, _op_manager: &OpManager) -> operations::subscribe::SubscribeOp {
         operations::subscribe::start_op(self.key)
     }
@@ -401,6 +491,10 @@ struct PutContract {
 }
 
 impl ComposeNetworkMessage<operations::put::PutOp> for PutContract {
+    fn contract(&self) -> ContractInstanceId {
+        *self.contract.key().id()
+    }
+
     fn initiate_op(self, op_manager: &OpManager) -> operations::put::PutOp {
         let PutContract {
             contract,
@@ -433,6 +527,10 @@ pub(crate) enum UpsertResult {
 }
 
 impl ComposeNetworkMessage<operations::update::UpdateOp> for UpdateContract {
+    fn contract(&self) -> ContractInstanceId {
+        *self.key.id()
+    }
+
     fn initiate_op(self, _op_manager: &OpManager) -> operations::update::UpdateOp {
         let UpdateContract { key, new_state } = self;
         let related_contracts = RelatedContracts::default();
@@ -470,6 +568,66 @@ pub(crate) trait ContractExecutor: Send + 'static {
         notification_ch: tokio::sync::mpsc::UnboundedSender<HostResult>,
         summary: Option<StateSummary<'_>>,
     ) -> Result<(), Box<RequestError>>;
+
+    /// Summarizes the current state of a contract, so other peers can compute the changes
+    /// it is missing from their own states.
+    fn summarize_contract_state(
+        &mut self,
+        key: ContractKey,
+    ) -> impl Future<Output = Result<StateSummary<'static>, ExecutorError>> + Send;
+
+    /// Computes the changes in the current state of a contract which are missing from
+    /// the state of a peer, given the summary of that state.
+    fn get_contract_state_delta(
+        &mut self,
+        key: ContractKey,
+        summary: StateSummary<'static>,
+    ) -> impl Future<Output = Result<StateDelta<'static>, ExecutorError>> + Send;
+
+    /// Waits until the result of a network operation requested by the executor is available
+    /// and returns its transaction, so any request suspended on it can be resumed.
+    fn op_result_ready(
+        &mut self,
+    ) -> impl Future<Output = Result<Transaction, ExecutorError>> + Send;
+
+    /// Forgets a network operation no request is waiting on anymore, because the requests
+    /// suspended on it were either resumed or timed out, so later requests don't wait on it.
+    fn forget_op(&mut self, transaction: &Transaction);
+
+    /// Rebuilds the execution environment after a fatal error, reusing the same underlying stores.
+    fn reset(&mut self) -> Result<(), ExecutorError>;
+
+    fn execute_delegate_request(
+        &mut self,
+        req: DelegateRequest<'_>,
+        attested_contract: Option<&ContractInstanceId>,
+    ) -> Response;
+}
+
+/// Channel through which an executor and its forks let each other know about contract
+/// upgrades, so the clients subscribed to the upgraded contract are notified whichever
+/// executor serves them.
+struct UpgradeNotices {
+    sender: broadcast::Sender<(ContractKey, ContractKey)>,
+    receiver: broadcast::Receiver<(ContractKey, ContractKey)>,
+}
+
+impl UpgradeNotices {
+    const CAPACITY: usize = 64;
+
+    fn fork(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            receiver: self.sender.subscribe(),
+        }
+    }
+}
+
+impl Default for UpgradeNotices {
+    fn default() -> Self {
+        let (sender, receiver) = broadcast::channel(Self::CAPACITY);
+        Self { sender, receiver }
+    }
 }
 
 /// A WASM executor which will run any contracts, delegates, etc. registered.
@@ -487,6 +645,18 @@ pub struct Executor<R = Runtime> {
     subscriber_summaries: HashMap<ContractKey, HashMap<ClientId, Option<StateSummary<'static>>>>,
     /// Attested contract instances for a given delegate.
     delegate_attested_ids: HashMap<DelegateKey, Vec<ContractInstanceId>>,
+    /// Network operations in flight for suspended requests, by operation type and contract.
+    pending_ops: HashMap<(TypeId, ContractInstanceId), Transaction>,
+    /// Disk budget for the stored contracts, if any.
+    storage_quota: Option<StorageQuota>,
+    /// Contracts of the requests suspended waiting on the network, kept from being evicted
+    /// until the requests are resumed.
+    suspended_in_flight: HashMap<Transaction, Vec<InFlight>>,
+    /// State writes gathered by the requests suspended waiting on the network, by contract,
+    /// along with the transaction they are suspended on. Reused once the requests are resumed.
+    suspended_batches: HashMap<ContractKey, (Transaction, StateBatch)>,
+    /// Contract upgrades announced by this executor or any of its forks.
+    upgrades: UpgradeNotices,
 
     event_loop_channel: Option<ExecutorToEventLoopChannel<ExecutorHalve>>,
 }
@@ -508,6 +678,11 @@ impl<R> Executor<R> {
             update_notifications: HashMap::default(),
             subscriber_summaries: HashMap::default(),
             delegate_attested_ids: HashMap::default(),
+            pending_ops: HashMap::default(),
+            storage_quota: None,
+            suspended_in_flight: HashMap::default(),
+            suspended_batches: HashMap::default(),
+            upgrades: UpgradeNotices::default(),
             event_loop_channel,
         })
     }
@@ -530,17 +705,75 @@ impl<R> Executor<R> {
         const MAX_SIZE: i64 = 10 * 1024 * 1024;
         const MAX_MEM_CACHE: u32 = 10_000_000;
 
-        let state_store =
-            StateStore::new(Storage::new(&config.db_dir()).await?, MAX_MEM_CACHE).unwrap();
-        let contract_store = ContractStore::new(config.contracts_dir(), MAX_SIZE)?;
+        let mut state_store = StateStore::new(Storage::new(&config.db_dir()).await?, MAX_MEM_CACHE)
+            .unwrap()
+            .with_history(config.state_history.unwrap_or(0));
+        let mut contract_store = ContractStore::new(config.contracts_dir(), MAX_SIZE)?;
 
-        let delegate_store = DelegateStore::new(config.delegates_dir(), MAX_SIZE)?;
+        let mut delegate_store = DelegateStore::new(config.delegates_dir(), MAX_SIZE)?;
+
+        if let Some(snapshot) = &config.load_snapshot {
+            let snapshot = StoreSnapshot::read(snapshot)?;
+            let missing_params = snapshot
+                .import(&mut contract_store, &mut delegate_store, &mut state_store)
+                .await?;
+            for id in missing_params {
+                tracing::warn!(contract = %id, "state not imported from snapshot, missing parameters");
+            }
+            tracing::info!(
+                "imported {} contracts and {} delegates from snapshot",
+                snapshot.contracts(),
+                snapshot.delegates()
+            );
+        }
+        Self::check_stores(config, &mut state_store).await;
 
         let secret_store = SecretsStore::new(config.secrets_dir(), config.secrets.clone())?;
 
         Ok((contract_store, delegate_store, secret_store, state_store))
     }
 
+    /// Reports any inconsistencies in the stores, without repairing them since that may
+    /// discard data. Lock files are not reported, since another process may be using the
+    /// stores.
+    async fn check_stores(config: &Config, state_store: &mut StateStore<Storage>) {
+        let options = VerifyOptions {
+            skip_locks: true,
+            ..Default::default()
+        };
+        let report = match verify_stores(
+            &config.contracts_dir(),
+            &config.delegates_dir(),
+            &config.secrets_dir(),
+            state_store,
+            options,
+        )
+        .await
+        {
+            Ok(report) => report,
+            Err(err) => {
+                tracing::error!("failed verifying the stores: {err}");
+                return;
+            }
+        };
+        for issue in &report.issues {
+            tracing::warn!("inconsistent store: {issue}");
+        }
+        if !report.is_consistent() {
+            tracing::warn!(
+                "found {} inconsistencies in the stores, run `fdev verify-stores --repair` \
+                 with the node stopped to fix them",
+                report.issues.len()
+            );
+        }
+    }
+
+    /// Requests an operation to the network without blocking the executor.
+    ///
+    /// The first time a request is made it is sent to the event loop and the caller is suspended
+    /// (see [`ExecutorError::suspended_on`]) instead of waiting for the result, so the executor
+    /// can keep serving other requests. Once the result is delivered the suspended request is
+    /// replayed, and the same call will then return the result.
     async fn op_request<Op, M>(&mut self, request: M) -> Result<Op::Result, ExecutorError>
     where
         Op: Operation + Send + TryFrom<OpEnum, Error = OpError> + 'static,
@@ -552,32 +785,176 @@ impl<R> Executor<R> {
                 "missing event loop channel"
             )));
         };
+        let pending_key = (TypeId::of::<Op>(), request.contract());
+        if let Some(transaction) = self.pending_ops.get(&pending_key).copied() {
+            let Some(result) = ch.take_op_result::<Op>(&transaction) else {
+                return Err(ExecutorError::suspended(transaction));
+            };
+            self.pending_ops.remove(&pending_key);
+            return Self::op_result::<Op>(result);
+        }
         let transaction = ch
             .send_to_event_loop(request)
             .await
             .map_err(ExecutorError::other)?;
-        // FIXME: must add a way to suspend a request while waiting for result and resume upon getting
-        // an answer back so we don't block the executor itself.
-        // otherwise it may be possible to end up in a deadlock waiting for a tree of contract
-        // dependencies to be resolved
-        let result = loop {
-            match ch.receive_op_result::<Op>(transaction).await {
-                Ok(result) => break result,
-                Err(CallbackError::MissingResult) => {
-                    tokio::time::sleep(Duration::from_secs(10)).await;
-                    continue;
-                }
-                Err(CallbackError::Conversion(err)) => {
-                    tracing::error!("expect message of one type but got an other: {err}");
-                    return Err(ExecutorError::other(err));
-                }
-                Err(CallbackError::Err(other)) => return Err(other),
-            }
+        self.pending_ops.insert(pending_key, transaction);
+        Err(ExecutorError::suspended(transaction))
+    }
+
+    /// Requests an operation to the network and waits for its result.
+    ///
+    /// Only for requests served outside of the contract handler, which are not suspended and
+    /// replayed, like the ones made through [`Executor::contract_requests`].
+    async fn op_request_blocking<Op, M>(&mut self, request: M) -> Result<Op::Result, ExecutorError>
+    where
+        Op: Operation + Send + TryFrom<OpEnum, Error = OpError> + 'static,
+        <Op as Operation>::Result: TryFrom<Op, Error = OpError>,
+        M: ComposeNetworkMessage<Op>,
+    {
+        let Some(ch) = &mut self.event_loop_channel else {
+            return Err(ExecutorError::other(anyhow::anyhow!(
+                "missing event loop channel"
+            )));
         };
-        let result = <Op::Result>::try_from(result).map_err(|err| {
-            tracing::debug!("didn't get result back: {err}");
+        let transaction = ch
+            .send_to_event_loop(request)
+            .await
+            .map_err(ExecutorError::other)?;
+        let wait_result = async {
+            while ch.next_op_result().await? != transaction {}
+            Ok::<_, ExecutorError>(())
+        };
+        tokio::time::timeout(OPERATION_TTL, wait_result)
+            .await
+            .map_err(|_| ExecutorError::request(RequestError::Timeout))??;
+        let result = ch
+            .take_op_result::<Op>(&transaction)
+            .ok_or_else(ExecutorError::internal_error)?;
+        Self::op_result::<Op>(result)
+    }
+
+    fn op_result<Op>(result: Result<Op, OpError>) -> Result<Op::Result, ExecutorError>
+    where
+        Op: Operation,
+        <Op as Operation>::Result: TryFrom<Op, Error = OpError>,
+    {
+        let result = result.map_err(|err| {
+            tracing::error!("expect message of one type but got an other: {err}");
             ExecutorError::other(err)
         })?;
-        Ok(result)
+        <Op::Result>::try_from(result).map_err(|err| {
+            tracing::debug!("didn't get result back: {err}");
+            ExecutorError::other(err)
+        })
+    }
+
+    /// Keeps the contract from being evicted by any executor sharing the storage quota until
+    /// the returned guard is dropped.
+    fn in_flight(&self, key: &ContractKey) -> Option<InFlight> {
+        self.storage_quota
+            .as_ref()
+            .map(|quota| quota.in_flight(*key.id()))
+    }
+
+    fn forget_pending_op(&mut self, transaction: &Transaction) {
+        self.pending_ops.retain(|_, pending| pending != transaction);
+        if let Some(ch) = &mut self.event_loop_channel {
+            ch.end.completed.remove(transaction);
+        }
     }
+
+    /// Requests an operation to the network for which the executor doesn't need the result.
+    async fn op_request_detached<Op, M>(&mut self, request: M) -> Result<(), ExecutorError>
+    where
+        Op: Operation + Send + 'static,
+        M: ComposeNetworkMessage<Op>,
+    {
+        let Some(ch) = &mut self.event_loop_channel else {
+            return Err(ExecutorError::other(anyhow::anyhow!(
+                "missing event loop channel"
+            )));
+        };
+        let transaction = ch
+            .send_to_event_loop(request)
+            .await
+            .map_err(ExecutorError::other)?;
+        ch.end.detached.insert(transaction);
+        Ok(())
+    }
+
+    /// Waits for the next operation result, notifying the subscribers of the contracts
+    /// upgraded by the forks of this executor meanwhile.
+    async fn next_op_result(&mut self) -> Result<Transaction, ExecutorError> {
+        loop {
+            let upgrade = match &mut self.event_loop_channel {
+                Some(ch) => tokio::select! {
+                    tx = ch.next_op_result() => return tx,
+                    upgrade = self.upgrades.receiver.recv() => upgrade,
+                },
+                None => self.upgrades.receiver.recv().await,
+            };
+            match upgrade {
+                Ok((predecessor, successor)) => self.notify_upgrade(predecessor, successor),
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "missed contract upgrade notices");
+                }
+                Err(broadcast::error::RecvError::Closed) => return std::future::pending().await,
+            }
+        }
+    }
+
+    /// Notifies the subscribers of an upgraded contract, both the ones served by this executor
+    /// and, since the contracts are sharded between them, the ones served by its forks.
+    fn announce_upgrade(&mut self, predecessor: ContractKey, successor: ContractKey) {
+        self.notify_upgrade(predecessor, successor);
+        // there are no other receivers if the executor was never forked
+        let _ = self.upgrades.sender.send((predecessor, successor));
+    }
+
+    /// Lets the clients subscribed to an upgraded contract know they should subscribe to
+    /// its successor instead, dropping their subscriptions.
+    fn notify_upgrade(&mut self, predecessor: ContractKey, successor: ContractKey) {
+        self.subscriber_summaries.remove(&predecessor);
+        if let Some(quota) = &self.storage_quota {
+            quota.set_subscribed(*predecessor.id(), false);
+        }
+        let Some(notifiers) = self.update_notifications.remove(&predecessor) else {
+            return;
+        };
+        for (cli_id, notifier) in notifiers {
+            let notice = upgrade_notice(predecessor, successor);
+            let notice =
+                WsClientError::from(freenet_stdlib::client_api::ErrorKind::RequestError(notice));
+            if notifier.send(Err(notice)).is_err() {
+                tracing::debug!(cli_id = %cli_id, contract = %predecessor, "client gone before upgrade notice");
+            }
+        }
+    }
+}
+
+/// Cause of the subscription error sent to the clients subscribed to an upgraded contract,
+/// followed by the instance id of its successor.
+const UPGRADE_NOTICE_CAUSE: &str = "contract upgraded, subscribe to ";
+
+/// Builds the notice sent to the clients subscribed to an upgraded contract.
+///
+/// The client API has no dedicated response for upgrades, so the notice is a subscription
+/// error for the predecessor whose cause reads `contract upgraded, subscribe to <successor>`,
+/// where `<successor>` is the encoded instance id of the successor. Clients can recover the
+/// successor with [`upgraded_to`].
+fn upgrade_notice(predecessor: ContractKey, successor: ContractKey) -> RequestError {
+    RequestError::from(StdContractError::Subscribe {
+        key: predecessor,
+        cause: format!("{UPGRADE_NOTICE_CAUSE}{}", successor.id()).into(),
+    })
+}
+
+/// Returns the successor of the contract if the error is the notice sent to its subscribers
+/// when it was upgraded, see [`upgrade_notice`].
+pub fn upgraded_to(error: &RequestError) -> Option<ContractKey> {
+    let RequestError::ContractError(StdContractError::Subscribe { cause, .. }) = error else {
+        return None;
+    };
+    let successor = cause.strip_prefix(UPGRADE_NOTICE_CAUSE)?;
+    ContractKey::from_id(successor.to_owned()).ok()
 }
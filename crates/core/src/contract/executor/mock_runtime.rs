@@ -35,6 +35,24 @@ impl Executor<MockRuntime> {
         Ok(executor)
     }
 
+    pub(crate) async fn fork(
+        &self,
+        event_loop_channel: ExecutorToEventLoopChannel<ExecutorHalve>,
+    ) -> anyhow::Result<Self> {
+        let mut executor = Executor::new(
+            self.state_store.clone(),
+            || Ok(()),
+            self.mode,
+            MockRuntime {
+                contract_store: self.runtime.contract_store.clone(),
+            },
+            Some(event_loop_channel),
+        )
+        .await?;
+        executor.upgrades = self.upgrades.fork();
+        Ok(executor)
+    }
+
     pub async fn handle_request(
         &mut self,
         _id: ClientId,
@@ -105,6 +123,15 @@ impl ContractExecutor for Executor<MockRuntime> {
                     .map_err(ExecutorError::other)?;
                 Ok(UpsertResult::Updated(incoming_state))
             }
+            (Either::Right(delta), None) => {
+                // deltas are the whole state, see `get_contract_state_delta`
+                let incoming_state = WrappedState::new(delta.into_bytes());
+                self.state_store
+                    .update(&key, incoming_state.clone())
+                    .await
+                    .map_err(ExecutorError::other)?;
+                Ok(UpsertResult::Updated(incoming_state))
+            }
             (update, contract) => unreachable!("{update:?}, {contract:?}"),
         }
     }
@@ -118,6 +145,56 @@ impl ContractExecutor for Executor<MockRuntime> {
     ) -> Result<(), Box<RequestError>> {
         Ok(())
     }
+
+    async fn summarize_contract_state(
+        &mut self,
+        key: ContractKey,
+    ) -> Result<StateSummary<'static>, ExecutorError> {
+        let state = self
+            .state_store
+            .get(&key)
+            .await
+            .map_err(ExecutorError::other)?;
+        Ok(StateSummary::from(
+            blake3::hash(state.as_ref()).as_bytes().to_vec(),
+        ))
+    }
+
+    /// Without running the contract the whole state is the only delta which can be computed.
+    async fn get_contract_state_delta(
+        &mut self,
+        key: ContractKey,
+        _summary: StateSummary<'static>,
+    ) -> Result<StateDelta<'static>, ExecutorError> {
+        let state = self
+            .state_store
+            .get(&key)
+            .await
+            .map_err(ExecutorError::other)?;
+        Ok(StateDelta::from(state.as_ref().to_vec()))
+    }
+
+    async fn op_result_ready(&mut self) -> Result<Transaction, ExecutorError> {
+        self.next_op_result().await
+    }
+
+    fn forget_op(&mut self, transaction: &Transaction) {
+        self.forget_pending_op(transaction)
+    }
+
+    fn reset(&mut self) -> Result<(), ExecutorError> {
+        Ok(())
+    }
+
+    fn execute_delegate_request(
+        &mut self,
+        _req: DelegateRequest<'_>,
+        _attested_contract: Option<&ContractInstanceId>,
+    ) -> Response {
+        Err(ExecutorError::other(anyhow::anyhow!(
+            "delegates are not supported by the mock runtime"
+        )))
+    }
 }
 
 #[cfg(test)]
@@ -151,4 +228,43 @@ mod test {
         assert_eq!(counter, 1);
         Ok(())
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn sync_state_with_delta() -> Result<(), Box<dyn std::error::Error>> {
+        async fn executor(dir: &std::path::Path) -> anyhow::Result<Executor<MockRuntime>> {
+            let contract_store = ContractStore::new(dir.join("contracts"), 10_000)?;
+            let state_store = StateStore::new(Storage::new(dir).await?, 10_000)?;
+            let runtime = MockRuntime { contract_store };
+            Executor::new(state_store, || Ok(()), OperationMode::Local, runtime, None).await
+        }
+
+        let contract = ContractContainer::Wasm(ContractWasmAPIVersion::V1(WrappedContract::new(
+            Arc::new(ContractCode::from(vec![1])),
+            Parameters::from(vec![]),
+        )));
+        let key = contract.key();
+        let (dir_a, dir_b) = (tempfile::tempdir()?, tempfile::tempdir()?);
+        let mut a = executor(dir_a.path()).await?;
+        let mut b = executor(dir_b.path()).await?;
+        for (executor, state) in [(&mut a, vec![1]), (&mut b, vec![2])] {
+            executor
+                .upsert_contract_state(
+                    key,
+                    Either::Left(state.into()),
+                    RelatedContracts::default(),
+                    Some(contract.clone()),
+                )
+                .await?;
+        }
+
+        let summary = b.summarize_contract_state(key).await?;
+        let delta = a.get_contract_state_delta(key, summary).await?;
+        b.upsert_contract_state(key, Either::Right(delta), RelatedContracts::default(), None)
+            .await?;
+        assert_eq!(
+            a.summarize_contract_state(key).await?.as_ref(),
+            b.summarize_contract_state(key).await?.as_ref()
+        );
+        Ok(())
+    }
 }
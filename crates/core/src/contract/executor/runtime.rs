@@ -1,3 +1,5 @@
+use std::pin::Pin;
+
 use super::*;
 
 impl ContractExecutor for Executor<Runtime> {
@@ -6,6 +8,7 @@ impl ContractExecutor for Executor<Runtime> {
         key: ContractKey,
         return_contract_code: bool,
     ) -> Result<(Option<WrappedState>, Option<ContractContainer>), ExecutorError> {
+        let _in_flight = self.in_flight(&key);
         match self.perform_contract_get(return_contract_code, key).await {
             Ok((state, code)) => Ok((state, code)),
             Err(err) => Err(err),
@@ -19,136 +22,30 @@ impl ContractExecutor for Executor<Runtime> {
         related_contracts: RelatedContracts<'static>,
         code: Option<ContractContainer>,
     ) -> Result<UpsertResult, ExecutorError> {
-        let params = if let Some(code) = &code {
-            code.params()
-        } else {
-            self.state_store
-                .get_params(&key)
-                .await
-                .map_err(ExecutorError::other)?
-                .ok_or_else(|| {
-                    ExecutorError::request(StdContractError::Put {
-                        key,
-                        cause: "missing contract parameters".into(),
-                    })
-                })?
-        };
-
-        let remove_if_fail = if self
-            .runtime
-            .contract_store
-            .fetch_contract(&key, &params)
-            .is_none()
-        {
-            let code = code.ok_or_else(|| {
-                ExecutorError::request(StdContractError::MissingContract { key: key.into() })
-            })?;
-            self.runtime
-                .contract_store
-                .store_contract(code.clone())
-                .map_err(ExecutorError::other)?;
-            true
-        } else {
-            false
-        };
-
-        let mut updates = match update {
-            Either::Left(incoming_state) => {
-                let result = self
-                    .runtime
-                    .validate_state(&key, &params, &incoming_state, &related_contracts)
-                    .map_err(|err| {
-                        if remove_if_fail {
-                            let _ = self.runtime.contract_store.remove_contract(&key);
-                        }
-                        ExecutorError::execution(err, None)
-                    })?;
-                match result {
-                    ValidateResult::Valid => {
-                        self.state_store
-                            .store(key, incoming_state.clone(), params.clone())
-                            .await
-                            .map_err(ExecutorError::other)?;
-                    }
-                    ValidateResult::Invalid => {
-                        return Err(ExecutorError::request(StdContractError::invalid_put(key)));
-                    }
-                    ValidateResult::RequestRelated(mut related) => {
-                        if let Some(key) = related.pop() {
-                            return Err(ExecutorError::request(StdContractError::MissingRelated {
-                                key,
-                            }));
-                        } else {
-                            return Err(ExecutorError::internal_error());
-                        }
-                    }
-                }
-
-                vec![UpdateData::State(incoming_state.clone().into())]
+        let in_flight = self.in_flight(&key);
+        // the related contracts fetched before the request was suspended, since the results
+        // of their network operations were already claimed
+        let mut batch = self
+            .suspended_batches
+            .remove(&key)
+            .map(|(_, batch)| batch)
+            .unwrap_or_default();
+        let result = self
+            .perform_contract_upsert(key, update, related_contracts, code, &mut batch)
+            .await;
+        if let Some(transaction) = result.as_ref().err().and_then(|err| err.suspended_on()) {
+            if !batch.is_empty() {
+                self.suspended_batches.insert(key, (transaction, batch));
             }
-            Either::Right(delta) => {
-                // todo: forward delta like we are doing with puts
-                tracing::warn!("Delta updates are not yet supported");
-                vec![UpdateData::Delta(delta)]
+            if let Some(in_flight) = in_flight {
+                // the contract is kept until the request is resumed
+                self.suspended_in_flight
+                    .entry(transaction)
+                    .or_default()
+                    .push(in_flight);
             }
-        };
-
-        let current_state = match self.state_store.get(&key).await {
-            Ok(s) => s,
-            Err(StateStoreError::MissingContract(_)) => {
-                tracing::warn!("Missing contract {key} for upsert");
-                return Err(ExecutorError::request(StdContractError::MissingContract {
-                    key: key.into(),
-                }));
-            }
-            Err(StateStoreError::Any(err)) => return Err(ExecutorError::other(err)),
-        };
-
-        for (id, state) in related_contracts
-            .states()
-            .filter_map(|(id, c)| c.as_ref().map(|c| (id, c)))
-        {
-            updates.push(UpdateData::RelatedState {
-                related_to: *id,
-                state: state.clone(),
-            });
-        }
-
-        let updated_state = match self
-            .attempt_state_update(&params, &current_state, &key, &updates)
-            .await?
-        {
-            Either::Left(s) => s,
-            Either::Right(mut r) => {
-                let Some(c) = r.pop() else {
-                    // this branch should be unreachable since attempt_state_update should only
-                    return Err(ExecutorError::internal_error());
-                };
-                return Err(ExecutorError::request(StdContractError::MissingRelated {
-                    key: c.contract_instance_id,
-                }));
-            }
-        };
-        match self
-            .runtime
-            .validate_state(&key, &params, &updated_state, &related_contracts)
-            .map_err(|e| ExecutorError::execution(e, None))?
-        {
-            ValidateResult::Valid => {
-                if updated_state.as_ref() == current_state.as_ref() {
-                    Ok(UpsertResult::NoChange)
-                } else {
-                    Ok(UpsertResult::Updated(updated_state))
-                }
-            }
-            ValidateResult::Invalid => Err(ExecutorError::request(
-                freenet_stdlib::client_api::ContractError::Update {
-                    key,
-                    cause: "invalid outcome state".into(),
-                },
-            )),
-            ValidateResult::RequestRelated(_) => todo!(),
         }
+        result
     }
 
     fn register_contract_notifier(
@@ -171,6 +68,9 @@ impl ContractExecutor for Executor<Runtime> {
         } else {
             channels.push((cli_id, notification_ch));
         }
+        if let Some(quota) = &self.storage_quota {
+            quota.set_subscribed(*key.id(), true);
+        }
 
         if self
             .subscriber_summaries
@@ -185,6 +85,52 @@ impl ContractExecutor for Executor<Runtime> {
         }
         Ok(())
     }
+
+    async fn summarize_contract_state(
+        &mut self,
+        key: ContractKey,
+    ) -> Result<StateSummary<'static>, ExecutorError> {
+        let _in_flight = self.in_flight(&key);
+        let (parameters, state) = self.local_params_and_state(&key).await?;
+        self.runtime
+            .summarize_state(&key, &parameters, &state)
+            .map_err(|err| ExecutorError::execution(err, None))
+    }
+
+    async fn get_contract_state_delta(
+        &mut self,
+        key: ContractKey,
+        summary: StateSummary<'static>,
+    ) -> Result<StateDelta<'static>, ExecutorError> {
+        let _in_flight = self.in_flight(&key);
+        let (parameters, state) = self.local_params_and_state(&key).await?;
+        self.runtime
+            .get_state_delta(&key, &parameters, &state, &summary)
+            .map_err(|err| ExecutorError::execution(err, None))
+    }
+
+    async fn op_result_ready(&mut self) -> Result<Transaction, ExecutorError> {
+        self.next_op_result().await
+    }
+
+    fn forget_op(&mut self, transaction: &Transaction) {
+        self.suspended_in_flight.remove(transaction);
+        self.suspended_batches
+            .retain(|_, (suspended_on, _)| suspended_on != transaction);
+        self.forget_pending_op(transaction)
+    }
+
+    fn reset(&mut self) -> Result<(), ExecutorError> {
+        self.runtime.reset().map_err(ExecutorError::other)
+    }
+
+    fn execute_delegate_request(
+        &mut self,
+        req: DelegateRequest<'_>,
+        attested_contract: Option<&ContractInstanceId>,
+    ) -> Response {
+        self.delegate_request(req, attested_contract)
+    }
 }
 
 impl Executor<Runtime> {
@@ -194,8 +140,20 @@ impl Executor<Runtime> {
     ) -> anyhow::Result<Self> {
         let (contract_store, delegate_store, secret_store, state_store) =
             Self::get_stores(&config).await?;
+        let storage_quota = match config.max_storage_bytes {
+            Some(max_bytes) => Some(
+                StorageQuota::load(
+                    max_bytes,
+                    config.delegates_dir(),
+                    &contract_store,
+                    &state_store,
+                )
+                .await,
+            ),
+            None => None,
+        };
         let rt = Runtime::build(contract_store, delegate_store, secret_store, false).unwrap();
-        Executor::new(
+        let mut executor = Executor::new(
             state_store,
             move || {
                 crate::util::set_cleanup_on_exit(config.paths().clone())?;
@@ -205,7 +163,32 @@ impl Executor<Runtime> {
             rt,
             event_loop_channel,
         )
-        .await
+        .await?;
+        executor.storage_quota = storage_quota;
+        Ok(executor)
+    }
+
+    /// Creates a new executor sharing the stores and the compiled modules of this one,
+    /// which can run concurrently with it.
+    ///
+    /// Subscriptions, attested contracts and network operations in flight are not shared,
+    /// so all the requests about a given contract or delegate must go to the same executor.
+    pub(crate) async fn fork(
+        &self,
+        event_loop_channel: Option<ExecutorToEventLoopChannel<ExecutorHalve>>,
+    ) -> anyhow::Result<Self> {
+        let rt = self.runtime.fork()?;
+        let mut executor = Executor::new(
+            self.state_store.clone(),
+            || Ok(()),
+            self.mode,
+            rt,
+            event_loop_channel,
+        )
+        .await?;
+        executor.storage_quota = self.storage_quota.clone();
+        executor.upgrades = self.upgrades.fork();
+        Ok(executor)
     }
 
     pub fn register_contract_notifier(
@@ -228,6 +211,9 @@ impl Executor<Runtime> {
         } else {
             channels.push((cli_id, notification_ch));
         }
+        if let Some(quota) = &self.storage_quota {
+            quota.set_subscribed(*key.id(), true);
+        }
 
         if self
             .subscriber_summaries
@@ -367,7 +353,11 @@ impl Executor<Runtime> {
                         .or_default()
                         .push(*contract);
                 }
-                match self.runtime.register_delegate(delegate, cipher, nonce) {
+                let registered = self.runtime.register_delegate(delegate, cipher, nonce);
+                if let Some(quota) = &self.storage_quota {
+                    quota.refresh_delegates();
+                }
+                match registered {
                     Ok(_) => Ok(DelegateResponse {
                         key,
                         values: Vec::new(),
@@ -380,7 +370,11 @@ impl Executor<Runtime> {
             }
             DelegateRequest::UnregisterDelegate(key) => {
                 self.delegate_attested_ids.remove(&key);
-                match self.runtime.unregister_delegate(&key) {
+                let unregistered = self.runtime.unregister_delegate(&key);
+                if let Some(quota) = &self.storage_quota {
+                    quota.refresh_delegates();
+                }
+                match unregistered {
                     Ok(_) => Ok(HostResponse::Ok),
                     Err(err) => {
                         tracing::error!("failed unregistering delegate `{key}`: {err}");
@@ -460,8 +454,29 @@ impl Executor<Runtime> {
                 .await;
         }
 
-        self.verify_and_store_contract(state.clone(), contract, related_contracts)
+        // the contract is stored along with its related contracts and any state imported from
+        // its predecessor in a single commit
+        let mut batch = StateBatch::default();
+        self.verify_and_store_contract(state.clone(), contract, related_contracts, &mut batch)
+            .await?;
+        let upgrade = self
+            .import_predecessor_state(&key, &params, &state, &mut batch)
             .await?;
+        let state = match &upgrade {
+            Some((_, migrated)) if migrated.as_ref() != state.as_ref() => {
+                batch.update(key, migrated.clone());
+                migrated.clone()
+            }
+            _ => state,
+        };
+        self.state_store
+            .commit(batch)
+            .await
+            .map_err(ExecutorError::other)?;
+        if let Some((predecessor, _)) = upgrade {
+            self.announce_upgrade(predecessor, key);
+        }
+        self.track_storage(&key).await?;
 
         self.send_update_notification(&key, &params, &state)
             .await
@@ -503,6 +518,7 @@ impl Executor<Runtime> {
         let new_state = self
             .get_updated_state(&parameters, current_state, key, updates)
             .await?;
+        self.track_storage(&key).await?;
 
         // in the network impl this would be sent over the network
         let summary = self
@@ -517,12 +533,12 @@ impl Executor<Runtime> {
         }
         // notify peers with deltas from summary in network
         let request = UpdateContract { key, new_state };
-        let _op: operations::update::UpdateResult = self.op_request(request).await?;
+        let _op: operations::update::UpdateResult = self.op_request_blocking(request).await?;
 
         Ok(ContractResponse::UpdateResponse { key, summary }.into())
     }
 
-    /// Attempts to update the state with the provided updates.
+    /// Attempts to update the state with the provided updates, without storing the new state.
     /// If there were no updates, it will return the current state.
     async fn attempt_state_update(
         &mut self,
@@ -561,22 +577,7 @@ impl Executor<Runtime> {
             tracing::debug!("No changes in state for contract {key}, avoiding update");
             return Ok(Either::Left(current_state.clone()));
         }
-
-        self.state_store
-            .update(key, new_state.clone())
-            .await
-            .map_err(ExecutorError::other)?;
-
-        if let Err(err) = self
-            .send_update_notification(key, parameters, &new_state)
-            .await
-        {
-            tracing::error!(
-                "Failed while sending notifications for contract {}: {}",
-                key,
-                err
-            );
-        }
+        // the new state is persisted by the caller, along with any related contracts
         Ok(Either::Left(new_state))
     }
 
@@ -592,6 +593,8 @@ impl Executor<Runtime> {
         key: ContractKey,
         mut updates: Vec<UpdateData<'_>>,
     ) -> Result<WrappedState, ExecutorError> {
+        // the new state is stored along with any related contracts fetched to compute it
+        let mut batch = StateBatch::default();
         let new_state = {
             let start = Instant::now();
             loop {
@@ -600,8 +603,9 @@ impl Executor<Runtime> {
                     .await?;
                 let missing = match state_update_res {
                     Either::Left(new_state) => {
+                        batch.update(key, new_state.clone());
                         self.state_store
-                            .update(&key, new_state.clone())
+                            .commit(batch)
                             .await
                             .map_err(ExecutorError::other)?;
                         break new_state;
@@ -615,7 +619,11 @@ impl Executor<Runtime> {
                     mode,
                 } in missing
                 {
-                    match self.state_store.get(&id.into()).await {
+                    let stored = match batch.get(&id.into()) {
+                        Some(state) => Ok(state.clone()),
+                        None => self.state_store.get(&id.into()).await,
+                    };
+                    match stored {
                         Ok(state) => {
                             // in this case we are already subscribed to and are updating this contract,
                             // we can try first with the existing value
@@ -626,7 +634,10 @@ impl Executor<Runtime> {
                         }
 
                         Err(StateStoreError::MissingContract(_)) => {
-                            let state = match self.local_state_or_from_network(&id, false).await? {
+                            let state = match self
+                                .local_state_or_from_network(&id, false, &batch)
+                                .await?
+                            {
                                 Either::Left(state) => state,
                                 Either::Right(GetResult {
                                     state, contract, ..
@@ -643,6 +654,7 @@ impl Executor<Runtime> {
                                         state.clone(),
                                         contract,
                                         RelatedContracts::default(),
+                                        &mut batch,
                                     )
                                     .await?;
                                     state
@@ -675,12 +687,193 @@ impl Executor<Runtime> {
         Ok(new_state)
     }
 
+    /// Validates and merges the incoming state or delta, committing the resulting state in
+    /// `batch` along with the state of any related contracts fetched from the network.
+    ///
+    /// The state of the contract itself is only added to the batch once all the related
+    /// contracts are resolved, so if the request is suspended the batch only holds the
+    /// related contracts fetched so far, which are reused once the request is resumed.
+    async fn perform_contract_upsert(
+        &mut self,
+        key: ContractKey,
+        update: Either<WrappedState, StateDelta<'static>>,
+        mut related_contracts: RelatedContracts<'static>,
+        code: Option<ContractContainer>,
+        batch: &mut StateBatch,
+    ) -> Result<UpsertResult, ExecutorError> {
+        let params = if let Some(code) = &code {
+            code.params()
+        } else {
+            self.state_store
+                .get_params(&key)
+                .await
+                .map_err(ExecutorError::other)?
+                .ok_or_else(|| {
+                    ExecutorError::request(StdContractError::Put {
+                        key,
+                        cause: "missing contract parameters".into(),
+                    })
+                })?
+        };
+
+        let remove_if_fail = if self
+            .runtime
+            .contract_store
+            .fetch_contract(&key, &params)
+            .is_none()
+        {
+            let code = code.ok_or_else(|| {
+                ExecutorError::request(StdContractError::MissingContract { key: key.into() })
+            })?;
+            self.runtime
+                .contract_store
+                .store_contract(code.clone())
+                .map_err(ExecutorError::other)?;
+            true
+        } else {
+            false
+        };
+
+        let (replaces_state, current_state, mut updates, upgrade) = match update {
+            Either::Left(incoming_state) => {
+                let is_valid = match self
+                    .validate_with_related(
+                        &key,
+                        &params,
+                        &incoming_state,
+                        &mut related_contracts,
+                        &mut Vec::new(),
+                        batch,
+                    )
+                    .await
+                {
+                    Ok(is_valid) => is_valid,
+                    Err(err) => {
+                        // if suspended the code will be stored again once the request is resumed
+                        if remove_if_fail {
+                            let _ = self.runtime.contract_store.remove_contract(&key);
+                        }
+                        return Err(err);
+                    }
+                };
+                if !is_valid {
+                    return Err(ExecutorError::request(StdContractError::invalid_put(key)));
+                }
+                let upgrade = if remove_if_fail {
+                    self.import_predecessor_state(&key, &params, &incoming_state, batch)
+                        .await?
+                } else {
+                    None
+                };
+                let current_state = upgrade
+                    .as_ref()
+                    .map_or_else(|| incoming_state.clone(), |(_, state)| state.clone());
+                let updates = vec![UpdateData::State(incoming_state.into())];
+                (true, current_state, updates, upgrade)
+            }
+            Either::Right(delta) => {
+                let current_state = match self.state_store.get(&key).await {
+                    Ok(s) => s,
+                    Err(StateStoreError::MissingContract(_)) => {
+                        tracing::warn!("Missing contract {key} for upsert");
+                        return Err(ExecutorError::request(StdContractError::MissingContract {
+                            key: key.into(),
+                        }));
+                    }
+                    Err(StateStoreError::Any(err)) => return Err(ExecutorError::other(err)),
+                };
+                (false, current_state, vec![UpdateData::Delta(delta)], None)
+            }
+        };
+
+        for (id, state) in related_contracts
+            .states()
+            .filter_map(|(id, c)| c.as_ref().map(|c| (id, c)))
+        {
+            updates.push(UpdateData::RelatedState {
+                related_to: *id,
+                state: state.clone(),
+            });
+        }
+
+        if let Some(quota) = &self.storage_quota {
+            quota.touch(key.id());
+        }
+        let updated_state = match self
+            .attempt_state_update(&params, &current_state, &key, &updates)
+            .await?
+        {
+            Either::Left(s) => s,
+            Either::Right(mut r) => {
+                let Some(c) = r.pop() else {
+                    // this branch should be unreachable since attempt_state_update should only
+                    return Err(ExecutorError::internal_error());
+                };
+                return Err(ExecutorError::request(StdContractError::MissingRelated {
+                    key: c.contract_instance_id,
+                }));
+            }
+        };
+        let is_valid = self
+            .validate_with_related(
+                &key,
+                &params,
+                &updated_state,
+                &mut related_contracts,
+                &mut Vec::new(),
+                batch,
+            )
+            .await?;
+        if !is_valid {
+            return Err(ExecutorError::request(
+                freenet_stdlib::client_api::ContractError::Update {
+                    key,
+                    cause: "invalid outcome state".into(),
+                },
+            ));
+        }
+        let changed = updated_state.as_ref() != current_state.as_ref();
+        if replaces_state {
+            batch.store(key, updated_state.clone(), params.clone());
+        } else if changed {
+            batch.update(key, updated_state.clone());
+        }
+        self.state_store
+            .commit(std::mem::take(batch))
+            .await
+            .map_err(ExecutorError::other)?;
+        if let Some((predecessor, _)) = upgrade {
+            self.announce_upgrade(predecessor, key);
+        }
+        if replaces_state || changed {
+            // the replaced state is kept in the state history
+            self.track_storage(&key).await?;
+        }
+        if !changed {
+            return Ok(UpsertResult::NoChange);
+        }
+        if let Err(err) = self
+            .send_update_notification(&key, &params, &updated_state)
+            .await
+        {
+            tracing::error!(
+                "Failed while sending notifications for contract {}: {}",
+                key,
+                err
+            );
+        }
+        Ok(UpsertResult::Updated(updated_state))
+    }
+
     async fn perform_contract_get(
         &mut self,
         return_contract_code: bool,
         key: ContractKey,
     ) -> Result<(Option<WrappedState>, Option<ContractContainer>), ExecutorError> {
         let mut got_contract: Option<ContractContainer> = None;
+        if let Some(quota) = &self.storage_quota {
+            quota.touch(key.id());
+        }
 
         if return_contract_code {
             if let Some(contract) = self.get_contract_locally(&key).await? {
@@ -716,11 +909,14 @@ impl Executor<Runtime> {
         Ok(State::from(state))
     }
 
+    /// Validates the state of a new contract, fetching any related contracts it requests
+    /// from the network, and adds it along with the related contracts to the batch.
     async fn verify_and_store_contract(
         &mut self,
         state: WrappedState,
         trying_container: ContractContainer,
         mut related_contracts: RelatedContracts<'_>,
+        batch: &mut StateBatch,
     ) -> Result<(), ExecutorError> {
         let key = trying_container.key();
         let params = trying_container.params();
@@ -765,7 +961,7 @@ impl Executor<Runtime> {
                     related_contracts.missing(related);
                     for (id, related) in related_contracts.update() {
                         if related.is_none() {
-                            match self.local_state_or_from_network(id, false).await? {
+                            match self.local_state_or_from_network(id, false, batch).await? {
                                 Either::Left(state) => {
                                     *related = Some(state.into());
                                 }
@@ -798,10 +994,7 @@ impl Executor<Runtime> {
                 }));
             }
 
-            self.state_store
-                .store(trying_key, trying_state.clone(), trying_params.clone())
-                .await
-                .map_err(ExecutorError::other)?;
+            batch.store(trying_key, trying_state.clone(), trying_params.clone());
             if trying_key != original_key {
                 trying_key = original_key;
                 trying_params = original_params.clone();
@@ -818,6 +1011,79 @@ impl Executor<Runtime> {
         Ok(())
     }
 
+    /// Imports the state of the contract the given (new) one succeeds, if it declares any and
+    /// its state is available locally, returning the predecessor along with the resulting
+    /// state, which is `state` if the contract didn't import anything.
+    ///
+    /// The predecessor state is passed to the contract as an update for a related contract,
+    /// so the contract decides how to merge it, and the outcome must be a valid state.
+    /// The resulting state is not stored, and the clients subscribed to the predecessor must
+    /// be redirected to the contract once it is.
+    async fn import_predecessor_state(
+        &mut self,
+        key: &ContractKey,
+        params: &Parameters<'_>,
+        state: &WrappedState,
+        batch: &mut StateBatch,
+    ) -> Result<Option<(ContractKey, WrappedState)>, ExecutorError> {
+        let Some(code_hash) = self
+            .runtime
+            .contract_predecessor(key, params)
+            .map_err(|err| ExecutorError::execution(err, None))?
+        else {
+            return Ok(None);
+        };
+        let predecessor = ContractKey::from_params(code_hash.encode(), params.clone())
+            .map_err(ExecutorError::other)?;
+        if predecessor.id() == key.id() {
+            return Ok(None);
+        }
+        let predecessor_state = match self.state_store.get(&predecessor).await {
+            Ok(state) => state,
+            Err(StateStoreError::MissingContract(_)) => return Ok(None),
+            Err(StateStoreError::Any(err)) => return Err(ExecutorError::other(err)),
+        };
+        if !self
+            .runtime
+            .upgrade_authorized(&predecessor, key, params)
+            .map_err(|err| ExecutorError::execution(err, None))?
+        {
+            tracing::warn!(contract = %key, %predecessor, "upgrade not authorized by the predecessor contract");
+            return Ok(None);
+        }
+        let update = UpdateData::RelatedState {
+            related_to: *predecessor.id(),
+            state: State::from(predecessor_state.as_ref()).into_owned(),
+        };
+        let migrated = self
+            .runtime
+            .update_state(key, params, state, &[update])
+            .map_err(|err| ExecutorError::execution(err, Some(InnerOpError::Upsert(*key))))?;
+        let Some(migrated) = migrated.new_state else {
+            // the contract didn't import anything from its predecessor
+            return Ok(Some((predecessor, state.clone())));
+        };
+        let migrated = WrappedState::new(migrated.into_bytes());
+        let is_valid = self
+            .validate_with_related(
+                key,
+                params,
+                &migrated,
+                &mut RelatedContracts::default(),
+                &mut Vec::new(),
+                batch,
+            )
+            .await?;
+        if !is_valid {
+            return Err(ExecutorError::request(StdContractError::Put {
+                key: *key,
+                cause: format!("invalid state after importing the state of {predecessor}").into(),
+            }));
+        }
+        tracing::info!(contract = %key, %predecessor, "imported the state of the predecessor contract");
+        Ok(Some((predecessor, migrated)))
+    }
+
     async fn send_update_notification(
         &mut self,
         key: &ContractKey,
@@ -857,11 +1123,106 @@ impl Executor<Runtime> {
             }
             if !failures.is_empty() {
                 notifiers.retain(|(c, _)| !failures.contains(c));
+                if notifiers.is_empty() {
+                    if let Some(quota) = &self.storage_quota {
+                        quota.set_subscribed(*key.id(), false);
+                    }
+                }
             }
         }
         Ok(())
     }
 
+    /// Accounts for the storage used by the given contract, evicting the least recently used
+    /// contracts if the storage budget is exceeded.
+    ///
+    /// Contracts this node is seeding or which have subscribers are never evicted.
+    async fn track_storage(&mut self, key: &ContractKey) -> Result<(), ExecutorError> {
+        let Some(quota) = self.storage_quota.clone() else {
+            return Ok(());
+        };
+        let contract_store = &self.runtime.contract_store;
+        let code = contract_store
+            .code_hash_from_key(key)
+            .and_then(|code_hash| {
+                contract_store
+                    .code_size(&code_hash)
+                    .map(|size| (code_hash, size))
+            });
+        let state_bytes = self.state_store.stored_bytes(key).await;
+        quota.record(*key.id(), code, state_bytes);
+
+        let ring = self
+            .event_loop_channel
+            .as_ref()
+            .map(|ch| ch.op_manager.ring.clone());
+        let candidates = quota.eviction_candidates(key.id(), |id| {
+            let Some(ring) = &ring else {
+                return true;
+            };
+            let key = ContractKey::from(*id);
+            !ring.is_seeding_contract(&key)
+                && ring
+                    .subscribers_of(&key)
+                    .map_or(true, |subscribers| subscribers.is_empty())
+        });
+        self.evict_contracts(&quota, candidates).await
+    }
+
+    /// Removes the code, parameters, state and state history of the contracts from the stores.
+    ///
+    /// The states of all the contracts are removed in a single transaction before their code,
+    /// so a failure never leaves a contract with a state but without code; the code left
+    /// behind by a failure is accounted for and evicted again later.
+    async fn evict_contracts(
+        &mut self,
+        quota: &StorageQuota,
+        ids: Vec<ContractInstanceId>,
+    ) -> Result<(), ExecutorError> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let mut batch = StateBatch::default();
+        for id in &ids {
+            batch.remove(ContractKey::from(*id));
+        }
+        self.state_store
+            .commit(batch)
+            .await
+            .map_err(ExecutorError::other)?;
+        for id in ids {
+            let key = ContractKey::from(id);
+            if let Err(err) = self.runtime.contract_store.remove_contract(&key) {
+                tracing::warn!(contract = %key, "failed removing contract code: {err}");
+                quota.record(id, None, 0);
+                continue;
+            }
+            quota.remove(&id);
+            tracing::info!(contract = %key, used = quota.used(), "evicted contract to stay within the storage budget");
+        }
+        Ok(())
+    }
+
+    async fn local_params_and_state(
+        &self,
+        key: &ContractKey,
+    ) -> Result<(Parameters<'static>, WrappedState), ExecutorError> {
+        let parameters = self
+            .state_store
+            .get_params(key)
+            .await
+            .map_err(ExecutorError::other)?
+            .ok_or_else(|| {
+                ExecutorError::request(StdContractError::MissingContract { key: (*key).into() })
+            })?;
+        let state = self
+            .state_store
+            .get(key)
+            .await
+            .map_err(ExecutorError::other)?;
+        Ok((parameters, state))
+    }
+
     async fn get_contract_locally(
         &self,
         key: &ContractKey,
@@ -881,13 +1242,182 @@ impl Executor<Runtime> {
     }
 }
 
+/// Maximum length of the chain of related contracts resolved while validating a state.
+const MAX_RELATED_CONTRACTS_DEPTH: usize = 3;
+/// Maximum number of related contracts a contract can request while validating a state.
+const MAX_RELATED_CONTRACTS_REQUESTED: usize = 10;
+
+/// Checks the related contracts requested by a contract while validating its state are
+/// within the limits, given the total requested so far, the chain of contracts being
+/// validated and the related contracts already resolved.
+fn check_related_request(
+    key: &ContractKey,
+    requested: &[ContractInstanceId],
+    total_requested: usize,
+    validating: &[ContractInstanceId],
+    related_contracts: &RelatedContracts<'_>,
+) -> Result<(), ExecutorError> {
+    let Some(&first) = requested.first() else {
+        return Err(ExecutorError::internal_error());
+    };
+    if total_requested > MAX_RELATED_CONTRACTS_REQUESTED {
+        tracing::warn!(
+            contract = %key,
+            "contract requested more than {MAX_RELATED_CONTRACTS_REQUESTED} related contracts"
+        );
+        return Err(ExecutorError::request(StdContractError::MissingRelated {
+            key: first,
+        }));
+    }
+    if validating.len() >= MAX_RELATED_CONTRACTS_DEPTH {
+        tracing::warn!(
+            contract = %key,
+            "related contracts exceed the maximum depth of {MAX_RELATED_CONTRACTS_DEPTH}"
+        );
+        return Err(ExecutorError::request(StdContractError::MissingRelated {
+            key: first,
+        }));
+    }
+    if let Some(id) = requested.iter().find(|id| validating.contains(id)) {
+        tracing::warn!(contract = %key, related = %id, "cycle in related contracts");
+        return Err(ExecutorError::request(StdContractError::MissingRelated {
+            key: *id,
+        }));
+    }
+    let already_provided = related_contracts
+        .states()
+        .any(|(id, state)| state.is_some() && requested.contains(id));
+    if already_provided {
+        // the contract would keep requesting the same contracts indefinitely
+        tracing::warn!(contract = %key, "related contracts requested again");
+        return Err(ExecutorError::request(StdContractError::MissingRelated {
+            key: first,
+        }));
+    }
+    Ok(())
+}
+
 impl Executor<Runtime> {
+    /// Validates the state of a contract, resolving any related contracts requested
+    /// by the contract during validation.
+    ///
+    /// Related contracts are read from the local store or otherwise fetched from the network,
+    /// in which case their own state is validated (resolving their related contracts in turn)
+    /// before being stored locally. `validating` holds the chain of contracts whose validation
+    /// led to this one, and is used to bound the depth of the dependency tree and detect cycles.
+    /// Related contracts fetched from the network are added to `batch`, to be stored along
+    /// with the state being validated.
+    fn validate_with_related<'a>(
+        &'a mut self,
+        key: &'a ContractKey,
+        params: &'a Parameters<'a>,
+        state: &'a WrappedState,
+        related_contracts: &'a mut RelatedContracts<'static>,
+        validating: &'a mut Vec<ContractInstanceId>,
+        batch: &'a mut StateBatch,
+    ) -> Pin<Box<dyn Future<Output = Result<bool, ExecutorError>> + Send + 'a>> {
+        Box::pin(async move {
+            validating.push(*key.id());
+            let result = async {
+                let mut total_requested = 0;
+                loop {
+                    let requested = match self
+                        .runtime
+                        .validate_state(key, params, state, related_contracts)
+                        .map_err(|err| ExecutorError::execution(err, None))?
+                    {
+                        ValidateResult::Valid => return Ok(true),
+                        ValidateResult::Invalid => return Ok(false),
+                        ValidateResult::RequestRelated(requested) => requested,
+                    };
+                    total_requested += requested.len();
+                    check_related_request(
+                        key,
+                        &requested,
+                        total_requested,
+                        validating,
+                        related_contracts,
+                    )?;
+
+                    related_contracts.missing(requested);
+                    let missing: Vec<_> = related_contracts
+                        .states()
+                        .filter_map(|(id, state)| state.is_none().then_some(*id))
+                        .collect();
+                    for id in missing {
+                        let related_state =
+                            self.fetch_related_state(&id, validating, batch).await?;
+                        for (related_id, related) in related_contracts.update() {
+                            if *related_id == id {
+                                *related = Some(related_state);
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            .await;
+            validating.pop();
+            result
+        })
+    }
+
+    /// Gets the state of a related contract, fetching it from the network if necessary.
+    async fn fetch_related_state(
+        &mut self,
+        id: &ContractInstanceId,
+        validating: &mut Vec<ContractInstanceId>,
+        batch: &mut StateBatch,
+    ) -> Result<State<'static>, ExecutorError> {
+        let GetResult {
+            state, contract, ..
+        } = match self.local_state_or_from_network(id, true, batch).await? {
+            Either::Left(state) => return Ok(state.into()),
+            Either::Right(result) => result,
+        };
+        let Some(contract) = contract else {
+            return Err(ExecutorError::request(StdContractError::Get {
+                key: (*id).into(),
+                cause: "missing contract".into(),
+            }));
+        };
+        let key = contract.key();
+        let params = contract.params();
+        self.runtime
+            .contract_store
+            .store_contract(contract)
+            .map_err(ExecutorError::other)?;
+        let mut related_contracts = RelatedContracts::default();
+        let is_valid = self
+            .validate_with_related(
+                &key,
+                &params,
+                &state,
+                &mut related_contracts,
+                validating,
+                batch,
+            )
+            .await
+            .inspect_err(|_| {
+                let _ = self.runtime.contract_store.remove_contract(&key);
+            })?;
+        if !is_valid {
+            let _ = self.runtime.contract_store.remove_contract(&key);
+            return Err(ExecutorError::request(StdContractError::Put {
+                key,
+                cause: "not valid".into(),
+            }));
+        }
+        batch.store(key, state.clone(), params);
+        Ok(state.into())
+    }
+
     async fn subscribe(&mut self, key: ContractKey) -> Result<(), ExecutorError> {
         if self.mode == OperationMode::Local {
             return Ok(());
         }
         let request = SubscribeContract { key };
-        let _sub: operations::subscribe::SubscribeResult = self.op_request(request).await?;
+        self.op_request_detached(request).await?;
         Ok(())
     }
 
@@ -896,7 +1426,11 @@ impl Executor<Runtime> {
         &mut self,
         id: &ContractInstanceId,
         return_contract_code: bool,
+        pending: &StateBatch,
     ) -> Result<Either<WrappedState, operations::get::GetResult>, ExecutorError> {
+        if let Some(state) = pending.get(&(*id).into()) {
+            return Ok(Either::Left(state.clone()));
+        }
         if let Ok(contract) = self.state_store.get(&(*id).into()).await {
             return Ok(Either::Left(contract));
         };
@@ -908,3 +1442,204 @@ impl Executor<Runtime> {
         Ok(Either::Right(get_result))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::wasm_runtime::tests::{get_test_module, with_predecessor, with_upgrade_key};
+    use freenet_stdlib::client_api::ErrorKind;
+
+    async fn executor(dir: &std::path::Path) -> anyhow::Result<Executor<Runtime>> {
+        let contract_store = ContractStore::new(dir.join("contracts"), 10_000)?;
+        let delegate_store = DelegateStore::new(dir.join("delegates"), 10_000)?;
+        let secrets_store = SecretsStore::new(dir.join("secrets"), Default::default())?;
+        let state_store = StateStore::new(Storage::new(dir).await?, 10_000)?;
+        let runtime = Runtime::build(contract_store, delegate_store, secrets_store, false).unwrap();
+        Executor::new(state_store, || Ok(()), OperationMode::Local, runtime, None).await
+    }
+
+    async fn put(
+        executor: &mut Executor<Runtime>,
+        code: Vec<u8>,
+    ) -> Result<ContractKey, ExecutorError> {
+        let contract = ContractContainer::Wasm(ContractWasmAPIVersion::V1(WrappedContract::new(
+            Arc::new(ContractCode::from(code)),
+            Parameters::from(vec![]),
+        )));
+        let key = contract.key();
+        executor
+            .upsert_contract_state(
+                key,
+                Either::Left(WrappedState::new(vec![1, 2, 3, 4])),
+                RelatedContracts::default(),
+                Some(contract),
+            )
+            .await?;
+        Ok(key)
+    }
+
+    /// Returns the related contract reported as missing if the request is rejected.
+    fn rejected_related(
+        requested: &[ContractInstanceId],
+        total_requested: usize,
+        validating: &[ContractInstanceId],
+        related_contracts: &RelatedContracts<'_>,
+    ) -> Option<ContractInstanceId> {
+        let key = ContractKey::from(ContractInstanceId::new([0; 32]));
+        let err = check_related_request(
+            &key,
+            requested,
+            total_requested,
+            validating,
+            related_contracts,
+        )
+        .err()?;
+        match err.unwrap_request() {
+            RequestError::ContractError(StdContractError::MissingRelated { key }) => Some(key),
+            other => panic!("unexpected error: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn related_contracts_depth_limit() {
+        let related = ContractInstanceId::new([1; 32]);
+        let chain: Vec<_> = (2..)
+            .take(MAX_RELATED_CONTRACTS_DEPTH)
+            .map(|i| ContractInstanceId::new([i; 32]))
+            .collect();
+        let related_contracts = RelatedContracts::default();
+        let within = &chain[..MAX_RELATED_CONTRACTS_DEPTH - 1];
+        assert_eq!(
+            rejected_related(&[related], 1, within, &related_contracts),
+            None
+        );
+        assert_eq!(
+            rejected_related(&[related], 1, &chain, &related_contracts),
+            Some(related)
+        );
+    }
+
+    #[test]
+    fn related_contracts_fan_out_limit() {
+        let validating = [ContractInstanceId::new([0; 32])];
+        let related_contracts = RelatedContracts::default();
+        let requested: Vec<_> = (1..)
+            .take(MAX_RELATED_CONTRACTS_REQUESTED)
+            .map(|i| ContractInstanceId::new([i; 32]))
+            .collect();
+        assert_eq!(
+            rejected_related(&requested, requested.len(), &validating, &related_contracts),
+            None
+        );
+        // the contracts requested in previous rounds count towards the limit
+        assert_eq!(
+            rejected_related(
+                &requested[..1],
+                requested.len() + 1,
+                &validating,
+                &related_contracts
+            ),
+            Some(requested[0])
+        );
+    }
+
+    #[test]
+    fn related_contracts_cycle() {
+        let (first, second) = (
+            ContractInstanceId::new([1; 32]),
+            ContractInstanceId::new([2; 32]),
+        );
+        let other = ContractInstanceId::new([3; 32]);
+        let related_contracts = RelatedContracts::default();
+        assert_eq!(
+            rejected_related(&[other, first], 2, &[first, second], &related_contracts),
+            Some(first)
+        );
+    }
+
+    #[test]
+    fn related_contracts_requested_again() {
+        let validating = [ContractInstanceId::new([0; 32])];
+        let related = ContractInstanceId::new([1; 32]);
+        let mut related_contracts = RelatedContracts::default();
+        related_contracts.missing(vec![related]);
+        // requesting a contract which is still missing is fine...
+        assert_eq!(
+            rejected_related(&[related], 1, &validating, &related_contracts),
+            None
+        );
+        // ...but not one already provided to the contract
+        for (_, state) in related_contracts.update() {
+            *state = Some(State::from(vec![1]));
+        }
+        assert_eq!(
+            rejected_related(&[related], 2, &validating, &related_contracts),
+            Some(related)
+        );
+    }
+
+    /// Lets the executor handle the upgrade notices sent by other executors.
+    async fn receive_upgrades(executor: &mut Executor<Runtime>) {
+        let idle = tokio::time::timeout(Duration::from_millis(100), executor.op_result_ready());
+        assert!(idle.await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn upgrade_imports_predecessor_state() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let mut executor = executor(dir.path()).await?;
+        let params = Parameters::from(vec![]);
+        let code = get_test_module("test_contract_1")?;
+        let upgrade_key = ed25519_dalek::SigningKey::from_bytes(&[1; 32]);
+        let predecessor = put(&mut executor, with_upgrade_key(code.clone(), &upgrade_key)).await?;
+        let predecessor_hash = predecessor.code_hash().unwrap();
+
+        // the client is subscribed to the predecessor through a fork of the executor
+        let mut fork = executor.fork(None).await?;
+        let (notifier, mut notices) = mpsc::unbounded_channel();
+        fork.register_contract_notifier(predecessor, ClientId::FIRST, notifier, None)?;
+
+        // a successor not authorized by the predecessor doesn't import its state...
+        let other_key = ed25519_dalek::SigningKey::from_bytes(&[2; 32]);
+        let impostor = with_predecessor(code.clone(), predecessor_hash, &other_key);
+        let impostor = put(&mut executor, impostor).await?;
+        let state = executor.state_store.get(&impostor).await?;
+        assert!(executor
+            .import_predecessor_state(&impostor, &params, &state, &mut StateBatch::default())
+            .await?
+            .is_none());
+        receive_upgrades(&mut fork).await;
+        assert!(notices.try_recv().is_err());
+
+        // ...while an authorized one does, and the subscribers of the predecessor are redirected
+        let successor = with_predecessor(code, predecessor_hash, &upgrade_key);
+        let successor = put(&mut executor, successor).await?;
+        let state = executor.state_store.get(&successor).await?;
+        let (imported_from, imported) = executor
+            .import_predecessor_state(&successor, &params, &state, &mut StateBatch::default())
+            .await?
+            .expect("imported state");
+        assert_eq!(imported_from, predecessor);
+        assert_eq!(imported.as_ref(), &[1, 2, 3, 4]);
+        receive_upgrades(&mut fork).await;
+        let Ok(Err(notice)) = notices.try_recv() else {
+            panic!("expected an upgrade notice");
+        };
+        let ErrorKind::RequestError(notice) = notice.kind() else {
+            panic!("unexpected notice: {notice}");
+        };
+        let RequestError::ContractError(StdContractError::Subscribe { key, cause }) = notice else {
+            panic!("unexpected notice: {notice}");
+        };
+        assert_eq!(key.id(), predecessor.id());
+        assert_eq!(
+            cause.to_string(),
+            format!("contract upgraded, subscribe to {}", successor.id())
+        );
+        assert_eq!(
+            upgraded_to(notice).map(|key| *key.id()),
+            Some(*successor.id())
+        );
+        Ok(())
+    }
+}
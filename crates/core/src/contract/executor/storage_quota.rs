@@ -0,0 +1,337 @@
+//! Disk budget for the contracts and delegates stored by the node.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use freenet_stdlib::prelude::{CodeHash, ContractInstanceId};
+use parking_lot::Mutex;
+
+use crate::wasm_runtime::{ContractStore, StateStorage, StateStore};
+
+/// Tracks the disk used by the stored contracts (code and its compiled modules, parameters,
+/// state and state history) and delegates against a configured budget, along with the order
+/// in which contracts were last used, so the least recently used ones can be evicted once the
+/// budget is exceeded.
+///
+/// Clones share the same accounting.
+#[derive(Clone)]
+pub(crate) struct StorageQuota {
+    max_bytes: u64,
+    delegates_dir: PathBuf,
+    usage: Arc<Mutex<Usage>>,
+}
+
+#[derive(Default)]
+struct Usage {
+    /// incremented every time a contract is used
+    clock: u64,
+    contracts: HashMap<ContractInstanceId, ContractUsage>,
+    /// size of each stored code, including its compiled modules, and number of contracts using it
+    code: HashMap<CodeHash, (u64, usize)>,
+    /// bytes used by the state, parameters and state history of all the contracts
+    state_bytes: u64,
+    delegate_bytes: u64,
+    /// contracts local clients are subscribed to
+    subscribed: HashSet<ContractInstanceId>,
+    /// contracts being processed by any of the executors sharing the quota
+    in_flight: HashMap<ContractInstanceId, usize>,
+}
+
+struct ContractUsage {
+    code_hash: Option<CodeHash>,
+    state_bytes: u64,
+    last_used: u64,
+}
+
+impl Usage {
+    fn total(&self) -> u64 {
+        let code_bytes: u64 = self.code.values().map(|(size, _)| size).sum();
+        code_bytes + self.state_bytes + self.delegate_bytes
+    }
+}
+
+impl StorageQuota {
+    pub fn new(max_bytes: u64, delegates_dir: PathBuf) -> Self {
+        let quota = Self {
+            max_bytes,
+            delegates_dir,
+            usage: Arc::new(Mutex::new(Usage::default())),
+        };
+        quota.refresh_delegates();
+        quota
+    }
+
+    /// Builds the quota accounting for all the contracts already in the stores.
+    pub async fn load<S>(
+        max_bytes: u64,
+        delegates_dir: PathBuf,
+        contract_store: &ContractStore,
+        state_store: &StateStore<S>,
+    ) -> Self
+    where
+        S: StateStorage + Send + 'static,
+        <S as StateStorage>::Error: Into<anyhow::Error>,
+    {
+        let quota = Self::new(max_bytes, delegates_dir);
+        for (id, code_hash) in contract_store.contracts() {
+            let state_bytes = state_store.stored_bytes(&id.into()).await;
+            let code = contract_store
+                .code_size(&code_hash)
+                .map(|size| (code_hash, size));
+            quota.record(id, code, state_bytes);
+        }
+        tracing::info!(
+            "using {} bytes of storage out of {} bytes",
+            quota.used(),
+            quota.max_bytes
+        );
+        quota
+    }
+
+    /// Total bytes used by the contracts and delegates.
+    pub fn used(&self) -> u64 {
+        self.usage.lock().total()
+    }
+
+    /// Records the storage used by a contract and marks it as the most recently used.
+    ///
+    /// The size of the code is updated every time, since its compiled modules are only
+    /// persisted once the contract is executed.
+    pub fn record(&self, id: ContractInstanceId, code: Option<(CodeHash, u64)>, state_bytes: u64) {
+        let usage = &mut *self.usage.lock();
+        usage.clock += 1;
+        let last_used = usage.clock;
+        let contract = usage.contracts.entry(id).or_insert(ContractUsage {
+            code_hash: None,
+            state_bytes: 0,
+            last_used,
+        });
+        contract.last_used = last_used;
+        usage.state_bytes = usage.state_bytes - contract.state_bytes + state_bytes;
+        contract.state_bytes = state_bytes;
+        if let Some((code_hash, size)) = code {
+            let code = usage.code.entry(code_hash).or_insert((size, 0));
+            code.0 = size;
+            if contract.code_hash.is_none() {
+                contract.code_hash = Some(code_hash);
+                code.1 += 1;
+            }
+        }
+    }
+
+    /// Marks the contract as the most recently used.
+    pub fn touch(&self, id: &ContractInstanceId) {
+        let usage = &mut *self.usage.lock();
+        usage.clock += 1;
+        if let Some(contract) = usage.contracts.get_mut(id) {
+            contract.last_used = usage.clock;
+        }
+    }
+
+    /// Stops accounting for a contract which has been removed from the stores.
+    pub fn remove(&self, id: &ContractInstanceId) {
+        let usage = &mut *self.usage.lock();
+        usage.subscribed.remove(id);
+        let Some(contract) = usage.contracts.remove(id) else {
+            return;
+        };
+        usage.state_bytes -= contract.state_bytes;
+        if let Some(code_hash) = contract.code_hash {
+            if let Some((_, users)) = usage.code.get_mut(&code_hash) {
+                *users -= 1;
+                if *users == 0 {
+                    usage.code.remove(&code_hash);
+                }
+            }
+        }
+    }
+
+    /// Marks the contract as being processed until the returned guard is dropped, so it is not
+    /// evicted meanwhile by an executor serving another shard.
+    pub fn in_flight(&self, id: ContractInstanceId) -> InFlight {
+        *self.usage.lock().in_flight.entry(id).or_default() += 1;
+        InFlight {
+            usage: self.usage.clone(),
+            id,
+        }
+    }
+
+    /// Contracts local clients are subscribed to are never evicted.
+    pub fn set_subscribed(&self, id: ContractInstanceId, subscribed: bool) {
+        let usage = &mut *self.usage.lock();
+        if subscribed {
+            usage.subscribed.insert(id);
+        } else {
+            usage.subscribed.remove(&id);
+        }
+    }
+
+    /// Updates the bytes used by delegates, must be called whenever delegates are
+    /// registered or unregistered.
+    pub fn refresh_delegates(&self) {
+        let delegate_bytes = dir_size(&self.delegates_dir);
+        self.usage.lock().delegate_bytes = delegate_bytes;
+    }
+
+    /// Returns the least recently used contracts which should be evicted to get back under
+    /// the budget, among the ones which are evictable, excluding any local subscriptions and
+    /// contracts in flight.
+    ///
+    /// Delegates are accounted for but never evicted, so the budget may still be exceeded.
+    pub fn eviction_candidates(
+        &self,
+        keep: &ContractInstanceId,
+        is_evictable: impl Fn(&ContractInstanceId) -> bool,
+    ) -> Vec<ContractInstanceId> {
+        let usage = &*self.usage.lock();
+        let mut excess = usage.total().saturating_sub(self.max_bytes);
+        if excess == 0 {
+            return vec![];
+        }
+        let mut by_last_use: Vec<_> = usage
+            .contracts
+            .iter()
+            .filter(|(id, _)| {
+                *id != keep && !usage.subscribed.contains(*id) && !usage.in_flight.contains_key(*id)
+            })
+            .collect();
+        by_last_use.sort_unstable_by_key(|(_, contract)| contract.last_used);
+
+        let mut code_users: HashMap<CodeHash, usize> = HashMap::new();
+        let mut candidates = vec![];
+        for (id, contract) in by_last_use {
+            if excess == 0 {
+                break;
+            }
+            if !is_evictable(id) {
+                continue;
+            }
+            let mut freed = contract.state_bytes;
+            if let Some(code_hash) = &contract.code_hash {
+                let (size, users) = usage.code[code_hash];
+                let evicted = code_users.entry(*code_hash).or_default();
+                *evicted += 1;
+                // the code is only removed along with the last contract using it
+                if *evicted == users {
+                    freed += size;
+                }
+            }
+            excess = excess.saturating_sub(freed);
+            candidates.push(*id);
+        }
+        candidates
+    }
+}
+
+/// Keeps a contract from being evicted while alive, see [`StorageQuota::in_flight`].
+pub(crate) struct InFlight {
+    usage: Arc<Mutex<Usage>>,
+    id: ContractInstanceId,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let usage = &mut *self.usage.lock();
+        if let Some(count) = usage.in_flight.get_mut(&self.id) {
+            *count -= 1;
+            if *count == 0 {
+                usage.in_flight.remove(&self.id);
+            }
+        }
+    }
+}
+
+fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => dir_size(&entry.path()),
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(byte: u8) -> ContractInstanceId {
+        ContractInstanceId::new([byte; 32])
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let delegates_dir = crate::util::tests::get_temp_dir();
+        let quota = StorageQuota::new(1_000, delegates_dir.path().into());
+        let shared_code = Some((CodeHash::new([0; 32]), 200));
+        quota.record(id(1), shared_code, 300);
+        quota.record(id(2), shared_code, 300);
+        quota.record(id(3), Some((CodeHash::new([3; 32]), 100)), 100);
+        assert_eq!(quota.used(), 1_000);
+        assert!(quota.eviction_candidates(&id(3), |_| true).is_empty());
+
+        // 200 bytes over budget
+        quota.record(id(3), None, 300);
+        quota.touch(&id(1));
+        // evicting the second contract alone doesn't free the shared code
+        assert_eq!(quota.eviction_candidates(&id(3), |_| true), vec![id(2)]);
+        quota.record(id(3), None, 600);
+        assert_eq!(
+            quota.eviction_candidates(&id(3), |_| true),
+            vec![id(2), id(1)]
+        );
+
+        // subscribed or non evictable contracts are kept
+        quota.set_subscribed(id(2), true);
+        assert_eq!(quota.eviction_candidates(&id(3), |_| true), vec![id(1)]);
+        assert!(quota
+            .eviction_candidates(&id(3), |c| *c != id(1))
+            .is_empty());
+
+        quota.remove(&id(1));
+        quota.remove(&id(2));
+        assert_eq!(quota.used(), 700);
+    }
+
+    #[test]
+    fn keeps_contracts_in_flight() {
+        let delegates_dir = crate::util::tests::get_temp_dir();
+        let quota = StorageQuota::new(500, delegates_dir.path().into());
+        quota.record(id(1), None, 300);
+        quota.record(id(2), None, 300);
+
+        let in_flight = quota.in_flight(id(1));
+        // a second request for the same contract, e.g. from a forked executor
+        let other = quota.clone().in_flight(id(1));
+        assert!(quota.eviction_candidates(&id(2), |_| true).is_empty());
+        drop(in_flight);
+        assert!(quota.eviction_candidates(&id(2), |_| true).is_empty());
+        drop(other);
+        assert_eq!(quota.eviction_candidates(&id(2), |_| true), vec![id(1)]);
+    }
+
+    #[test]
+    fn code_size_grows_with_compiled_modules() {
+        let delegates_dir = crate::util::tests::get_temp_dir();
+        let quota = StorageQuota::new(1_000, delegates_dir.path().into());
+        let code_hash = CodeHash::new([0; 32]);
+        quota.record(id(1), Some((code_hash, 100)), 100);
+        quota.record(id(2), Some((code_hash, 100)), 100);
+        assert_eq!(quota.used(), 300);
+
+        // a module compiled from the shared code is persisted
+        quota.record(id(1), Some((code_hash, 400)), 100);
+        assert_eq!(quota.used(), 600);
+        quota.remove(&id(1));
+        assert_eq!(quota.used(), 500);
+        quota.remove(&id(2));
+        assert_eq!(quota.used(), 0);
+    }
+}
@@ -5,6 +5,7 @@ use std::sync::atomic::{AtomicU64, Ordering::SeqCst};
 use std::sync::Arc;
 use std::time::Duration;
 
+use freenet_stdlib::client_api::{DelegateRequest, HostResponse};
 use freenet_stdlib::prelude::*;
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
@@ -64,9 +65,27 @@ pub(crate) trait ContractHandler {
     where
         Self: Sized + 'static;
 
+    /// Builds another handler which shares the executor resources (stores, compiled modules...)
+    /// of this one, to run concurrently with it as part of a pool.
+    fn fork(
+        &self,
+        contract_handler_channel: ContractHandlerChannel<ContractHandlerHalve>,
+        executor_request_sender: ExecutorToEventLoopChannel<ExecutorHalve>,
+    ) -> impl Future<Output = anyhow::Result<Self>> + Send
+    where
+        Self: Sized + 'static;
+
     fn channel(&mut self) -> &mut ContractHandlerChannel<ContractHandlerHalve>;
 
     fn executor(&mut self) -> &mut Self::ContractExecutor;
+
+    /// Borrows both the channel and the executor, so they can be polled concurrently.
+    fn channel_and_executor(
+        &mut self,
+    ) -> (
+        &mut ContractHandlerChannel<ContractHandlerHalve>,
+        &mut Self::ContractExecutor,
+    );
 }
 
 pub(crate) struct NetworkContractHandler<R = Runtime> {
@@ -90,6 +109,18 @@ impl ContractHandler for NetworkContractHandler<Runtime> {
         Ok(Self { executor, channel })
     }
 
+    async fn fork(
+        &self,
+        channel: ContractHandlerChannel<ContractHandlerHalve>,
+        executor_request_sender: ExecutorToEventLoopChannel<ExecutorHalve>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized + 'static,
+    {
+        let executor = self.executor.fork(Some(executor_request_sender)).await?;
+        Ok(Self { executor, channel })
+    }
+
     fn channel(&mut self) -> &mut ContractHandlerChannel<ContractHandlerHalve> {
         &mut self.channel
     }
@@ -97,6 +128,15 @@ impl ContractHandler for NetworkContractHandler<Runtime> {
     fn executor(&mut self) -> &mut Self::ContractExecutor {
         &mut self.executor
     }
+
+    fn channel_and_executor(
+        &mut self,
+    ) -> (
+        &mut ContractHandlerChannel<ContractHandlerHalve>,
+        &mut Self::ContractExecutor,
+    ) {
+        (&mut self.channel, &mut self.executor)
+    }
 }
 
 #[cfg(test)]
@@ -116,6 +156,18 @@ impl ContractHandler for NetworkContractHandler<super::MockRuntime> {
         Ok(Self { executor, channel })
     }
 
+    async fn fork(
+        &self,
+        channel: ContractHandlerChannel<ContractHandlerHalve>,
+        executor_request_sender: ExecutorToEventLoopChannel<ExecutorHalve>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized + 'static,
+    {
+        let executor = self.executor.fork(executor_request_sender).await?;
+        Ok(Self { executor, channel })
+    }
+
     fn channel(&mut self) -> &mut ContractHandlerChannel<ContractHandlerHalve> {
         &mut self.channel
     }
@@ -123,6 +175,15 @@ impl ContractHandler for NetworkContractHandler<super::MockRuntime> {
     fn executor(&mut self) -> &mut Self::ContractExecutor {
         &mut self.executor
     }
+
+    fn channel_and_executor(
+        &mut self,
+    ) -> (
+        &mut ContractHandlerChannel<ContractHandlerHalve>,
+        &mut Self::ContractExecutor,
+    ) {
+        (&mut self.channel, &mut self.executor)
+    }
 }
 
 #[derive(Eq)]
@@ -286,6 +347,72 @@ impl ContractHandlerChannel<ContractHandlerHalve> {
         }
         Err(ContractError::NoEvHandlerResponse)
     }
+
+    /// Splits this channel into a number of channels, each to be served by a different handler.
+    ///
+    /// Events are routed by the contract (or delegate) they refer to, so all the events for
+    /// a given contract are received, in order, by the same handler. The returned router
+    /// must be running for any events to be forwarded.
+    pub(crate) fn into_shards(self, shards: usize) -> (ShardRouter, Vec<Self>) {
+        let (senders, channels): (Vec<_>, Vec<_>) = (0..shards)
+            .map(|_| {
+                let (event_sender, event_receiver) = mpsc::unbounded_channel();
+                let channel = ContractHandlerChannel {
+                    end: ContractHandlerHalve {
+                        event_receiver,
+                        waiting_response: BTreeMap::new(),
+                    },
+                };
+                (event_sender, channel)
+            })
+            .unzip();
+        let router = ShardRouter {
+            event_receiver: self.end.event_receiver,
+            shards: senders,
+        };
+        (router, channels)
+    }
+}
+
+/// Forwards the events received by a contract handler channel to its shards.
+pub(crate) struct ShardRouter {
+    event_receiver: mpsc::UnboundedReceiver<InternalCHEvent>,
+    shards: Vec<mpsc::UnboundedSender<InternalCHEvent>>,
+}
+
+impl ShardRouter {
+    pub async fn run(mut self) -> Result<(), ContractError> {
+        while let Some(event) = self.event_receiver.recv().await {
+            let shard = self.shard(&event.ev);
+            self.shards[shard]
+                .send(event)
+                .map_err(|err| ContractError::ChannelDropped(Box::new(err.0.ev)))?;
+        }
+        Err(ContractError::NoEvHandlerResponse)
+    }
+
+    fn shard(&self, event: &ContractHandlerEvent) -> usize {
+        let id: &[u8; 32] = match event {
+            ContractHandlerEvent::PutQuery { key, .. }
+            | ContractHandlerEvent::GetQuery { key, .. }
+            | ContractHandlerEvent::UpdateQuery { key, .. }
+            | ContractHandlerEvent::RegisterSubscriberListener { key, .. }
+            | ContractHandlerEvent::SummarizeQuery { key }
+            | ContractHandlerEvent::DeltaQuery { key, .. } => &**key.id(),
+            ContractHandlerEvent::DelegateRequest { data, .. } => &**data.key(),
+            // responses are never sent to the handler
+            _ => return 0,
+        };
+        Self::shard_of(id, self.shards.len())
+    }
+
+    /// Contract instance ids and delegate keys are hashes already, so they are evenly
+    /// distributed between the shards by their leading bytes.
+    pub(super) fn shard_of(id: &[u8; 32], shards: usize) -> usize {
+        let mut leading = [0; 8];
+        leading.copy_from_slice(&id[..8]);
+        (u64::from_le_bytes(leading) % shards as u64) as usize
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -345,6 +472,47 @@ pub(crate) enum ContractHandlerEvent {
         subscriber_listener: UnboundedSender<HostResult>,
     },
     RegisterSubscriberListenerResponse,
+    /// Summarize the state of a contract stored in this node
+    SummarizeQuery {
+        key: ContractKey,
+    },
+    /// The response to a summarize query
+    SummarizeResponse {
+        key: ContractKey,
+        summary: Result<StateSummary<'static>, ExecutorError>,
+    },
+    /// Compute the changes to the state of a contract in this node missing from a peer state
+    DeltaQuery {
+        key: ContractKey,
+        summary: StateSummary<'static>,
+    },
+    /// The response to a delta query
+    DeltaResponse {
+        key: ContractKey,
+        delta: Result<StateDelta<'static>, ExecutorError>,
+    },
+    /// Request to a delegate on behalf of a client
+    DelegateRequest {
+        data: DelegateRequest<'static>,
+        attested_contract: Option<ContractInstanceId>,
+    },
+    /// The response to a delegate request
+    DelegateResponse(Result<HostResponse, ExecutorError>),
+}
+
+impl ContractHandlerEvent {
+    /// The contract a request is for, if any.
+    pub(super) fn contract(&self) -> Option<ContractKey> {
+        match self {
+            ContractHandlerEvent::PutQuery { key, .. }
+            | ContractHandlerEvent::GetQuery { key, .. }
+            | ContractHandlerEvent::UpdateQuery { key, .. }
+            | ContractHandlerEvent::RegisterSubscriberListener { key, .. }
+            | ContractHandlerEvent::SummarizeQuery { key }
+            | ContractHandlerEvent::DeltaQuery { key, .. } => Some(*key),
+            _ => None,
+        }
+    }
 }
 
 impl std::fmt::Display for ContractHandlerEvent {
@@ -413,6 +581,27 @@ impl std::fmt::Display for ContractHandlerEvent {
             ContractHandlerEvent::RegisterSubscriberListenerResponse => {
                 write!(f, "register subscriber listener response")
             }
+            ContractHandlerEvent::SummarizeQuery { key } => {
+                write!(f, "summarize query {{ {key} }}")
+            }
+            ContractHandlerEvent::SummarizeResponse { key, summary } => match summary {
+                Ok(_) => write!(f, "summarize query response {{ {key} }}"),
+                Err(e) => write!(f, "summarize query failed {{ {key}, {e} }}"),
+            },
+            ContractHandlerEvent::DeltaQuery { key, .. } => {
+                write!(f, "delta query {{ {key} }}")
+            }
+            ContractHandlerEvent::DeltaResponse { key, delta } => match delta {
+                Ok(_) => write!(f, "delta query response {{ {key} }}"),
+                Err(e) => write!(f, "delta query failed {{ {key}, {e} }}"),
+            },
+            ContractHandlerEvent::DelegateRequest { data, .. } => {
+                write!(f, "delegate request {{ {} }}", data.key())
+            }
+            ContractHandlerEvent::DelegateResponse(response) => match response {
+                Ok(_) => write!(f, "delegate response"),
+                Err(e) => write!(f, "delegate request failed {{ {e} }}"),
+            },
         }
     }
 }
@@ -516,6 +705,20 @@ pub(super) mod in_memory {
             Ok(MemoryContractHandler::new(channel, executor_request_sender, &identifier).await)
         }
 
+        async fn fork(
+            &self,
+            channel: ContractHandlerChannel<ContractHandlerHalve>,
+            executor_request_sender: ExecutorToEventLoopChannel<ExecutorHalve>,
+        ) -> anyhow::Result<Self>
+        where
+            Self: Sized + 'static,
+        {
+            Ok(MemoryContractHandler {
+                channel,
+                runtime: self.runtime.fork(executor_request_sender).await?,
+            })
+        }
+
         fn channel(&mut self) -> &mut ContractHandlerChannel<ContractHandlerHalve> {
             &mut self.channel
         }
@@ -523,6 +726,15 @@ pub(super) mod in_memory {
         fn executor(&mut self) -> &mut Self::ContractExecutor {
             &mut self.runtime
         }
+
+        fn channel_and_executor(
+            &mut self,
+        ) -> (
+            &mut ContractHandlerChannel<ContractHandlerHalve>,
+            &mut Self::ContractExecutor,
+        ) {
+            (&mut self.channel, &mut self.runtime)
+        }
     }
 
     #[test]
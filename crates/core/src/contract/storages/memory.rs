@@ -0,0 +1,218 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::Infallible,
+    path::Path,
+    sync::Arc,
+};
+
+use chrono::{DateTime, Utc};
+use freenet_stdlib::prelude::*;
+use parking_lot::RwLock;
+
+use crate::wasm_runtime::{StateBatch, StateStorage, StateVersion};
+
+/// State storage which keeps everything in memory, so nothing outlives the process.
+///
+/// Useful for tests and simulations, which can be seeded from a store snapshot instead of
+/// a data directory. Clones share the same data.
+#[derive(Clone, Default)]
+pub struct MemoryStorage(Arc<RwLock<Tables>>);
+
+#[derive(Default)]
+struct Tables {
+    states: HashMap<ContractInstanceId, WrappedState>,
+    params: HashMap<ContractInstanceId, Parameters<'static>>,
+    history: HashMap<ContractInstanceId, BTreeMap<u64, (DateTime<Utc>, WrappedState)>>,
+}
+
+impl Tables {
+    fn push_history(
+        &mut self,
+        key: &ContractKey,
+        replaced_at: DateTime<Utc>,
+        state: WrappedState,
+        max_versions: usize,
+    ) {
+        let history = self.history.entry(*key.id()).or_default();
+        let next = history.last_key_value().map_or(0, |(v, _)| v + 1);
+        history.insert(next, (replaced_at, state));
+        while history.len() > max_versions {
+            history.pop_first();
+        }
+    }
+}
+
+impl MemoryStorage {
+    /// The data directory is only taken for parity with the on-disk backends.
+    pub async fn new(_data_dir: &Path) -> Result<Self, Infallible> {
+        Ok(Self::default())
+    }
+}
+
+impl StateStorage for MemoryStorage {
+    type Error = Infallible;
+
+    async fn store(&mut self, key: ContractKey, state: WrappedState) -> Result<(), Self::Error> {
+        self.0.write().states.insert(*key.id(), state);
+        Ok(())
+    }
+
+    async fn get(&self, key: &ContractKey) -> Result<Option<WrappedState>, Self::Error> {
+        Ok(self.0.read().states.get(key.id()).cloned())
+    }
+
+    async fn store_params(
+        &mut self,
+        key: ContractKey,
+        params: Parameters<'static>,
+    ) -> Result<(), Self::Error> {
+        self.0.write().params.insert(*key.id(), params);
+        Ok(())
+    }
+
+    async fn get_params<'a>(
+        &'a self,
+        key: &'a ContractKey,
+    ) -> Result<Option<Parameters<'static>>, Self::Error> {
+        Ok(self.0.read().params.get(key.id()).cloned())
+    }
+
+    async fn keys(&self) -> Result<Vec<ContractKey>, Self::Error> {
+        let tables = self.0.read();
+        Ok(tables
+            .states
+            .keys()
+            .map(|id| ContractKey::from(*id))
+            .collect())
+    }
+
+    async fn remove(&mut self, key: &ContractKey) -> Result<(), Self::Error> {
+        let tables = &mut *self.0.write();
+        tables.states.remove(key.id());
+        tables.params.remove(key.id());
+        tables.history.remove(key.id());
+        Ok(())
+    }
+
+    async fn commit(&mut self, batch: &StateBatch) -> Result<(), Self::Error> {
+        let tables = &mut *self.0.write();
+        for (key, state, params) in batch.writes() {
+            tables.states.insert(*key.id(), state.clone());
+            if let Some(params) = params {
+                tables.params.insert(*key.id(), params.clone());
+            }
+        }
+        for (key, replaced_at, state) in batch.replaced() {
+            tables.push_history(key, *replaced_at, state.clone(), batch.history_len());
+        }
+        for key in batch.removed() {
+            tables.states.remove(key.id());
+            tables.params.remove(key.id());
+            tables.history.remove(key.id());
+        }
+        Ok(())
+    }
+
+    async fn push_history(
+        &mut self,
+        key: ContractKey,
+        replaced_at: DateTime<Utc>,
+        state: WrappedState,
+        max_versions: usize,
+    ) -> Result<(), Self::Error> {
+        self.0
+            .write()
+            .push_history(&key, replaced_at, state, max_versions);
+        Ok(())
+    }
+
+    async fn history(&self, key: &ContractKey) -> Result<Vec<StateVersion>, Self::Error> {
+        let tables = self.0.read();
+        let Some(history) = tables.history.get(key.id()) else {
+            return Ok(vec![]);
+        };
+        Ok(history
+            .iter()
+            .map(|(version, (replaced_at, state))| StateVersion {
+                version: *version,
+                replaced_at: *replaced_at,
+                state: state.clone(),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasm_runtime::StateStore;
+
+    #[tokio::test]
+    async fn history_and_batches() -> anyhow::Result<()> {
+        let mut store = StateStore::new(MemoryStorage::default(), 10_000)?.with_history(2);
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let related = ContractKey::from(ContractInstanceId::new([2; 32]));
+
+        let mut batch = StateBatch::default();
+        batch.store(key, WrappedState::new(vec![0]), Parameters::from(vec![]));
+        batch.store(
+            related,
+            WrappedState::new(vec![1]),
+            Parameters::from(vec![]),
+        );
+        store.commit(batch).await?;
+        for i in 1..4 {
+            store.update(&key, WrappedState::new(vec![i])).await?;
+        }
+        let versions: Vec<_> = store
+            .history(&key)
+            .await?
+            .iter()
+            .map(|v| v.version)
+            .collect();
+        assert_eq!(versions, vec![1, 2]);
+        assert_eq!(store.restore(&key, 1).await?.as_ref(), &[1]);
+        assert_eq!(store.get(&key).await?.as_ref(), &[1]);
+
+        store.remove(&key).await?;
+        assert!(store.history(&key).await?.is_empty());
+        assert_eq!(store.keys().await?, vec![related]);
+
+        let mut batch = StateBatch::default();
+        batch.remove(related);
+        store.commit(batch).await?;
+        assert!(store.keys().await?.is_empty());
+        assert!(store.get(&related).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stored_bytes_follow_history() -> anyhow::Result<()> {
+        let storage = MemoryStorage::default();
+        let mut store = StateStore::new(storage.clone(), 10_000)?.with_history(2);
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        store
+            .store(key, WrappedState::new(vec![0]), Parameters::from(vec![]))
+            .await?;
+        assert_eq!(store.stored_bytes(&key).await, 1);
+
+        // the replaced states are counted without reading the history again
+        for state in [vec![1; 2], vec![2; 3], vec![3]] {
+            store.update(&key, WrappedState::new(state)).await?;
+        }
+        assert_eq!(store.stored_bytes(&key).await, 1 + 2 + 3);
+        let reloaded = StateStore::new(storage.clone(), 10_000)?;
+        assert_eq!(reloaded.stored_bytes(&key).await, 1 + 2 + 3);
+
+        // restoring keeps the whole history, including the replaced state
+        let oldest = store.history(&key).await?[0].version;
+        store.restore(&key, oldest).await?;
+        assert_eq!(store.stored_bytes(&key).await, 2 + 2 + 3 + 1);
+        let reloaded = StateStore::new(storage, 10_000)?;
+        assert_eq!(reloaded.stored_bytes(&key).await, 2 + 2 + 3 + 1);
+
+        store.remove(&key).await?;
+        assert_eq!(store.stored_bytes(&key).await, 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+//! Migration of the state database between the redb and sqlite backends.
+
+use std::path::Path;
+
+use super::{redb::ReDb, sqlite::Pool};
+use crate::wasm_runtime::{StateBatch, StateStorage};
+
+/// A backend the state database can be stored with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum StorageBackend {
+    Redb,
+    Sqlite,
+}
+
+impl StorageBackend {
+    /// Name of the database file the backend keeps in the data directory.
+    fn db_file(&self) -> &'static str {
+        match self {
+            StorageBackend::Redb => "db",
+            StorageBackend::Sqlite => "freenet.db",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MigrationReport {
+    /// Contracts whose state and parameters were copied.
+    pub contracts: usize,
+    /// Prior states copied from the state histories.
+    pub history_versions: usize,
+}
+
+/// Copies every contract state, along with its parameters and state history, from the
+/// database of the `from` backend in `source_dir` to a database of the other backend in
+/// `target_dir`, reading each entry back from the target to verify it.
+///
+/// Contracts are copied one at a time, replacing any entries already in the target, so an
+/// interrupted migration can be run again. Each backend uses its own database file, so both
+/// directories can be the same. History versions are renumbered from 0 in the target.
+pub async fn migrate_state_db(
+    from: StorageBackend,
+    source_dir: &Path,
+    target_dir: &Path,
+) -> anyhow::Result<MigrationReport> {
+    let source_db = source_dir.join(from.db_file());
+    if !source_db.exists() {
+        anyhow::bail!("no {from:?} state database found at {source_db:?}");
+    }
+    match from {
+        StorageBackend::Redb => {
+            let source = ReDb::new(source_dir).await?;
+            let mut target = Pool::new(Some(target_dir)).await?;
+            copy_states(&source, &mut target).await
+        }
+        StorageBackend::Sqlite => {
+            let source = Pool::new(Some(source_dir)).await?;
+            let mut target = ReDb::new(target_dir).await?;
+            copy_states(&source, &mut target).await
+        }
+    }
+}
+
+async fn copy_states<S, T>(source: &S, target: &mut T) -> anyhow::Result<MigrationReport>
+where
+    S: StateStorage,
+    <S as StateStorage>::Error: Into<anyhow::Error>,
+    T: StateStorage,
+    <T as StateStorage>::Error: Into<anyhow::Error>,
+{
+    let mut report = MigrationReport::default();
+    for key in source.keys().await.map_err(Into::into)? {
+        let Some(state) = source.get(&key).await.map_err(Into::into)? else {
+            continue;
+        };
+        let params = source.get_params(&key).await.map_err(Into::into)?;
+        let history = source.history(&key).await.map_err(Into::into)?;
+
+        target.remove(&key).await.map_err(Into::into)?;
+        for version in &history {
+            target
+                .push_history(key, version.replaced_at, version.state.clone(), usize::MAX)
+                .await
+                .map_err(Into::into)?;
+        }
+        let mut batch = StateBatch::default();
+        match &params {
+            Some(params) => batch.store(key, state.clone(), params.clone()),
+            None => batch.update(key, state.clone()),
+        }
+        target.commit(&batch).await.map_err(Into::into)?;
+
+        let copied = target.get(&key).await.map_err(Into::into)?;
+        let copied_params = target.get_params(&key).await.map_err(Into::into)?;
+        let copied_history = target.history(&key).await.map_err(Into::into)?;
+        let history_matches = copied_history.len() == history.len()
+            && copied_history
+                .iter()
+                .zip(&history)
+                .all(|(copied, version)| {
+                    copied.replaced_at == version.replaced_at
+                        && copied.state.as_ref() == version.state.as_ref()
+                });
+        if copied.as_ref().map(|s| s.as_ref()) != Some(state.as_ref())
+            || copied_params.as_ref().map(|p| p.as_ref()) != params.as_ref().map(|p| p.as_ref())
+            || !history_matches
+        {
+            anyhow::bail!("entries of contract {key} differ after being copied");
+        }
+
+        report.contracts += 1;
+        report.history_versions += history.len();
+        if report.contracts % 1_000 == 0 {
+            tracing::info!("migrated {} contracts", report.contracts);
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+    use freenet_stdlib::prelude::{ContractInstanceId, ContractKey, Parameters, WrappedState};
+
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn redb_to_sqlite_and_back() -> anyhow::Result<()> {
+        let redb_dir = crate::util::tests::get_temp_dir();
+        let sqlite_dir = crate::util::tests::get_temp_dir();
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let replaced_at = DateTime::<Utc>::from_timestamp_millis(1_000).unwrap();
+        {
+            let mut redb = ReDb::new(redb_dir.path()).await?;
+            redb.store(key, WrappedState::new(vec![2])).await?;
+            redb.store_params(key, Parameters::from(vec![3])).await?;
+            redb.push_history(key, replaced_at, WrappedState::new(vec![1]), 10)
+                .await?;
+        }
+
+        let report =
+            migrate_state_db(StorageBackend::Redb, redb_dir.path(), sqlite_dir.path()).await?;
+        assert_eq!(report.contracts, 1);
+        assert_eq!(report.history_versions, 1);
+        let sqlite = Pool::new(Some(sqlite_dir.path())).await?;
+        assert_eq!(sqlite.get(&key).await?.unwrap().as_ref(), &[2]);
+        assert_eq!(sqlite.get_params(&key).await?.unwrap().as_ref(), &[3]);
+        let history = sqlite.history(&key).await?;
+        assert_eq!(history[0].replaced_at, replaced_at);
+
+        // copying again replaces the entries instead of duplicating the history
+        let back = crate::util::tests::get_temp_dir();
+        migrate_state_db(StorageBackend::Sqlite, sqlite_dir.path(), back.path()).await?;
+        let report =
+            migrate_state_db(StorageBackend::Sqlite, sqlite_dir.path(), back.path()).await?;
+        assert_eq!(report.history_versions, 1);
+        let redb = ReDb::new(back.path()).await?;
+        assert_eq!(redb.history(&key).await?.len(), 1);
+        Ok(())
+    }
+}
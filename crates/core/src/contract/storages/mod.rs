@@ -4,14 +4,25 @@ pub mod sqlite;
 #[cfg(all(feature = "sqlite", not(feature = "redb")))]
 pub use sqlite::Pool as SqlitePool;
 
-#[cfg(all(feature = "sqlite", not(feature = "redb")))]
+#[cfg(all(feature = "sqlite", not(feature = "redb"), not(feature = "in-memory")))]
 pub type Storage = SqlitePool;
 
 /// State storage implementation based on the [`redb`]
 #[cfg(feature = "redb")]
 pub mod redb;
-#[cfg(feature = "redb")]
+#[cfg(all(feature = "redb", not(feature = "in-memory")))]
 use self::redb::ReDb;
 
-#[cfg(feature = "redb")]
+#[cfg(all(feature = "redb", not(feature = "in-memory")))]
 pub type Storage = ReDb;
+
+/// Migration of the state database between the `redb` and `sqlite` backends
+#[cfg(all(feature = "redb", feature = "sqlite"))]
+pub mod migration;
+
+/// State storage implementation which doesn't persist anything
+pub mod memory;
+pub use memory::MemoryStorage;
+
+#[cfg(feature = "in-memory")]
+pub type Storage = MemoryStorage;
@@ -1,21 +1,81 @@
-use std::path::Path;
+use std::{path::Path, sync::Arc};
 
+use chrono::{DateTime, Utc};
 use freenet_stdlib::prelude::*;
-use redb::{Database, TableDefinition};
+use redb::{Database, ReadableTable, TableDefinition};
 
-use crate::wasm_runtime::StateStorage;
+use crate::wasm_runtime::{StateBatch, StateStorage, StateVersion};
 
 const CONTRACT_PARAMS_TABLE: TableDefinition<&[u8], &[u8]> =
     TableDefinition::new("contract_params");
 const STATE_TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("state");
+/// Replaced states, keyed by the contract followed by the big endian version, so the
+/// history of a contract is a contiguous range in version order.
+/// Values are the big endian timestamp in milliseconds followed by the state.
+const HISTORY_TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("state_history");
 
-pub struct ReDb(Database);
+fn history_key(key: &ContractKey, version: u64) -> Vec<u8> {
+    let mut history_key = key.as_bytes().to_vec();
+    history_key.extend_from_slice(&version.to_be_bytes());
+    history_key
+}
+
+fn decode_version(history_key: &[u8], value: &[u8]) -> Result<StateVersion, redb::Error> {
+    let corrupted = || redb::Error::Corrupted("invalid state history entry".to_owned());
+    let version = history_key
+        .len()
+        .checked_sub(8)
+        .and_then(|start| history_key[start..].try_into().ok())
+        .map(u64::from_be_bytes)
+        .ok_or_else(corrupted)?;
+    if value.len() < 8 {
+        return Err(corrupted());
+    }
+    let (replaced_at, state) = value.split_at(8);
+    let replaced_at = replaced_at
+        .try_into()
+        .map(i64::from_be_bytes)
+        .map_err(|_| corrupted())?;
+    Ok(StateVersion {
+        version,
+        replaced_at: DateTime::from_timestamp_millis(replaced_at).unwrap_or_default(),
+        state: WrappedState::new(state.to_vec()),
+    })
+}
+
+/// Appends a replaced state to the history of the contract, keeping the last `max_versions`.
+fn push_history(
+    tbl: &mut redb::Table<'_, &'static [u8], &'static [u8]>,
+    key: &ContractKey,
+    replaced_at: DateTime<Utc>,
+    state: &WrappedState,
+    max_versions: usize,
+) -> Result<(), redb::Error> {
+    let (start, end) = (history_key(key, 0), history_key(key, u64::MAX));
+    let mut versions = vec![];
+    for entry in tbl.range(start.as_slice()..=end.as_slice())? {
+        let (k, v) = entry?;
+        versions.push(decode_version(k.value(), v.value())?.version);
+    }
+    let next = versions.last().map_or(0, |v| v + 1);
+    let mut value = replaced_at.timestamp_millis().to_be_bytes().to_vec();
+    value.extend_from_slice(state.as_ref());
+    tbl.insert(history_key(key, next).as_slice(), value.as_slice())?;
+    let excess = (versions.len() + 1).saturating_sub(max_versions);
+    for version in versions.into_iter().take(excess) {
+        tbl.remove(history_key(key, version).as_slice())?;
+    }
+    Ok(())
+}
+
+#[derive(Clone)]
+pub struct ReDb(Arc<Database>);
 
 impl ReDb {
     pub async fn new(data_dir: &Path) -> Result<Self, redb::Error> {
         let db_path = data_dir.join("db");
         tracing::info!("loading contract store from {db_path:?}");
-        match Database::create(db_path).map(Self) {
+        match Database::create(db_path).map(|db| Self(Arc::new(db))) {
             Ok(db) => {
                 let txn = db.0.begin_write()?;
                 {
@@ -28,6 +88,11 @@ impl ReDb {
                         tracing::error!(error = %e, "failed to open CONTRACT_PARAMS_TABLE");
                         e
                     })?;
+
+                    txn.open_table(HISTORY_TABLE).map_err(|e| {
+                        tracing::error!(error = %e, "failed to open HISTORY_TABLE");
+                        e
+                    })?;
                 }
                 txn.commit()?;
 
@@ -98,4 +163,205 @@ impl StateStorage for ReDb {
             None => Ok(None),
         }
     }
+
+    async fn keys(&self) -> Result<Vec<ContractKey>, Self::Error> {
+        let txn = self.0.begin_read()?;
+        let tbl = txn.open_table(STATE_TABLE)?;
+        let mut keys = vec![];
+        for entry in tbl.iter()? {
+            let (k, _) = entry?;
+            if let Ok(id) = <[u8; 32]>::try_from(k.value()) {
+                keys.push(ContractKey::from(ContractInstanceId::new(id)));
+            }
+        }
+        Ok(keys)
+    }
+
+    async fn remove(&mut self, key: &ContractKey) -> Result<(), Self::Error> {
+        let txn = self.0.begin_write()?;
+
+        {
+            let mut tbl = txn.open_table(STATE_TABLE)?;
+            tbl.remove(key.as_bytes())?;
+            let mut tbl = txn.open_table(CONTRACT_PARAMS_TABLE)?;
+            tbl.remove(key.as_bytes())?;
+            let mut tbl = txn.open_table(HISTORY_TABLE)?;
+            let (start, end) = (history_key(key, 0), history_key(key, u64::MAX));
+            tbl.retain_in(start.as_slice()..=end.as_slice(), |_, _| false)?;
+        }
+        txn.commit().map_err(Into::into)
+    }
+
+    async fn commit(&mut self, batch: &StateBatch) -> Result<(), Self::Error> {
+        let txn = self.0.begin_write()?;
+
+        {
+            let mut states = txn.open_table(STATE_TABLE)?;
+            let mut params_tbl = txn.open_table(CONTRACT_PARAMS_TABLE)?;
+            for (key, state, params) in batch.writes() {
+                states.insert(key.as_bytes(), state.as_ref())?;
+                if let Some(params) = params {
+                    params_tbl.insert(key.as_bytes(), params.as_ref())?;
+                }
+            }
+            let mut history = txn.open_table(HISTORY_TABLE)?;
+            for (key, replaced_at, state) in batch.replaced() {
+                push_history(&mut history, key, *replaced_at, state, batch.history_len())?;
+            }
+            for key in batch.removed() {
+                states.remove(key.as_bytes())?;
+                params_tbl.remove(key.as_bytes())?;
+                let (start, end) = (history_key(key, 0), history_key(key, u64::MAX));
+                history.retain_in(start.as_slice()..=end.as_slice(), |_, _| false)?;
+            }
+        }
+        txn.commit().map_err(Into::into)
+    }
+
+    async fn push_history(
+        &mut self,
+        key: ContractKey,
+        replaced_at: DateTime<Utc>,
+        state: WrappedState,
+        max_versions: usize,
+    ) -> Result<(), Self::Error> {
+        let txn = self.0.begin_write()?;
+
+        {
+            let mut tbl = txn.open_table(HISTORY_TABLE)?;
+            push_history(&mut tbl, &key, replaced_at, &state, max_versions)?;
+        }
+        txn.commit().map_err(Into::into)
+    }
+
+    async fn history(&self, key: &ContractKey) -> Result<Vec<StateVersion>, Self::Error> {
+        let txn = self.0.begin_read()?;
+        let tbl = txn.open_table(HISTORY_TABLE)?;
+        let (start, end) = (history_key(key, 0), history_key(key, u64::MAX));
+        let mut history = vec![];
+        for entry in tbl.range(start.as_slice()..=end.as_slice())? {
+            let (k, v) = entry?;
+            history.push(decode_version(k.value(), v.value())?);
+        }
+        Ok(history)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasm_runtime::StateStore;
+
+    #[tokio::test]
+    async fn bounded_state_history() -> anyhow::Result<()> {
+        let dir = crate::util::tests::get_temp_dir();
+        let storage = ReDb::new(dir.path()).await?;
+        let mut store = StateStore::new(storage, 10_000)?.with_history(2);
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        store
+            .store(key, WrappedState::new(vec![0]), Parameters::from(vec![]))
+            .await?;
+        for i in 1..4 {
+            store.update(&key, WrappedState::new(vec![i])).await?;
+        }
+        let history = store.history(&key).await?;
+        let versions: Vec<_> = history.iter().map(|v| v.version).collect();
+        assert_eq!(versions, vec![1, 2]);
+        assert_eq!(history[0].state.as_ref(), &[1]);
+
+        let restored = store.restore(&key, 1).await?;
+        assert_eq!(restored.as_ref(), &[1]);
+        assert_eq!(store.get(&key).await?.as_ref(), &[1]);
+        // the replaced state is kept so the rollback can be undone
+        let history = store.history(&key).await?;
+        assert_eq!(history.last().unwrap().version, 3);
+        assert_eq!(history.last().unwrap().state.as_ref(), &[3]);
+
+        store.remove(&key).await?;
+        assert!(store.history(&key).await?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn corrupted_history_entry() {
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let history_key = history_key(&key, 3);
+        let mut value = 5i64.to_be_bytes().to_vec();
+        value.push(1);
+        let version = decode_version(&history_key, &value).unwrap();
+        assert_eq!(version.version, 3);
+        assert_eq!(version.state.as_ref(), &[1]);
+
+        assert!(decode_version(&history_key, &value[..7]).is_err());
+        assert!(decode_version(&history_key[..7], &value).is_err());
+    }
+
+    #[tokio::test]
+    async fn atomic_batch_commit() -> anyhow::Result<()> {
+        let dir = crate::util::tests::get_temp_dir();
+        let storage = ReDb::new(dir.path()).await?;
+        let mut store = StateStore::new(storage, 10_000)?;
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let related = ContractKey::from(ContractInstanceId::new([2; 32]));
+
+        let mut batch = StateBatch::default();
+        batch.store(key, WrappedState::new(vec![1]), Parameters::from(vec![]));
+        batch.store(
+            related,
+            WrappedState::new(vec![2]),
+            Parameters::from(vec![]),
+        );
+        batch.update(key, WrappedState::new(vec![3]));
+        store.commit(batch).await?;
+        assert_eq!(store.get(&key).await?.as_ref(), &[3]);
+        assert_eq!(store.get(&related).await?.as_ref(), &[2]);
+
+        // nothing is written if any of the writes can't be applied
+        let missing = ContractKey::from(ContractInstanceId::new([3; 32]));
+        let mut batch = StateBatch::default();
+        batch.update(key, WrappedState::new(vec![4]));
+        batch.update(missing, WrappedState::new(vec![5]));
+        assert!(store.commit(batch).await.is_err());
+        assert_eq!(store.get(&key).await?.as_ref(), &[3]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_batch_write_is_rolled_back() -> anyhow::Result<()> {
+        let dir = crate::util::tests::get_temp_dir();
+        let storage = ReDb::new(dir.path()).await?;
+        let mut store = StateStore::new(storage.clone(), 10_000)?.with_history(2);
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let related = ContractKey::from(ContractInstanceId::new([2; 32]));
+        let mut batch = StateBatch::default();
+        batch.store(key, WrappedState::new(vec![1]), Parameters::from(vec![]));
+        batch.store(
+            related,
+            WrappedState::new(vec![2]),
+            Parameters::from(vec![]),
+        );
+        store.commit(batch).await?;
+
+        // archiving the replaced state of the related contract fails once its state is written
+        let txn = storage.0.begin_write()?;
+        {
+            let mut history = txn.open_table(HISTORY_TABLE)?;
+            history.insert(history_key(&related, 0).as_slice(), [0].as_slice())?;
+        }
+        txn.commit()?;
+        let mut batch = StateBatch::default();
+        batch.update(key, WrappedState::new(vec![3]));
+        batch.update(related, WrappedState::new(vec![4]));
+        let new = ContractKey::from(ContractInstanceId::new([3; 32]));
+        batch.store(new, WrappedState::new(vec![5]), Parameters::from(vec![]));
+        assert!(store.commit(batch).await.is_err());
+
+        // none of the writes were persisted
+        let state = |state: Option<WrappedState>| state.map(|state| state.as_ref().to_vec());
+        assert_eq!(state(storage.get(&key).await?), Some(vec![1]));
+        assert_eq!(state(storage.get(&related).await?), Some(vec![2]));
+        assert!(storage.get(&new).await?.is_none());
+        assert!(storage.history(&key).await?.is_empty());
+        Ok(())
+    }
 }
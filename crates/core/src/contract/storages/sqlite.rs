@@ -1,12 +1,13 @@
 use std::{path::Path, str::FromStr};
 
+use chrono::{DateTime, Utc};
 use freenet_stdlib::prelude::*;
 use sqlx::{
-    sqlite::{SqliteConnectOptions, SqliteRow},
+    sqlite::{SqliteConnectOptions, SqliteConnection, SqliteRow},
     ConnectOptions, Row, SqlitePool,
 };
 
-use crate::wasm_runtime::{ContractError, StateStorage, StateStoreError};
+use crate::wasm_runtime::{ContractError, StateBatch, StateStorage, StateStoreError, StateVersion};
 
 async fn create_contracts_table(pool: &SqlitePool) -> Result<(), SqlDbError> {
     sqlx::query(
@@ -18,6 +19,52 @@ async fn create_contracts_table(pool: &SqlitePool) -> Result<(), SqlDbError> {
     )
     .execute(pool)
     .await?;
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS state_history (
+            contract        BLOB,
+                    version         INTEGER,
+                    replaced_at     INTEGER,
+                    state           BLOB,
+                    PRIMARY KEY (contract, version)
+                )",
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Appends a replaced state to the history of the contract, keeping the last `max_versions`.
+async fn push_history(
+    conn: &mut SqliteConnection,
+    key: &ContractKey,
+    replaced_at: DateTime<Utc>,
+    state: &WrappedState,
+    max_versions: usize,
+) -> Result<(), SqlDbError> {
+    let version: i64 = sqlx::query(
+        "SELECT COALESCE(MAX(version) + 1, 0) AS version FROM state_history WHERE contract = ?",
+    )
+    .bind(key.as_bytes())
+    .map(|row: SqliteRow| row.get("version"))
+    .fetch_one(&mut *conn)
+    .await?;
+    sqlx::query(
+        "INSERT INTO state_history (contract, version, replaced_at, state) 
+                 VALUES ($1, $2, $3, $4)
+                 ",
+    )
+    .bind(key.as_bytes())
+    .bind(version)
+    .bind(replaced_at.timestamp_millis())
+    .bind(state.as_ref())
+    .execute(&mut *conn)
+    .await?;
+    let oldest = version - i64::try_from(max_versions).unwrap_or(i64::MAX) + 1;
+    sqlx::query("DELETE FROM state_history WHERE contract = ? AND version < ?")
+        .bind(key.as_bytes())
+        .bind(oldest)
+        .execute(&mut *conn)
+        .await?;
     Ok(())
 }
 
@@ -101,7 +148,10 @@ impl StateStorage for Pool {
     ) -> Result<Option<Parameters<'static>>, Self::Error> {
         match sqlx::query("SELECT params FROM states WHERE contract = ?")
             .bind(key.as_bytes())
-            .map(|row: SqliteRow| Some(Parameters::from(row.get::<Vec<u8>, _>("params"))))
+            .map(|row: SqliteRow| {
+                row.get::<Option<Vec<u8>>, _>("params")
+                    .map(Parameters::from)
+            })
             .fetch_one(&self.0)
             .await
         {
@@ -110,6 +160,97 @@ impl StateStorage for Pool {
             Err(_) => Err(SqlDbError::ContractNotFound),
         }
     }
+
+    async fn keys(&self) -> Result<Vec<ContractKey>, Self::Error> {
+        let keys = sqlx::query("SELECT contract FROM states WHERE state IS NOT NULL")
+            .map(|row: SqliteRow| row.get::<Vec<u8>, _>("contract"))
+            .fetch_all(&self.0)
+            .await?
+            .into_iter()
+            .filter_map(|id| <[u8; 32]>::try_from(id).ok())
+            .map(|id| ContractKey::from(ContractInstanceId::new(id)))
+            .collect();
+        Ok(keys)
+    }
+
+    async fn remove(&mut self, key: &ContractKey) -> Result<(), Self::Error> {
+        sqlx::query("DELETE FROM states WHERE contract = ?")
+            .bind(key.as_bytes())
+            .execute(&self.0)
+            .await?;
+        sqlx::query("DELETE FROM state_history WHERE contract = ?")
+            .bind(key.as_bytes())
+            .execute(&self.0)
+            .await?;
+        Ok(())
+    }
+
+    async fn commit(&mut self, batch: &StateBatch) -> Result<(), Self::Error> {
+        let mut txn = self.0.begin().await?;
+        for (key, state, params) in batch.writes() {
+            sqlx::query(
+                "INSERT INTO states (contract, state) 
+                     VALUES ($1, $2) 
+                     ON CONFLICT(contract) DO UPDATE SET state = excluded.state
+                     ",
+            )
+            .bind(key.as_bytes())
+            .bind(state.as_ref())
+            .execute(&mut *txn)
+            .await?;
+            if let Some(params) = params {
+                sqlx::query("UPDATE states SET params = ? WHERE contract = ?")
+                    .bind(params.as_ref())
+                    .bind(key.as_bytes())
+                    .execute(&mut *txn)
+                    .await?;
+            }
+        }
+        for (key, replaced_at, state) in batch.replaced() {
+            push_history(&mut txn, key, *replaced_at, state, batch.history_len()).await?;
+        }
+        for key in batch.removed() {
+            sqlx::query("DELETE FROM states WHERE contract = ?")
+                .bind(key.as_bytes())
+                .execute(&mut *txn)
+                .await?;
+            sqlx::query("DELETE FROM state_history WHERE contract = ?")
+                .bind(key.as_bytes())
+                .execute(&mut *txn)
+                .await?;
+        }
+        // dropping the transaction without committing rolls it back
+        txn.commit().await?;
+        Ok(())
+    }
+
+    async fn push_history(
+        &mut self,
+        key: ContractKey,
+        replaced_at: DateTime<Utc>,
+        state: WrappedState,
+        max_versions: usize,
+    ) -> Result<(), Self::Error> {
+        let mut txn = self.0.begin().await?;
+        push_history(&mut txn, &key, replaced_at, &state, max_versions).await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    async fn history(&self, key: &ContractKey) -> Result<Vec<StateVersion>, Self::Error> {
+        let history = sqlx::query(
+            "SELECT version, replaced_at, state FROM state_history WHERE contract = ? ORDER BY version",
+        )
+        .bind(key.as_bytes())
+        .map(|row: SqliteRow| StateVersion {
+            version: row.get::<i64, _>("version") as u64,
+            replaced_at: DateTime::from_timestamp_millis(row.get("replaced_at")).unwrap_or_default(),
+            state: WrappedState::new(row.get("state")),
+        })
+        .fetch_all(&self.0)
+        .await?;
+        Ok(history)
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -125,3 +266,43 @@ pub enum SqlDbError {
     #[error(transparent)]
     StateStore(#[from] StateStoreError),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasm_runtime::{StateBatch, StateStore};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn bounded_state_history() -> anyhow::Result<()> {
+        let dir = crate::util::tests::get_temp_dir();
+        let storage = Pool::new(Some(dir.path())).await?;
+        let mut store = StateStore::new(storage, 10_000)?.with_history(2);
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        store
+            .store(key, WrappedState::new(vec![0]), Parameters::from(vec![]))
+            .await?;
+        for i in 1..3 {
+            store.update(&key, WrappedState::new(vec![i])).await?;
+        }
+        // states replaced by a batch are archived along with it
+        let mut batch = StateBatch::default();
+        batch.update(key, WrappedState::new(vec![3]));
+        store.commit(batch).await?;
+
+        let history = store.history(&key).await?;
+        let versions: Vec<_> = history.iter().map(|v| v.version).collect();
+        assert_eq!(versions, vec![1, 2]);
+        assert_eq!(history[0].state.as_ref(), &[1]);
+        assert_eq!(history[1].state.as_ref(), &[2]);
+
+        let restored = store.restore(&key, 1).await?;
+        assert_eq!(restored.as_ref(), &[1]);
+        let history = store.history(&key).await?;
+        assert_eq!(history.last().unwrap().version, 3);
+        assert_eq!(history.last().unwrap().state.as_ref(), &[3]);
+
+        store.remove(&key).await?;
+        assert!(store.history(&key).await?.is_empty());
+        Ok(())
+    }
+}
@@ -45,7 +45,12 @@ pub mod dev_tool {
         test::MemoryEventsGen, test::NetworkEventGenerator, ClientEventsProxy, ClientId,
         OpenRequest,
     };
-    pub use contract::{storages::Storage, Executor, OperationMode};
+    #[cfg(all(feature = "redb", feature = "sqlite"))]
+    pub use contract::storages::migration::{migrate_state_db, MigrationReport, StorageBackend};
+    pub use contract::{
+        storages::{MemoryStorage, Storage},
+        upgraded_to, Executor, OperationMode,
+    };
     pub use flatbuffers;
     pub use message::Transaction;
     pub use node::{
@@ -54,7 +59,11 @@ pub mod dev_tool {
     };
     pub use ring::Location;
     pub use transport::{TransportKeypair, TransportPublicKey};
-    pub use wasm_runtime::{ContractStore, DelegateStore, Runtime, SecretsStore, StateStore};
+    pub use wasm_runtime::{
+        verify_stores, ContractRuntimeInterface, ContractStore, DelegateStore, IntegrityReport,
+        Issue, Runtime, SecretsBackup, SecretsStore, StateStore, StateVersion, StoreSnapshot,
+        VerifyOptions,
+    };
 }
 
 #[cfg(test)]
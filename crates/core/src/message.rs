@@ -16,6 +16,7 @@ use crate::{
         connect::ConnectMsg, get::GetMsg, put::PutMsg, subscribe::SubscribeMsg, update::UpdateMsg,
     },
     ring::{Location, PeerKeyLocation},
+    transport::TrafficClass,
 };
 pub(crate) use sealed_msg_type::{TransactionType, TransactionTypeId};
 
@@ -281,6 +282,26 @@ impl Versioned for NetMessageV1 {
     }
 }
 
+impl NetMessage {
+    /// Priority of the message over the rest of the outbound traffic, connect and other
+    /// control messages go ahead of operation requests so connections are kept under load.
+    pub(crate) fn traffic_class(&self) -> TrafficClass {
+        match self {
+            NetMessage::V1(
+                NetMessageV1::Connect(_)
+                | NetMessageV1::Unsubscribed { .. }
+                | NetMessageV1::Aborted(_),
+            ) => TrafficClass::Control,
+            NetMessage::V1(
+                NetMessageV1::Put(_)
+                | NetMessageV1::Get(_)
+                | NetMessageV1::Subscribe(_)
+                | NetMessageV1::Update(_),
+            ) => TrafficClass::Request,
+        }
+    }
+}
+
 impl From<NetMessage> for semver::Version {
     fn from(msg: NetMessage) -> Self {
         msg.version()
@@ -326,6 +347,7 @@ pub(crate) enum QueryResult {
         state: WrappedState,
         contract: Option<ContractContainer>,
     },
+    DelegateResult(freenet_stdlib::client_api::HostResponse),
 }
 
 impl Display for NodeEvent {
@@ -469,6 +491,24 @@ mod tests {
         );
     }
 
+    #[test]
+    fn traffic_class() {
+        let aborted = NetMessage::V1(NetMessageV1::Aborted(Transaction::new::<GetMsg>()));
+        assert_eq!(aborted.traffic_class(), TrafficClass::Control);
+        let unsubscribed = NetMessage::V1(NetMessageV1::Unsubscribed {
+            transaction: Transaction::new::<SubscribeMsg>(),
+            key: ContractKey::from(freenet_stdlib::prelude::ContractInstanceId::new([1; 32])),
+            from: PeerKeyLocation::random().peer,
+        });
+        assert_eq!(unsubscribed.traffic_class(), TrafficClass::Control);
+        let update = NetMessage::from(UpdateMsg::InSync {
+            id: Transaction::new::<UpdateMsg>(),
+            target: PeerKeyLocation::random(),
+        });
+        assert_eq!(update.traffic_class(), TrafficClass::Request);
+        assert!(aborted.traffic_class() > update.traffic_class());
+    }
+
     #[test]
     fn get_ttl_cutoff_transaction() {
         let ttl_tx = Transaction::ttl_transaction();
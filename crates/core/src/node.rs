@@ -54,6 +54,8 @@ use crate::{
 };
 
 use crate::operations::handle_op_request;
+#[cfg(test)]
+pub(crate) use network_bridge::{event_loop_notification_channel, EventLoopNotificationsReceiver};
 pub(crate) use network_bridge::{ConnectionError, EventLoopNotificationsSender, NetworkBridge};
 
 use crate::topology::rate::Rate;
@@ -117,8 +119,9 @@ pub struct NodeConfig {
     pub(crate) rnd_if_htl_above: Option<usize>,
     pub(crate) max_number_conn: Option<usize>,
     pub(crate) min_number_conn: Option<usize>,
-    pub(crate) max_upstream_bandwidth: Option<Rate>,
-    pub(crate) max_downstream_bandwidth: Option<Rate>,
+    /// Caps on the bandwidth used by the node, enforced by the transport.
+    pub(crate) max_upstream_bandwidth: Rate,
+    pub(crate) max_downstream_bandwidth: Rate,
 }
 
 impl NodeConfig {
@@ -159,14 +162,18 @@ impl NodeConfig {
             peer_id: config.peer_id.clone(),
             network_listener_ip: config.network_api.address,
             network_listener_port: config.network_api.port,
-            config: Arc::new(config),
             location: None,
             max_hops_to_live: None,
             rnd_if_htl_above: None,
             max_number_conn: None,
             min_number_conn: None,
-            max_upstream_bandwidth: None,
-            max_downstream_bandwidth: None,
+            max_upstream_bandwidth: Rate::new_per_second(
+                config.network_api.max_upstream_bandwidth as f64,
+            ),
+            max_downstream_bandwidth: Rate::new_per_second(
+                config.network_api.max_downstream_bandwidth as f64,
+            ),
+            config: Arc::new(config),
         })
     }
 
@@ -895,7 +902,7 @@ pub async fn run_local_node(
             client_id: id,
             request,
             notification_channel,
-            token,
+            attested_contract,
             ..
         } = req;
         tracing::trace!(cli_id = %id, "got request -> {request}");
@@ -907,22 +914,12 @@ pub async fn run_local_node(
                     .await
             }
             ClientRequest::DelegateOp(op) => {
-                let attested_contract =
-                    token.and_then(|token| gw.attested_contracts.get(&token).map(|(t, _)| t));
-                executor.delegate_request(op, attested_contract)
+                executor.delegate_request(op, attested_contract.as_ref())
             }
             ClientRequest::Disconnect { cause } => {
                 if let Some(cause) = cause {
                     tracing::info!("disconnecting cause: {cause}");
                 }
-                // fixme: token must live for a bit to allow reconnections
-                if let Some(rm_token) = gw
-                    .attested_contracts
-                    .iter()
-                    .find_map(|(k, (_, eid))| (eid == &id).then(|| k.clone()))
-                {
-                    gw.attested_contracts.remove(&rm_token);
-                }
                 continue;
             }
             _ => Err(ExecutorError::other(anyhow::anyhow!("not supported"))),
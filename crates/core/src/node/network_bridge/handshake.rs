@@ -952,6 +952,15 @@ async fn gw_peer_connection_listener(
                             tracing::debug!(%joiner_key, "Joiner not provided, using joiner key");
                             PeerId::new(conn.remote_addr(), joiner_key)
                         });
+                        if conn.remote_public_key().is_some_and(|key| *key != joiner.pub_key) {
+                            tracing::warn!(
+                                at=?conn.my_address(),
+                                from=%conn.remote_addr(),
+                                %joiner,
+                                "Joiner key doesn't match the key authenticated by the handshake, terminating connection"
+                            );
+                            break Err(HandshakeError::ConnectionClosed(conn.remote_addr()));
+                        }
                         break Ok((
                             InternalEvent::InboundGwJoinRequest(InboundGwJoinRequest {
                                 conn,
@@ -1120,8 +1129,9 @@ mod tests {
         operations::connect::{ConnectMsg, ConnectResponse},
         ring::{Connection, PeerKeyLocation, Ring},
         transport::{
-            ConnectionEvent, OutboundConnectionHandler, PacketData, RemoteConnection,
-            SymmetricMessage, SymmetricMessagePayload, TransportPublicKey, UnknownEncryption,
+            ConnectionEvent, OutboundConnectionHandler, OutboundPacket, PacketData,
+            RemoteConnection, SymmetricMessage, SymmetricMessagePayload, TransportPublicKey,
+            UnknownEncryption,
         },
     };
 
@@ -1131,7 +1141,7 @@ mod tests {
         packet_senders:
             HashMap<SocketAddr, (Aes128Gcm, mpsc::Sender<PacketData<UnknownEncryption>>)>,
         packet_id: u32,
-        packet_receivers: Vec<mpsc::Receiver<(SocketAddr, Arc<[u8]>)>>,
+        packet_receivers: Vec<mpsc::Receiver<OutboundPacket>>,
         in_key: Aes128Gcm,
         my_addr: SocketAddr,
     }
@@ -1223,7 +1233,7 @@ mod tests {
         }
 
         async fn recv_outbound_msg(&mut self) -> anyhow::Result<Either<NetMessage, ()>> {
-            let (_, msg) = self.packet_receivers[0]
+            let (_, msg, _) = self.packet_receivers[0]
                 .recv()
                 .await
                 .ok_or_else(|| anyhow::Error::msg("Failed to receive packet"))?;
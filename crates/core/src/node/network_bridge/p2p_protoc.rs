@@ -31,7 +31,8 @@ use crate::node::network_bridge::handshake::{
 };
 use crate::node::PeerId;
 use crate::transport::{
-    create_connection_handler, PeerConnection, TransportError, TransportKeypair,
+    create_connection_handler, BandwidthLimits, BandwidthUsage, PeerConnection, TransportError,
+    TransportKeypair,
 };
 use crate::{
     client_events::ClientId,
@@ -42,12 +43,15 @@ use crate::{
     },
     message::{MessageStats, NetMessage, NodeEvent, Transaction},
     node::{handle_aborted_op, process_message, NetEventRegister, NodeConfig, OpManager},
-    ring::PeerKeyLocation,
+    ring::{ConnectionManager, PeerKeyLocation},
     tracing::NetEventLog,
 };
 
 type P2pBridgeEvent = Either<(PeerId, Box<NetMessage>), NodeEvent>;
 
+/// How often the bandwidth measured by the transport is reported to the topology manager.
+const BANDWIDTH_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
 #[derive(Clone)]
 pub(crate) struct P2pBridge {
     accepted_peers: Arc<DashSet<PeerId>>,
@@ -117,6 +121,7 @@ pub(in crate::node) struct P2pConnManager {
     listening_port: u16,
     is_gateway: bool,
     check_version: bool,
+    bandwidth_limits: BandwidthLimits,
 }
 
 impl P2pConnManager {
@@ -144,6 +149,10 @@ impl P2pConnManager {
             listening_port: listen_port,
             is_gateway: config.is_gateway,
             check_version: !config.config.network_api.ignore_protocol,
+            bandwidth_limits: BandwidthLimits {
+                upstream: config.config.network_api.max_upstream_bandwidth as usize,
+                downstream: config.config.network_api.max_downstream_bandwidth as usize,
+            },
         })
     }
 
@@ -162,13 +171,20 @@ impl P2pConnManager {
 
         let mut state = EventListenerState::new();
 
+        let bandwidth_usage = BandwidthUsage::default();
         let (outbound_conn_handler, inbound_conn_handler) = create_connection_handler::<UdpSocket>(
             self.key_pair.clone(),
             self.listening_ip,
             self.listening_port,
             self.is_gateway,
+            self.bandwidth_limits,
+            bandwidth_usage.clone(),
         )
         .await?;
+        GlobalExecutor::spawn(report_bandwidth_usage(
+            bandwidth_usage,
+            self.bridge.op_manager.ring.connection_manager.clone(),
+        ));
 
         let (mut handshake_handler, handshake_handler_msg, outbound_message) =
             HandshakeHandler::new(
@@ -875,8 +891,9 @@ async fn peer_connection_listener(
                 match msg {
                     Left(msg) => {
                         tracing::debug!(to=%conn.remote_addr() ,"Sending message to peer. Msg: {msg}");
+                        let class = msg.traffic_class();
                         conn
-                            .send(msg)
+                            .send_with_class(msg, class)
                             .await?;
                     }
                     Right(action) => {
@@ -908,6 +925,15 @@ async fn peer_connection_listener(
     }
 }
 
+/// Periodically reports the bandwidth measured by the transport to the topology manager.
+async fn report_bandwidth_usage(usage: BandwidthUsage, connection_manager: ConnectionManager) {
+    let mut interval = tokio::time::interval(BANDWIDTH_REPORT_INTERVAL);
+    loop {
+        interval.tick().await;
+        connection_manager.report_bandwidth_usage(usage.take(), std::time::Instant::now());
+    }
+}
+
 #[inline(always)]
 fn decode_msg(data: &[u8]) -> Result<NetMessage, ConnectionError> {
     bincode::deserialize(data).map_err(|err| ConnectionError::Serialization(Some(err)))
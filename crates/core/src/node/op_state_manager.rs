@@ -56,7 +56,7 @@ pub(crate) struct OpManager {
 }
 
 impl OpManager {
-    pub(super) fn new<ER: NetEventRegister + Clone>(
+    pub(crate) fn new<ER: NetEventRegister + Clone>(
         notification_channel: EventLoopNotificationsSender,
         ch_outbound: ContractHandlerChannel<SenderHalve>,
         config: &NodeConfig,
@@ -14,12 +14,12 @@ use crate::{
     client_events::{combinator::ClientEventsCombinator, BoxedClient},
     config::GlobalExecutor,
     contract::{
-        self, ClientResponsesSender, ContractHandler, ContractHandlerChannel,
+        self, ClientResponsesSender, ContractHandler, ContractHandlerChannel, ContractHandlerPool,
         ExecutorToEventLoopChannel, NetworkEventListenerHalve, WaitingResolution,
     },
     message::NodeEvent,
     node::NodeConfig,
-    operations::connect,
+    operations::{connect, update},
 };
 
 use super::OpManager;
@@ -91,17 +91,22 @@ impl NodeP2P {
             event_register.clone(),
             connection_manager,
         )?);
-        let (executor_listener, executor_sender) = contract::executor_channel(op_manager.clone());
-        let contract_handler = CH::build(ch_inbound, executor_sender, ch_builder)
-            .await
-            .map_err(|e| anyhow::anyhow!(e))?;
+        let (executor_listener, executor_senders) = contract::executor_channels(
+            op_manager.clone(),
+            ContractHandlerPool::<CH>::default_size(),
+        );
+        let contract_handlers =
+            ContractHandlerPool::<CH>::build(ch_inbound, executor_senders, ch_builder)
+                .await
+                .map_err(|e| anyhow::anyhow!(e))?;
 
         let conn_manager =
             P2pConnManager::build(&config, op_manager.clone(), event_register).await?;
 
         let parent_span = tracing::Span::current();
         let contract_executor_task = GlobalExecutor::spawn(
-            contract::contract_handling(contract_handler)
+            contract_handlers
+                .run()
                 .instrument(tracing::info_span!(parent: parent_span.clone(), "contract_handling")),
         )
         .map(|r| match r {
@@ -110,6 +115,10 @@ impl NodeP2P {
             Err(e) => anyhow::anyhow!(e),
         })
         .boxed();
+        GlobalExecutor::spawn(
+            update::anti_entropy(op_manager.clone())
+                .instrument(tracing::info_span!(parent: parent_span.clone(), "anti_entropy")),
+        );
         let clients = ClientEventsCombinator::new(clients);
         let (node_controller_tx, node_controller_rx) = tokio::sync::mpsc::channel(1);
         let client_events_task = GlobalExecutor::spawn(
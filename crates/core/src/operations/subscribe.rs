@@ -387,6 +387,7 @@ impl Operation for SubscribeOp {
                             provider = %sender.peer,
                             "Subscribed to contract"
                         );
+                        op_manager.ring.set_upstream(key, sender.clone());
                         if op_manager.ring.add_subscriber(key, sender.clone()).is_err() {
                             // concurrently it reached max number of subscribers for this contract
                             tracing::debug!(
@@ -1,9 +1,13 @@
 // TODO: complete update logic in the network
+use std::sync::Arc;
+use std::time::Duration;
+
 use freenet_stdlib::client_api::{ErrorKind, HostResponse};
 use freenet_stdlib::prelude::*;
+use rand::seq::IteratorRandom;
 
 use super::{OpEnum, OpError, OpInitialization, OpOutcome, Operation, OperationResult};
-use crate::contract::ContractHandlerEvent;
+use crate::contract::{ContractHandlerEvent, StoreResponse};
 use crate::message::{InnerMessage, NetMessage, Transaction};
 use crate::ring::{Location, PeerKeyLocation, RingError};
 use crate::{
@@ -11,7 +15,7 @@ use crate::{
     node::{NetworkBridge, OpManager, PeerId},
 };
 
-pub(crate) use self::messages::UpdateMsg;
+pub(crate) use self::messages::{UpdateMsg, UpdatePayload};
 
 pub(crate) struct UpdateOp {
     pub id: Transaction,
@@ -173,8 +177,13 @@ impl Operation for UpdateOp {
 
                     if is_subscribed_contract {
                         tracing::debug!("Peer is subscribed to contract. About to update it");
-                        update_contract(op_manager, *key, value.clone(), related_contracts.clone())
-                            .await?;
+                        update_contract(
+                            op_manager,
+                            *key,
+                            UpdateData::State(State::from(value.clone())),
+                            related_contracts.clone(),
+                        )
+                        .await?;
                         tracing::debug!(
                             tx = %id,
                             "Successfully updated a value for contract {} @ {:?} - update",
@@ -208,7 +217,7 @@ impl Operation for UpdateOp {
                 UpdateMsg::BroadcastTo {
                     id,
                     key,
-                    new_value,
+                    update,
                     sender,
                     target,
                 } => {
@@ -218,13 +227,44 @@ impl Operation for UpdateOp {
                     }
 
                     tracing::debug!("Attempting contract value update - BroadcastTo - update");
-                    let new_value = update_contract(
+                    let updated = update_contract(
                         op_manager,
                         *key,
-                        new_value.clone(),
+                        update.clone().into(),
                         RelatedContracts::default(),
                     )
-                    .await?;
+                    .await;
+                    let new_value = match updated {
+                        Ok(new_value) => new_value,
+                        Err(err) if matches!(update, UpdatePayload::Delta(_)) => {
+                            // the delta was computed from a state this peer doesn't have
+                            tracing::debug!(tx = %id, %key, %err, "Failed to apply delta, requesting the whole state - BroadcastTo - update");
+                            acknowledge_update(op_manager, conn_manager, *key, sender, None)
+                                .await?;
+                            return build_op_result(self.id, None, None, stats);
+                        }
+                        Err(err) => return Err(err),
+                    };
+                    match summarize_contract(op_manager, *key).await {
+                        Ok(summary) => {
+                            acknowledge_update(
+                                op_manager,
+                                conn_manager,
+                                *key,
+                                sender,
+                                Some(summary),
+                            )
+                            .await?
+                        }
+                        Err(err) => {
+                            tracing::debug!(tx = %id, %key, %err, "Failed to summarize contract state, not acknowledging update - BroadcastTo - update");
+                        }
+                    }
+                    let Some(new_value) = new_value else {
+                        // the change was already received, i.e. from another peer
+                        tracing::debug!(tx = %id, %key, "No changes in state, stopping broadcast - BroadcastTo - update");
+                        return build_op_result(self.id, None, None, stats);
+                    };
                     tracing::debug!("Contract successfully updated - BroadcastTo - update");
 
                     let broadcast_to = op_manager.get_broadcast_targets_update(key, &sender.peer);
@@ -265,43 +305,39 @@ impl Operation for UpdateOp {
                     let sender = op_manager.ring.connection_manager.own_location();
                     let mut broadcasted_to = *broadcasted_to;
 
-                    let mut broadcasting = Vec::with_capacity(broadcast_to.len());
-
+                    let mut updates = Vec::with_capacity(broadcast_to.len());
                     for peer in broadcast_to.iter() {
+                        updates.push(
+                            update_for_subscriber(op_manager, *key, &peer.peer, new_value).await,
+                        );
+                    }
+
+                    let mut broadcasting = Vec::with_capacity(broadcast_to.len());
+                    for (peer, update) in broadcast_to.iter().zip(updates) {
                         let msg = UpdateMsg::BroadcastTo {
                             id: *id,
                             key: *key,
-                            new_value: new_value.clone(),
+                            update,
                             sender: sender.clone(),
                             target: peer.clone(),
                         };
                         let f = conn_manager.send(&peer.peer, msg.into());
                         broadcasting.push(f);
                     }
-                    let error_futures = futures::future::join_all(broadcasting)
-                        .await
-                        .into_iter()
-                        .enumerate()
-                        .filter_map(|(p, err)| {
-                            if let Err(err) = err {
-                                Some((p, err))
-                            } else {
-                                None
-                            }
-                        });
+                    let results = futures::future::join_all(broadcasting).await;
 
                     let mut incorrect_results = 0;
-                    for (peer_num, err) in error_futures {
-                        // remove the failed peers in reverse order
-                        let peer = broadcast_to.get(peer_num).unwrap();
-                        tracing::warn!(
-                            "failed broadcasting update change to {} with error {}; dropping connection",
-                            peer.peer,
-                            err
-                        );
-                        // TODO: review this, maybe we should just dropping this subscription
-                        conn_manager.drop_connection(&peer.peer).await?;
-                        incorrect_results += 1;
+                    for (peer, result) in broadcast_to.iter().zip(results) {
+                        if let Err(err) = result {
+                            tracing::warn!(
+                                "failed broadcasting update change to {} with error {}; dropping connection",
+                                peer.peer,
+                                err
+                            );
+                            // TODO: review this, maybe we should just dropping this subscription
+                            conn_manager.drop_connection(&peer.peer).await?;
+                            incorrect_results += 1;
+                        }
                     }
 
                     broadcasted_to += broadcast_to.len() - incorrect_results;
@@ -357,6 +393,152 @@ impl Operation for UpdateOp {
                         }
                     };
                 }
+                UpdateMsg::BroadcastAck {
+                    id,
+                    key,
+                    sender,
+                    summary,
+                    ..
+                } => {
+                    if let Some(summary) = summary {
+                        // following changes are sent to the subscriber as deltas from this state
+                        op_manager.ring.update_subscriber_summary(
+                            key,
+                            &sender.peer,
+                            summary.clone(),
+                        );
+                        return_msg = None;
+                    } else {
+                        tracing::debug!(tx = %id, %key, peer = %sender.peer, "Subscriber failed to apply delta, sending the whole state");
+                        op_manager.ring.forget_subscriber_summary(key, &sender.peer);
+                        return_msg = Some(UpdateMsg::BroadcastTo {
+                            id: *id,
+                            key: *key,
+                            update: UpdatePayload::State(contract_state(op_manager, *key).await?),
+                            sender: op_manager.ring.connection_manager.own_location(),
+                            target: sender.clone(),
+                        });
+                    }
+                    new_state = None;
+                }
+                UpdateMsg::RequestSync { id, key, target } => {
+                    let Some(UpdateState::AwaitingSync { .. }) = self.state else {
+                        return Err(OpError::invalid_transition(self.id));
+                    };
+                    let summary = summarize_contract(op_manager, *key).await?;
+                    tracing::debug!(tx = %id, %key, target = %target.peer, "Starting anti-entropy round");
+                    return_msg = Some(UpdateMsg::SyncSummary {
+                        id: *id,
+                        key: *key,
+                        sender: op_manager.ring.connection_manager.own_location(),
+                        target: target.clone(),
+                        summary,
+                    });
+                    new_state = self.state;
+                }
+                UpdateMsg::SyncSummary {
+                    id,
+                    key,
+                    sender,
+                    summary,
+                    ..
+                } => {
+                    if !op_manager.ring.is_seeding_contract(key) {
+                        tracing::debug!(tx = %id, %key, "Not seeding contract, ignoring anti-entropy round");
+                        return Err(OpError::RingError(RingError::NoCachingPeers(*key)));
+                    }
+                    op_manager
+                        .ring
+                        .update_subscriber_summary(key, &sender.peer, summary.clone());
+                    let own_summary = summarize_contract(op_manager, *key).await?;
+                    if own_summary.as_ref() == summary.as_ref() {
+                        tracing::debug!(tx = %id, %key, peer = %sender.peer, "State already in sync");
+                        return_msg = Some(UpdateMsg::InSync {
+                            id: *id,
+                            target: sender.clone(),
+                        });
+                        new_state = None;
+                    } else {
+                        let delta = contract_state_delta(op_manager, *key, summary.clone()).await?;
+                        return_msg = Some(UpdateMsg::SyncResponse {
+                            id: *id,
+                            key: *key,
+                            sender: op_manager.ring.connection_manager.own_location(),
+                            target: sender.clone(),
+                            delta,
+                            summary: own_summary,
+                        });
+                        new_state = Some(UpdateState::SyncOngoing { key: *key });
+                    }
+                }
+                UpdateMsg::SyncResponse {
+                    id,
+                    key,
+                    sender,
+                    delta,
+                    summary,
+                    ..
+                } => {
+                    let Some(UpdateState::AwaitingSync { .. }) = self.state else {
+                        return Err(OpError::invalid_transition(self.id));
+                    };
+                    op_manager
+                        .ring
+                        .update_subscriber_summary(key, &sender.peer, summary.clone());
+                    if let Some(new_value) = merge_delta(op_manager, *key, delta.clone()).await? {
+                        broadcast_merged_state(
+                            op_manager,
+                            conn_manager,
+                            *key,
+                            &new_value,
+                            &sender.peer,
+                        )
+                        .await;
+                    }
+                    // the delta for the peer is computed once its changes are merged, so the
+                    // state of both peers is the same once it merges the delta back
+                    let delta = contract_state_delta(op_manager, *key, summary.clone()).await?;
+                    tracing::debug!(tx = %id, %key, peer = %sender.peer, "Merged state delta from peer");
+                    return_msg = Some(UpdateMsg::SyncDelta {
+                        id: *id,
+                        key: *key,
+                        sender: op_manager.ring.connection_manager.own_location(),
+                        target: sender.clone(),
+                        delta,
+                    });
+                    new_state = None;
+                }
+                UpdateMsg::SyncDelta {
+                    id,
+                    key,
+                    sender,
+                    delta,
+                    ..
+                } => {
+                    let Some(UpdateState::SyncOngoing { .. }) = self.state else {
+                        return Err(OpError::invalid_transition(self.id));
+                    };
+                    if let Some(new_value) = merge_delta(op_manager, *key, delta.clone()).await? {
+                        broadcast_merged_state(
+                            op_manager,
+                            conn_manager,
+                            *key,
+                            &new_value,
+                            &sender.peer,
+                        )
+                        .await;
+                    }
+                    tracing::debug!(tx = %id, %key, peer = %sender.peer, "Merged state delta from peer");
+                    return_msg = None;
+                    new_state = None;
+                }
+                UpdateMsg::InSync { .. } => {
+                    let Some(UpdateState::AwaitingSync { .. }) = self.state else {
+                        return Err(OpError::invalid_transition(self.id));
+                    };
+                    return_msg = None;
+                    new_state = None;
+                }
                 _ => return Err(OpError::UnexpectedOpState),
             }
 
@@ -484,13 +666,13 @@ fn build_op_result(
     })
 }
 
+/// Applies the update to the local state, returns the new state or `None` if it didn't change.
 async fn update_contract(
     op_manager: &OpManager,
     key: ContractKey,
-    state: WrappedState,
+    update_data: UpdateData<'static>,
     related_contracts: RelatedContracts<'static>,
-) -> Result<WrappedState, OpError> {
-    let update_data = UpdateData::State(State::from(state));
+) -> Result<Option<WrappedState>, OpError> {
     match op_manager
         .notify_contract_handler(ContractHandlerEvent::UpdateQuery {
             key,
@@ -501,7 +683,8 @@ async fn update_contract(
     {
         Ok(ContractHandlerEvent::UpdateResponse {
             new_value: Ok(new_val),
-        }) => Ok(new_val),
+        }) => Ok(Some(new_val)),
+        Ok(ContractHandlerEvent::UpdateNoChange { .. }) => Ok(None),
         Ok(ContractHandlerEvent::UpdateResponse {
             new_value: Err(_rr),
         }) => {
@@ -513,6 +696,233 @@ async fn update_contract(
     }
 }
 
+/// The update to send to a subscriber: the delta from its last known state if smaller than
+/// the whole state, otherwise the whole state.
+async fn update_for_subscriber(
+    op_manager: &OpManager,
+    key: ContractKey,
+    subscriber: &PeerId,
+    new_value: &WrappedState,
+) -> UpdatePayload {
+    let Some(summary) = op_manager.ring.subscriber_summary(&key, subscriber) else {
+        return UpdatePayload::State(new_value.clone());
+    };
+    match contract_state_delta(op_manager, key, summary).await {
+        Ok(delta) if delta.as_ref().len() < new_value.as_ref().len() => UpdatePayload::Delta(delta),
+        Ok(_) => UpdatePayload::State(new_value.clone()),
+        Err(err) => {
+            tracing::debug!(%key, %subscriber, %err, "Failed to compute delta, sending the whole state");
+            UpdatePayload::State(new_value.clone())
+        }
+    }
+}
+
+/// Lets the peer which broadcasted an update know the summary of the resulting state, so it
+/// sends the following changes as deltas from it, or that the update couldn't be applied.
+///
+/// The broadcast transaction is already completed at the sender, so the acknowledgement is
+/// sent with a new one.
+async fn acknowledge_update<NB: NetworkBridge>(
+    op_manager: &OpManager,
+    conn_manager: &mut NB,
+    key: ContractKey,
+    upstream: &PeerKeyLocation,
+    summary: Option<StateSummary<'static>>,
+) -> Result<(), OpError> {
+    let msg = UpdateMsg::BroadcastAck {
+        id: Transaction::new::<UpdateMsg>(),
+        key,
+        sender: op_manager.ring.connection_manager.own_location(),
+        target: upstream.clone(),
+        summary,
+    };
+    conn_manager.send(&upstream.peer, msg.into()).await?;
+    Ok(())
+}
+
+async fn contract_state(op_manager: &OpManager, key: ContractKey) -> Result<WrappedState, OpError> {
+    match op_manager
+        .notify_contract_handler(ContractHandlerEvent::GetQuery {
+            key,
+            return_contract_code: false,
+        })
+        .await
+    {
+        Ok(ContractHandlerEvent::GetResponse {
+            response: Ok(StoreResponse {
+                state: Some(state), ..
+            }),
+            ..
+        }) => Ok(state),
+        Ok(ContractHandlerEvent::GetResponse { .. }) => {
+            tracing::error!(%key, "Failed to get contract state");
+            Err(OpError::UnexpectedOpState)
+        }
+        Err(err) => Err(err.into()),
+        Ok(_) => Err(OpError::UnexpectedOpState),
+    }
+}
+
+/// Applies a delta computed by a peer seeding the contract to the local state, which the
+/// contract merges with it. Returns the new state or `None` if it didn't change.
+async fn merge_delta(
+    op_manager: &OpManager,
+    key: ContractKey,
+    delta: StateDelta<'static>,
+) -> Result<Option<WrappedState>, OpError> {
+    match op_manager
+        .notify_contract_handler(ContractHandlerEvent::UpdateQuery {
+            key,
+            data: UpdateData::Delta(delta),
+            related_contracts: RelatedContracts::default(),
+        })
+        .await
+    {
+        Ok(ContractHandlerEvent::UpdateResponse {
+            new_value: Ok(new_value),
+        }) => Ok(Some(new_value)),
+        Ok(ContractHandlerEvent::UpdateNoChange { .. }) => Ok(None),
+        Ok(ContractHandlerEvent::UpdateResponse {
+            new_value: Err(err),
+        }) => {
+            tracing::error!(%key, %err, "Failed to merge state delta");
+            Err(OpError::UnexpectedOpState)
+        }
+        Err(err) => Err(err.into()),
+        Ok(_) => Err(OpError::UnexpectedOpState),
+    }
+}
+
+/// Broadcasts the state resulting from merging the changes of the anti-entropy partner to the
+/// subscribers of the contract, but the partner, which already has them.
+///
+/// The anti-entropy round is completed independently, so the broadcast is sent with a new
+/// transaction.
+async fn broadcast_merged_state<NB: NetworkBridge>(
+    op_manager: &OpManager,
+    conn_manager: &mut NB,
+    key: ContractKey,
+    new_value: &WrappedState,
+    partner: &PeerId,
+) {
+    let id = Transaction::new::<UpdateMsg>();
+    let sender = op_manager.ring.connection_manager.own_location();
+    for peer in op_manager.get_broadcast_targets_update(&key, partner) {
+        let update = update_for_subscriber(op_manager, key, &peer.peer, new_value).await;
+        let msg = UpdateMsg::BroadcastTo {
+            id,
+            key,
+            update,
+            sender: sender.clone(),
+            target: peer.clone(),
+        };
+        if let Err(err) = conn_manager.send(&peer.peer, msg.into()).await {
+            tracing::debug!(tx = %id, %key, peer = %peer.peer, %err, "Failed to broadcast merged state");
+        }
+    }
+}
+
+async fn summarize_contract(
+    op_manager: &OpManager,
+    key: ContractKey,
+) -> Result<StateSummary<'static>, OpError> {
+    match op_manager
+        .notify_contract_handler(ContractHandlerEvent::SummarizeQuery { key })
+        .await
+    {
+        Ok(ContractHandlerEvent::SummarizeResponse {
+            summary: Ok(summary),
+            ..
+        }) => Ok(summary),
+        Ok(ContractHandlerEvent::SummarizeResponse {
+            summary: Err(err), ..
+        }) => {
+            tracing::error!(%key, %err, "Failed to summarize contract state");
+            Err(OpError::UnexpectedOpState)
+        }
+        Err(err) => Err(err.into()),
+        Ok(_) => Err(OpError::UnexpectedOpState),
+    }
+}
+
+async fn contract_state_delta(
+    op_manager: &OpManager,
+    key: ContractKey,
+    summary: StateSummary<'static>,
+) -> Result<StateDelta<'static>, OpError> {
+    match op_manager
+        .notify_contract_handler(ContractHandlerEvent::DeltaQuery { key, summary })
+        .await
+    {
+        Ok(ContractHandlerEvent::DeltaResponse {
+            delta: Ok(delta), ..
+        }) => Ok(delta),
+        Ok(ContractHandlerEvent::DeltaResponse {
+            delta: Err(err), ..
+        }) => {
+            tracing::error!(%key, %err, "Failed to compute contract state delta");
+            Err(OpError::UnexpectedOpState)
+        }
+        Err(err) => Err(err.into()),
+        Ok(_) => Err(OpError::UnexpectedOpState),
+    }
+}
+
+/// Interval between anti-entropy rounds.
+const ANTI_ENTROPY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Periodically reconciles the state of every contract this peer is seeding with another,
+/// randomly chosen, peer seeding it.
+///
+/// In each round both peers exchange the summaries of their states and merge the delta
+/// computed by the other one, so all the seeders of a contract converge even if they missed
+/// broadcasted updates or were partitioned. Deltas are merged through `update_state`, so the
+/// contract decides how concurrent changes are combined, and the changes merged in a round
+/// are broadcasted to the subscribers of the contract.
+pub(crate) async fn anti_entropy(op_manager: Arc<OpManager>) {
+    let mut interval = tokio::time::interval(ANTI_ENTROPY_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let own_peer = op_manager.ring.connection_manager.get_peer_key();
+        for key in op_manager.ring.seeding_contracts() {
+            let Some(target) = op_manager
+                .ring
+                .seeding_peers_of(&key)
+                .into_iter()
+                .filter(|pk| Some(&pk.peer) != own_peer.as_ref())
+                .choose(&mut rand::thread_rng())
+            else {
+                continue;
+            };
+            if let Err(err) = request_sync(&op_manager, key, target).await {
+                tracing::debug!(%key, %err, "Failed to start anti-entropy round");
+            }
+        }
+    }
+}
+
+/// Starts an anti-entropy round for the contract with the target peer.
+pub(crate) async fn request_sync(
+    op_manager: &OpManager,
+    key: ContractKey,
+    target: PeerKeyLocation,
+) -> Result<(), OpError> {
+    let id = Transaction::new::<UpdateMsg>();
+    let op = UpdateOp {
+        id,
+        state: Some(UpdateState::AwaitingSync { key }),
+        stats: Some(UpdateStats {
+            target: Some(target.clone()),
+        }),
+    };
+    let msg = UpdateMsg::RequestSync { id, key, target };
+    op_manager
+        .notify_op_change(NetMessage::from(msg), OpEnum::Update(op))
+        .await
+}
+
 /// This will be called from the node when processing an open request
 // todo: new_state should be a delta when possible!
 pub(crate) fn start_op(
@@ -617,8 +1027,10 @@ pub(crate) async fn request_update(
 mod messages {
     use std::{borrow::Borrow, fmt::Display};
 
-    use freenet_stdlib::prelude::{ContractKey, RelatedContracts, StateSummary, WrappedState};
-    use serde::{Deserialize, Serialize};
+    use freenet_stdlib::prelude::{
+        ContractKey, RelatedContracts, State, StateDelta, StateSummary, UpdateData, WrappedState,
+    };
+    use serde::{Deserialize, Deserializer, Serialize};
 
     use crate::{
         message::{InnerMessage, Transaction},
@@ -669,9 +1081,78 @@ mod messages {
             id: Transaction,
             sender: PeerKeyLocation,
             key: ContractKey,
-            new_value: WrappedState,
+            update: UpdatePayload,
+            target: PeerKeyLocation,
+        },
+        /// Internal node instruction to start an anti-entropy round with a peer seeding the contract.
+        RequestSync {
+            id: Transaction,
+            key: ContractKey,
+            target: PeerKeyLocation,
+        },
+        /// Summary of the state of the peer starting an anti-entropy round.
+        SyncSummary {
+            id: Transaction,
+            key: ContractKey,
+            sender: PeerKeyLocation,
+            target: PeerKeyLocation,
+            #[serde(deserialize_with = "StateSummary::deser_state_summary")]
+            summary: StateSummary<'static>,
+        },
+        /// Changes missing from the state of the peer which started the round, along with the
+        /// summary of the state of the responding peer.
+        SyncResponse {
+            id: Transaction,
+            key: ContractKey,
+            sender: PeerKeyLocation,
+            target: PeerKeyLocation,
+            #[serde(deserialize_with = "StateDelta::deser_state_delta")]
+            delta: StateDelta<'static>,
+            #[serde(deserialize_with = "StateSummary::deser_state_summary")]
+            summary: StateSummary<'static>,
+        },
+        /// Changes missing from the state of the responding peer, completing the round.
+        SyncDelta {
+            id: Transaction,
+            key: ContractKey,
+            sender: PeerKeyLocation,
+            target: PeerKeyLocation,
+            #[serde(deserialize_with = "StateDelta::deser_state_delta")]
+            delta: StateDelta<'static>,
+        },
+        /// Both peers already have the same state, completing the round.
+        InSync {
+            id: Transaction,
             target: PeerKeyLocation,
         },
+        /// Acknowledges a change broadcasted to a subscriber, with the summary of its resulting
+        /// state, or without it if the change couldn't be applied.
+        BroadcastAck {
+            id: Transaction,
+            key: ContractKey,
+            sender: PeerKeyLocation,
+            target: PeerKeyLocation,
+            #[serde(deserialize_with = "deser_optional_summary")]
+            summary: Option<StateSummary<'static>>,
+        },
+    }
+
+    /// A state change sent to a subscriber.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub(crate) enum UpdatePayload {
+        /// The whole new state, sent if the state summary of the subscriber is unknown.
+        State(WrappedState),
+        /// The changes missing from the last known state of the subscriber.
+        Delta(#[serde(deserialize_with = "StateDelta::deser_state_delta")] StateDelta<'static>),
+    }
+
+    impl From<UpdatePayload> for UpdateData<'static> {
+        fn from(payload: UpdatePayload) -> Self {
+            match payload {
+                UpdatePayload::State(state) => UpdateData::State(State::from(state)),
+                UpdatePayload::Delta(delta) => UpdateData::Delta(delta),
+            }
+        }
     }
 
     impl InnerMessage for UpdateMsg {
@@ -683,6 +1164,12 @@ mod messages {
                 UpdateMsg::SeekNode { id, .. } => id,
                 UpdateMsg::Broadcasting { id, .. } => id,
                 UpdateMsg::BroadcastTo { id, .. } => id,
+                UpdateMsg::RequestSync { id, .. } => id,
+                UpdateMsg::SyncSummary { id, .. } => id,
+                UpdateMsg::SyncResponse { id, .. } => id,
+                UpdateMsg::SyncDelta { id, .. } => id,
+                UpdateMsg::InSync { id, .. } => id,
+                UpdateMsg::BroadcastAck { id, .. } => id,
             }
         }
 
@@ -692,6 +1179,12 @@ mod messages {
                 UpdateMsg::SuccessfulUpdate { target, .. } => Some(target),
                 UpdateMsg::SeekNode { target, .. } => Some(target),
                 UpdateMsg::BroadcastTo { target, .. } => Some(target),
+                UpdateMsg::RequestSync { target, .. } => Some(target),
+                UpdateMsg::SyncSummary { target, .. } => Some(target),
+                UpdateMsg::SyncResponse { target, .. } => Some(target),
+                UpdateMsg::SyncDelta { target, .. } => Some(target),
+                UpdateMsg::InSync { target, .. } => Some(target),
+                UpdateMsg::BroadcastAck { target, .. } => Some(target),
                 _ => None,
             }
         }
@@ -702,6 +1195,10 @@ mod messages {
                 UpdateMsg::SeekNode { key, .. } => Some(Location::from(key.id())),
                 UpdateMsg::Broadcasting { key, .. } => Some(Location::from(key.id())),
                 UpdateMsg::BroadcastTo { key, .. } => Some(Location::from(key.id())),
+                UpdateMsg::SyncSummary { key, .. } => Some(Location::from(key.id())),
+                UpdateMsg::SyncResponse { key, .. } => Some(Location::from(key.id())),
+                UpdateMsg::SyncDelta { key, .. } => Some(Location::from(key.id())),
+                UpdateMsg::BroadcastAck { key, .. } => Some(Location::from(key.id())),
                 _ => None,
             }
         }
@@ -712,11 +1209,23 @@ mod messages {
             match self {
                 Self::SeekNode { sender, .. } => Some(sender),
                 Self::BroadcastTo { sender, .. } => Some(sender),
+                Self::SyncSummary { sender, .. } => Some(sender),
+                Self::SyncResponse { sender, .. } => Some(sender),
+                Self::SyncDelta { sender, .. } => Some(sender),
+                Self::BroadcastAck { sender, .. } => Some(sender),
                 _ => None,
             }
         }
     }
 
+    fn deser_optional_summary<'de, D>(deser: D) -> Result<Option<StateSummary<'static>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let summary: Option<StateSummary<'de>> = Deserialize::deserialize(deser)?;
+        Ok(summary.map(StateSummary::into_owned))
+    }
+
     impl Display for UpdateMsg {
         fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
             match self {
@@ -726,6 +1235,12 @@ mod messages {
                 UpdateMsg::SeekNode { id, .. } => write!(f, "SeekNode(id: {id})"),
                 UpdateMsg::Broadcasting { id, .. } => write!(f, "Broadcasting(id: {id})"),
                 UpdateMsg::BroadcastTo { id, .. } => write!(f, "BroadcastTo(id: {id})"),
+                UpdateMsg::RequestSync { id, .. } => write!(f, "RequestSync(id: {id})"),
+                UpdateMsg::SyncSummary { id, .. } => write!(f, "SyncSummary(id: {id})"),
+                UpdateMsg::SyncResponse { id, .. } => write!(f, "SyncResponse(id: {id})"),
+                UpdateMsg::SyncDelta { id, .. } => write!(f, "SyncDelta(id: {id})"),
+                UpdateMsg::InSync { id, .. } => write!(f, "InSync(id: {id})"),
+                UpdateMsg::BroadcastAck { id, .. } => write!(f, "BroadcastAck(id: {id})"),
             }
         }
     }
@@ -748,4 +1263,261 @@ pub enum UpdateState {
         value: WrappedState,
     },
     BroadcastOngoing,
+    /// Started an anti-entropy round, waiting for the changes missing from the local state.
+    AwaitingSync {
+        key: ContractKey,
+    },
+    /// Sent the changes missing from the state of the peer which started an anti-entropy round,
+    /// waiting for the changes missing from the local state.
+    SyncOngoing {
+        key: ContractKey,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, net::Ipv6Addr};
+
+    use either::Either;
+
+    use super::*;
+    use crate::{
+        config::ConfigArgs,
+        contract::contract_handler_channel,
+        message::NetMessageV1,
+        node::{
+            event_loop_notification_channel, ConnectionError, EventLoopNotificationsReceiver,
+            NodeConfig,
+        },
+        operations::handle_op_request,
+        ring::ConnectionManager,
+        tracing::TestEventListener,
+        transport::TransportKeypair,
+    };
+
+    /// Records the messages sent by a peer instead of delivering them.
+    #[derive(Clone, Default)]
+    struct SentMessages(Arc<parking_lot::Mutex<Vec<(PeerId, NetMessage)>>>);
+
+    impl SentMessages {
+        fn take(&self) -> Vec<(PeerId, UpdateMsg)> {
+            std::mem::take(&mut *self.0.lock())
+                .into_iter()
+                .map(|(target, msg)| match msg {
+                    NetMessage::V1(NetMessageV1::Update(msg)) => (target, msg),
+                    other => panic!("unexpected message: {other:?}"),
+                })
+                .collect()
+        }
+    }
+
+    impl NetworkBridge for SentMessages {
+        async fn drop_connection(&mut self, _peer: &PeerId) -> Result<(), ConnectionError> {
+            Ok(())
+        }
+
+        async fn send(&self, target: &PeerId, msg: NetMessage) -> Result<(), ConnectionError> {
+            self.0.lock().push((target.clone(), msg));
+            Ok(())
+        }
+    }
+
+    /// A peer seeding a contract whose state is a set of bytes, merged by union.
+    struct TestPeer {
+        op_manager: Arc<OpManager>,
+        events: EventLoopNotificationsReceiver,
+        sent: SentMessages,
+        state: Arc<parking_lot::Mutex<BTreeSet<u8>>>,
+    }
+
+    impl TestPeer {
+        async fn new(name: &str, key: ContractKey, state: &[u8]) -> anyhow::Result<Self> {
+            let mut config_args = ConfigArgs::default();
+            config_args.id = Some(name.to_owned());
+            let mut config = NodeConfig::new(config_args.build().await?).await?;
+            let keypair = TransportKeypair::new();
+            let port = crate::util::get_free_port().unwrap();
+            config.with_peer_id(PeerId::new(
+                (Ipv6Addr::LOCALHOST, port).into(),
+                keypair.public().clone(),
+            ));
+            config.key_pair = keypair;
+            config.with_location(Location::random());
+
+            let (events, notification_tx) = event_loop_notification_channel();
+            let (ch_outbound, mut ch_inbound, _) = contract_handler_channel();
+            let connection_manager = ConnectionManager::new(&config);
+            let op_manager = Arc::new(OpManager::new(
+                notification_tx,
+                ch_outbound,
+                &config,
+                TestEventListener::new().await,
+                connection_manager,
+            )?);
+            op_manager.ring.seed_contract(key);
+
+            let state = Arc::new(parking_lot::Mutex::new(
+                state.iter().copied().collect::<BTreeSet<_>>(),
+            ));
+            let contract_state = state.clone();
+            tokio::spawn(async move {
+                while let Ok((id, ev)) = ch_inbound.recv_from_sender().await {
+                    let response = {
+                        let mut state = contract_state.lock();
+                        match ev {
+                            ContractHandlerEvent::SummarizeQuery { key } => {
+                                ContractHandlerEvent::SummarizeResponse {
+                                    key,
+                                    summary: Ok(StateSummary::from(
+                                        state.iter().copied().collect::<Vec<_>>(),
+                                    )),
+                                }
+                            }
+                            ContractHandlerEvent::DeltaQuery { key, summary } => {
+                                let missing = state
+                                    .iter()
+                                    .copied()
+                                    .filter(|b| !summary.as_ref().contains(b))
+                                    .collect::<Vec<_>>();
+                                ContractHandlerEvent::DeltaResponse {
+                                    key,
+                                    delta: Ok(StateDelta::from(missing)),
+                                }
+                            }
+                            ContractHandlerEvent::UpdateQuery {
+                                key,
+                                data: UpdateData::Delta(delta),
+                                ..
+                            } => {
+                                let before = state.len();
+                                state.extend(delta.as_ref().iter().copied());
+                                if state.len() == before {
+                                    ContractHandlerEvent::UpdateNoChange { key }
+                                } else {
+                                    ContractHandlerEvent::UpdateResponse {
+                                        new_value: Ok(WrappedState::new(
+                                            state.iter().copied().collect(),
+                                        )),
+                                    }
+                                }
+                            }
+                            other => panic!("unexpected contract handler event: {other}"),
+                        }
+                    };
+                    if ch_inbound.send_to_sender(id, response).await.is_err() {
+                        break;
+                    }
+                }
+            });
+
+            Ok(Self {
+                op_manager,
+                events,
+                sent: SentMessages::default(),
+                state,
+            })
+        }
+
+        fn location(&self) -> PeerKeyLocation {
+            self.op_manager.ring.connection_manager.own_location()
+        }
+
+        fn state(&self) -> Vec<u8> {
+            self.state.lock().iter().copied().collect()
+        }
+
+        /// The next update message this peer notified to its own event loop.
+        async fn next_notified(&mut self) -> UpdateMsg {
+            loop {
+                let event = tokio::time::timeout(Duration::from_secs(1), self.events.recv())
+                    .await
+                    .expect("message not notified")
+                    .expect("event loop channel closed");
+                if let Either::Left(NetMessage::V1(NetMessageV1::Update(msg))) = event {
+                    return msg;
+                }
+            }
+        }
+
+        async fn handle(&mut self, msg: UpdateMsg) {
+            handle_op_request::<UpdateOp, _>(&self.op_manager, &mut self.sent, &msg)
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn anti_entropy_exchange() -> anyhow::Result<()> {
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let mut leaf = TestPeer::new("anti-entropy-leaf", key, &[1, 2]).await?;
+        let mut upstream = TestPeer::new("anti-entropy-upstream", key, &[2, 3]).await?;
+        let subscriber = PeerKeyLocation::random();
+
+        // the leaf seeder only knows the peer it subscribed through
+        leaf.op_manager.ring.set_upstream(&key, upstream.location());
+        upstream
+            .op_manager
+            .ring
+            .add_subscriber(&key, leaf.location())
+            .unwrap();
+        upstream
+            .op_manager
+            .ring
+            .add_subscriber(&key, subscriber.clone())
+            .unwrap();
+        let partners = leaf.op_manager.ring.seeding_peers_of(&key);
+        assert_eq!(partners, vec![upstream.location()]);
+
+        request_sync(&leaf.op_manager, key, partners[0].clone()).await?;
+        let request = leaf.next_notified().await;
+        assert!(matches!(request, UpdateMsg::RequestSync { .. }));
+        leaf.handle(request).await;
+
+        let [(target, summary)] = <[_; 1]>::try_from(leaf.sent.take()).unwrap();
+        assert_eq!(target, upstream.location().peer);
+        assert!(matches!(summary, UpdateMsg::SyncSummary { .. }));
+        upstream.handle(summary).await;
+
+        let [(target, response)] = <[_; 1]>::try_from(upstream.sent.take()).unwrap();
+        assert_eq!(target, leaf.location().peer);
+        let UpdateMsg::SyncResponse { delta, .. } = &response else {
+            panic!("expected a sync response, got {response}");
+        };
+        assert_eq!(delta.as_ref(), &[3]);
+        leaf.handle(response).await;
+        assert_eq!(leaf.state(), vec![1, 2, 3]);
+
+        // the leaf has no other subscribers to broadcast the merged changes to
+        let [(target, sync_delta)] = <[_; 1]>::try_from(leaf.sent.take()).unwrap();
+        assert_eq!(target, upstream.location().peer);
+        let UpdateMsg::SyncDelta { delta, .. } = &sync_delta else {
+            panic!("expected a sync delta, got {sync_delta}");
+        };
+        assert_eq!(delta.as_ref(), &[1]);
+        upstream.handle(sync_delta).await;
+        assert_eq!(upstream.state(), vec![1, 2, 3]);
+
+        // the merged changes are broadcasted to the other subscribers
+        let [(target, broadcast)] = <[_; 1]>::try_from(upstream.sent.take()).unwrap();
+        assert_eq!(target, subscriber.peer);
+        let UpdateMsg::BroadcastTo { update, .. } = &broadcast else {
+            panic!("expected a broadcast, got {broadcast}");
+        };
+        assert!(matches!(update, UpdatePayload::State(state) if state.as_ref() == [1, 2, 3]));
+
+        // once both states converge the next round finishes right away
+        request_sync(&leaf.op_manager, key, upstream.location()).await?;
+        let request = leaf.next_notified().await;
+        leaf.handle(request).await;
+        let [(_, summary)] = <[_; 1]>::try_from(leaf.sent.take()).unwrap();
+        upstream.handle(summary).await;
+        let [(target, in_sync)] = <[_; 1]>::try_from(upstream.sent.take()).unwrap();
+        assert_eq!(target, leaf.location().peer);
+        assert!(matches!(in_sync, UpdateMsg::InSync { .. }));
+        leaf.handle(in_sync).await;
+        assert!(leaf.sent.take().is_empty());
+        assert_eq!(leaf.state(), upstream.state());
+
+        Ok(())
+    }
 }
@@ -19,7 +19,7 @@ use tracing::Instrument;
 
 use dashmap::mapref::one::Ref as DmRef;
 use either::Either;
-use freenet_stdlib::prelude::ContractKey;
+use freenet_stdlib::prelude::{ContractKey, StateSummary};
 use itertools::Itertools;
 use parking_lot::RwLock;
 use rand::Rng;
@@ -86,9 +86,9 @@ impl Ring {
 
     const DEFAULT_MAX_CONNECTIONS: usize = 200;
 
-    const DEFAULT_MAX_UPSTREAM_BANDWIDTH: Rate = Rate::new_per_second(1_000_000.0);
-
-    const DEFAULT_MAX_DOWNSTREAM_BANDWIDTH: Rate = Rate::new_per_second(1_000_000.0);
+    /// The topology manager aims for a bandwidth usage this much below the caps enforced by
+    /// the transport, leaving room for bursts before packets start being delayed.
+    const BANDWIDTH_HEADROOM: f64 = 1.5;
 
     /// Above this number of remaining hops, randomize which node a message which be forwarded to.
     const DEFAULT_RAND_WALK_ABOVE_HTL: usize = 7;
@@ -203,6 +203,11 @@ impl Ring {
         self.seeding_manager.is_seeding_contract(key)
     }
 
+    /// Contracts this peer is seeding.
+    pub fn seeding_contracts(&self) -> Vec<ContractKey> {
+        self.seeding_manager.seeding_contracts()
+    }
+
     pub fn record_request(
         &self,
         recipient: PeerKeyLocation,
@@ -282,6 +287,42 @@ impl Ring {
         self.seeding_manager.subscribers_of(contract)
     }
 
+    /// Records the peer through which this peer subscribed to a contract.
+    pub fn set_upstream(&self, contract: &ContractKey, upstream: PeerKeyLocation) {
+        self.seeding_manager.set_upstream(contract, upstream)
+    }
+
+    /// All the peers known to be seeding a contract: its subscribers and the upstream peer.
+    pub fn seeding_peers_of(&self, contract: &ContractKey) -> Vec<PeerKeyLocation> {
+        self.seeding_manager.seeding_peers_of(contract)
+    }
+
+    /// Last known summary of the state of a subscriber.
+    pub fn subscriber_summary(
+        &self,
+        contract: &ContractKey,
+        peer: &PeerId,
+    ) -> Option<StateSummary<'static>> {
+        self.seeding_manager.subscriber_summary(contract, peer)
+    }
+
+    /// Forgets the summary of the state of a subscriber, so it receives the whole state next.
+    pub fn forget_subscriber_summary(&self, contract: &ContractKey, peer: &PeerId) {
+        self.seeding_manager
+            .forget_subscriber_summary(contract, peer)
+    }
+
+    /// Records the summary of the state of a subscriber, ignored if the peer is not subscribed.
+    pub fn update_subscriber_summary(
+        &self,
+        contract: &ContractKey,
+        peer: &PeerId,
+        summary: StateSummary<'static>,
+    ) {
+        self.seeding_manager
+            .update_subscriber_summary(contract, peer, summary)
+    }
+
     pub async fn prune_connection(&self, peer: PeerId) {
         tracing::debug!(%peer, "Removing connection");
         self.live_tx_tracker.prune_transactions_from_peer(&peer);
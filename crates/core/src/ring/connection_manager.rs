@@ -1,6 +1,10 @@
+use std::collections::HashMap;
+
 use parking_lot::Mutex;
 
+use crate::topology::meter::{AttributionSource, ResourceType};
 use crate::topology::{Limits, TopologyManager};
+use crate::transport::PeerUsage;
 
 use super::*;
 
@@ -26,8 +30,12 @@ impl ConnectionManager {
     pub fn default_with_key(pub_key: TransportPublicKey) -> Self {
         let min_connections = Ring::DEFAULT_MIN_CONNECTIONS;
         let max_connections = Ring::DEFAULT_MAX_CONNECTIONS;
-        let max_upstream_bandwidth = Ring::DEFAULT_MAX_UPSTREAM_BANDWIDTH;
-        let max_downstream_bandwidth = Ring::DEFAULT_MAX_DOWNSTREAM_BANDWIDTH;
+        let max_upstream_bandwidth = Rate::new_per_second(
+            crate::config::DEFAULT_MAX_UPSTREAM_BANDWIDTH as f64 / Ring::BANDWIDTH_HEADROOM,
+        );
+        let max_downstream_bandwidth = Rate::new_per_second(
+            crate::config::DEFAULT_MAX_DOWNSTREAM_BANDWIDTH as f64 / Ring::BANDWIDTH_HEADROOM,
+        );
         let rnd_if_htl_above = Ring::DEFAULT_RAND_WALK_ABOVE_HTL;
 
         Self::init(
@@ -56,17 +64,13 @@ impl ConnectionManager {
             Ring::DEFAULT_MAX_CONNECTIONS
         };
 
-        let max_upstream_bandwidth = if let Some(v) = config.max_upstream_bandwidth {
-            v
-        } else {
-            Ring::DEFAULT_MAX_UPSTREAM_BANDWIDTH
-        };
+        let max_upstream_bandwidth = Rate::new_per_second(
+            config.max_upstream_bandwidth.per_second() / Ring::BANDWIDTH_HEADROOM,
+        );
 
-        let max_downstream_bandwidth = if let Some(v) = config.max_downstream_bandwidth {
-            v
-        } else {
-            Ring::DEFAULT_MAX_DOWNSTREAM_BANDWIDTH
-        };
+        let max_downstream_bandwidth = Rate::new_per_second(
+            config.max_downstream_bandwidth.per_second() / Ring::BANDWIDTH_HEADROOM,
+        );
 
         let rnd_if_htl_above = if let Some(v) = config.rnd_if_htl_above {
             v
@@ -182,6 +186,41 @@ impl ConnectionManager {
         accepted
     }
 
+    /// Reports the bandwidth measured by the transport to the topology manager. Traffic with
+    /// remotes which aren't connected peers yet, like ongoing handshakes, isn't attributed.
+    pub(crate) fn report_bandwidth_usage(
+        &self,
+        usage: HashMap<SocketAddr, PeerUsage>,
+        at_time: Instant,
+    ) {
+        if usage.is_empty() {
+            return;
+        }
+        let location_for_peer = self.location_for_peer.read();
+        let mut topology_manager = self.topology_manager.write();
+        for (peer, location) in location_for_peer.iter() {
+            let Some(usage) = usage.get(&peer.addr) else {
+                continue;
+            };
+            let source = AttributionSource::Peer(PeerKeyLocation {
+                peer: peer.clone(),
+                location: Some(*location),
+            });
+            topology_manager.report_resource_usage(
+                &source,
+                ResourceType::OutboundBandwidthBytes,
+                usage.sent as f64,
+                at_time,
+            );
+            topology_manager.report_resource_usage(
+                &source,
+                ResourceType::InboundBandwidthBytes,
+                usage.received as f64,
+                at_time,
+            );
+        }
+    }
+
     /// Update this node location.
     pub fn update_location(&self, loc: Option<Location>) {
         if let Some(loc) = loc {
@@ -1,6 +1,9 @@
+use std::collections::HashMap;
+
 use super::{Location, PeerKeyLocation, Score};
+use crate::node::PeerId;
 use dashmap::{mapref::one::Ref as DmRef, DashMap};
-use freenet_stdlib::prelude::ContractKey;
+use freenet_stdlib::prelude::{ContractKey, StateSummary};
 
 pub(crate) struct SeedingManager {
     /// The container for subscriber is a vec instead of something like a hashset
@@ -11,6 +14,11 @@ pub(crate) struct SeedingManager {
     subscribers: DashMap<ContractKey, Vec<PeerKeyLocation>>,
     /// Contracts this peer is seeding.
     seeding_contract: DashMap<ContractKey, Score>,
+    /// Last known summary of the state of each subscriber, so state changes can be sent
+    /// to them as deltas instead of whole states.
+    subscriber_summaries: DashMap<ContractKey, HashMap<PeerId, StateSummary<'static>>>,
+    /// Peer through which this peer subscribed to each contract, which is seeding it as well.
+    upstreams: DashMap<ContractKey, PeerKeyLocation>,
 }
 
 impl SeedingManager {
@@ -30,6 +38,8 @@ impl SeedingManager {
         Self {
             subscribers: DashMap::new(),
             seeding_contract: DashMap::new(),
+            subscriber_summaries: DashMap::new(),
+            upstreams: DashMap::new(),
         }
     }
 
@@ -73,6 +83,8 @@ impl SeedingManager {
                 .map(|entry| *entry.key())
             {
                 self.seeding_contract.remove(&dropped_contract);
+                self.subscriber_summaries.remove(&dropped_contract);
+                self.upstreams.remove(&dropped_contract);
                 if let Some((_, mut subscribers_of_contract)) =
                     self.subscribers.remove(&dropped_contract)
                 {
@@ -99,6 +111,14 @@ impl SeedingManager {
         self.seeding_contract.contains_key(key)
     }
 
+    /// Contracts this peer is seeding.
+    pub fn seeding_contracts(&self) -> Vec<ContractKey> {
+        self.seeding_contract
+            .iter()
+            .map(|entry| *entry.key())
+            .collect()
+    }
+
     /// Will return an error in case the max number of subscribers has been added.
     pub fn add_subscriber(
         &self,
@@ -130,12 +150,139 @@ impl SeedingManager {
         self.subscribers.get(contract)
     }
 
+    /// Records the peer through which this peer subscribed to a contract.
+    pub fn set_upstream(&self, contract: &ContractKey, upstream: PeerKeyLocation) {
+        self.upstreams.insert(*contract, upstream);
+    }
+
+    /// All the peers known to be seeding a contract: its subscribers and the upstream peer.
+    pub fn seeding_peers_of(&self, contract: &ContractKey) -> Vec<PeerKeyLocation> {
+        let mut peers = self
+            .subscribers
+            .get(contract)
+            .map(|subs| subs.value().clone())
+            .unwrap_or_default();
+        if let Some(upstream) = self.upstreams.get(contract) {
+            if !peers.contains(upstream.value()) {
+                peers.push(upstream.value().clone());
+            }
+        }
+        peers
+    }
+
     pub fn prune_subscriber(&self, loc: Location) {
-        self.subscribers.alter_all(|_, mut subs| {
+        let mut pruned = vec![];
+        self.subscribers.alter_all(|contract, mut subs| {
             if let Some(pos) = subs.iter().position(|l| l.location == Some(loc)) {
-                subs.swap_remove(pos);
+                pruned.push((*contract, subs.swap_remove(pos).peer));
             }
             subs
         });
+        self.upstreams
+            .retain(|_, upstream| upstream.location != Some(loc));
+        for (contract, peer) in pruned {
+            if let Some(mut summaries) = self.subscriber_summaries.get_mut(&contract) {
+                summaries.remove(&peer);
+            }
+        }
+    }
+
+    /// Last known summary of the state of a subscriber.
+    pub fn subscriber_summary(
+        &self,
+        contract: &ContractKey,
+        peer: &PeerId,
+    ) -> Option<StateSummary<'static>> {
+        self.subscriber_summaries
+            .get(contract)
+            .and_then(|summaries| summaries.get(peer).cloned())
+    }
+
+    /// Forgets the summary of the state of a subscriber, so it receives the whole state next.
+    pub fn forget_subscriber_summary(&self, contract: &ContractKey, peer: &PeerId) {
+        if let Some(mut summaries) = self.subscriber_summaries.get_mut(contract) {
+            summaries.remove(peer);
+        }
+    }
+
+    /// Records the summary of the state of a subscriber, ignored if the peer is not subscribed.
+    pub fn update_subscriber_summary(
+        &self,
+        contract: &ContractKey,
+        peer: &PeerId,
+        summary: StateSummary<'static>,
+    ) {
+        let is_subscriber = self
+            .subscribers
+            .get(contract)
+            .is_some_and(|subs| subs.iter().any(|sub| &sub.peer == peer));
+        if is_subscriber {
+            self.subscriber_summaries
+                .entry(*contract)
+                .or_default()
+                .insert(peer.clone(), summary);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use freenet_stdlib::prelude::ContractInstanceId;
+
+    use super::*;
+
+    #[test]
+    fn subscriber_summaries() {
+        let seeding = SeedingManager::new();
+        let contract = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let subscriber = PeerKeyLocation::random();
+        let summary = StateSummary::from(vec![1, 2]);
+
+        // only the summaries of subscribers are kept
+        seeding.update_subscriber_summary(&contract, &subscriber.peer, summary.clone());
+        assert!(seeding
+            .subscriber_summary(&contract, &subscriber.peer)
+            .is_none());
+
+        seeding
+            .add_subscriber(&contract, subscriber.clone())
+            .unwrap();
+        seeding.update_subscriber_summary(&contract, &subscriber.peer, summary.clone());
+        let recorded = seeding.subscriber_summary(&contract, &subscriber.peer);
+        assert_eq!(recorded.unwrap().as_ref(), summary.as_ref());
+
+        seeding.forget_subscriber_summary(&contract, &subscriber.peer);
+        assert!(seeding
+            .subscriber_summary(&contract, &subscriber.peer)
+            .is_none());
+
+        seeding.update_subscriber_summary(&contract, &subscriber.peer, summary.clone());
+        seeding.prune_subscriber(subscriber.location.unwrap());
+        assert!(seeding
+            .subscriber_summary(&contract, &subscriber.peer)
+            .is_none());
+    }
+
+    #[test]
+    fn seeding_peers_include_upstream() {
+        let seeding = SeedingManager::new();
+        let contract = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let upstream = PeerKeyLocation::random();
+        let subscriber = PeerKeyLocation::random();
+
+        // a leaf seeder only knows the peer it subscribed through
+        seeding.set_upstream(&contract, upstream.clone());
+        assert_eq!(seeding.seeding_peers_of(&contract), vec![upstream.clone()]);
+
+        seeding
+            .add_subscriber(&contract, subscriber.clone())
+            .unwrap();
+        seeding.add_subscriber(&contract, upstream.clone()).unwrap();
+        let peers = seeding.seeding_peers_of(&contract);
+        assert_eq!(peers.len(), 2);
+        assert!(peers.contains(&upstream) && peers.contains(&subscriber));
+
+        seeding.prune_subscriber(upstream.location.unwrap());
+        assert_eq!(seeding.seeding_peers_of(&contract), vec![subscriber]);
     }
 }
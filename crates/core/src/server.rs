@@ -10,6 +10,7 @@ use freenet_stdlib::{
     prelude::*,
 };
 
+pub(crate) use http_gateway::AttestedContractMap;
 use http_gateway::HttpGateway;
 use tower_http::trace::TraceLayer;
 
@@ -83,7 +84,8 @@ pub mod local_node {
             _ => {}
         }
         let (mut gw, gw_router) = HttpGateway::as_router(&socket);
-        let (mut ws_proxy, ws_router) = WebSocketProxy::as_router(gw_router);
+        let (mut ws_proxy, ws_router) =
+            WebSocketProxy::as_router(gw_router, gw.attested_contracts.clone());
 
         serve(socket, ws_router.layer(TraceLayer::new_for_http()));
 
@@ -110,7 +112,7 @@ pub mod local_node {
                 client_id: id,
                 request,
                 notification_channel,
-                token,
+                attested_contract,
                 ..
             } = req;
             tracing::trace!(cli_id = %id, "got request -> {request}");
@@ -122,22 +124,12 @@ pub mod local_node {
                         .await
                 }
                 ClientRequest::DelegateOp(op) => {
-                    let attested_contract =
-                        token.and_then(|token| gw.attested_contracts.get(&token).map(|(t, _)| t));
-                    executor.delegate_request(op, attested_contract)
+                    executor.delegate_request(op, attested_contract.as_ref())
                 }
                 ClientRequest::Disconnect { cause } => {
                     if let Some(cause) = cause {
                         tracing::info!("disconnecting cause: {cause}");
                     }
-                    // fixme: token must live for a bit to allow reconnections
-                    if let Some(rm_token) = gw
-                        .attested_contracts
-                        .iter()
-                        .find_map(|(k, (_, eid))| (eid == &id).then(|| k.clone()))
-                    {
-                        gw.attested_contracts.remove(&rm_token);
-                    }
                     continue;
                 }
                 _ => Err(ExecutorError::other(anyhow::anyhow!("not supported"))),
@@ -189,7 +181,7 @@ pub async fn serve_gateway(config: WebsocketApiConfig) -> [BoxedClient; 2] {
 pub(crate) async fn serve_gateway_in(config: WebsocketApiConfig) -> (HttpGateway, WebSocketProxy) {
     let ws_socket = (config.address, config.port).into();
     let (gw, gw_router) = HttpGateway::as_router(&ws_socket);
-    let (ws_proxy, ws_router) = WebSocketProxy::as_router(gw_router);
+    let (ws_proxy, ws_router) = WebSocketProxy::as_router(gw_router, gw.attested_contracts.clone());
     serve(ws_socket, ws_router.layer(TraceLayer::new_for_http()));
     (gw, ws_proxy)
 }
@@ -1,11 +1,13 @@
 use std::collections::HashMap;
 use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
 
 use axum::extract::Path;
 use axum::response::IntoResponse;
 use axum::routing::get;
 use axum::{Extension, Router};
-use freenet_stdlib::client_api::{ClientError, ErrorKind, HostResponse};
+use dashmap::DashMap;
+use freenet_stdlib::client_api::{ClientError, ClientRequest, ErrorKind, HostResponse};
 use freenet_stdlib::prelude::ContractInstanceId;
 use futures::future::BoxFuture;
 use futures::FutureExt;
@@ -18,6 +20,12 @@ use super::{errors::WebSocketApiError, path_handlers, AuthToken, ClientConnectio
 
 mod v1;
 
+/// Contract instances attested for each authentication token, and the client they were issued to.
+///
+/// Shared between the HTTP gateway, which issues the tokens when serving a contract web app,
+/// and the websocket API, so requests from the app can be attested in any mode.
+pub(crate) type AttestedContractMap = Arc<DashMap<AuthToken, (ContractInstanceId, ClientId)>>;
+
 #[derive(Clone)]
 pub(super) struct HttpGatewayRequest(mpsc::Sender<ClientConnection>);
 
@@ -36,7 +44,7 @@ impl std::ops::Deref for HttpGatewayRequest {
 ///
 /// Check the Locutus book for [more information](https://docs.freenet.org/dev-guide.html).
 pub(crate) struct HttpGateway {
-    pub attested_contracts: HashMap<AuthToken, (ContractInstanceId, ClientId)>,
+    pub attested_contracts: AttestedContractMap,
     proxy_server_request: mpsc::Receiver<ClientConnection>,
     response_channels: HashMap<ClientId, mpsc::UnboundedSender<HostCallbackResult>>,
 }
@@ -81,7 +89,26 @@ impl ClientEventsProxy for HttpGateway {
                         client_id,
                         req,
                         auth_token,
-                    } => return Ok(OpenRequest::new(client_id, req).with_token(auth_token)),
+                    } => {
+                        if let ClientRequest::Disconnect { cause } = &*req {
+                            if let Some(cause) = cause {
+                                tracing::info!("disconnecting cause: {cause}");
+                            }
+                            // the client is gone, so are the tokens issued to it;
+                            // this is not a request for the node so it isn't forwarded
+                            // fixme: token must live for a bit to allow reconnections
+                            self.attested_contracts
+                                .retain(|_, (_, cli_id)| cli_id != &client_id);
+                            self.response_channels.remove(&client_id);
+                            continue;
+                        }
+                        let attested_contract = auth_token.as_ref().and_then(|token| {
+                            self.attested_contracts.get(token).map(|entry| entry.0)
+                        });
+                        return Ok(OpenRequest::new(client_id, req)
+                            .with_token(auth_token)
+                            .with_attested_contract(attested_contract));
+                    }
                 }
             }
             tracing::warn!("Shutting down http gateway receiver");
@@ -116,3 +143,76 @@ impl ClientEventsProxy for HttpGateway {
         .boxed()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn connect(
+        requests: &mpsc::Sender<ClientConnection>,
+        gw: &mut HttpGateway,
+        token: &str,
+        contract: ContractInstanceId,
+    ) -> ClientId {
+        let (callbacks, mut responses) = mpsc::unbounded_channel();
+        requests
+            .send(ClientConnection::NewConnection {
+                callbacks,
+                assigned_token: Some((AuthToken::from(token.to_owned()), contract)),
+            })
+            .await
+            .unwrap();
+        // drive the gateway until the connection has been registered
+        tokio::select! {
+            _ = gw.recv() => unreachable!("no request sent"),
+            Some(HostCallbackResult::NewId { id }) = responses.recv() => id,
+        }
+    }
+
+    #[tokio::test]
+    async fn tokens_removed_on_disconnect() {
+        let (requests, proxy_server_request) = mpsc::channel(10);
+        let mut gw = HttpGateway {
+            attested_contracts: AttestedContractMap::default(),
+            proxy_server_request,
+            response_channels: HashMap::new(),
+        };
+        let (contract_a, contract_b) = (
+            ContractInstanceId::new([1; 32]),
+            ContractInstanceId::new([2; 32]),
+        );
+        let client_a = connect(&requests, &mut gw, "a", contract_a).await;
+        let client_b = connect(&requests, &mut gw, "b", contract_b).await;
+
+        for (client_id, req) in [
+            (client_a, ClientRequest::Disconnect { cause: None }),
+            (client_b, ClientRequest::Authenticate { token: "b".into() }),
+        ] {
+            requests
+                .send(ClientConnection::Request {
+                    client_id,
+                    req: Box::new(req),
+                    auth_token: None,
+                })
+                .await
+                .unwrap();
+        }
+
+        // the disconnect is handled by the gateway and the next request forwarded
+        let forwarded = gw.recv().await.unwrap();
+        assert_eq!(forwarded.client_id, client_b);
+        assert!(matches!(
+            *forwarded.request,
+            ClientRequest::Authenticate { .. }
+        ));
+        assert!(!gw
+            .attested_contracts
+            .contains_key(&AuthToken::from("a".to_owned())));
+        assert!(!gw.response_channels.contains_key(&client_a));
+        let attested_b = gw
+            .attested_contracts
+            .get(&AuthToken::from("b".to_owned()))
+            .map(|entry| *entry);
+        assert_eq!(attested_b, Some((contract_b, client_b)));
+    }
+}
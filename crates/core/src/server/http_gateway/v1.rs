@@ -25,7 +25,7 @@ impl HttpGateway {
         (
             Self {
                 proxy_server_request: request_to_server,
-                attested_contracts: HashMap::new(),
+                attested_contracts: AttestedContractMap::default(),
                 response_channels: HashMap::new(),
             },
             router,
@@ -189,7 +189,6 @@ impl TopologyManager {
         }
     }
 
-    #[allow(dead_code)] // fixme: use this
     pub(crate) fn report_resource_usage(
         &mut self,
         attribution: &AttributionSource,
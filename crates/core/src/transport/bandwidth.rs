@@ -0,0 +1,38 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// Caps on the total bandwidth used by the transport, in bytes per second.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct BandwidthLimits {
+    pub upstream: usize,
+    /// UDP traffic can't be refused, once this is exceeded reading from the socket pauses so
+    /// the excess is dropped and the congestion control of the senders backs off.
+    pub downstream: usize,
+}
+
+/// Bytes exchanged with each remote, shared between the transport, which records them, and
+/// the node, which periodically takes them to report the usage to the topology manager.
+#[derive(Clone, Default)]
+pub(crate) struct BandwidthUsage(Arc<parking_lot::Mutex<HashMap<SocketAddr, PeerUsage>>>);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct PeerUsage {
+    pub sent: u64,
+    pub received: u64,
+}
+
+impl BandwidthUsage {
+    pub(super) fn record_sent(&self, remote_addr: SocketAddr, bytes: usize) {
+        self.0.lock().entry(remote_addr).or_default().sent += bytes as u64;
+    }
+
+    pub(super) fn record_received(&self, remote_addr: SocketAddr, bytes: usize) {
+        self.0.lock().entry(remote_addr).or_default().received += bytes as u64;
+    }
+
+    /// Returns the usage recorded since the last call.
+    pub(crate) fn take(&self) -> HashMap<SocketAddr, PeerUsage> {
+        std::mem::take(&mut *self.0.lock())
+    }
+}
@@ -0,0 +1,233 @@
+//! Delay based congestion control for the packets sent to a remote, following LEDBAT
+//! (RFC 6817). The window grows while the queuing delay measured from the RTT stays below a
+//! target and shrinks when it goes above it, so Freenet traffic yields to interactive traffic
+//! sharing the same path before it starts losing packets.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use super::packet_data::MAX_PACKET_SIZE;
+
+/// Queuing delay we are willing to add to the path, the maximum allowed by RFC 6817.
+const TARGET_DELAY: Duration = Duration::from_millis(100);
+/// How fast the window reacts to the queuing delay being off target.
+const GAIN: f64 = 1.0;
+const INITIAL_WINDOW: usize = 10 * MAX_PACKET_SIZE;
+const MIN_WINDOW: usize = 2 * MAX_PACKET_SIZE;
+const MAX_WINDOW: usize = 4 * 1024 * 1024;
+/// The base RTT is the minimum seen during each of the last minutes, so it adapts when the
+/// path changes.
+const BASE_RTT_HISTORY: usize = 10;
+const BASE_RTT_INTERVAL: Duration = Duration::from_secs(60);
+const MAX_RETRANSMISSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub(super) struct CongestionController {
+    window: usize,
+    in_flight: usize,
+    smoothed_rtt: Option<Duration>,
+    rtt_variance: Duration,
+    base_rtts: VecDeque<(Instant, Duration)>,
+    last_reduction: Option<Instant>,
+}
+
+impl CongestionController {
+    pub fn new() -> Self {
+        Self {
+            window: INITIAL_WINDOW,
+            in_flight: 0,
+            smoothed_rtt: None,
+            rtt_variance: Duration::ZERO,
+            base_rtts: VecDeque::with_capacity(BASE_RTT_HISTORY),
+            last_reduction: None,
+        }
+    }
+
+    /// Whether a packet of the given size fits in the window. A packet can always be sent when
+    /// nothing is in flight, so the connection never stalls.
+    pub fn can_send(&self, bytes: usize) -> bool {
+        self.in_flight == 0 || self.in_flight + bytes <= self.window
+    }
+
+    pub fn on_sent(&mut self, bytes: usize) {
+        self.in_flight += bytes;
+    }
+
+    /// Packets were acknowledged, along with the RTT measured for them unless they had been
+    /// retransmitted, since then we can't tell which transmission was acknowledged.
+    pub fn on_ack(&mut self, bytes: usize, rtt: Option<Duration>, now: Instant) {
+        self.in_flight = self.in_flight.saturating_sub(bytes);
+        let Some(rtt) = rtt else {
+            return;
+        };
+        self.update_rtt(rtt);
+        self.update_base_rtt(rtt, now);
+
+        let queuing_delay = rtt.saturating_sub(self.base_rtt());
+        let off_target =
+            (TARGET_DELAY.as_secs_f64() - queuing_delay.as_secs_f64()) / TARGET_DELAY.as_secs_f64();
+        let change = GAIN * off_target * bytes as f64 * MAX_PACKET_SIZE as f64 / self.window as f64;
+        // never grow faster than slow start would
+        let change = change.min(bytes as f64);
+        self.window =
+            (self.window as f64 + change).clamp(MIN_WINDOW as f64, MAX_WINDOW as f64) as usize;
+    }
+
+    /// A packet was not acknowledged in time. The window is halved, at most once per RTT so a
+    /// burst of losses counts as a single congestion event.
+    pub fn on_loss(&mut self, bytes: usize, now: Instant) {
+        self.in_flight = self.in_flight.saturating_sub(bytes);
+        let rtt = self.smoothed_rtt.unwrap_or(TARGET_DELAY);
+        if self
+            .last_reduction
+            .is_some_and(|reduced_at| now.duration_since(reduced_at) < rtt)
+        {
+            return;
+        }
+        self.window = (self.window / 2).max(MIN_WINDOW);
+        self.last_reduction = Some(now);
+    }
+
+    /// How long to wait for a receipt before retransmitting a packet, as in RFC 6298 but never
+    /// less than `min`.
+    pub fn retransmission_timeout(&self, min: Duration) -> Duration {
+        match self.smoothed_rtt {
+            Some(rtt) => (rtt + 4 * self.rtt_variance).clamp(min, MAX_RETRANSMISSION_TIMEOUT),
+            None => min,
+        }
+    }
+
+    fn update_rtt(&mut self, rtt: Duration) {
+        match self.smoothed_rtt {
+            None => {
+                self.smoothed_rtt = Some(rtt);
+                self.rtt_variance = rtt / 2;
+            }
+            Some(smoothed) => {
+                let deviation = if smoothed > rtt {
+                    smoothed - rtt
+                } else {
+                    rtt - smoothed
+                };
+                self.rtt_variance = (self.rtt_variance * 3 + deviation) / 4;
+                self.smoothed_rtt = Some((smoothed * 7 + rtt) / 8);
+            }
+        }
+    }
+
+    fn update_base_rtt(&mut self, rtt: Duration, now: Instant) {
+        match self.base_rtts.back_mut() {
+            Some((started, base)) if now.duration_since(*started) < BASE_RTT_INTERVAL => {
+                *base = (*base).min(rtt);
+            }
+            _ => {
+                if self.base_rtts.len() == BASE_RTT_HISTORY {
+                    self.base_rtts.pop_front();
+                }
+                self.base_rtts.push_back((now, rtt));
+            }
+        }
+    }
+
+    fn base_rtt(&self) -> Duration {
+        self.base_rtts
+            .iter()
+            .map(|(_, rtt)| *rtt)
+            .min()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RTT: Duration = Duration::from_millis(50);
+
+    /// Sends and acknowledges a full window, returning the window afterwards.
+    fn round_trip(cc: &mut CongestionController, rtt: Duration, now: &mut Instant) -> usize {
+        let window = cc.window;
+        cc.on_sent(window);
+        *now += rtt;
+        cc.on_ack(window, Some(rtt), *now);
+        cc.window
+    }
+
+    #[test]
+    fn grows_without_queuing_delay() {
+        let mut cc = CongestionController::new();
+        let mut now = Instant::now();
+        let mut window = cc.window;
+        for _ in 0..10 {
+            let grown = round_trip(&mut cc, RTT, &mut now);
+            assert!(grown > window);
+            window = grown;
+        }
+    }
+
+    #[test]
+    fn yields_when_delay_builds_up() {
+        let mut cc = CongestionController::new();
+        let mut now = Instant::now();
+        for _ in 0..10 {
+            round_trip(&mut cc, RTT, &mut now);
+        }
+        // competing traffic fills the queue on the path
+        let mut window = cc.window;
+        for _ in 0..5 {
+            let shrunk = round_trip(&mut cc, RTT + 2 * TARGET_DELAY, &mut now);
+            assert!(shrunk < window);
+            window = shrunk;
+        }
+        // at the target delay the window stays put
+        assert_eq!(round_trip(&mut cc, RTT + TARGET_DELAY, &mut now), window);
+    }
+
+    #[test]
+    fn halves_once_per_rtt_on_loss() {
+        let mut cc = CongestionController::new();
+        let mut now = Instant::now();
+        round_trip(&mut cc, RTT, &mut now);
+        let window = cc.window;
+
+        cc.on_sent(3 * MAX_PACKET_SIZE);
+        cc.on_loss(MAX_PACKET_SIZE, now);
+        cc.on_loss(MAX_PACKET_SIZE, now + RTT / 2);
+        assert_eq!(cc.window, window / 2);
+        cc.on_loss(MAX_PACKET_SIZE, now + RTT * 2);
+        assert_eq!(cc.window, window / 4);
+        assert_eq!(cc.in_flight, 0);
+
+        for _ in 0..10 {
+            now += RTT * 2;
+            cc.on_loss(0, now);
+        }
+        assert_eq!(cc.window, MIN_WINDOW);
+    }
+
+    #[test]
+    fn window_limits_in_flight() {
+        let mut cc = CongestionController::new();
+        assert!(cc.can_send(MAX_WINDOW));
+        cc.on_sent(INITIAL_WINDOW - MAX_PACKET_SIZE);
+        assert!(cc.can_send(MAX_PACKET_SIZE));
+        cc.on_sent(MAX_PACKET_SIZE);
+        assert!(!cc.can_send(1));
+        cc.on_ack(MAX_PACKET_SIZE, None, Instant::now());
+        assert!(cc.can_send(MAX_PACKET_SIZE));
+    }
+
+    #[test]
+    fn retransmission_timeout_follows_rtt() {
+        let min = Duration::from_millis(600);
+        let mut cc = CongestionController::new();
+        assert_eq!(cc.retransmission_timeout(min), min);
+        let mut now = Instant::now();
+        round_trip(&mut cc, RTT, &mut now);
+        assert_eq!(cc.retransmission_timeout(min), min);
+        for _ in 0..20 {
+            round_trip(&mut cc, Duration::from_secs(1), &mut now);
+        }
+        let timeout = cc.retransmission_timeout(min);
+        assert!(timeout > min && timeout <= MAX_RETRANSMISSION_TIMEOUT);
+    }
+}
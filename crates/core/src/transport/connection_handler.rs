@@ -8,7 +8,7 @@ use std::time::{Duration, Instant};
 
 use crate::transport::crypto::TransportSecretKey;
 use crate::transport::packet_data::{AssymetricRSA, UnknownEncryption};
-use crate::transport::symmetric_message::OutboundConnection;
+use crate::transport::symmetric_message::{AckExtension, HandshakeAuth, OutboundConnection};
 use aes_gcm::{Aes128Gcm, KeyInit};
 use futures::{
     future::BoxFuture,
@@ -26,9 +26,11 @@ use tracing::{span, Instrument};
 use version_cmp::PROTOC_VERSION;
 
 use super::{
-    crypto::{TransportKeypair, TransportPublicKey},
+    bandwidth::{BandwidthLimits, BandwidthUsage},
+    crypto::{EphemeralKeypair, TransportKeypair, TransportPublicKey},
     packet_data::{PacketData, SymmetricAES, MAX_PACKET_SIZE},
-    peer_connection::{PeerConnection, RemoteConnection},
+    peer_connection::{PeerConnection, RemoteConnection, SuspendedStreams},
+    rate_limiter::{DownstreamLimiter, OutboundPacket, PacketRateLimiter, TrafficClass},
     sent_packet_tracker::SentPacketTracker,
     symmetric_message::{SymmetricMessage, SymmetricMessagePayload},
     Socket, TransportError,
@@ -40,7 +42,6 @@ const INTERVAL_INCREASE_FACTOR: u64 = 2;
 const MAX_INTERVAL: Duration = Duration::from_millis(5000); // Maximum interval limit
 
 const DEFAULT_BW_TRACKER_WINDOW_SIZE: Duration = Duration::from_secs(10);
-const BANDWITH_LIMIT: usize = 1024 * 1024 * 10; // 10 MB/s
 
 pub type SerializedMessage = Vec<u8>;
 
@@ -59,11 +60,15 @@ type GatewayConnectionFuture = BoxFuture<
 type TraverseNatFuture =
     BoxFuture<'static, Result<(RemoteConnection, InboundRemoteConnection), TransportError>>;
 
+/// Binds the socket and starts the transport. The bytes exchanged with each remote are
+/// recorded in `bandwidth_usage`.
 pub(crate) async fn create_connection_handler<S: Socket>(
     keypair: TransportKeypair,
     listen_host: IpAddr,
     listen_port: u16,
     is_gateway: bool,
+    bandwidth_limits: BandwidthLimits,
+    bandwidth_usage: BandwidthUsage,
 ) -> Result<(OutboundConnectionHandler, InboundConnectionHandler), TransportError> {
     // Bind the UDP socket to the specified port
     let socket = S::bind((listen_host, listen_port).into()).await?;
@@ -72,6 +77,8 @@ pub(crate) async fn create_connection_handler<S: Socket>(
         keypair,
         is_gateway,
         (listen_host, listen_port).into(),
+        bandwidth_limits,
+        bandwidth_usage,
     )?;
     Ok((
         och,
@@ -120,6 +127,8 @@ impl OutboundConnectionHandler {
         keypair: TransportKeypair,
         is_gateway: bool,
         socket_addr: SocketAddr,
+        bandwidth_limits: BandwidthLimits,
+        bandwidth_usage: BandwidthUsage,
     ) -> Result<(Self, mpsc::Receiver<PeerConnection>), TransportError> {
         // Channel buffer is one so senders will await until the receiver is ready, important for bandwidth limiting
         let (conn_handler_sender, conn_handler_receiver) = mpsc::channel(100);
@@ -136,16 +145,26 @@ impl OutboundConnectionHandler {
             new_connection_notifier: new_connection_sender,
             outbound_packets: outbound_sender,
             this_addr: socket_addr,
+            // the limit is enforced over the whole window
+            downstream: DownstreamLimiter::new(
+                DEFAULT_BW_TRACKER_WINDOW_SIZE,
+                bandwidth_limits.downstream * DEFAULT_BW_TRACKER_WINDOW_SIZE.as_secs() as usize,
+            ),
+            bandwidth_usage: bandwidth_usage.clone(),
+            suspended_streams: SuspendedStreams::default(),
         };
-        let bw_tracker = super::rate_limiter::PacketRateLimiter::new(
-            DEFAULT_BW_TRACKER_WINDOW_SIZE,
-            outbound_recv,
-        );
+        let bw_tracker = PacketRateLimiter::new(DEFAULT_BW_TRACKER_WINDOW_SIZE);
         let connection_handler = OutboundConnectionHandler {
             send_queue: conn_handler_sender,
         };
 
-        task::spawn(bw_tracker.rate_limiter(BANDWITH_LIMIT, socket));
+        task::spawn(bw_tracker.rate_limiter(
+            // the limit is enforced over the whole window
+            bandwidth_limits.upstream * DEFAULT_BW_TRACKER_WINDOW_SIZE.as_secs() as usize,
+            outbound_recv,
+            socket,
+            bandwidth_usage,
+        ));
         task::spawn(RANDOM_U64.scope(StdRng::from_entropy().gen(), transport.listen()));
 
         Ok((connection_handler, new_connection_notifier))
@@ -158,7 +177,17 @@ impl OutboundConnectionHandler {
         keypair: TransportKeypair,
         is_gateway: bool,
     ) -> Result<(Self, mpsc::Receiver<PeerConnection>), TransportError> {
-        Self::config_listener(socket, keypair, is_gateway, socket_addr)
+        Self::config_listener(
+            socket,
+            keypair,
+            is_gateway,
+            socket_addr,
+            BandwidthLimits {
+                upstream: crate::config::DEFAULT_MAX_UPSTREAM_BANDWIDTH as usize,
+                downstream: crate::config::DEFAULT_MAX_DOWNSTREAM_BANDWIDTH as usize,
+            },
+            BandwidthUsage::default(),
+        )
     }
 
     pub async fn connect(
@@ -201,8 +230,12 @@ struct UdpPacketsListener<S = UdpSocket> {
     this_peer_keypair: TransportKeypair,
     is_gateway: bool,
     new_connection_notifier: mpsc::Sender<PeerConnection>,
-    outbound_packets: mpsc::Sender<(SocketAddr, Arc<[u8]>)>,
+    outbound_packets: mpsc::Sender<OutboundPacket>,
     this_addr: SocketAddr,
+    /// shared with the connections, which throttle their inbound streams past the limit
+    downstream: DownstreamLimiter,
+    bandwidth_usage: BandwidthUsage,
+    suspended_streams: SuspendedStreams,
 }
 
 type OngoingConnection = (
@@ -291,6 +324,8 @@ impl<S: Socket> UdpPacketsListener<S> {
                 recv_result = self.socket_listener.recv_from(&mut buf) => {
                     match recv_result {
                         Ok((size, remote_addr)) => {
+                            self.bandwidth_usage.record_received(remote_addr, size);
+                            self.downstream.add_packet(size);
                             if let Some(time) = outdated_peer.get(&remote_addr) {
                                 if time.elapsed() < Duration::from_secs(60 * 10) {
                                     continue;
@@ -479,14 +514,18 @@ impl<S: Socket> UdpPacketsListener<S> {
         GatewayConnectionFuture,
         mpsc::Sender<PacketData<UnknownEncryption>>,
     ) {
-        let secret = self.this_peer_keypair.secret.clone();
+        let keypair = self.this_peer_keypair.clone();
         let outbound_packets = self.outbound_packets.clone();
+        let suspended_streams = self.suspended_streams.clone();
+        let downstream = self.downstream.clone();
 
         let (inbound_from_remote, mut next_inbound) =
             mpsc::channel::<PacketData<UnknownEncryption>>(1);
         let f = async move {
-            let decrypted_intro_packet =
-                secret.decrypt(remote_intro_packet.data()).map_err(|err| {
+            let decrypted_intro_packet = keypair
+                .secret
+                .decrypt(remote_intro_packet.data())
+                .map_err(|err| {
                     tracing::debug!(%remote_addr, %err, "Failed to decrypt intro packet");
                     err
                 })?;
@@ -497,21 +536,18 @@ impl<S: Socket> UdpPacketsListener<S> {
                     cause: "Packet too small to contain protocol version".into(),
                 })?;
 
-            let outbound_key_bytes = decrypted_intro_packet
+            let outbound_key_bytes: [u8; 16] = decrypted_intro_packet
                 .get(PROTOC_VERSION.len()..PROTOC_VERSION.len() + 16)
+                .and_then(|bytes| bytes.try_into().ok())
                 .ok_or_else(|| TransportError::ConnectionEstablishmentFailure {
                     cause: "Packet too small to contain outbound key bytes".into(),
                 })?;
 
-            let outbound_key = Aes128Gcm::new_from_slice(outbound_key_bytes).map_err(|_| {
-                TransportError::ConnectionEstablishmentFailure {
-                    cause: "invalid symmetric key".into(),
-                }
-            })?;
+            let outbound_key = Aes128Gcm::new(&outbound_key_bytes.into());
             if protoc != PROTOC_VERSION {
                 let packet = SymmetricMessage::ack_error(&outbound_key)?;
                 outbound_packets
-                    .send((remote_addr, packet.prepared_send()))
+                    .send((remote_addr, packet.prepared_send(), TrafficClass::Control))
                     .await
                     .map_err(|_| TransportError::ChannelClosed)?;
                 return Err(TransportError::ConnectionEstablishmentFailure {
@@ -523,27 +559,69 @@ impl<S: Socket> UdpPacketsListener<S> {
                 });
             }
 
-            let inbound_key = Aes128Gcm::new(&inbound_key_bytes.into());
-            let outbound_ack_packet =
-                SymmetricMessage::ack_ok(&outbound_key, inbound_key_bytes, remote_addr)?;
+            let remote_capabilities = intro_capabilities(&decrypted_intro_packet);
+            let handshake = intro_ephemeral_key(&decrypted_intro_packet)
+                .map(|remote_ephemeral| (EphemeralKeypair::new(), remote_ephemeral));
+            let ack_extension = remote_capabilities.map(|_| AckExtension {
+                capabilities: capabilities::SUPPORTED,
+                auth: handshake.as_ref().map(|(ephemeral, remote_ephemeral)| {
+                    ephemeral.authenticate(&keypair, *remote_ephemeral)
+                }),
+            });
+            let outbound_ack_packet = SymmetricMessage::ack_ok(
+                &outbound_key,
+                inbound_key_bytes,
+                remote_addr,
+                ack_extension.as_ref(),
+            )?;
 
             tracing::debug!(%remote_addr, "Sending outbound ack packet: {:?}", outbound_ack_packet.data());
 
             outbound_packets
-                .send((remote_addr, outbound_ack_packet.clone().prepared_send()))
+                .send((
+                    remote_addr,
+                    outbound_ack_packet.clone().prepared_send(),
+                    TrafficClass::Control,
+                ))
                 .await
                 .map_err(|_| TransportError::ChannelClosed)?;
 
+            // with the X25519 handshake the key sent by the remote is only used for the ack
+            let (outbound_key, outbound_key_bytes, inbound_key_bytes) = match &handshake {
+                Some((ephemeral, remote_ephemeral)) => {
+                    let (outbound, inbound) = ephemeral.session_keys(*remote_ephemeral);
+                    (Aes128Gcm::new(&outbound.into()), outbound, inbound)
+                }
+                None => (outbound_key, outbound_key_bytes, inbound_key_bytes),
+            };
+            let inbound_key = Aes128Gcm::new(&inbound_key_bytes.into());
+
             // wait until the remote sends the ack packet
             let timeout = tokio::time::timeout(Duration::from_secs(5), next_inbound.recv());
-            match timeout.await {
+            let remote_public_key = match timeout.await {
                 Ok(Some(packet)) => {
-                    let _ = packet.try_decrypt_sym(&inbound_key).map_err(|_| {
+                    let decrypted = packet.try_decrypt_sym(&inbound_key).map_err(|_| {
                             tracing::debug!(%remote_addr, "Failed to decrypt packet with inbound key: {:?}", packet.data());
                             TransportError::ConnectionEstablishmentFailure {
                                 cause: "invalid symmetric key".into(),
                             }
                         })?;
+                    // the remote key isn't known at this point, it is bound to the identity the
+                    // remote claims once it sends its join request
+                    match &handshake {
+                        Some((ephemeral, remote_ephemeral)) => {
+                            let auth = SymmetricMessage::ack_extension(decrypted.data())
+                                .and_then(|extension| extension.auth);
+                            authenticate_remote(
+                                ephemeral,
+                                auth.as_ref(),
+                                Some(*remote_ephemeral),
+                                None,
+                            )?;
+                            auth.map(|auth| auth.static_key)
+                        }
+                        None => None,
+                    }
                 }
                 Ok(None) => {
                     return Err(TransportError::ConnectionEstablishmentFailure {
@@ -555,7 +633,7 @@ impl<S: Socket> UdpPacketsListener<S> {
                         cause: "connection timed out waiting for response".into(),
                     });
                 }
-            }
+            };
 
             let sent_tracker = Arc::new(parking_lot::Mutex::new(SentPacketTracker::new()));
 
@@ -563,6 +641,7 @@ impl<S: Socket> UdpPacketsListener<S> {
             let remote_conn = RemoteConnection {
                 outbound_packets,
                 outbound_symmetric_key: outbound_key,
+                outbound_symmetric_key_bytes: outbound_key_bytes,
                 remote_addr,
                 sent_tracker: sent_tracker.clone(),
                 last_packet_id: Arc::new(AtomicU32::new(0)),
@@ -570,6 +649,11 @@ impl<S: Socket> UdpPacketsListener<S> {
                 inbound_symmetric_key: inbound_key,
                 inbound_symmetric_key_bytes: inbound_key_bytes,
                 my_address: None,
+                remote_public_key,
+                capabilities: capabilities::SUPPORTED & remote_capabilities.unwrap_or(0),
+                ack_extension,
+                suspended_streams,
+                downstream,
             };
 
             let inbound_conn = InboundRemoteConnection {
@@ -611,6 +695,13 @@ impl<S: Socket> UdpPacketsListener<S> {
             RemoteInbound {
                 /// Encrypted intro packet for comparison
                 intro_packet: PacketData<AssymetricRSA>,
+                /// Inbound key of the remote, used for our packets unless the X25519 handshake
+                /// is used
+                outbound_key_bytes: [u8; 16],
+                /// Capabilities of the remote, if it advertised them
+                remote_capabilities: Option<u8>,
+                /// Ephemeral key of the remote if it uses the X25519 handshake
+                remote_ephemeral: Option<[u8; 32]>,
             },
         }
 
@@ -624,18 +715,26 @@ impl<S: Socket> UdpPacketsListener<S> {
             // probably the first packet to punch through the NAT
             if let Ok(decrypted_intro_packet) = packet.try_decrypt_asym(transport_secret_key) {
                 tracing::debug!(%remote_addr, "received intro packet");
-                let protoc = &decrypted_intro_packet.data()[..PROTOC_VERSION.len()];
+                let intro = decrypted_intro_packet.data();
+                let (Some(protoc), Some(outbound_key_bytes)) = (
+                    intro.get(..PROTOC_VERSION.len()),
+                    intro.get(PROTOC_VERSION.len()..PROTOC_VERSION.len() + 16),
+                ) else {
+                    tracing::debug!(%remote_addr, "intro packet too small");
+                    return Err(());
+                };
                 if protoc != PROTOC_VERSION {
                     tracing::debug!(%remote_addr, ?protoc, this_protoc = ?PROTOC_VERSION, "remote is using a different protocol version");
                     return Err(());
                 }
-                let outbound_key_bytes =
-                    &decrypted_intro_packet.data()[PROTOC_VERSION.len()..PROTOC_VERSION.len() + 16];
-                let outbound_key =
-                    Aes128Gcm::new_from_slice(outbound_key_bytes).expect("correct length");
-                *outbound_sym_key = Some(outbound_key.clone());
+                let outbound_key_bytes: [u8; 16] =
+                    outbound_key_bytes.try_into().expect("correct length");
+                *outbound_sym_key = Some(Aes128Gcm::new(&outbound_key_bytes.into()));
                 *state = ConnectionState::RemoteInbound {
                     intro_packet: packet.assert_assymetric(),
+                    outbound_key_bytes,
+                    remote_capabilities: intro_capabilities(intro),
+                    remote_ephemeral: intro_ephemeral_key(intro),
                 };
                 return Ok(());
             }
@@ -644,7 +743,9 @@ impl<S: Socket> UdpPacketsListener<S> {
         }
 
         let outbound_packets = self.outbound_packets.clone();
-        let transport_secret_key = self.this_peer_keypair.secret.clone();
+        let keypair = self.this_peer_keypair.clone();
+        let suspended_streams = self.suspended_streams.clone();
+        let downstream = self.downstream.clone();
         let (inbound_from_remote, mut next_inbound) =
             mpsc::channel::<PacketData<UnknownEncryption>>(1);
         let this_addr = self.this_addr;
@@ -660,11 +761,16 @@ impl<S: Socket> UdpPacketsListener<S> {
             let inbound_sym_key_bytes = rand::random::<[u8; 16]>();
             let inbound_sym_key = Aes128Gcm::new(&inbound_sym_key_bytes.into());
 
+            let ephemeral = EphemeralKeypair::new();
+
             let mut outbound_sym_key: Option<Aes128Gcm> = None;
             let outbound_intro_packet = {
-                let mut data = [0u8; { 16 + PROTOC_VERSION.len() }];
+                const KEY_END: usize = PROTOC_VERSION.len() + 16;
+                let mut data = [0u8; { KEY_END + 1 + 32 }];
                 data[..PROTOC_VERSION.len()].copy_from_slice(&PROTOC_VERSION);
-                data[PROTOC_VERSION.len()..].copy_from_slice(&inbound_sym_key_bytes);
+                data[PROTOC_VERSION.len()..KEY_END].copy_from_slice(&inbound_sym_key_bytes);
+                data[KEY_END] = capabilities::SUPPORTED;
+                data[KEY_END + 1..].copy_from_slice(&ephemeral.public());
                 PacketData::<_, MAX_PACKET_SIZE>::encrypt_with_pubkey(&data, &remote_public_key)
             };
 
@@ -675,19 +781,39 @@ impl<S: Socket> UdpPacketsListener<S> {
                     ConnectionState::StartOutbound { .. } => {
                         tracing::debug!(%remote_addr, "sending protocol version and inbound key");
                         outbound_packets
-                            .send((remote_addr, outbound_intro_packet.data().into()))
+                            .send((
+                                remote_addr,
+                                outbound_intro_packet.data().into(),
+                                TrafficClass::Control,
+                            ))
                             .await
                             .map_err(|_| TransportError::ChannelClosed)?;
                     }
-                    ConnectionState::RemoteInbound { .. } => {
+                    ConnectionState::RemoteInbound {
+                        remote_capabilities,
+                        remote_ephemeral,
+                        ..
+                    } => {
                         tracing::debug!(%remote_addr, "sending back protocol version and inbound key to remote");
                         let our_inbound = SymmetricMessage::ack_ok(
                             outbound_sym_key.as_ref().expect("should be set"),
                             inbound_sym_key_bytes,
                             remote_addr,
+                            remote_capabilities
+                                .map(|_| AckExtension {
+                                    capabilities: capabilities::SUPPORTED,
+                                    auth: remote_ephemeral.map(|remote_ephemeral| {
+                                        ephemeral.authenticate(&keypair, remote_ephemeral)
+                                    }),
+                                })
+                                .as_ref(),
                         )?;
                         outbound_packets
-                            .send((remote_addr, our_inbound.data().into()))
+                            .send((
+                                remote_addr,
+                                our_inbound.data().into(),
+                                TrafficClass::Control,
+                            ))
                             .await
                             .map_err(|_| TransportError::ChannelClosed)?;
                         sent_tracker.report_sent_packet(
@@ -709,6 +835,8 @@ impl<S: Socket> UdpPacketsListener<S> {
                                     packet.try_decrypt_sym(&inbound_sym_key)
                                 {
                                     // the remote got our inbound key, so we know that they are at least at the RemoteInbound state
+                                    let extension =
+                                        SymmetricMessage::ack_extension(decrypted_packet.data());
                                     let symmetric_message =
                                         SymmetricMessage::deser(decrypted_packet.data())?;
 
@@ -729,13 +857,51 @@ impl<S: Socket> UdpPacketsListener<S> {
                                                     remote_addr: my_address,
                                                 }),
                                         } => {
-                                            let outbound_sym_key = Aes128Gcm::new_from_slice(&key)
-                                                .map_err(|_| {
-                                                    TransportError::ConnectionEstablishmentFailure {
-                                                        cause: "invalid symmetric key".into(),
-                                                    }
-                                                })?;
-                                            tracing::debug!(%remote_addr, "Sending back ack connection: {:?}", key);
+                                            let remote_capabilities = extension
+                                                .as_ref()
+                                                .map(|extension| extension.capabilities);
+                                            let (
+                                                outbound_sym_key,
+                                                outbound_sym_key_bytes,
+                                                inbound_sym_key,
+                                                inbound_sym_key_bytes,
+                                                handshake_auth,
+                                            ) = match extension.and_then(|extension| extension.auth)
+                                            {
+                                                Some(auth) => {
+                                                    let remote_ephemeral = authenticate_remote(
+                                                        &ephemeral,
+                                                        Some(&auth),
+                                                        None,
+                                                        Some(&remote_public_key),
+                                                    )?;
+                                                    let (outbound, inbound) =
+                                                        ephemeral.session_keys(remote_ephemeral);
+                                                    (
+                                                        Aes128Gcm::new(&outbound.into()),
+                                                        outbound,
+                                                        Aes128Gcm::new(&inbound.into()),
+                                                        inbound,
+                                                        Some(ephemeral.authenticate(
+                                                            &keypair,
+                                                            remote_ephemeral,
+                                                        )),
+                                                    )
+                                                }
+                                                None => (
+                                                    Aes128Gcm::new(&key.into()),
+                                                    key,
+                                                    inbound_sym_key,
+                                                    inbound_sym_key_bytes,
+                                                    None,
+                                                ),
+                                            };
+                                            let ack_extension =
+                                                remote_capabilities.map(|_| AckExtension {
+                                                    capabilities: capabilities::SUPPORTED,
+                                                    auth: handshake_auth,
+                                                });
+                                            tracing::debug!(%remote_addr, "Sending back ack connection");
                                             outbound_packets
                                                 .send((
                                                     remote_addr,
@@ -743,9 +909,11 @@ impl<S: Socket> UdpPacketsListener<S> {
                                                         &outbound_sym_key,
                                                         inbound_sym_key_bytes,
                                                         remote_addr,
+                                                        ack_extension.as_ref(),
                                                     )?
                                                     .data()
                                                     .into(),
+                                                    TrafficClass::Control,
                                                 ))
                                                 .await
                                                 .map_err(|_| TransportError::ChannelClosed)?;
@@ -755,6 +923,8 @@ impl<S: Socket> UdpPacketsListener<S> {
                                                 RemoteConnection {
                                                     outbound_packets: outbound_packets.clone(),
                                                     outbound_symmetric_key: outbound_sym_key,
+                                                    outbound_symmetric_key_bytes:
+                                                        outbound_sym_key_bytes,
                                                     remote_addr,
                                                     sent_tracker: Arc::new(
                                                         parking_lot::Mutex::new(sent_tracker),
@@ -765,6 +935,14 @@ impl<S: Socket> UdpPacketsListener<S> {
                                                     inbound_symmetric_key_bytes:
                                                         inbound_sym_key_bytes,
                                                     my_address: Some(my_address),
+                                                    remote_public_key: Some(
+                                                        remote_public_key.clone(),
+                                                    ),
+                                                    capabilities: capabilities::SUPPORTED
+                                                        & remote_capabilities.unwrap_or(0),
+                                                    ack_extension,
+                                                    suspended_streams: suspended_streams.clone(),
+                                                    downstream: downstream.clone(),
                                                 },
                                                 InboundRemoteConnection {
                                                     inbound_packet_sender: inbound_sender,
@@ -788,7 +966,7 @@ impl<S: Socket> UdpPacketsListener<S> {
                                 if decrypt_asym(
                                     remote_addr,
                                     &packet,
-                                    &transport_secret_key,
+                                    &keypair.secret,
                                     &mut outbound_sym_key,
                                     &mut state,
                                 )
@@ -804,7 +982,9 @@ impl<S: Socket> UdpPacketsListener<S> {
                             ConnectionState::RemoteInbound {
                                 // this is the packet encrypted with out RSA pub key
                                 ref intro_packet,
-                                ..
+                                outbound_key_bytes,
+                                remote_capabilities,
+                                remote_ephemeral,
                             } => {
                                 // next packet should be an acknowledgement packet, but might also be a repeated
                                 // intro packet so we need to handle that
@@ -814,6 +994,65 @@ impl<S: Socket> UdpPacketsListener<S> {
                                     failures += 1;
                                     continue;
                                 }
+                                if let Some(remote_ephemeral) = remote_ephemeral {
+                                    // the remote acks with the session keys, or with our inbound key
+                                    // if it got our intro packet too
+                                    let (outbound, inbound) =
+                                        ephemeral.session_keys(remote_ephemeral);
+                                    let session_key = Aes128Gcm::new(&inbound.into());
+                                    let auth = packet
+                                        .try_decrypt_sym(&session_key)
+                                        .or_else(|_| packet.try_decrypt_sym(&inbound_sym_key))
+                                        .ok()
+                                        .and_then(|decrypted| {
+                                            SymmetricMessage::ack_extension(decrypted.data())
+                                        })
+                                        .and_then(|extension| extension.auth);
+                                    if let Err(err) = authenticate_remote(
+                                        &ephemeral,
+                                        auth.as_ref(),
+                                        Some(remote_ephemeral),
+                                        Some(&remote_public_key),
+                                    ) {
+                                        tracing::debug!(%remote_addr, %err, "unexpected packet from remote");
+                                        failures += 1;
+                                        continue;
+                                    }
+                                    let (inbound_sender, inbound_recv) = mpsc::channel(1);
+                                    return Ok((
+                                        RemoteConnection {
+                                            outbound_packets: outbound_packets.clone(),
+                                            outbound_symmetric_key: Aes128Gcm::new(
+                                                &outbound.into(),
+                                            ),
+                                            outbound_symmetric_key_bytes: outbound,
+                                            remote_addr,
+                                            sent_tracker: Arc::new(parking_lot::Mutex::new(
+                                                SentPacketTracker::new(),
+                                            )),
+                                            last_packet_id: Arc::new(AtomicU32::new(0)),
+                                            inbound_packet_recv: inbound_recv,
+                                            inbound_symmetric_key: session_key,
+                                            inbound_symmetric_key_bytes: inbound,
+                                            my_address: None,
+                                            remote_public_key: Some(remote_public_key.clone()),
+                                            capabilities: capabilities::SUPPORTED
+                                                & remote_capabilities.unwrap_or(0),
+                                            ack_extension: Some(AckExtension {
+                                                capabilities: capabilities::SUPPORTED,
+                                                auth: Some(
+                                                    ephemeral
+                                                        .authenticate(&keypair, remote_ephemeral),
+                                                ),
+                                            }),
+                                            suspended_streams: suspended_streams.clone(),
+                                            downstream: downstream.clone(),
+                                        },
+                                        InboundRemoteConnection {
+                                            inbound_packet_sender: inbound_sender,
+                                        },
+                                    ));
+                                }
                                 // if is not an intro packet, the connection is successful and we can proceed
                                 let (inbound_sender, inbound_recv) = mpsc::channel(1);
                                 return Ok((
@@ -821,6 +1060,7 @@ impl<S: Socket> UdpPacketsListener<S> {
                                         outbound_packets: outbound_packets.clone(),
                                         outbound_symmetric_key: outbound_sym_key
                                             .expect("should be set at this stage"),
+                                        outbound_symmetric_key_bytes: outbound_key_bytes,
                                         remote_addr,
                                         sent_tracker: Arc::new(parking_lot::Mutex::new(
                                             SentPacketTracker::new(),
@@ -830,6 +1070,15 @@ impl<S: Socket> UdpPacketsListener<S> {
                                         inbound_symmetric_key: inbound_sym_key,
                                         inbound_symmetric_key_bytes: inbound_sym_key_bytes,
                                         my_address: None,
+                                        remote_public_key: Some(remote_public_key.clone()),
+                                        capabilities: capabilities::SUPPORTED
+                                            & remote_capabilities.unwrap_or(0),
+                                        ack_extension: remote_capabilities.map(|_| AckExtension {
+                                            capabilities: capabilities::SUPPORTED,
+                                            auth: None,
+                                        }),
+                                        suspended_streams: suspended_streams.clone(),
+                                        downstream: downstream.clone(),
                                     },
                                     InboundRemoteConnection {
                                         inbound_packet_sender: inbound_sender,
@@ -895,6 +1144,45 @@ fn handle_ack_connection_error(err: Cow<'static, str>) -> TransportError {
     }
 }
 
+/// Returns the ephemeral key appended to a decrypted intro packet by remotes advertising
+/// the X25519 handshake.
+fn intro_ephemeral_key(intro: &[u8]) -> Option<[u8; 32]> {
+    const KEY_START: usize = PROTOC_VERSION.len() + 16 + 1;
+    if intro_capabilities(intro)? & capabilities::HANDSHAKE_X25519 == 0 {
+        return None;
+    }
+    intro.get(KEY_START..KEY_START + 32)?.try_into().ok()
+}
+
+/// Returns the capabilities advertised in a decrypted intro packet, older peers don't send them.
+fn intro_capabilities(intro: &[u8]) -> Option<u8> {
+    intro.get(PROTOC_VERSION.len() + 16).copied()
+}
+
+/// Checks the remote signed its ephemeral key and ours, returning its ephemeral key.
+///
+/// The ephemeral key must be the one the remote sent in its intro packet and the static key
+/// the one we are connecting to, when those are known.
+fn authenticate_remote(
+    ephemeral: &EphemeralKeypair,
+    auth: Option<&HandshakeAuth>,
+    remote_ephemeral: Option<[u8; 32]>,
+    remote_public_key: Option<&TransportPublicKey>,
+) -> Result<[u8; 32], TransportError> {
+    match auth {
+        Some(auth)
+            if remote_ephemeral.map_or(true, |key| key == auth.ephemeral_key)
+                && remote_public_key.map_or(true, |key| *key == auth.static_key)
+                && ephemeral.verify(auth) =>
+        {
+            Ok(auth.ephemeral_key)
+        }
+        _ => Err(TransportError::ConnectionEstablishmentFailure {
+            cause: "failed to authenticate the remote handshake".into(),
+        }),
+    }
+}
+
 fn key_from_addr(addr: &SocketAddr) -> [u8; 16] {
     let current_time = chrono::Utc::now();
     let mut hasher = blake3::Hasher::new();
@@ -935,10 +1223,31 @@ struct InboundRemoteConnection {
     inbound_packet_sender: mpsc::Sender<PacketData<UnknownEncryption>>,
 }
 
+/// Optional protocol features, advertised in a byte following the inbound key of the intro
+/// packet and in the extension of the connection ack. Older peers ignore both, so a feature is
+/// only used when both peers advertise it.
+pub(super) mod capabilities {
+    pub(crate) const HANDSHAKE_X25519: u8 = 0b0000_0001;
+    /// Session keys are rotated with `Rekey` messages.
+    pub(crate) const REKEY: u8 = 0b0000_0010;
+    /// Streams are flow controlled with `StreamProgress` and resumed with `StreamResume`.
+    pub(crate) const STREAM_FLOW_CONTROL: u8 = 0b0000_0100;
+
+    pub(crate) const SUPPORTED: u8 = HANDSHAKE_X25519 | REKEY | STREAM_FLOW_CONTROL;
+}
+
 mod version_cmp {
     pub(super) const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-    pub(super) const PROTOC_VERSION: [u8; 8] = parse_version_with_flags(VERSION);
+    /// Bumped on incompatible changes to the network messages, so older builds of the same
+    /// version refuse to connect instead of failing to decode them.
+    const PROTOCOL_REVISION: u8 = 1;
+
+    pub(super) const PROTOC_VERSION: [u8; 8] = {
+        let mut version = parse_version_with_flags(VERSION);
+        version[7] = PROTOCOL_REVISION;
+        version
+    };
 
     const fn parse_version_with_flags(version: &str) -> [u8; 8] {
         let mut major = 0u8;
@@ -985,7 +1294,7 @@ mod version_cmp {
             (flags >> 16) as u8,
             (flags >> 8) as u8,
             flags as u8,
-            0, // Reserved for future use
+            0, // Protocol revision
         ]
     }
 
@@ -1097,6 +1406,25 @@ mod test {
         }
     }
 
+    #[test]
+    fn intro_handshake_capabilities() {
+        let legacy_intro = [PROTOC_VERSION.as_slice(), &[1; 16]].concat();
+        assert_eq!(intro_ephemeral_key(&legacy_intro), None);
+
+        let intro = [
+            legacy_intro.as_slice(),
+            &[capabilities::SUPPORTED],
+            &[2; 32],
+        ]
+        .concat();
+        // the version sent to older peers is unchanged
+        assert_eq!(&intro[..PROTOC_VERSION.len()], PROTOC_VERSION.as_slice());
+        assert_eq!(intro_ephemeral_key(&intro), Some([2; 32]));
+
+        let without_x25519 = [legacy_intro.as_slice(), &[0], &[2; 32]].concat();
+        assert_eq!(intro_ephemeral_key(&without_x25519), None);
+    }
+
     type Channels = Arc<DashMap<SocketAddr, mpsc::UnboundedSender<(SocketAddr, Vec<u8>)>>>;
 
     #[derive(Default, Clone)]
@@ -1573,22 +1901,25 @@ mod test {
     #[tokio::test]
     async fn simulate_gateway_connection() -> anyhow::Result<()> {
         let channels = Arc::new(DashMap::new());
-        let (_peer_a_pub, mut peer_a, _peer_a_addr) =
+        let (peer_a_pub, mut peer_a, _peer_a_addr) =
             set_peer_connection(Default::default(), channels.clone()).await?;
         let (gw_pub, (_oc, mut gw_conn), gw_addr) =
             set_gateway_connection(Default::default(), channels).await?;
 
         let gw = tokio::spawn(async move {
             let gw_conn = gw_conn.recv();
-            let _ = tokio::time::timeout(Duration::from_secs(10), gw_conn)
+            let conn = tokio::time::timeout(Duration::from_secs(10), gw_conn)
                 .await?
                 .ok_or(anyhow::anyhow!("no connection"))?;
+            // the gateway learns the key of the peer from the handshake
+            assert_eq!(conn.remote_public_key(), Some(&peer_a_pub));
             Ok::<_, anyhow::Error>(())
         });
 
         let peer_a = tokio::spawn(async move {
-            let peer_b_conn = peer_a.connect(gw_pub, gw_addr).await;
-            let _ = tokio::time::timeout(Duration::from_secs(60), peer_b_conn).await??;
+            let peer_b_conn = peer_a.connect(gw_pub.clone(), gw_addr).await;
+            let conn = tokio::time::timeout(Duration::from_secs(60), peer_b_conn).await??;
+            assert_eq!(conn.remote_public_key(), Some(&gw_pub));
             Ok::<_, anyhow::Error>(())
         });
 
@@ -1770,7 +2101,8 @@ mod test {
             let data = vec![0u8; MAX_DATA_SIZE + 1];
             let data =
                 tokio::task::spawn_blocking(move || bincode::serialize(&data).unwrap()).await?;
-            conn.outbound_short_message(data).await?;
+            conn.outbound_short_message(data, TrafficClass::Request)
+                .await?;
             Ok::<_, anyhow::Error>(())
         });
 
@@ -1811,6 +2143,43 @@ mod test {
         .await
     }
 
+    /// Streams larger than the initial congestion window while some of their fragments and
+    /// receipts are lost, so the window has to shrink and reopen for the messages to go through.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn simulate_streamed_message_with_packet_loss() -> anyhow::Result<()> {
+        #[derive(Clone, Copy)]
+        struct TestData(&'static str);
+
+        impl TestFixture for TestData {
+            type Message = String;
+            fn expected_iterations(&self) -> usize {
+                5
+            }
+
+            fn gen_msg(&mut self) -> Self::Message {
+                self.0.repeat(30_000)
+            }
+
+            fn assert_message_ok(&self, _: usize, msg: Self::Message) -> bool {
+                if self.0 == "foo" {
+                    msg.contains("bar") && msg.len() == "bar".len() * 30_000
+                } else {
+                    msg.contains("foo") && msg.len() == "foo".len() * 30_000
+                }
+            }
+        }
+
+        run_test(
+            TestConfig {
+                packet_drop_policy: PacketDropPolicy::Ranges(vec![40..45, 100..110, 200..201]),
+                wait_time: Duration::from_secs(10),
+                ..Default::default()
+            },
+            vec![TestData("foo"), TestData("bar")],
+        )
+        .await
+    }
+
     #[ignore = "should be fixed"]
     #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
     async fn simulate_packet_dropping() -> anyhow::Result<()> {
@@ -1,6 +1,14 @@
 use rand::rngs::OsRng;
-use rsa::{pkcs8, Pkcs1v15Encrypt, RsaPrivateKey, RsaPublicKey};
+use rsa::{pkcs8, Pkcs1v15Encrypt, Pkcs1v15Sign, RsaPrivateKey, RsaPublicKey};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, ReusableSecret};
+
+use super::symmetric_message::HandshakeAuth;
+
+const SESSION_KEY_CONTEXT: &str = "freenet transport 2024-06 session key";
+const REKEY_CONTEXT: &str = "freenet transport 2024-06 rekey";
+const TRANSCRIPT_CONTEXT: &[u8] = b"freenet transport handshake";
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct TransportKeypair {
@@ -55,6 +63,14 @@ impl TransportPublicKey {
             .encrypt(&mut rng, padding, data)
             .expect("failed to encrypt")
     }
+
+    /// Verifies a signature made with [`TransportSecretKey::sign`].
+    pub(super) fn verify(&self, data: &[u8], signature: &[u8]) -> bool {
+        let digest = Sha256::digest(data);
+        self.0
+            .verify(Pkcs1v15Sign::new::<Sha256>(), &digest, signature)
+            .is_ok()
+    }
 }
 
 impl std::fmt::Debug for TransportPublicKey {
@@ -94,6 +110,13 @@ impl TransportSecretKey {
         self.0.decrypt(Pkcs1v15Encrypt, data)
     }
 
+    pub(super) fn sign(&self, data: &[u8]) -> Vec<u8> {
+        let digest = Sha256::digest(data);
+        self.0
+            .sign(Pkcs1v15Sign::new::<Sha256>(), &digest)
+            .expect("failed to sign")
+    }
+
     #[cfg(test)]
     pub fn to_pkcs8_pem(&self) -> Result<Vec<u8>, pkcs8::Error> {
         use pkcs8::EncodePrivateKey;
@@ -110,6 +133,83 @@ impl TransportSecretKey {
     }
 }
 
+/// An X25519 key pair used for a single connection handshake.
+///
+/// Session keys are derived from the exchange of the ephemeral keys of both peers, so traffic
+/// can't be decrypted later with the static transport keys. Each peer signs both ephemeral keys
+/// with its static key so the remote can authenticate it.
+pub(super) struct EphemeralKeypair {
+    secret: ReusableSecret,
+    public: PublicKey,
+}
+
+impl EphemeralKeypair {
+    pub fn new() -> Self {
+        let secret = ReusableSecret::random_from_rng(OsRng);
+        let public = PublicKey::from(&secret);
+        Self { secret, public }
+    }
+
+    pub fn public(&self) -> [u8; 32] {
+        self.public.to_bytes()
+    }
+
+    /// Returns the keys for the packets sent to and received from the remote, in that order.
+    pub fn session_keys(&self, remote_ephemeral: [u8; 32]) -> ([u8; 16], [u8; 16]) {
+        let shared = self
+            .secret
+            .diffie_hellman(&PublicKey::from(remote_ephemeral));
+        let derive = |sender: [u8; 32], receiver: [u8; 32]| {
+            let mut material = Vec::with_capacity(96);
+            material.extend_from_slice(shared.as_bytes());
+            material.extend_from_slice(&sender);
+            material.extend_from_slice(&receiver);
+            let key = blake3::derive_key(SESSION_KEY_CONTEXT, &material);
+            let mut session_key = [0; 16];
+            session_key.copy_from_slice(&key[..16]);
+            session_key
+        };
+        (
+            derive(self.public(), remote_ephemeral),
+            derive(remote_ephemeral, self.public()),
+        )
+    }
+
+    pub fn authenticate(
+        &self,
+        keypair: &TransportKeypair,
+        remote_ephemeral: [u8; 32],
+    ) -> HandshakeAuth {
+        let transcript = transcript(&self.public(), &remote_ephemeral);
+        HandshakeAuth {
+            ephemeral_key: self.public(),
+            static_key: keypair.public.clone(),
+            signature: keypair.secret.sign(&transcript),
+        }
+    }
+
+    /// Checks the remote signed both ephemeral keys with the static key it presents.
+    pub fn verify(&self, auth: &HandshakeAuth) -> bool {
+        let transcript = transcript(&auth.ephemeral_key, &self.public());
+        auth.static_key.verify(&transcript, &auth.signature)
+    }
+}
+
+/// Derives the key which replaces a session key when the keys are rotated.
+///
+/// Both peers derive the next key on their own, so it never goes over the wire, and a key
+/// leaked later doesn't reveal the keys it replaced.
+pub(super) fn next_session_key(key: [u8; 16]) -> [u8; 16] {
+    let next = blake3::derive_key(REKEY_CONTEXT, &key);
+    let mut session_key = [0; 16];
+    session_key.copy_from_slice(&next[..16]);
+    session_key
+}
+
+fn transcript(signer_ephemeral: &[u8; 32], verifier_ephemeral: &[u8; 32]) -> Vec<u8> {
+    [TRANSCRIPT_CONTEXT, signer_ephemeral, verifier_ephemeral].concat()
+}
+
 #[cfg(test)]
 #[test]
 fn key_sizes_and_decryption() {
@@ -125,3 +225,26 @@ fn key_sizes_and_decryption() {
     let bytes = pair.secret.decrypt(&encrypted).unwrap();
     assert_eq!(bytes, sym_key_bytes.as_slice());
 }
+
+#[cfg(test)]
+#[test]
+fn ephemeral_handshake() {
+    let (initiator, responder) = (TransportKeypair::new(), TransportKeypair::new());
+    let (initiator_eph, responder_eph) = (EphemeralKeypair::new(), EphemeralKeypair::new());
+
+    let (outbound, inbound) = initiator_eph.session_keys(responder_eph.public());
+    assert_eq!(
+        responder_eph.session_keys(initiator_eph.public()),
+        (inbound, outbound)
+    );
+    assert_ne!(outbound, inbound);
+
+    let auth = responder_eph.authenticate(&responder, initiator_eph.public());
+    assert!(initiator_eph.verify(&auth));
+    assert!(!responder_eph.verify(&auth), "bound to the verifier key");
+    let forged = HandshakeAuth {
+        static_key: initiator.public.clone(),
+        ..auth
+    };
+    assert!(!initiator_eph.verify(&forged));
+}
@@ -7,6 +7,8 @@ use std::{borrow::Cow, io, net::SocketAddr};
 use futures::Future;
 use tokio::net::UdpSocket;
 
+mod bandwidth;
+mod congestion_control;
 mod connection_handler;
 mod crypto;
 mod packet_data;
@@ -14,6 +16,7 @@ mod peer_connection;
 mod rate_limiter;
 // todo: optimize trackers
 mod received_packet_tracker;
+mod replay_window;
 mod sent_packet_tracker;
 mod symmetric_message;
 
@@ -22,19 +25,22 @@ type MessagePayload = Vec<u8>;
 type PacketId = u32;
 
 pub use self::crypto::{TransportKeypair, TransportPublicKey};
+pub(crate) use self::{
+    bandwidth::{BandwidthLimits, BandwidthUsage, PeerUsage},
+    connection_handler::{
+        create_connection_handler, InboundConnectionHandler, OutboundConnectionHandler,
+    },
+    peer_connection::PeerConnection,
+    rate_limiter::TrafficClass,
+};
 #[cfg(test)]
 pub(crate) use self::{
     connection_handler::ConnectionEvent,
     packet_data::{PacketData, UnknownEncryption},
     peer_connection::RemoteConnection,
+    rate_limiter::OutboundPacket,
     symmetric_message::{SymmetricMessage, SymmetricMessagePayload},
 };
-pub(crate) use self::{
-    connection_handler::{
-        create_connection_handler, InboundConnectionHandler, OutboundConnectionHandler,
-    },
-    peer_connection::PeerConnection,
-};
 
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum TransportError {
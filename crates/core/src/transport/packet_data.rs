@@ -233,7 +233,7 @@ impl<const N: usize> PacketData<UnknownEncryption, N> {
             let mut data = [0; N];
             data[..decrypted.len()].copy_from_slice(&decrypted[..]);
             PacketData {
-                size: data.len(),
+                size: decrypted.len(),
                 data,
                 data_type: PhantomData,
             }
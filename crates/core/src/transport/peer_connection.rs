@@ -7,25 +7,34 @@ use std::{collections::HashMap, time::Instant};
 use crate::transport::connection_handler::NAT_TRAVERSAL_MAX_ATTEMPTS;
 use crate::transport::packet_data::UnknownEncryption;
 use crate::transport::sent_packet_tracker::MESSAGE_CONFIRMATION_TIMEOUT;
-use aes_gcm::Aes128Gcm;
+use aes_gcm::{Aes128Gcm, KeyInit};
 use futures::stream::FuturesUnordered;
-use futures::StreamExt;
+use futures::{StreamExt, TryFutureExt};
+use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 use tokio::task::JoinHandle;
 use tracing::{instrument, span, Instrument};
 
 mod inbound_stream;
 mod outbound_stream;
+mod suspended_streams;
+
+pub(crate) use self::suspended_streams::SuspendedStreams;
 
 use super::{
-    connection_handler::SerializedMessage,
+    connection_handler::{capabilities, SerializedMessage},
+    crypto::{self, TransportPublicKey},
     packet_data::{self, PacketData},
+    rate_limiter::{DownstreamLimiter, OutboundPacket, TrafficClass},
     received_packet_tracker::ReceivedPacketTracker,
     received_packet_tracker::ReportResult,
+    replay_window::{ReplayCheck, ReplayWindow},
     sent_packet_tracker::{ResendAction, SentPacketTracker},
-    symmetric_message::{self, SymmetricMessage, SymmetricMessagePayload},
-    TransportError,
+    symmetric_message::{
+        self, AckExtension, FragmentProgress, SymmetricMessage, SymmetricMessagePayload,
+    },
+    PacketId, TransportError,
 };
 use crate::util::time_source::InstantTimeSrc;
 
@@ -36,10 +45,19 @@ type Result<T = (), E = TransportError> = std::result::Result<T, E>;
 /// since we need to account for the space overhead of SymmetricMessage::LongMessage metadata
 const MAX_DATA_SIZE: usize = packet_data::MAX_DATA_SIZE - 100;
 
+/// The outbound key is rotated after sending this many bytes with it...
+const REKEY_AFTER_BYTES: u64 = 1 << 30;
+/// ...or after using it for this long.
+const REKEY_AFTER: Duration = Duration::from_secs(60 * 60);
+/// How long the previous inbound key is kept once packets encrypted with the new key are
+/// received, to decrypt packets which were already in flight or are retransmitted.
+const PREVIOUS_KEY_RETENTION: Duration = Duration::from_secs(120);
+
 #[must_use]
 pub(crate) struct RemoteConnection {
-    pub(super) outbound_packets: mpsc::Sender<(SocketAddr, Arc<[u8]>)>,
+    pub(super) outbound_packets: mpsc::Sender<OutboundPacket>,
     pub(super) outbound_symmetric_key: Aes128Gcm,
+    pub(super) outbound_symmetric_key_bytes: [u8; 16],
     pub(super) remote_addr: SocketAddr,
     pub(super) sent_tracker: Arc<parking_lot::Mutex<SentPacketTracker<InstantTimeSrc>>>,
     pub(super) last_packet_id: Arc<AtomicU32>,
@@ -47,6 +65,16 @@ pub(crate) struct RemoteConnection {
     pub(super) inbound_symmetric_key: Aes128Gcm,
     pub(super) inbound_symmetric_key_bytes: [u8; 16],
     pub(super) my_address: Option<SocketAddr>,
+    /// The static transport key of the remote, if the handshake authenticated it.
+    pub(super) remote_public_key: Option<TransportPublicKey>,
+    /// Capabilities supported by both peers, see `connection_handler::capabilities`.
+    pub(super) capabilities: u8,
+    /// Sent along with repeated connection acks if the remote advertised its capabilities.
+    pub(super) ack_extension: Option<AckExtension>,
+    /// Streams interrupted by a previous connection, shared by all the connections.
+    pub(super) suspended_streams: SuspendedStreams,
+    /// Bandwidth received by all the connections, inbound streams are throttled past the limit.
+    pub(super) downstream: DownstreamLimiter,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -55,8 +83,10 @@ pub(crate) struct RemoteConnection {
 pub(crate) struct StreamId(u32);
 
 impl StreamId {
+    /// Ids start at a random one, so the streams of a restarted peer aren't mistaken for the
+    /// ones it sent before.
     pub fn next() -> Self {
-        static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+        static NEXT_ID: Lazy<AtomicU32> = Lazy::new(|| AtomicU32::new(rand::random()));
         Self(NEXT_ID.fetch_add(1, std::sync::atomic::Ordering::Release))
     }
 }
@@ -67,14 +97,13 @@ impl std::fmt::Display for StreamId {
     }
 }
 
-type InboundStreamResult = Result<(StreamId, SerializedMessage), StreamId>;
-
 /// The `PeerConnection` struct is responsible for managing the connection with a remote peer.
 /// It provides methods for sending and receiving messages to and from the remote peer.
 ///
 /// The `PeerConnection` struct maintains the state of the connection, including the remote
-/// connection details, trackers for received and sent packets, and futures for inbound and
-/// outbound streams.
+/// connection details, trackers for received and sent packets, inbound streams and futures for
+/// outbound streams. Streams which are unfinished when the connection is dropped are suspended,
+/// and resumed if a new connection to the same remote is established.
 ///
 /// The `send` method is used to send serialized data to the remote peer. If the data size
 /// exceeds the maximum allowed size, it is sent as a stream; otherwise, it is sent as a
@@ -93,12 +122,25 @@ type InboundStreamResult = Result<(StreamId, SerializedMessage), StreamId>;
 pub(crate) struct PeerConnection {
     remote_conn: RemoteConnection,
     received_tracker: ReceivedPacketTracker<InstantTimeSrc>,
-    inbound_streams: HashMap<StreamId, mpsc::Sender<(u32, Vec<u8>)>>,
-    inbound_stream_futures: FuturesUnordered<JoinHandle<InboundStreamResult>>,
-    outbound_stream_futures: FuturesUnordered<JoinHandle<Result>>,
+    inbound_streams: HashMap<StreamId, inbound_stream::InboundStream>,
+    /// Progress reported by the receiver of each outbound stream
+    outbound_streams: HashMap<StreamId, watch::Sender<Option<FragmentProgress>>>,
+    outbound_stream_futures: FuturesUnordered<JoinHandle<Result<StreamId>>>,
     failure_count: usize,
     first_failure_time: Option<std::time::Instant>,
     last_packet_report_time: Instant,
+    replay_window: ReplayWindow,
+    /// The outbound key shared with the tasks sending streams, so they switch to a new key
+    /// along with the connection.
+    outbound_key: watch::Sender<Aes128Gcm>,
+    /// Inbound key replaced by the last rekey, and when the first packet encrypted with the
+    /// new key was received.
+    previous_inbound_key: Option<(Aes128Gcm, Option<Instant>)>,
+    /// Id of the rekey packet sent to the remote, the outbound key is rotated once it is
+    /// acknowledged.
+    pending_rekey: Option<PacketId>,
+    last_rekey: Instant,
+    sent_since_rekey: u64,
 }
 
 impl std::fmt::Debug for PeerConnection {
@@ -113,28 +155,47 @@ impl std::fmt::Debug for PeerConnection {
 type PeerConnectionMock = (
     PeerConnection,
     mpsc::Sender<PacketData<UnknownEncryption>>,
-    mpsc::Receiver<(SocketAddr, Arc<[u8]>)>,
+    mpsc::Receiver<OutboundPacket>,
 );
 
 #[cfg(test)]
 type RemoteConnectionMock = (
     RemoteConnection,
     mpsc::Sender<PacketData<UnknownEncryption>>,
-    mpsc::Receiver<(SocketAddr, Arc<[u8]>)>,
+    mpsc::Receiver<OutboundPacket>,
 );
 
 impl PeerConnection {
     pub(super) fn new(remote_conn: RemoteConnection) -> Self {
-        Self {
+        // older peers wouldn't understand the request to resume a stream
+        let resumed = match &remote_conn.remote_public_key {
+            Some(remote) if remote_conn.capabilities & capabilities::STREAM_FLOW_CONTROL != 0 => {
+                remote_conn.suspended_streams.resume_outbound(remote)
+            }
+            _ => vec![],
+        };
+        let (outbound_key, _) = watch::channel(remote_conn.outbound_symmetric_key.clone());
+        let mut conn = Self {
             remote_conn,
             received_tracker: ReceivedPacketTracker::new(),
             inbound_streams: HashMap::new(),
-            inbound_stream_futures: FuturesUnordered::new(),
+            outbound_streams: HashMap::new(),
             outbound_stream_futures: FuturesUnordered::new(),
             failure_count: 0,
             first_failure_time: None,
             last_packet_report_time: Instant::now(),
+            replay_window: ReplayWindow::new(),
+            outbound_key,
+            previous_inbound_key: None,
+            pending_rekey: None,
+            last_rekey: Instant::now(),
+            sent_since_rekey: 0,
+        };
+        for (stream_id, stream) in resumed {
+            tracing::debug!(%stream_id, remote = %conn.remote_conn.remote_addr, "resuming outbound stream");
+            conn.spawn_outbound_stream(stream_id, stream);
         }
+        conn
     }
 
     #[cfg(test)]
@@ -150,6 +211,7 @@ impl PeerConnection {
         let remote = RemoteConnection {
             outbound_packets,
             outbound_symmetric_key,
+            outbound_symmetric_key_bytes: [0; 16],
             remote_addr,
             sent_tracker: Arc::new(Mutex::new(SentPacketTracker::new())),
             last_packet_id: Arc::new(AtomicU32::new(0)),
@@ -157,6 +219,11 @@ impl PeerConnection {
             inbound_symmetric_key,
             inbound_symmetric_key_bytes: [1; 16],
             my_address: Some(my_address),
+            remote_public_key: None,
+            capabilities: capabilities::SUPPORTED,
+            ack_extension: None,
+            suspended_streams: SuspendedStreams::default(),
+            downstream: DownstreamLimiter::default(),
         };
         (
             Self::new(remote),
@@ -179,6 +246,7 @@ impl PeerConnection {
             RemoteConnection {
                 outbound_packets,
                 outbound_symmetric_key,
+                outbound_symmetric_key_bytes: [0; 16],
                 remote_addr,
                 sent_tracker: Arc::new(Mutex::new(SentPacketTracker::new())),
                 last_packet_id: Arc::new(AtomicU32::new(0)),
@@ -186,28 +254,50 @@ impl PeerConnection {
                 inbound_symmetric_key,
                 inbound_symmetric_key_bytes: [1; 16],
                 my_address: Some(my_address),
+                remote_public_key: None,
+                capabilities: capabilities::SUPPORTED,
+                ack_extension: None,
+                suspended_streams: SuspendedStreams::default(),
+                downstream: DownstreamLimiter::default(),
             },
             inbound_packet_sender,
             outbound_packets_recv,
         )
     }
 
-    #[instrument(name = "peer_connection", skip_all)]
+    /// Sends a message with the priority of operation requests.
     pub async fn send<T>(&mut self, data: T) -> Result
+    where
+        T: Serialize + Send + 'static,
+    {
+        self.send_with_class(data, TrafficClass::Request).await
+    }
+
+    /// Sends a message with the given priority over the rest of the outbound traffic, messages
+    /// too long to fit in a single packet are always sent as bulk streams.
+    #[instrument(name = "peer_connection", skip(self, data))]
+    pub async fn send_with_class<T>(&mut self, data: T, class: TrafficClass) -> Result
     where
         T: Serialize + Send + 'static,
     {
         let data = tokio::task::spawn_blocking(move || bincode::serialize(&data).unwrap())
             .await
             .unwrap();
+        if data.len() as u64 > inbound_stream::MAX_STREAM_LENGTH {
+            return Err(TransportError::Other(anyhow::anyhow!(
+                "message of {} bytes is too long to send",
+                data.len()
+            )));
+        }
+        self.sent_since_rekey += data.len() as u64;
         if data.len() + SymmetricMessage::short_message_overhead() > MAX_DATA_SIZE {
             tracing::trace!("sending as stream");
             self.outbound_stream(data).await;
         } else {
             tracing::trace!("sending as short message");
-            self.outbound_short_message(data).await?;
+            self.outbound_short_message(data, class).await?;
         }
-        Ok(())
+        self.start_rekey().await
     }
 
     #[instrument(name = "peer_connection", skip(self))]
@@ -236,9 +326,12 @@ impl PeerConnection {
                 inbound = self.remote_conn.inbound_packet_recv.recv() => {
                     let packet_data = inbound.ok_or(TransportError::ConnectionClosed(self.remote_addr()))?;
                     last_received = std::time::Instant::now();
-                    let Ok(decrypted) = packet_data.try_decrypt_sym(&self.remote_conn.inbound_symmetric_key).inspect_err(|error| {
-                        tracing::debug!(%error, remote = ?self.remote_conn.remote_addr, "Failed to decrypt packet, might be an intro packet or a partial packet");
-                    }) else {
+                    let Ok(decrypted) = self
+                        .decrypt_inbound(&packet_data)
+                        .inspect_err(|error| {
+                            tracing::debug!(%error, remote = ?self.remote_conn.remote_addr, "Failed to decrypt packet, might be an intro packet or a partial packet");
+                        })
+                    else {
                         let now = Instant::now();
                         if let Some(first_failure_time) = self.first_failure_time {
                             if now.duration_since(first_failure_time) <= FAILURE_TIME_WINDOW {
@@ -263,7 +356,35 @@ impl PeerConnection {
                         tracing::trace!(remote = ?self.remote_conn.remote_addr, "ignoring packet");
                         continue;
                     };
-                    let msg = SymmetricMessage::deser(decrypted.data()).unwrap();
+                    let Some(packet_id) = SymmetricMessage::packet_id(decrypted.data()) else {
+                        tracing::debug!(remote = ?self.remote_conn.remote_addr, "packet too small");
+                        continue;
+                    };
+                    let Ok(msg) = SymmetricMessage::deser(decrypted.data()).inspect_err(|error| {
+                        tracing::debug!(%error, %packet_id, remote = ?self.remote_conn.remote_addr, "failed to deserialize packet");
+                    }) else {
+                        continue;
+                    };
+                    if matches!(msg.payload, SymmetricMessagePayload::StreamFragment { .. })
+                        && self.remote_conn.downstream.is_exceeded()
+                    {
+                        // dropped before being recorded or acknowledged, so the remote sends the
+                        // fragment again once the stream window allows it
+                        tracing::trace!(%packet_id, remote = %self.remote_conn.remote_addr, "downstream limit reached, dropping stream fragment");
+                        continue;
+                    }
+                    match self.replay_window.check(packet_id) {
+                        ReplayCheck::New => {}
+                        ReplayCheck::Duplicate => {
+                            tracing::trace!(%packet_id, "dropping duplicated packet");
+                            self.received_tracker.report_duplicate_packet(packet_id);
+                            continue;
+                        }
+                        ReplayCheck::TooOld => {
+                            tracing::debug!(%packet_id, remote = ?self.remote_conn.remote_addr, "dropping packet older than the replay window");
+                            continue;
+                        }
+                    }
                     let SymmetricMessage {
                         packet_id,
                         confirm_receipt,
@@ -297,6 +418,7 @@ impl PeerConnection {
                         .sent_tracker
                         .lock()
                         .report_received_receipts(&confirm_receipt);
+                    self.finish_rekey();
 
                     let report_result = self.received_tracker.report_received_packet(packet_id);
                     match (report_result, should_send_receipts) {
@@ -320,26 +442,14 @@ impl PeerConnection {
                         return Ok(msg);
                     }
                 }
-                inbound_stream = self.inbound_stream_futures.next(), if !self.inbound_stream_futures.is_empty() => {
-                    let Some(res) = inbound_stream else {
-                        tracing::error!("unexpected no-stream from ongoing_inbound_streams");
-                        continue
-                    };
-                    let Ok((stream_id, msg)) = res.map_err(|e| TransportError::Other(e.into()))? else {
-                        tracing::error!("unexpected error from ongoing_inbound_streams");
-                        // TODO: may leave orphan stream recvs hanging around in this case
-                        continue;
-                    };
-                    self.inbound_streams.remove(&stream_id);
-                    tracing::trace!(%stream_id, "stream finished");
-                    return Ok(msg);
-                }
                 outbound_stream = self.outbound_stream_futures.next(), if !self.outbound_stream_futures.is_empty() => {
                     let Some(res) = outbound_stream else {
                         tracing::error!("unexpected no-stream from ongoing_outbound_streams");
                         continue
                     };
-                    res.map_err(|e| TransportError::Other(e.into()))??
+                    let stream_id = res.map_err(|e| TransportError::Other(e.into()))??;
+                    self.outbound_streams.remove(&stream_id);
+                    tracing::trace!(%stream_id, "stream sent");
                 }
                 _ = keep_alive.tick() => {
                     if last_received.elapsed() > KILL_CONNECTION_AFTER {
@@ -348,6 +458,7 @@ impl PeerConnection {
                     }
                     tracing::trace!(remote = ?self.remote_conn.remote_addr, "sending keep-alive");
                     self.noop(vec![]).await?;
+                    self.start_rekey().await?;
                 }
                 _ = resend_check.take().unwrap_or(tokio::time::sleep(Duration::from_millis(10))) => {
                     loop {
@@ -362,9 +473,14 @@ impl PeerConnection {
                                 break;
                             }
                             ResendAction::Resend(idx, packet) => {
+                                // retransmissions go ahead of bulk traffic, the remote is waiting on them
                                 self.remote_conn
                                     .outbound_packets
-                                    .send((self.remote_conn.remote_addr, packet.clone()))
+                                    .send((
+                                        self.remote_conn.remote_addr,
+                                        packet.clone(),
+                                        TrafficClass::Request,
+                                    ))
                                     .await
                                     .map_err(|_| TransportError::ConnectionClosed(self.remote_addr()))?;
                                 self.remote_conn.sent_tracker.lock().report_sent_packet(idx, packet);
@@ -385,6 +501,14 @@ impl PeerConnection {
         self.remote_conn.remote_addr
     }
 
+    /// Returns the static transport key of the remote, if the handshake authenticated it.
+    ///
+    /// Connections accepted by a gateway from peers without the X25519 handshake don't
+    /// authenticate the remote key.
+    pub fn remote_public_key(&self) -> Option<&TransportPublicKey> {
+        self.remote_conn.remote_public_key.as_ref()
+    }
+
     async fn process_inbound(
         &mut self,
         payload: SymmetricMessagePayload,
@@ -400,10 +524,15 @@ impl PeerConnection {
                     &self.remote_conn.outbound_symmetric_key,
                     self.remote_conn.inbound_symmetric_key_bytes,
                     self.remote_conn.remote_addr,
+                    self.remote_conn.ack_extension.as_ref(),
                 )?;
                 self.remote_conn
                     .outbound_packets
-                    .send((self.remote_conn.remote_addr, packet.data().into()))
+                    .send((
+                        self.remote_conn.remote_addr,
+                        packet.data().into(),
+                        TrafficClass::Control,
+                    ))
                     .await
                     .map_err(|_| TransportError::ConnectionClosed(self.remote_addr()))?;
                 Ok(None)
@@ -414,33 +543,262 @@ impl PeerConnection {
                 fragment_number,
                 payload,
             } => {
-                if let Some(sender) = self.inbound_streams.get(&stream_id) {
-                    sender
-                        .send((fragment_number, payload))
-                        .await
-                        .map_err(|_| TransportError::ConnectionClosed(self.remote_addr()))?;
-                    tracing::trace!(%stream_id, %fragment_number, "fragment pushed to existing stream");
-                } else {
-                    let (sender, receiver) = mpsc::channel(1);
-                    tracing::trace!(%stream_id, %fragment_number, "new stream");
-                    self.inbound_streams.insert(stream_id, sender);
-                    let mut stream = inbound_stream::InboundStream::new(total_length_bytes);
-                    if let Some(msg) = stream.push_fragment(fragment_number, payload) {
-                        self.inbound_streams.remove(&stream_id);
-                        tracing::trace!(%stream_id, %fragment_number, "stream finished");
-                        return Ok(Some(msg));
+                if !self
+                    .open_inbound_stream(stream_id, total_length_bytes)
+                    .await?
+                {
+                    return Ok(None);
+                }
+                let flow_control = self.stream_flow_control();
+                let window = self.inbound_window(stream_id);
+                let stream = self
+                    .inbound_streams
+                    .get_mut(&stream_id)
+                    .expect("stream is open");
+                let Some(msg) = stream.push_fragment(fragment_number, payload) else {
+                    tracing::trace!(%stream_id, %fragment_number, "fragment pushed to stream");
+                    if flow_control && stream.progress_due(window) {
+                        let progress = stream.progress(window);
+                        self.stream_progress(stream_id, progress).await?;
                     }
-                    self.inbound_stream_futures
-                        .push(tokio::spawn(inbound_stream::recv_stream(
-                            stream_id, receiver, stream,
-                        )));
+                    return Ok(None);
+                };
+                let progress = stream.progress(window);
+                self.inbound_streams.remove(&stream_id);
+                if flow_control {
+                    if let Some(remote) = &self.remote_conn.remote_public_key {
+                        self.remote_conn.suspended_streams.complete_inbound(
+                            remote,
+                            stream_id,
+                            total_length_bytes,
+                            progress.last_contiguous,
+                        );
+                    }
+                    // the sender holds on to the stream until it knows all of it was received
+                    self.stream_progress(stream_id, progress).await?;
+                }
+                tracing::trace!(%stream_id, %fragment_number, "stream finished");
+                Ok(Some(msg))
+            }
+            StreamProgress {
+                stream_id,
+                progress,
+            } => {
+                if let Some(sender) = self.outbound_streams.get(&stream_id) {
+                    sender.send_replace(Some(progress));
+                }
+                Ok(None)
+            }
+            StreamResume {
+                stream_id,
+                total_length_bytes,
+            } => {
+                if self
+                    .open_inbound_stream(stream_id, total_length_bytes)
+                    .await?
+                {
+                    let window = self.inbound_window(stream_id);
+                    let progress = self
+                        .inbound_streams
+                        .get_mut(&stream_id)
+                        .expect("stream is open")
+                        .progress(window);
+                    self.stream_progress(stream_id, progress).await?;
                 }
                 Ok(None)
             }
             NoOp => Ok(None),
+            Rekey => {
+                tracing::debug!(remote = %self.remote_conn.remote_addr, "remote rotated its key");
+                let key = crypto::next_session_key(self.remote_conn.inbound_symmetric_key_bytes);
+                let previous = std::mem::replace(
+                    &mut self.remote_conn.inbound_symmetric_key,
+                    Aes128Gcm::new(&key.into()),
+                );
+                self.remote_conn.inbound_symmetric_key_bytes = key;
+                self.previous_inbound_key = Some((previous, None));
+                Ok(None)
+            }
         }
     }
 
+    /// Makes sure there is an inbound stream for the fragments received, resuming it if it was
+    /// interrupted. Returns false if the fragments must be dropped, because the stream was
+    /// already delivered or too many streams are being received.
+    async fn open_inbound_stream(
+        &mut self,
+        stream_id: StreamId,
+        total_length_bytes: u64,
+    ) -> Result<bool> {
+        if self.inbound_streams.contains_key(&stream_id) {
+            return Ok(true);
+        }
+        let remote_addr = self.remote_conn.remote_addr;
+        if total_length_bytes > inbound_stream::MAX_STREAM_LENGTH {
+            tracing::debug!(%stream_id, %remote_addr, total_length_bytes, "stream too long, dropping fragment");
+            return Ok(false);
+        }
+        if !self.stream_flow_control() {
+            // older senders can't be told to hold off or resend, the packet layer delivers the
+            // fragments once
+            self.inbound_streams.insert(
+                stream_id,
+                inbound_stream::InboundStream::without_flow_control(total_length_bytes),
+            );
+            return Ok(true);
+        }
+        // streams are only resumed by remotes whose key was authenticated by the handshake
+        let remote = self.remote_conn.remote_public_key.clone();
+        let suspended_streams = self.remote_conn.suspended_streams.clone();
+        if let Some(total_fragments) = remote.as_ref().and_then(|remote| {
+            suspended_streams.completed_inbound(remote, stream_id, total_length_bytes)
+        }) {
+            // the sender missed that the stream was delivered
+            let progress = FragmentProgress::new(total_fragments, [], total_fragments);
+            self.stream_progress(stream_id, progress).await?;
+            return Ok(false);
+        }
+        if self.inbound_streams.len() >= inbound_stream::MAX_CONCURRENT_INBOUND_STREAMS {
+            // the sender asks again for progress once it runs out of window
+            tracing::debug!(%stream_id, %remote_addr, "too many inbound streams, dropping fragment");
+            return Ok(false);
+        }
+        let resumed = remote.as_ref().and_then(|remote| {
+            suspended_streams.resume_inbound(remote, stream_id, total_length_bytes)
+        });
+        let stream = match resumed {
+            Some(stream) => {
+                tracing::debug!(%stream_id, %remote_addr, "resuming inbound stream");
+                stream
+            }
+            None => {
+                tracing::trace!(%stream_id, %remote_addr, "new stream");
+                inbound_stream::InboundStream::new(total_length_bytes)
+            }
+        };
+        self.inbound_streams.insert(stream_id, stream);
+        Ok(true)
+    }
+
+    /// The window by which an inbound stream can be extended, sharing the buffer with the other
+    /// streams being received.
+    fn inbound_window(&self, stream_id: StreamId) -> u32 {
+        let reserved_by_others = self
+            .inbound_streams
+            .iter()
+            .filter(|(id, _)| **id != stream_id)
+            .fold(0u32, |reserved, (_, stream)| {
+                reserved.saturating_add(stream.reserved())
+            });
+        inbound_stream::stream_window(self.inbound_streams.len(), reserved_by_others)
+    }
+
+    /// Whether the remote reports the progress of streams, older peers send and receive all the
+    /// fragments of a stream without it.
+    fn stream_flow_control(&self) -> bool {
+        self.remote_conn.capabilities & capabilities::STREAM_FLOW_CONTROL != 0
+    }
+
+    async fn stream_progress(&mut self, stream_id: StreamId, progress: FragmentProgress) -> Result {
+        packet_sending(
+            self.remote_conn.remote_addr,
+            &self.remote_conn.outbound_packets,
+            self.remote_conn
+                .last_packet_id
+                .fetch_add(1, std::sync::atomic::Ordering::Release),
+            &self.remote_conn.last_packet_id,
+            &self.remote_conn.outbound_symmetric_key,
+            vec![],
+            SymmetricMessagePayload::StreamProgress {
+                stream_id,
+                progress,
+            },
+            TrafficClass::Control,
+            &self.remote_conn.sent_tracker,
+        )
+        .await
+    }
+
+    /// Decrypts a packet with the current inbound key, or the one it replaced if the remote may
+    /// still be sending packets encrypted with it.
+    fn decrypt_inbound(
+        &mut self,
+        packet: &PacketData<UnknownEncryption>,
+    ) -> std::result::Result<PacketData<packet_data::SymmetricAES>, aes_gcm::Error> {
+        let error = match packet.try_decrypt_sym(&self.remote_conn.inbound_symmetric_key) {
+            Ok(decrypted) => {
+                if let Some((_, replaced_at @ None)) = &mut self.previous_inbound_key {
+                    // the remote switched to the new key, older packets are only retransmissions
+                    *replaced_at = Some(Instant::now());
+                }
+                return Ok(decrypted);
+            }
+            Err(error) => error,
+        };
+        match &self.previous_inbound_key {
+            Some((key, None)) => packet.try_decrypt_sym(key),
+            Some((key, Some(replaced_at))) if replaced_at.elapsed() < PREVIOUS_KEY_RETENTION => {
+                packet.try_decrypt_sym(key)
+            }
+            _ => Err(error),
+        }
+    }
+
+    /// Asks the remote to rotate the keys once the current outbound key has been used for long
+    /// enough, if it supports it. The current key is used until the remote acknowledges it.
+    async fn start_rekey(&mut self) -> Result<()> {
+        if self.remote_conn.capabilities & capabilities::REKEY == 0
+            || self.pending_rekey.is_some()
+            || (self.sent_since_rekey < REKEY_AFTER_BYTES
+                && self.last_rekey.elapsed() < REKEY_AFTER)
+        {
+            return Ok(());
+        }
+        let packet_id = self
+            .remote_conn
+            .last_packet_id
+            .fetch_add(1, std::sync::atomic::Ordering::Release);
+        packet_sending(
+            self.remote_conn.remote_addr,
+            &self.remote_conn.outbound_packets,
+            packet_id,
+            &self.remote_conn.last_packet_id,
+            &self.remote_conn.outbound_symmetric_key,
+            vec![],
+            SymmetricMessagePayload::Rekey,
+            TrafficClass::Control,
+            &self.remote_conn.sent_tracker,
+        )
+        .await?;
+        self.pending_rekey = Some(packet_id);
+        Ok(())
+    }
+
+    /// Switches to the next outbound key once the remote acknowledged the rekey, for the
+    /// packets of the streams being sent too.
+    fn finish_rekey(&mut self) {
+        let Some(packet_id) = self.pending_rekey else {
+            return;
+        };
+        if !self
+            .remote_conn
+            .sent_tracker
+            .lock()
+            .is_acknowledged(packet_id)
+        {
+            return;
+        }
+        tracing::debug!(remote = %self.remote_conn.remote_addr, "rotated outbound key");
+        let key = crypto::next_session_key(self.remote_conn.outbound_symmetric_key_bytes);
+        self.remote_conn.outbound_symmetric_key = Aes128Gcm::new(&key.into());
+        self.remote_conn.outbound_symmetric_key_bytes = key;
+        self.outbound_key
+            .send_replace(self.remote_conn.outbound_symmetric_key.clone());
+        self.pending_rekey = None;
+        self.last_rekey = Instant::now();
+        self.sent_since_rekey = 0;
+    }
+
     #[inline]
     async fn noop(&mut self, receipts: Vec<u32>) -> Result<()> {
         packet_sending(
@@ -449,16 +807,22 @@ impl PeerConnection {
             self.remote_conn
                 .last_packet_id
                 .fetch_add(1, std::sync::atomic::Ordering::Release),
+            &self.remote_conn.last_packet_id,
             &self.remote_conn.outbound_symmetric_key,
             receipts,
             (),
+            TrafficClass::Control,
             &self.remote_conn.sent_tracker,
         )
         .await
     }
 
     #[inline]
-    pub(crate) async fn outbound_short_message(&mut self, data: SerializedMessage) -> Result<()> {
+    pub(crate) async fn outbound_short_message(
+        &mut self,
+        data: SerializedMessage,
+        class: TrafficClass,
+    ) -> Result<()> {
         let receipts = self.received_tracker.get_receipts();
         let packet_id = self
             .remote_conn
@@ -468,9 +832,11 @@ impl PeerConnection {
             self.remote_conn.remote_addr,
             &self.remote_conn.outbound_packets,
             packet_id,
+            &self.remote_conn.last_packet_id,
             &self.remote_conn.outbound_symmetric_key,
             receipts,
             symmetric_message::ShortMessage(data),
+            class,
             &self.remote_conn.sent_tracker,
         )
         .await?;
@@ -478,35 +844,73 @@ impl PeerConnection {
     }
 
     async fn outbound_stream(&mut self, data: SerializedMessage) {
-        let stream_id = StreamId::next();
+        let stream = outbound_stream::OutboundStream::new(data);
+        self.spawn_outbound_stream(StreamId::next(), stream);
+    }
+
+    fn spawn_outbound_stream(
+        &mut self,
+        stream_id: StreamId,
+        stream: outbound_stream::OutboundStream,
+    ) {
+        let (progress_sender, progress) = watch::channel(None);
+        self.outbound_streams.insert(stream_id, progress_sender);
         let task = tokio::spawn(
             outbound_stream::send_stream(
                 stream_id,
                 self.remote_conn.last_packet_id.clone(),
                 self.remote_conn.outbound_packets.clone(),
                 self.remote_conn.remote_addr,
-                data,
-                self.remote_conn.outbound_symmetric_key.clone(),
+                stream,
+                self.stream_flow_control(),
+                progress,
+                self.outbound_key.subscribe(),
                 self.remote_conn.sent_tracker.clone(),
+                self.remote_conn.remote_public_key.clone(),
+                self.remote_conn.suspended_streams.clone(),
             )
+            .map_ok(move |()| stream_id)
             .instrument(span!(tracing::Level::DEBUG, "outbound_stream")),
         );
         self.outbound_stream_futures.push(task);
     }
 }
 
+impl Drop for PeerConnection {
+    fn drop(&mut self) {
+        // the outbound streams suspend themselves once they notice the progress senders are gone
+        let Some(remote) = &self.remote_conn.remote_public_key else {
+            return;
+        };
+        if self.remote_conn.capabilities & capabilities::STREAM_FLOW_CONTROL == 0 {
+            // older senders don't resume streams
+            return;
+        }
+        for (stream_id, stream) in self.inbound_streams.drain() {
+            tracing::debug!(%stream_id, remote = %self.remote_conn.remote_addr, "suspending inbound stream");
+            self.remote_conn
+                .suspended_streams
+                .suspend_inbound(remote, stream_id, stream);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn packet_sending(
     remote_addr: SocketAddr,
-    outbound_packets: &mpsc::Sender<(SocketAddr, Arc<[u8]>)>,
+    outbound_packets: &mpsc::Sender<OutboundPacket>,
     packet_id: u32,
+    last_packet_id: &AtomicU32,
     outbound_sym_key: &Aes128Gcm,
     confirm_receipt: Vec<u32>,
     payload: impl Into<SymmetricMessagePayload>,
+    class: TrafficClass,
     sent_tracker: &parking_lot::Mutex<SentPacketTracker<InstantTimeSrc>>,
 ) -> Result<()> {
     let start_time = std::time::Instant::now();
     tracing::trace!(%remote_addr, %packet_id, "Attempting to send packet");
 
+    let payload = payload.into();
     match SymmetricMessage::try_serialize_msg_to_packet_data(
         packet_id,
         payload,
@@ -517,7 +921,7 @@ async fn packet_sending(
             let packet_size = packet.data().len();
             tracing::trace!(%remote_addr, %packet_id, packet_size, "Sending single packet");
             match outbound_packets
-                .send((remote_addr, packet.clone().prepared_send()))
+                .send((remote_addr, packet.clone().prepared_send(), class))
                 .await
             {
                 Ok(_) => {
@@ -538,9 +942,9 @@ async fn packet_sending(
             tracing::trace!(%remote_addr, %packet_id, "Sending multi-packet message");
             macro_rules! send {
                 ($packets:ident) => {{
-                    for packet in $packets {
+                    for (packet_id, packet) in $packets {
                         outbound_packets
-                            .send((remote_addr, packet.clone().prepared_send()))
+                            .send((remote_addr, packet.clone().prepared_send(), class))
                             .await
                             .map_err(|_| TransportError::ConnectionClosed(remote_addr))?;
                         sent_tracker
@@ -549,6 +953,10 @@ async fn packet_sending(
                     }
                 }};
             }
+            // the receipts are sent in separate packets, each with its own id so they aren't
+            // dropped as duplicates
+            let next_packet_id =
+                || last_packet_id.fetch_add(1, std::sync::atomic::Ordering::Release);
 
             let max_num = SymmetricMessage::max_num_of_confirm_receipts_of_noop_message();
             let packet = SymmetricMessage::serialize_msg_to_packet_data(
@@ -559,14 +967,18 @@ async fn packet_sending(
             )?;
 
             if max_num > confirm_receipt.len() {
+                let receipts_id = next_packet_id();
                 let packets = [
-                    packet,
-                    SymmetricMessage::serialize_msg_to_packet_data(
-                        packet_id,
-                        SymmetricMessagePayload::NoOp,
-                        outbound_sym_key,
-                        confirm_receipt,
-                    )?,
+                    (packet_id, packet),
+                    (
+                        receipts_id,
+                        SymmetricMessage::serialize_msg_to_packet_data(
+                            receipts_id,
+                            SymmetricMessagePayload::NoOp,
+                            outbound_sym_key,
+                            confirm_receipt,
+                        )?,
+                    ),
                 ];
 
                 send!(packets);
@@ -574,28 +986,35 @@ async fn packet_sending(
             }
 
             let mut packets = Vec::with_capacity(8);
-            packets.push(packet);
+            packets.push((packet_id, packet));
 
             while !confirm_receipt.is_empty() {
                 let len = confirm_receipt.len();
+                let receipts_id = next_packet_id();
 
                 if len <= max_num {
-                    packets.push(SymmetricMessage::serialize_msg_to_packet_data(
-                        packet_id,
-                        SymmetricMessagePayload::NoOp,
-                        outbound_sym_key,
-                        confirm_receipt,
-                    )?);
+                    packets.push((
+                        receipts_id,
+                        SymmetricMessage::serialize_msg_to_packet_data(
+                            receipts_id,
+                            SymmetricMessagePayload::NoOp,
+                            outbound_sym_key,
+                            confirm_receipt,
+                        )?,
+                    ));
                     break;
                 }
 
                 let receipts = confirm_receipt.split_off(max_num);
-                packets.push(SymmetricMessage::serialize_msg_to_packet_data(
-                    packet_id,
-                    SymmetricMessagePayload::NoOp,
-                    outbound_sym_key,
-                    receipts,
-                )?);
+                packets.push((
+                    receipts_id,
+                    SymmetricMessage::serialize_msg_to_packet_data(
+                        receipts_id,
+                        SymmetricMessagePayload::NoOp,
+                        outbound_sym_key,
+                        receipts,
+                    )?,
+                ));
             }
 
             send!(packets);
@@ -606,20 +1025,111 @@ async fn packet_sending(
 
 #[cfg(test)]
 mod tests {
-    use aes_gcm::KeyInit;
-    use futures::TryFutureExt;
     use std::net::Ipv4Addr;
 
+    use tokio::sync::oneshot;
+
     use super::{
-        inbound_stream::{recv_stream, InboundStream},
-        outbound_stream::send_stream,
+        inbound_stream::{stream_window, InboundStream, MAX_CONCURRENT_INBOUND_STREAMS},
+        outbound_stream::{send_stream, OutboundStream},
         *,
     };
-    use crate::transport::packet_data::MAX_PACKET_SIZE;
+    use crate::transport::{crypto::TransportKeypair, packet_data::MAX_PACKET_SIZE};
+
+    const REMOTE_ADDR: SocketAddr =
+        SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::LOCALHOST), 8080);
+    /// The outbound and inbound keys of a test connection.
+    const KEYS: ([u8; 16], [u8; 16]) = ([2; 16], [3; 16]);
+
+    /// A connection to the remote with the `remote` public key, sharing `suspended_streams`.
+    fn connection_to(
+        remote: &TransportPublicKey,
+        remote_addr: SocketAddr,
+        (outbound_key, inbound_key): ([u8; 16], [u8; 16]),
+        suspended_streams: SuspendedStreams,
+    ) -> PeerConnectionMock {
+        let (outbound_packets, outbound_packets_recv) = mpsc::channel(100);
+        let (inbound_packet_sender, inbound_packet_recv) = mpsc::channel(100);
+        let remote_conn = RemoteConnection {
+            outbound_packets,
+            outbound_symmetric_key: Aes128Gcm::new(&outbound_key.into()),
+            outbound_symmetric_key_bytes: outbound_key,
+            remote_addr,
+            sent_tracker: Arc::new(parking_lot::Mutex::new(SentPacketTracker::new())),
+            last_packet_id: Arc::new(AtomicU32::new(0)),
+            inbound_packet_recv,
+            inbound_symmetric_key: Aes128Gcm::new(&inbound_key.into()),
+            inbound_symmetric_key_bytes: inbound_key,
+            my_address: None,
+            remote_public_key: Some(remote.clone()),
+            capabilities: capabilities::SUPPORTED,
+            ack_extension: None,
+            suspended_streams,
+            downstream: DownstreamLimiter::default(),
+        };
+        (
+            PeerConnection::new(remote_conn),
+            inbound_packet_sender,
+            outbound_packets_recv,
+        )
+    }
+
+    /// Forwards the packets sent by a test connection to the other one. After `limit` packets
+    /// the connection drops: the rest are discarded and the sender of the limit is notified.
+    fn forward(
+        mut from: mpsc::Receiver<OutboundPacket>,
+        to: mpsc::Sender<PacketData<UnknownEncryption>>,
+        limit: Option<(usize, oneshot::Sender<()>)>,
+    ) {
+        tokio::spawn(async move {
+            let (limit, mut dropped) = match limit {
+                Some((limit, dropped)) => (limit, Some(dropped)),
+                None => (usize::MAX, None),
+            };
+            let mut forwarded = 0;
+            while let Some((_, packet, _)) = from.recv().await {
+                if forwarded == limit {
+                    if let Some(dropped) = dropped.take() {
+                        let _ = dropped.send(());
+                    }
+                    continue;
+                }
+                forwarded += 1;
+                // the other connection may be gone already
+                let _ = to.send(PacketData::from_buf(&packet)).await;
+            }
+        });
+    }
+
+    async fn next_payload(
+        outbound: &mut mpsc::Receiver<OutboundPacket>,
+        key: [u8; 16],
+    ) -> SymmetricMessagePayload {
+        let (_, packet, _) = outbound.recv().await.expect("packet sent");
+        let decrypted = PacketData::<_, MAX_PACKET_SIZE>::from_buf(&packet)
+            .try_decrypt_sym(&Aes128Gcm::new(&key.into()))
+            .expect("decrypt");
+        SymmetricMessage::deser(decrypted.data())
+            .expect("deser")
+            .payload
+    }
+
+    fn fragment(
+        stream_id: StreamId,
+        total_length_bytes: u64,
+        fragment_number: u32,
+    ) -> SymmetricMessagePayload {
+        SymmetricMessagePayload::StreamFragment {
+            stream_id,
+            total_length_bytes,
+            fragment_number,
+            payload: vec![0; 10],
+        }
+    }
 
     #[tokio::test]
     async fn test_inbound_outbound_interaction() -> Result<(), Box<dyn std::error::Error>> {
-        const MSG_LEN: usize = 1000;
+        const MSG_LEN: usize = 1_000_000;
         let (sender, mut receiver) = mpsc::channel(1);
         let remote_addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8080);
         let message: Vec<_> = std::iter::repeat(0)
@@ -631,28 +1141,33 @@ mod tests {
         let sent_tracker = Arc::new(parking_lot::Mutex::new(SentPacketTracker::new()));
 
         let stream_id = StreamId::next();
+        let (progress_sender, progress) = watch::channel(None);
         // Send a long message using the outbound stream
         let outbound = tokio::task::spawn(send_stream(
             stream_id,
             Arc::new(AtomicU32::new(0)),
             sender,
             remote_addr,
-            message.clone(),
-            cipher.clone(),
-            sent_tracker,
+            OutboundStream::new(message.clone()),
+            true,
+            progress,
+            watch::channel(cipher.clone()).1,
+            sent_tracker.clone(),
+            None,
+            SuspendedStreams::default(),
         ))
         .map_err(|e| e.into());
 
         let inbound = async {
             // need to take care of decrypting and deserializing the inbound data before collecting into the message
-            let (tx, rx) = mpsc::channel(1);
-            let stream = InboundStream::new(MSG_LEN as u64);
-            let inbound_msg = tokio::task::spawn(recv_stream(stream_id, rx, stream));
-            while let Some((_, network_packet)) = receiver.recv().await {
+            let mut stream = InboundStream::new(MSG_LEN as u64);
+            let mut inbound_msg = None;
+            while let Some((_, network_packet, _)) = receiver.recv().await {
                 let decrypted = PacketData::<_, MAX_PACKET_SIZE>::from_buf(&network_packet)
                     .try_decrypt_sym(&cipher)
                     .map_err(|e| e.to_string())?;
                 let SymmetricMessage {
+                    packet_id,
                     payload:
                         SymmetricMessagePayload::StreamFragment {
                             fragment_number,
@@ -664,12 +1179,16 @@ mod tests {
                 else {
                     return Err("unexpected message".into());
                 };
-                tx.send((fragment_number, payload)).await?;
+                sent_tracker.lock().report_received_receipts(&[packet_id]);
+                if let Some(msg) = stream.push_fragment(fragment_number, payload) {
+                    inbound_msg = Some(msg);
+                }
+                // the stream is only done once the receiver reports having all the fragments
+                if inbound_msg.is_some() || stream.progress_due(stream_window(1, 0)) {
+                    progress_sender.send_replace(Some(stream.progress(stream_window(1, 0))));
+                }
             }
-            let (_, msg) = inbound_msg
-                .await?
-                .map_err(|_| anyhow::anyhow!("stream failed"))?;
-            Ok::<_, Box<dyn std::error::Error>>(msg)
+            Ok::<_, Box<dyn std::error::Error>>(inbound_msg.ok_or("stream failed")?)
         };
 
         let (out_res, inbound_msg) = tokio::try_join!(outbound, inbound)?;
@@ -677,4 +1196,259 @@ mod tests {
         assert_eq!(message, inbound_msg);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn rekey() -> Result<(), Box<dyn std::error::Error>> {
+        let remote_addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8080);
+        // the key bytes of the test connections
+        let (outbound_key_bytes, inbound_key_bytes) = ([0; 16], [1; 16]);
+        let outbound_key = Aes128Gcm::new(&outbound_key_bytes.into());
+        let inbound_key = Aes128Gcm::new(&inbound_key_bytes.into());
+        let (mut peer, inbound, mut outbound) = PeerConnection::new_test(
+            remote_addr,
+            remote_addr,
+            outbound_key.clone(),
+            inbound_key.clone(),
+        );
+        peer.sent_since_rekey = REKEY_AFTER_BYTES;
+
+        // remotes which don't support it are never asked to rotate the keys
+        peer.remote_conn.capabilities = capabilities::SUPPORTED & !capabilities::REKEY;
+        peer.start_rekey().await?;
+        assert!(peer.pending_rekey.is_none());
+        peer.remote_conn.capabilities = capabilities::SUPPORTED;
+
+        // the next outbound key is used once the remote acknowledges the rekey
+        peer.start_rekey().await?;
+        let (_, packet, _) = outbound.recv().await.unwrap();
+        let decrypted = PacketData::<_, MAX_PACKET_SIZE>::from_buf(&packet)
+            .try_decrypt_sym(&outbound_key)
+            .map_err(|e| e.to_string())?;
+        let SymmetricMessage {
+            packet_id,
+            payload: SymmetricMessagePayload::Rekey,
+            ..
+        } = SymmetricMessage::deser(decrypted.data())?
+        else {
+            return Err("expected a rekey message".into());
+        };
+        peer.finish_rekey();
+        assert!(peer.pending_rekey.is_some());
+        peer.remote_conn
+            .sent_tracker
+            .lock()
+            .report_received_receipts(&[packet_id]);
+        peer.finish_rekey();
+        assert!(peer.pending_rekey.is_none());
+        let next_outbound_key =
+            Aes128Gcm::new(&crypto::next_session_key(outbound_key_bytes).into());
+        peer.noop(vec![]).await?;
+        let (_, packet, _) = outbound.recv().await.unwrap();
+        assert!(PacketData::<_, MAX_PACKET_SIZE>::from_buf(&packet)
+            .try_decrypt_sym(&next_outbound_key)
+            .is_ok());
+        tokio::spawn(async move { while outbound.recv().await.is_some() {} });
+
+        // after the remote rotates its key, packets with either key are accepted only once
+        let next_inbound_key = Aes128Gcm::new(&crypto::next_session_key(inbound_key_bytes).into());
+        let rekey = SymmetricMessage::serialize_msg_to_packet_data(
+            1,
+            SymmetricMessagePayload::Rekey,
+            &inbound_key,
+            vec![],
+        )?;
+        let with_old_key =
+            SymmetricMessage::serialize_msg_to_packet_data(2, vec![2], &inbound_key, vec![])?;
+        let with_new_key =
+            SymmetricMessage::serialize_msg_to_packet_data(3, vec![3], &next_inbound_key, vec![])?;
+        let retransmitted =
+            SymmetricMessage::serialize_msg_to_packet_data(4, vec![4], &inbound_key, vec![])?;
+        tokio::spawn(async move {
+            for packet in [
+                rekey,
+                with_old_key,
+                with_new_key.clone(),
+                with_new_key,
+                retransmitted,
+            ] {
+                inbound.send(packet.into_unknown()).await?;
+            }
+            Ok::<_, mpsc::error::SendError<_>>(())
+        });
+        // the old key is kept while the remote keeps using it
+        assert_eq!(peer.recv().await?, vec![2]);
+        assert_eq!(peer.recv().await?, vec![3]);
+        // and for a while after the first packet with the new key
+        assert!(matches!(peer.previous_inbound_key, Some((_, Some(_)))));
+        assert_eq!(peer.recv().await?, vec![4]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn inbound_streams_capped() -> Result<(), Box<dyn std::error::Error>> {
+        let remote = TransportKeypair::new().public().clone();
+        let (mut peer, _inbound, mut outbound) =
+            connection_to(&remote, REMOTE_ADDR, KEYS, SuspendedStreams::default());
+        tokio::spawn(async move { while outbound.recv().await.is_some() {} });
+
+        let ids: Vec<_> = (0..=MAX_CONCURRENT_INBOUND_STREAMS)
+            .map(|_| StreamId::next())
+            .collect();
+        for stream_id in &ids {
+            assert_eq!(
+                peer.process_inbound(fragment(*stream_id, 100, 1)).await?,
+                None
+            );
+        }
+        assert_eq!(peer.inbound_streams.len(), MAX_CONCURRENT_INBOUND_STREAMS);
+        assert!(!peer
+            .inbound_streams
+            .contains_key(&ids[MAX_CONCURRENT_INBOUND_STREAMS]));
+
+        // once a stream finishes the next one is accepted
+        for fragment_number in 2..10 {
+            peer.process_inbound(fragment(ids[0], 100, fragment_number))
+                .await?;
+        }
+        assert_eq!(
+            peer.process_inbound(fragment(ids[0], 100, 10)).await?,
+            Some(vec![0; 100])
+        );
+        peer.process_inbound(fragment(ids[MAX_CONCURRENT_INBOUND_STREAMS], 100, 1))
+            .await?;
+        assert!(peer
+            .inbound_streams
+            .contains_key(&ids[MAX_CONCURRENT_INBOUND_STREAMS]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delivered_stream_reported_complete() -> Result<(), Box<dyn std::error::Error>> {
+        let remote = TransportKeypair::new().public().clone();
+        let suspended_streams = SuspendedStreams::default();
+        let (mut peer, _inbound, _outbound) =
+            connection_to(&remote, REMOTE_ADDR, KEYS, suspended_streams.clone());
+        let stream_id = StreamId::next();
+        assert_eq!(
+            peer.process_inbound(fragment(stream_id, 10, 1)).await?,
+            Some(vec![0; 10])
+        );
+        drop(peer);
+
+        // the sender missed the final progress and asks for it on a new connection
+        let (mut peer, _inbound, mut outbound) =
+            connection_to(&remote, REMOTE_ADDR, KEYS, suspended_streams);
+        peer.process_inbound(SymmetricMessagePayload::StreamResume {
+            stream_id,
+            total_length_bytes: 10,
+        })
+        .await?;
+        let SymmetricMessagePayload::StreamProgress { progress, .. } =
+            next_payload(&mut outbound, KEYS.0).await
+        else {
+            return Err("expected stream progress".into());
+        };
+        assert_eq!(progress.last_contiguous, 1);
+        assert_eq!(progress.window_end, 1);
+        assert!(peer.inbound_streams.is_empty());
+
+        // a stream with another length reusing the id is a new one
+        peer.process_inbound(SymmetricMessagePayload::StreamResume {
+            stream_id,
+            total_length_bytes: 20,
+        })
+        .await?;
+        let SymmetricMessagePayload::StreamProgress { progress, .. } =
+            next_payload(&mut outbound, KEYS.0).await
+        else {
+            return Err("expected stream progress".into());
+        };
+        assert_eq!(progress.last_contiguous, 0);
+        assert!(peer.inbound_streams.contains_key(&stream_id));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn inbound_stream_suspended_on_drop() -> Result<(), Box<dyn std::error::Error>> {
+        let remote = TransportKeypair::new().public().clone();
+        let suspended_streams = SuspendedStreams::default();
+        let (mut peer, _inbound, _outbound) =
+            connection_to(&remote, REMOTE_ADDR, KEYS, suspended_streams.clone());
+        let stream_id = StreamId::next();
+        peer.process_inbound(fragment(stream_id, 20, 1)).await?;
+        drop(peer);
+
+        let other = TransportKeypair::new().public().clone();
+        assert!(suspended_streams
+            .resume_inbound(&other, stream_id, 20)
+            .is_none());
+        let stream = suspended_streams
+            .resume_inbound(&remote, stream_id, 20)
+            .ok_or("stream not suspended")?;
+        assert_eq!(stream.buffered_bytes(), 10);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stream_resumed_after_reconnecting() -> Result<(), Box<dyn std::error::Error>> {
+        let (key_a, key_b) = (TransportKeypair::new(), TransportKeypair::new());
+        let addr_a = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8000);
+        let addr_b = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8001);
+        let (a_to_b, b_to_a) = ([2; 16], [3; 16]);
+        let (suspended_a, suspended_b) = (SuspendedStreams::default(), SuspendedStreams::default());
+        let connect = |limit: Option<(usize, oneshot::Sender<()>)>| {
+            let (a, a_inbound, a_outbound) = connection_to(
+                key_b.public(),
+                addr_b,
+                (a_to_b, b_to_a),
+                suspended_a.clone(),
+            );
+            let (b, b_inbound, b_outbound) = connection_to(
+                key_a.public(),
+                addr_a,
+                (b_to_a, a_to_b),
+                suspended_b.clone(),
+            );
+            forward(a_outbound, b_inbound, limit);
+            forward(b_outbound, a_inbound, None);
+            (a, b)
+        };
+        let message: Vec<u8> = (0..300 * MAX_DATA_SIZE).map(|_| rand::random()).collect();
+
+        // the connection drops after part of the stream was sent
+        let (dropped, connection_dropped) = oneshot::channel();
+        let (mut a, mut b) = connect(Some((64, dropped)));
+        a.send(message.clone()).await?;
+        let a_task = tokio::spawn(async move { while a.recv().await.is_ok() {} });
+        let b_task = tokio::spawn(async move { b.recv().await });
+        connection_dropped.await?;
+        a_task.abort();
+        b_task.abort();
+        let _ = a_task.await;
+        assert!(b_task.await.is_err(), "the stream can't be complete yet");
+
+        // the sender notices and suspends the stream
+        let suspended = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let streams = suspended_a.resume_outbound(key_b.public());
+                if !streams.is_empty() {
+                    return streams;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await?;
+        assert_eq!(suspended.len(), 1);
+        for (stream_id, stream) in suspended {
+            suspended_a.suspend_outbound(key_b.public(), stream_id, stream);
+        }
+
+        // once connected again the rest of the stream is sent
+        let (mut a, mut b) = connect(None);
+        let a_task = tokio::spawn(async move { while a.recv().await.is_ok() {} });
+        let received = tokio::time::timeout(Duration::from_secs(30), b.recv()).await??;
+        assert_eq!(bincode::deserialize::<Vec<u8>>(&received)?, message);
+        a_task.abort();
+        Ok(())
+    }
 }
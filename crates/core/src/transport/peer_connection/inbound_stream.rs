@@ -1,23 +1,26 @@
-use tokio::sync::mpsc;
-
 use crate::transport::peer_connection::outbound_stream::SerializedStream;
+use crate::transport::symmetric_message::FragmentProgress;
 use std::collections::BTreeMap;
 
-use super::StreamId;
-
 type FragmentIdx = u32;
 
-pub(super) async fn recv_stream(
-    stream_id: StreamId,
-    mut receiver: mpsc::Receiver<(FragmentIdx, Vec<u8>)>,
-    mut stream: InboundStream,
-) -> Result<(StreamId, Vec<u8>), StreamId> {
-    while let Some((fragment_number, payload)) = receiver.recv().await {
-        if let Some(msg) = stream.push_fragment(fragment_number, payload) {
-            return Ok((stream_id, msg));
-        }
-    }
-    Err(stream_id)
+/// Fragments which may be outstanding across all the inbound streams of a connection. Windows
+/// are only extended within what the other streams leave of it, so besides the initial windows
+/// of new streams it is never exceeded.
+const INBOUND_STREAMS_BUFFER: u32 = 1024;
+/// Inbound streams accepted at the same time from a single remote.
+pub(super) const MAX_CONCURRENT_INBOUND_STREAMS: usize = 8;
+/// The window a sender can assume for a new stream before hearing from the receiver.
+pub(super) const INITIAL_STREAM_WINDOW: u32 =
+    INBOUND_STREAMS_BUFFER / MAX_CONCURRENT_INBOUND_STREAMS as u32;
+/// Longer messages are not accepted as streams.
+pub(super) const MAX_STREAM_LENGTH: u64 = 128 * 1024 * 1024;
+
+/// The window by which an inbound stream can be extended: its share of the buffer, shrinking as
+/// more streams are received at once, and no more than the other streams leave of it.
+pub(super) fn stream_window(active_streams: usize, reserved_by_others: u32) -> u32 {
+    (INBOUND_STREAMS_BUFFER / active_streams.max(1) as u32)
+        .min(INBOUND_STREAMS_BUFFER.saturating_sub(reserved_by_others))
 }
 
 pub(super) struct InboundStream {
@@ -26,6 +29,8 @@ pub(super) struct InboundStream {
     last_contiguous_fragment_idx: FragmentIdx,
     non_contiguous_fragments: BTreeMap<FragmentIdx, Vec<u8>>,
     payload: Vec<u8>,
+    /// Fragments after this one are dropped, the sender has not been allowed to send them
+    window_end: FragmentIdx,
 }
 
 impl InboundStream {
@@ -35,9 +40,39 @@ impl InboundStream {
             last_contiguous_fragment_idx: 0,
             non_contiguous_fragments: BTreeMap::new(),
             payload: vec![],
+            window_end: INITIAL_STREAM_WINDOW,
         }
     }
 
+    /// A stream from an older sender, which sends all the fragments without waiting for the
+    /// receiver to report progress.
+    pub fn without_flow_control(total_length_bytes: u64) -> Self {
+        Self {
+            window_end: FragmentIdx::MAX,
+            ..Self::new(total_length_bytes)
+        }
+    }
+
+    pub fn total_length_bytes(&self) -> u64 {
+        self.total_length_bytes
+    }
+
+    /// Fragments the sender may still send within the window.
+    pub fn reserved(&self) -> u32 {
+        self.window_end
+            .saturating_sub(self.last_contiguous_fragment_idx)
+    }
+
+    /// Bytes of the message held so far.
+    pub fn buffered_bytes(&self) -> usize {
+        self.payload.len()
+            + self
+                .non_contiguous_fragments
+                .values()
+                .map(Vec::len)
+                .sum::<usize>()
+    }
+
     /// Returns some if the message has been completely streamed, none otherwise.
     pub fn push_fragment(
         &mut self,
@@ -50,6 +85,10 @@ impl InboundStream {
         //     non_contig = ?self.non_contiguous_fragments.keys().collect::<Vec<_>>(),
         //     "received stream fragment"
         // );
+        if fragment_number <= self.last_contiguous_fragment_idx || fragment_number > self.window_end
+        {
+            return None;
+        }
         if fragment_number == self.last_contiguous_fragment_idx + 1 {
             self.last_contiguous_fragment_idx = fragment_number;
             self.payload.append(&mut fragment);
@@ -69,6 +108,27 @@ impl InboundStream {
         self.get_and_clear()
     }
 
+    /// Whether the window can be moved far enough to be worth telling the sender.
+    pub fn progress_due(&self, window: u32) -> bool {
+        window > 0
+            && self.last_contiguous_fragment_idx.saturating_add(window)
+                >= self.window_end.saturating_add(window / 2)
+    }
+
+    /// Moves the window forward and returns the progress to advertise to the sender. The end of
+    /// the window is never moved back, since the sender may already be sending up to it, but once
+    /// the fragments up to it are received it is only extended by the current `window`.
+    pub fn progress(&mut self, window: u32) -> FragmentProgress {
+        self.window_end = self
+            .window_end
+            .max(self.last_contiguous_fragment_idx.saturating_add(window));
+        FragmentProgress::new(
+            self.last_contiguous_fragment_idx,
+            self.non_contiguous_fragments.keys().copied(),
+            self.window_end,
+        )
+    }
+
     fn get_and_clear(&mut self) -> Option<Vec<u8>> {
         if self.payload.len() as u64 == self.total_length_bytes {
             Some(std::mem::take(&mut self.payload))
@@ -80,7 +140,7 @@ impl InboundStream {
 
 #[cfg(test)]
 mod tests {
-    use super::InboundStream;
+    use super::*;
 
     #[test]
     fn test_simple_sequence() {
@@ -119,4 +179,78 @@ mod tests {
         assert!(stream.non_contiguous_fragments.is_empty());
         assert!(stream.payload.is_empty());
     }
+
+    #[test]
+    fn test_fragments_outside_window() {
+        let mut stream = InboundStream::new(6);
+        stream.window_end = 2;
+        assert_eq!(stream.push_fragment(3, vec![5, 6]), None);
+        assert!(stream.non_contiguous_fragments.is_empty());
+        assert_eq!(stream.push_fragment(1, vec![1, 2]), None);
+        // duplicates are ignored
+        assert_eq!(stream.push_fragment(1, vec![1, 2]), None);
+        assert_eq!(stream.push_fragment(2, vec![3, 4]), None);
+        assert_eq!(stream.payload, vec![1, 2, 3, 4]);
+
+        let progress = stream.progress(2);
+        assert_eq!(progress.last_contiguous, 2);
+        assert_eq!(progress.window_end, 4);
+        assert_eq!(
+            stream.push_fragment(3, vec![5, 6]),
+            Some(vec![1, 2, 3, 4, 5, 6])
+        );
+    }
+
+    #[test]
+    fn test_progress() {
+        let mut stream = InboundStream::new(10 * 1024);
+        let window = stream_window(1, 0);
+        for fragment_number in [1, 2, 4, 7] {
+            stream.push_fragment(fragment_number, vec![0; 1024]);
+        }
+        // a single stream gets a larger window than the initial one
+        assert!(stream.progress_due(window));
+        let progress = stream.progress(window);
+        assert_eq!(progress.last_contiguous, 2);
+        assert_eq!(progress.received().collect::<Vec<_>>(), vec![4, 7]);
+        assert_eq!(progress.window_end, 2 + window);
+        assert!(!stream.progress_due(window));
+    }
+
+    #[test]
+    fn test_window_shrinks() {
+        let mut stream = InboundStream::new(u64::MAX);
+        // a single stream gets the whole buffer
+        let window = stream_window(1, 0);
+        assert_eq!(window, INBOUND_STREAMS_BUFFER);
+        assert_eq!(stream.progress(window).window_end, INBOUND_STREAMS_BUFFER);
+        assert_eq!(stream.reserved(), INBOUND_STREAMS_BUFFER);
+
+        // once more streams are received their windows are taken from what is left
+        let others = (MAX_CONCURRENT_INBOUND_STREAMS - 1) as u32 * INITIAL_STREAM_WINDOW;
+        assert_eq!(
+            stream_window(MAX_CONCURRENT_INBOUND_STREAMS, stream.reserved()),
+            0
+        );
+        let window = stream_window(MAX_CONCURRENT_INBOUND_STREAMS, others);
+        assert_eq!(window, INITIAL_STREAM_WINDOW);
+
+        // what was advertised is kept, but the window is only extended by the smaller share
+        for fragment_number in 1..=INBOUND_STREAMS_BUFFER - 10 {
+            assert_eq!(stream.push_fragment(fragment_number, vec![0]), None);
+        }
+        let progress = stream.progress(window);
+        assert_eq!(
+            progress.window_end,
+            INBOUND_STREAMS_BUFFER - 10 + INITIAL_STREAM_WINDOW
+        );
+        assert_eq!(stream.reserved(), INITIAL_STREAM_WINDOW);
+        assert_eq!(
+            stream.buffered_bytes(),
+            INBOUND_STREAMS_BUFFER as usize - 10
+        );
+
+        // without room left nothing is extended
+        assert!(!stream.progress_due(stream_window(2, INBOUND_STREAMS_BUFFER)));
+    }
 }
@@ -1,22 +1,25 @@
+use std::collections::BTreeSet;
 use std::net::SocketAddr;
 use std::sync::atomic::AtomicU32;
 use std::sync::Arc;
-use std::vec;
+use std::time::Duration;
 
 use aes_gcm::Aes128Gcm;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 
 use crate::{
     transport::{
+        crypto::TransportPublicKey,
         packet_data,
-        sent_packet_tracker::SentPacketTracker,
-        symmetric_message::{self},
+        rate_limiter::{OutboundPacket, TrafficClass},
+        sent_packet_tracker::{SentPacketTracker, MESSAGE_CONFIRMATION_TIMEOUT},
+        symmetric_message::{self, FragmentProgress, SymmetricMessagePayload},
         TransportError,
     },
     util::time_source::InstantTimeSrc,
 };
 
-use super::StreamId;
+use super::{inbound_stream::INITIAL_STREAM_WINDOW, StreamId, SuspendedStreams};
 
 pub(crate) type SerializedStream = Vec<u8>;
 
@@ -25,122 +28,481 @@ pub(crate) type SerializedStream = Vec<u8>;
 /// since we need to account for the space overhead of SymmetricMessage::LongMessage metadata
 const MAX_DATA_SIZE: usize = packet_data::MAX_DATA_SIZE - 100;
 
-// TODO: unit test
+/// How long to wait for the receiver to report progress once the window is exhausted before
+/// asking it which fragments it still needs.
+const STREAM_PROGRESS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A message being streamed, along with what the receiver reported to have received of it.
+pub(super) struct OutboundStream {
+    message: SerializedStream,
+    total_fragments: u32,
+    /// All the fragments up to this one were received, fragment numbers are 1-indexed
+    last_contiguous_acked: u32,
+    /// Fragments received after `last_contiguous_acked`
+    acked: BTreeSet<u32>,
+    /// The receiver accepts fragments up to this one
+    window_end: u32,
+    /// Fragments before this one have been sent, the packet layer takes care of resending them
+    next_fragment: u32,
+    /// Nothing is sent until the receiver reports progress, which then replaces what we knew
+    awaiting_progress: bool,
+}
+
+impl OutboundStream {
+    pub fn new(message: SerializedStream) -> Self {
+        let total_fragments = message.len().div_ceil(MAX_DATA_SIZE) as u32;
+        Self {
+            message,
+            total_fragments,
+            last_contiguous_acked: 0,
+            acked: BTreeSet::new(),
+            window_end: INITIAL_STREAM_WINDOW,
+            next_fragment: 1,
+            awaiting_progress: false,
+        }
+    }
+
+    pub fn total_length_bytes(&self) -> u64 {
+        self.message.len() as u64
+    }
+
+    /// Stop sending until the receiver tells which fragments it is missing, they will be sent
+    /// again even if they were sent before.
+    pub fn await_progress(&mut self) {
+        self.awaiting_progress = true;
+    }
+
+    fn is_complete(&self) -> bool {
+        self.last_contiguous_acked >= self.total_fragments
+    }
+
+    fn fragment(&self, fragment_number: u32) -> Vec<u8> {
+        let start = (fragment_number as usize - 1) * MAX_DATA_SIZE;
+        let end = (start + MAX_DATA_SIZE).min(self.message.len());
+        self.message[start..end].to_vec()
+    }
+
+    /// The next fragment to send, if any fits in the receiver's window.
+    fn next_fragment(&mut self) -> Option<u32> {
+        if self.awaiting_progress {
+            return None;
+        }
+        while self.next_fragment <= self.last_contiguous_acked
+            || self.acked.contains(&self.next_fragment)
+        {
+            self.next_fragment += 1;
+        }
+        if self.next_fragment > self.total_fragments.min(self.window_end) {
+            return None;
+        }
+        self.next_fragment += 1;
+        Some(self.next_fragment - 1)
+    }
+
+    fn update(&mut self, progress: &FragmentProgress) {
+        if std::mem::take(&mut self.awaiting_progress) {
+            self.last_contiguous_acked = progress.last_contiguous;
+            self.acked = progress.received().collect();
+            self.window_end = progress.window_end;
+            self.next_fragment = progress.last_contiguous + 1;
+            return;
+        }
+        self.last_contiguous_acked = self.last_contiguous_acked.max(progress.last_contiguous);
+        self.acked.extend(progress.received());
+        let last_contiguous_acked = self.last_contiguous_acked;
+        self.acked.retain(|&acked| acked > last_contiguous_acked);
+        self.window_end = self.window_end.max(progress.window_end);
+    }
+}
+
 /// Handles sending a stream that is *not piped*. In the future this will be replaced by
 /// piped streams which start forwarding before the stream has been received.
+///
+/// Fragments are sent as the receiver's window allows, and the stream is done once the receiver
+/// reports all of them received. If the connection is dropped before that, which is noticed when
+/// the progress sender is dropped, the stream is suspended so it can be resumed if the remote,
+/// identified by its authenticated public key, connects again.
+///
+/// Without `flow_control`, for receivers which don't report progress, all the fragments are sent
+/// in order and the stream is done once they have been sent.
 #[allow(clippy::too_many_arguments)]
 pub(super) async fn send_stream(
     stream_id: StreamId,
     last_packet_id: Arc<AtomicU32>,
-    sender: mpsc::Sender<(SocketAddr, Arc<[u8]>)>,
+    sender: mpsc::Sender<OutboundPacket>,
     destination_addr: SocketAddr,
-    mut stream_to_send: SerializedStream,
-    outbound_symmetric_key: Aes128Gcm,
+    mut stream: OutboundStream,
+    flow_control: bool,
+    mut progress: watch::Receiver<Option<FragmentProgress>>,
+    outbound_symmetric_key: watch::Receiver<Aes128Gcm>,
     sent_packet_tracker: Arc<parking_lot::Mutex<SentPacketTracker<InstantTimeSrc>>>,
+    remote_public_key: Option<TransportPublicKey>,
+    suspended_streams: SuspendedStreams,
 ) -> Result<(), TransportError> {
-    tracing::debug!(stream_id = %stream_id.0, length = stream_to_send.len(), "sending stream");
-    let total_length_bytes = stream_to_send.len() as u32;
-    let mut total_packets = stream_to_send.len() / MAX_DATA_SIZE;
-    total_packets += if stream_to_send.len() % MAX_DATA_SIZE == 0 {
-        0
-    } else {
-        1
-    };
-    let mut sent_so_far = 0;
-    let mut next_fragment_number = 1; // Fragment numbers are 1-indexed
-
-    loop {
-        if sent_so_far == total_packets {
-            break;
-        }
-        let rest = {
-            if stream_to_send.len() > MAX_DATA_SIZE {
-                let mut rest = stream_to_send.split_off(MAX_DATA_SIZE);
-                std::mem::swap(&mut stream_to_send, &mut rest);
-                rest
-            } else {
-                std::mem::take(&mut stream_to_send)
-            }
-        };
-        let packet_id = last_packet_id.fetch_add(1, std::sync::atomic::Ordering::Release);
+    tracing::debug!(stream_id = %stream_id.0, length = stream.message.len(), "sending stream");
+    let total_length_bytes = stream.total_length_bytes();
+    // the key changes when the connection rotates it, it is read again for every packet
+    let request_progress = || async {
+        let outbound_symmetric_key = outbound_symmetric_key.borrow().clone();
         super::packet_sending(
             destination_addr,
             &sender,
-            packet_id,
+            last_packet_id.fetch_add(1, std::sync::atomic::Ordering::Release),
+            &last_packet_id,
             &outbound_symmetric_key,
             vec![],
-            symmetric_message::StreamFragment {
+            SymmetricMessagePayload::StreamResume {
                 stream_id,
-                total_length_bytes: total_length_bytes as u64,
-                fragment_number: next_fragment_number,
-                payload: rest,
+                total_length_bytes,
             },
+            TrafficClass::Control,
             &sent_packet_tracker,
         )
-        .await?;
-        next_fragment_number += 1;
-        sent_so_far += 1;
+        .await
+    };
+    if !flow_control {
+        stream.window_end = stream.total_fragments;
+    } else if stream.awaiting_progress {
+        request_progress().await?;
     }
 
-    // tracing::trace!(stream_id = %stream_id.0, total_packets = %sent_so_far, "stream sent");
+    loop {
+        let connection_dropped = progress.has_changed().is_err();
+        {
+            let latest = progress.borrow_and_update();
+            if let (true, Some(progress)) = (latest.has_changed(), &*latest) {
+                stream.update(progress);
+            }
+        }
+        if stream.is_complete() {
+            // tracing::trace!(stream_id = %stream_id.0, "stream sent");
+            return Ok(());
+        }
+        if connection_dropped {
+            if !flow_control {
+                return Ok(());
+            }
+            break;
+        }
+        if let Some(fragment_number) = stream.next_fragment() {
+            if !wait_for_window(&sent_packet_tracker, &progress).await {
+                // the fragment is sent again once the stream is resumed
+                continue;
+            }
+            let packet_id = last_packet_id.fetch_add(1, std::sync::atomic::Ordering::Release);
+            let outbound_symmetric_key = outbound_symmetric_key.borrow().clone();
+            super::packet_sending(
+                destination_addr,
+                &sender,
+                packet_id,
+                &last_packet_id,
+                &outbound_symmetric_key,
+                vec![],
+                symmetric_message::StreamFragment {
+                    stream_id,
+                    total_length_bytes,
+                    fragment_number,
+                    payload: stream.fragment(fragment_number),
+                },
+                TrafficClass::Bulk,
+                &sent_packet_tracker,
+            )
+            .await?;
+            continue;
+        }
+        if !flow_control {
+            // the packet layer resends the fragments which are lost
+            return Ok(());
+        }
+        match tokio::time::timeout(STREAM_PROGRESS_TIMEOUT, progress.changed()).await {
+            Ok(Ok(())) => {
+                if let Some(progress) = &*progress.borrow() {
+                    stream.update(progress);
+                }
+            }
+            Ok(Err(_)) => break,
+            Err(_) => {
+                tracing::debug!(stream_id = %stream_id.0, "no progress from the receiver");
+                stream.await_progress();
+                request_progress().await?;
+            }
+        }
+    }
 
+    let Some(remote_public_key) = remote_public_key else {
+        tracing::debug!(stream_id = %stream_id.0, "connection dropped, the remote can't be identified to resume the stream");
+        return Ok(());
+    };
+    tracing::debug!(stream_id = %stream_id.0, "connection dropped, suspending stream");
+    stream.await_progress();
+    suspended_streams.suspend_outbound(&remote_public_key, stream_id, stream);
     Ok(())
 }
 
+/// Waits until the congestion window has room for another fragment. Only streams are paced,
+/// other messages are sent from the same task which processes the receipts, so they can't wait.
+///
+/// Returns false if the connection is dropped meanwhile, nothing would open the window then.
+async fn wait_for_window(
+    sent_packet_tracker: &parking_lot::Mutex<SentPacketTracker<InstantTimeSrc>>,
+    progress: &watch::Receiver<Option<FragmentProgress>>,
+) -> bool {
+    let window_opened = sent_packet_tracker.lock().window_opened();
+    loop {
+        let notified = window_opened.notified();
+        if sent_packet_tracker
+            .lock()
+            .can_send(packet_data::MAX_PACKET_SIZE)
+        {
+            return true;
+        }
+        if progress.has_changed().is_err() {
+            return false;
+        }
+        // lost packets also free room in the window, so check again once they would have been
+        // resent even if no receipt arrives
+        let _ = tokio::time::timeout(MESSAGE_CONFIRMATION_TIMEOUT, notified).await;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use aes_gcm::KeyInit;
     use std::net::Ipv4Addr;
     use tests::packet_data::MAX_PACKET_SIZE;
 
-    use super::{
-        symmetric_message::{SymmetricMessage, SymmetricMessagePayload},
-        *,
-    };
-    use crate::transport::packet_data::PacketData;
+    use super::{symmetric_message::SymmetricMessage, *};
+    use crate::transport::{crypto::TransportKeypair, packet_data::PacketData};
+
+    const REMOTE_ADDR: SocketAddr =
+        SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::LOCALHOST), 8080);
+
+    struct TestStream {
+        outbound: mpsc::Receiver<OutboundPacket>,
+        progress: watch::Sender<Option<FragmentProgress>>,
+        cipher: Aes128Gcm,
+        sent_tracker: Arc<parking_lot::Mutex<SentPacketTracker<InstantTimeSrc>>>,
+        task: tokio::task::JoinHandle<Result<(), TransportError>>,
+    }
+
+    impl TestStream {
+        fn spawn(
+            stream: OutboundStream,
+            flow_control: bool,
+            suspended_streams: SuspendedStreams,
+        ) -> Self {
+            Self::spawn_to(stream, flow_control, None, suspended_streams)
+        }
+
+        fn spawn_to(
+            stream: OutboundStream,
+            flow_control: bool,
+            remote_public_key: Option<TransportPublicKey>,
+            suspended_streams: SuspendedStreams,
+        ) -> Self {
+            let (outbound_sender, outbound) = mpsc::channel(1);
+            let (progress, progress_recv) = watch::channel(None);
+            let cipher = {
+                let key = rand::random::<[u8; 16]>();
+                Aes128Gcm::new(&key.into())
+            };
+            let sent_tracker = Arc::new(parking_lot::Mutex::new(SentPacketTracker::new()));
+            let task = tokio::spawn(send_stream(
+                StreamId::next(),
+                Arc::new(AtomicU32::new(0)),
+                outbound_sender,
+                REMOTE_ADDR,
+                stream,
+                flow_control,
+                progress_recv,
+                watch::channel(cipher.clone()).1,
+                sent_tracker.clone(),
+                remote_public_key,
+                suspended_streams,
+            ));
+            Self {
+                outbound,
+                progress,
+                cipher,
+                sent_tracker,
+                task,
+            }
+        }
+
+        /// Returns the payload of the next packet sent, acknowledging it so the congestion
+        /// window keeps opening.
+        async fn next(&mut self) -> Option<SymmetricMessagePayload> {
+            let (_, packet, _) = self.outbound.recv().await?;
+            let decrypted_packet = PacketData::<_, MAX_PACKET_SIZE>::from_buf(packet.as_ref())
+                .try_decrypt_sym(&self.cipher)
+                .expect("decrypt");
+            let deserialized = SymmetricMessage::deser(decrypted_packet.data()).expect("deser");
+            self.sent_tracker
+                .lock()
+                .report_received_receipts(&[deserialized.packet_id]);
+            Some(deserialized.payload)
+        }
+
+        async fn next_fragment(&mut self) -> Option<(u32, Vec<u8>)> {
+            match self.next().await? {
+                SymmetricMessagePayload::StreamFragment {
+                    fragment_number,
+                    payload,
+                    ..
+                } => Some((fragment_number, payload)),
+                other => panic!("Expected a StreamFragment, got {:?}", other),
+            }
+        }
+
+        async fn nothing_sent(&mut self) -> bool {
+            tokio::time::timeout(Duration::from_millis(100), self.outbound.recv())
+                .await
+                .is_err()
+        }
+
+        fn report(
+            &self,
+            last_contiguous: u32,
+            received: impl IntoIterator<Item = u32>,
+            window_end: u32,
+        ) {
+            self.progress.send_replace(Some(FragmentProgress::new(
+                last_contiguous,
+                received,
+                window_end,
+            )));
+        }
+    }
+
+    fn random_message(fragments: usize) -> Vec<u8> {
+        std::iter::repeat(())
+            .take(fragments * MAX_DATA_SIZE - 10)
+            .map(|_| rand::random::<u8>())
+            .collect()
+    }
 
     #[tokio::test]
     async fn test_send_stream_success() -> Result<(), Box<dyn std::error::Error>> {
-        let (outbound_sender, mut outbound_receiver) = mpsc::channel(1);
-        let remote_addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8080);
-        let message: Vec<_> = std::iter::repeat(())
-            .take(100_000)
-            .map(|_| rand::random::<u8>())
-            .collect();
-        let cipher = {
-            let key = rand::random::<[u8; 16]>();
-            Aes128Gcm::new(&key.into())
-        };
-        let sent_tracker = Arc::new(parking_lot::Mutex::new(SentPacketTracker::new()));
-
-        let background_task = tokio::spawn(send_stream(
-            StreamId::next(),
-            Arc::new(AtomicU32::new(0)),
-            outbound_sender,
-            remote_addr,
-            message.clone(),
-            cipher.clone(),
-            sent_tracker,
-        ));
+        let message = random_message(100);
+        let mut stream = TestStream::spawn(
+            OutboundStream::new(message.clone()),
+            true,
+            SuspendedStreams::default(),
+        );
 
         let mut inbound_bytes = Vec::new();
-        while let Some((_, packet)) = outbound_receiver.recv().await {
-            let decrypted_packet = PacketData::<_, MAX_PACKET_SIZE>::from_buf(packet.as_ref())
-                .try_decrypt_sym(&cipher)
-                .map_err(|e| e.to_string())?;
-            let deserialized = SymmetricMessage::deser(decrypted_packet.data())?;
-            let SymmetricMessagePayload::StreamFragment { payload, .. } = deserialized.payload
-            else {
-                panic!("Expected a StreamFragment, got {:?}", deserialized.payload);
-            };
+        while let Some((fragment_number, payload)) = stream.next_fragment().await {
             inbound_bytes.extend_from_slice(payload.as_ref());
+            stream.report(fragment_number, [], fragment_number + INITIAL_STREAM_WINDOW);
         }
 
-        let result = background_task.await?;
+        let result = stream.task.await?;
         assert!(result.is_ok());
-        assert_eq!(&message[..10], &inbound_bytes[..10]);
-        assert_eq!(inbound_bytes.len(), 100_000);
-        assert_eq!(&message[99_990..], &inbound_bytes[99_990..]);
+        assert_eq!(message, inbound_bytes);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_stream_within_window() -> Result<(), Box<dyn std::error::Error>> {
+        let total_fragments = INITIAL_STREAM_WINDOW + 10;
+        let mut stream = TestStream::spawn(
+            OutboundStream::new(random_message(total_fragments as usize)),
+            true,
+            SuspendedStreams::default(),
+        );
+
+        for expected in 1..=INITIAL_STREAM_WINDOW {
+            let (fragment_number, _) = stream.next_fragment().await.unwrap();
+            assert_eq!(fragment_number, expected);
+        }
+        assert!(stream.nothing_sent().await);
+
+        // the receiver got all but the first fragment and opens its window
+        stream.report(0, 2..=INITIAL_STREAM_WINDOW, total_fragments);
+        for expected in INITIAL_STREAM_WINDOW + 1..=total_fragments {
+            let (fragment_number, _) = stream.next_fragment().await.unwrap();
+            assert_eq!(fragment_number, expected);
+        }
+        // the first fragment is still in flight, the stream isn't done until it's received
+        assert!(stream.nothing_sent().await);
+        assert!(!stream.task.is_finished());
+        stream.report(total_fragments, [], total_fragments);
+        assert!(stream.next().await.is_none());
+        stream.task.await??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_stream_without_flow_control() -> Result<(), Box<dyn std::error::Error>> {
+        let total_fragments = INITIAL_STREAM_WINDOW + 10;
+        let mut stream = TestStream::spawn(
+            OutboundStream::new(random_message(total_fragments as usize)),
+            false,
+            SuspendedStreams::default(),
+        );
+
+        // older receivers get all the fragments in order without reporting progress
+        for expected in 1..=total_fragments {
+            let (fragment_number, _) = stream.next_fragment().await.unwrap();
+            assert_eq!(fragment_number, expected);
+        }
+        assert!(stream.next().await.is_none());
+        stream.task.await??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resume_stream() -> Result<(), Box<dyn std::error::Error>> {
+        let message = random_message(12);
+        let remote = TransportKeypair::new().public().clone();
+        let suspended_streams = SuspendedStreams::default();
+        let mut stream = TestStream::spawn_to(
+            OutboundStream::new(message.clone()),
+            true,
+            Some(remote.clone()),
+            suspended_streams.clone(),
+        );
+        for _ in 1..=12 {
+            stream.next_fragment().await.unwrap();
+        }
+        stream.report(3, [5], 10);
+
+        // the connection drops
+        drop(stream.progress);
+        stream.task.await??;
+        // only to the same remote
+        let other = TransportKeypair::new().public().clone();
+        assert!(suspended_streams.resume_outbound(&other).is_empty());
+        let mut resumed = suspended_streams.resume_outbound(&remote);
+        assert_eq!(resumed.len(), 1);
+        let (_, outbound_stream) = resumed.pop().unwrap();
+
+        // on the new connection the receiver is asked which fragments are missing
+        let mut stream =
+            TestStream::spawn_to(outbound_stream, true, Some(remote), suspended_streams);
+        let Some(SymmetricMessagePayload::StreamResume {
+            total_length_bytes, ..
+        }) = stream.next().await
+        else {
+            panic!("Expected a StreamResume");
+        };
+        assert_eq!(total_length_bytes, message.len() as u64);
+        assert!(stream.nothing_sent().await);
+
+        // only what the receiver is missing is sent again
+        stream.report(3, [5, 7], 10);
+        let mut sent = vec![];
+        for _ in 0..5 {
+            let (fragment_number, payload) = stream.next_fragment().await.unwrap();
+            let start = (fragment_number as usize - 1) * MAX_DATA_SIZE;
+            assert_eq!(payload, message[start..start + payload.len()]);
+            sent.push(fragment_number);
+        }
+        assert_eq!(sent, vec![4, 6, 8, 9, 10]);
+        assert!(stream.nothing_sent().await);
+        stream.report(12, [], 12);
+        assert!(stream.next().await.is_none());
+        stream.task.await??;
         Ok(())
     }
 }
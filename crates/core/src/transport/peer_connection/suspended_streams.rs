@@ -0,0 +1,193 @@
+//! Streams interrupted by a dropped connection, kept for a while so they can be resumed where
+//! they left off if a connection to the same remote is established again.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::{
+    inbound_stream::{InboundStream, MAX_CONCURRENT_INBOUND_STREAMS},
+    outbound_stream::OutboundStream,
+    StreamId,
+};
+use crate::transport::crypto::TransportPublicKey;
+
+/// How long interrupted streams are kept waiting for the remote to connect again.
+const RESUME_TIMEOUT: Duration = Duration::from_secs(120);
+/// Interrupted inbound streams kept for each remote, as many as it can send at once.
+const MAX_SUSPENDED_INBOUND_PER_REMOTE: usize = MAX_CONCURRENT_INBOUND_STREAMS;
+/// Bytes of the interrupted inbound streams kept across all the remotes.
+const MAX_SUSPENDED_INBOUND_BYTES: usize = 256 * 1024 * 1024;
+
+type StreamKey = (TransportPublicKey, StreamId);
+
+/// Shared by all the connections of a transport, stream ids are only unique per sender so the
+/// streams are keyed by the static key of the remote too. Only remotes whose key was
+/// authenticated by the handshake resume streams, so a different peer which later gets the same
+/// address can't take them over.
+#[derive(Clone, Default)]
+pub(crate) struct SuspendedStreams(Arc<parking_lot::Mutex<Streams>>);
+
+#[derive(Default)]
+struct Streams {
+    inbound: HashMap<StreamKey, (InboundStream, Instant)>,
+    outbound: HashMap<StreamKey, (OutboundStream, Instant)>,
+    /// Inbound streams already delivered along with their length and number of fragments, so a
+    /// sender which missed the final progress before the connection dropped doesn't send them
+    /// again.
+    completed: HashMap<StreamKey, (u64, u32, Instant)>,
+}
+
+impl SuspendedStreams {
+    fn lock(&self) -> parking_lot::MutexGuard<'_, Streams> {
+        let mut streams = self.0.lock();
+        let now = Instant::now();
+        streams
+            .inbound
+            .retain(|_, (_, suspended_at)| now.duration_since(*suspended_at) < RESUME_TIMEOUT);
+        streams
+            .outbound
+            .retain(|_, (_, suspended_at)| now.duration_since(*suspended_at) < RESUME_TIMEOUT);
+        streams
+            .completed
+            .retain(|_, (_, _, completed_at)| now.duration_since(*completed_at) < RESUME_TIMEOUT);
+        streams
+    }
+
+    /// Keeps an interrupted inbound stream, unless the remote already has as many suspended as
+    /// it could be sending or they would take too much memory, so reconnecting over and over
+    /// doesn't pile them up.
+    pub(super) fn suspend_inbound(
+        &self,
+        remote: &TransportPublicKey,
+        stream_id: StreamId,
+        stream: InboundStream,
+    ) {
+        let mut streams = self.lock();
+        let from_remote = streams
+            .inbound
+            .keys()
+            .filter(|(key, _)| key == remote)
+            .count();
+        let suspended_bytes: usize = streams
+            .inbound
+            .values()
+            .map(|(stream, _)| stream.buffered_bytes())
+            .sum();
+        if from_remote >= MAX_SUSPENDED_INBOUND_PER_REMOTE
+            || suspended_bytes + stream.buffered_bytes() > MAX_SUSPENDED_INBOUND_BYTES
+        {
+            tracing::debug!(%stream_id, "too many suspended inbound streams, dropping stream");
+            return;
+        }
+        streams
+            .inbound
+            .insert((remote.clone(), stream_id), (stream, Instant::now()));
+    }
+
+    /// Returns the fragments received so far of a stream if it was interrupted. A stream with a
+    /// different length must be a new one from a sender which was restarted, so it is discarded.
+    pub(super) fn resume_inbound(
+        &self,
+        remote: &TransportPublicKey,
+        stream_id: StreamId,
+        total_length_bytes: u64,
+    ) -> Option<InboundStream> {
+        let (stream, _) = self.lock().inbound.remove(&(remote.clone(), stream_id))?;
+        (stream.total_length_bytes() == total_length_bytes).then_some(stream)
+    }
+
+    pub(super) fn complete_inbound(
+        &self,
+        remote: &TransportPublicKey,
+        stream_id: StreamId,
+        total_length_bytes: u64,
+        total_fragments: u32,
+    ) {
+        self.lock().completed.insert(
+            (remote.clone(), stream_id),
+            (total_length_bytes, total_fragments, Instant::now()),
+        );
+    }
+
+    /// Returns the number of fragments of the stream if it was already delivered. A stream with
+    /// a different length is a new one reusing the id, so it isn't reported as delivered.
+    pub(super) fn completed_inbound(
+        &self,
+        remote: &TransportPublicKey,
+        stream_id: StreamId,
+        total_length_bytes: u64,
+    ) -> Option<u32> {
+        self.lock()
+            .completed
+            .get(&(remote.clone(), stream_id))
+            .filter(|(length, _, _)| *length == total_length_bytes)
+            .map(|(_, total_fragments, _)| *total_fragments)
+    }
+
+    pub(super) fn suspend_outbound(
+        &self,
+        remote: &TransportPublicKey,
+        stream_id: StreamId,
+        stream: OutboundStream,
+    ) {
+        self.lock()
+            .outbound
+            .insert((remote.clone(), stream_id), (stream, Instant::now()));
+    }
+
+    /// Takes the streams which were being sent to the remote when the last connection dropped.
+    pub(super) fn resume_outbound(
+        &self,
+        remote: &TransportPublicKey,
+    ) -> Vec<(StreamId, OutboundStream)> {
+        let mut streams = self.lock();
+        let keys: Vec<_> = streams
+            .outbound
+            .keys()
+            .filter(|(key, _)| key == remote)
+            .cloned()
+            .collect();
+        keys.into_iter()
+            .filter_map(|key| {
+                let (stream, _) = streams.outbound.remove(&key)?;
+                Some((key.1, stream))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::crypto::TransportKeypair;
+
+    #[test]
+    fn suspended_inbound_capped_per_remote() {
+        let suspended_streams = SuspendedStreams::default();
+        let remote = TransportKeypair::new().public().clone();
+        let ids: Vec<_> = (0..=MAX_SUSPENDED_INBOUND_PER_REMOTE)
+            .map(|_| StreamId::next())
+            .collect();
+        for stream_id in &ids {
+            suspended_streams.suspend_inbound(&remote, *stream_id, InboundStream::new(10));
+        }
+
+        let other = TransportKeypair::new().public().clone();
+        let stream_id = StreamId::next();
+        suspended_streams.suspend_inbound(&other, stream_id, InboundStream::new(10));
+        assert!(suspended_streams
+            .resume_inbound(&other, stream_id, 10)
+            .is_some());
+
+        let resumed = ids
+            .iter()
+            .filter(|stream_id| {
+                suspended_streams
+                    .resume_inbound(&remote, **stream_id, 10)
+                    .is_some()
+            })
+            .count();
+        assert_eq!(resumed, MAX_SUSPENDED_INBOUND_PER_REMOTE);
+    }
+}
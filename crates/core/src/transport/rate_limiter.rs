@@ -1,60 +1,189 @@
 use tokio::sync::mpsc;
 
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use super::Socket;
+use super::{bandwidth::BandwidthUsage, packet_data::MAX_PACKET_SIZE, Socket};
 use crate::util::time_source::{InstantTimeSrc, TimeSource};
 
+/// Packets held by the rate limiter waiting for their turn, past this senders wait until
+/// there is room in the channel.
+const MAX_QUEUED_PACKETS: usize = 1024;
+
+/// Priority of a packet queued for sending, packets of a higher class are always sent first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum TrafficClass {
+    /// Stream fragments
+    Bulk,
+    /// Operation requests like gets and puts, and retransmissions
+    Request,
+    /// Connection handshakes, receipts and other control packets of the transport, as well as
+    /// connect and other control operations of the network
+    Control,
+}
+
+pub(crate) type OutboundPacket = (SocketAddr, Arc<[u8]>, TrafficClass);
+
+/// Packets waiting to be sent. Classes are served by strict priority, and the remotes within
+/// a class in round robin so a single busy connection can't starve the rest.
+#[derive(Default)]
+struct FairQueue {
+    classes: [ClassQueue; 3],
+    len: usize,
+}
+
+#[derive(Default)]
+struct ClassQueue {
+    /// Remotes with queued packets, in the order they will be served
+    turns: VecDeque<SocketAddr>,
+    packets: HashMap<SocketAddr, VecDeque<Arc<[u8]>>>,
+}
+
+impl FairQueue {
+    fn push(&mut self, (remote_addr, packet, class): OutboundPacket) {
+        let queue = &mut self.classes[class as usize];
+        let remote_packets = queue.packets.entry(remote_addr).or_default();
+        if remote_packets.is_empty() {
+            queue.turns.push_back(remote_addr);
+        }
+        remote_packets.push_back(packet);
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<(SocketAddr, Arc<[u8]>)> {
+        let queue = self
+            .classes
+            .iter_mut()
+            .rev()
+            .find(|queue| !queue.turns.is_empty())?;
+        let remote_addr = queue.turns.pop_front()?;
+        let remote_packets = queue.packets.get_mut(&remote_addr)?;
+        let packet = remote_packets.pop_front()?;
+        if remote_packets.is_empty() {
+            queue.packets.remove(&remote_addr);
+        } else {
+            queue.turns.push_back(remote_addr);
+        }
+        self.len -= 1;
+        Some((remote_addr, packet))
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Tracks the bandwidth received by all the connections, so inbound streams can be throttled
+/// when it exceeds the downstream limit without delaying the rest of the traffic.
+///
+/// Clones share the same usage.
+#[derive(Clone)]
+pub(crate) struct DownstreamLimiter {
+    limiter: Arc<parking_lot::Mutex<PacketRateLimiter<InstantTimeSrc>>>,
+    bandwidth_limit: usize,
+}
+
+impl DownstreamLimiter {
+    /// `bandwidth_limit` is in bytes per window.
+    pub(super) fn new(window_size: Duration, bandwidth_limit: usize) -> Self {
+        Self {
+            limiter: Arc::new(parking_lot::Mutex::new(PacketRateLimiter::new(window_size))),
+            bandwidth_limit,
+        }
+    }
+
+    /// Report that a packet was received
+    pub(super) fn add_packet(&self, packet_size: usize) {
+        self.limiter.lock().add_packet(packet_size);
+    }
+
+    /// Whether another packet would exceed the limit within the window.
+    pub(super) fn is_exceeded(&self) -> bool {
+        self.limiter
+            .lock()
+            .can_send_packet(self.bandwidth_limit, MAX_PACKET_SIZE)
+            .is_some()
+    }
+}
+
+impl Default for DownstreamLimiter {
+    /// Without any limit.
+    fn default() -> Self {
+        Self::new(Duration::from_secs(1), usize::MAX)
+    }
+}
+
 /// Keeps track of the bandwidth used in the last window_size. Recommend a `window_size` of
 /// 10 seconds.
 pub(super) struct PacketRateLimiter<T: TimeSource> {
     packets: VecDeque<(usize, Instant)>,
     window_size: Duration,
     current_bandwidth: usize,
-    outbound_packets: mpsc::Receiver<(SocketAddr, Arc<[u8]>)>,
     time_source: T,
 }
 
 impl PacketRateLimiter<InstantTimeSrc> {
-    pub(super) fn new(
-        window_size: Duration,
-        outbound_packets: mpsc::Receiver<(SocketAddr, Arc<[u8]>)>,
-    ) -> Self {
+    pub(super) fn new(window_size: Duration) -> Self {
         PacketRateLimiter {
             packets: VecDeque::new(),
             window_size,
             current_bandwidth: 0,
-            outbound_packets,
             time_source: InstantTimeSrc::new(),
         }
     }
 }
 
 impl<T: TimeSource> PacketRateLimiter<T> {
-    pub(super) async fn rate_limiter<S: Socket>(mut self, bandwidth_limit: usize, socket: Arc<S>) {
+    pub(super) async fn rate_limiter<S: Socket>(
+        mut self,
+        bandwidth_limit: usize,
+        mut outbound_packets: mpsc::Receiver<OutboundPacket>,
+        socket: Arc<S>,
+        usage: BandwidthUsage,
+    ) {
         tracing::info!(bandwidth_limit, "Rate limiter task started");
-        while let Some((socket_addr, packet)) = self.outbound_packets.recv().await {
-            if let Some(wait_time) = self.can_send_packet(bandwidth_limit, packet.len()) {
+        let mut queue = FairQueue::default();
+        loop {
+            if queue.is_empty() {
+                let Some(packet) = outbound_packets.recv().await else {
+                    break;
+                };
+                queue.push(packet);
+            }
+            while queue.len() < MAX_QUEUED_PACKETS {
+                let Ok(packet) = outbound_packets.try_recv() else {
+                    break;
+                };
+                queue.push(packet);
+            }
+            // wait before picking the next packet, so packets of a higher class queued in the
+            // meantime go first
+            if let Some(wait_time) = self.can_send_packet(bandwidth_limit, MAX_PACKET_SIZE) {
+                tracing::debug!("Waiting {:?} before sending more packets", wait_time);
                 tokio::time::sleep(wait_time).await;
-                tracing::debug!(%socket_addr, "Sending outbound packet after waiting {:?}", wait_time);
-                if let Err(error) = socket.send_to(&packet, socket_addr).await {
-                    tracing::debug!("Error sending packet: {:?}", error);
-                    continue;
-                }
-            } else if let Err(error) = socket.send_to(&packet, socket_addr).await {
+                continue;
+            }
+            let Some((socket_addr, packet)) = queue.pop() else {
+                continue;
+            };
+            if let Err(error) = socket.send_to(&packet, socket_addr).await {
                 tracing::debug!(%socket_addr, "Error sending packet: {:?}", error);
                 continue;
             }
             self.add_packet(packet.len());
+            usage.record_sent(socket_addr, packet.len());
         }
         tracing::debug!("Rate limiter task ended unexpectedly");
     }
 
     /// Report that a packet was sent
-    fn add_packet(&mut self, packet_size: usize) {
+    pub(super) fn add_packet(&mut self, packet_size: usize) {
         let now = self.time_source.now();
         self.packets.push_back((packet_size, now));
         self.current_bandwidth += packet_size;
@@ -83,7 +212,11 @@ impl<T: TimeSource> PacketRateLimiter<T> {
     /// `bandwidth_limit` (in bytes) should be set to 50% higher than the target upstream bandwidth the
     /// [topology manager](crate::topology::TopologyManager) is aiming for, as it serves
     /// as a hard limit which we'd prefer not to hit.
-    fn can_send_packet(&mut self, bandwidth_limit: usize, packet_size: usize) -> Option<Duration> {
+    pub(super) fn can_send_packet(
+        &mut self,
+        bandwidth_limit: usize,
+        packet_size: usize,
+    ) -> Option<Duration> {
         self.cleanup();
 
         if self.current_bandwidth + packet_size <= bandwidth_limit {
@@ -118,7 +251,6 @@ mod tests {
             packets: VecDeque::new(),
             window_size,
             current_bandwidth: 0,
-            outbound_packets: mpsc::channel(1).1,
             time_source: MockTimeSource::new(Instant::now()),
         }
     }
@@ -133,7 +265,7 @@ mod tests {
 
     #[test]
     fn test_adding_packets() {
-        let mut tracker = PacketRateLimiter::new(Duration::from_secs(1), mpsc::channel(1).1);
+        let mut tracker = PacketRateLimiter::new(Duration::from_secs(1));
         verify_bandwidth_match(&tracker);
         tracker.add_packet(1500);
         verify_bandwidth_match(&tracker);
@@ -142,7 +274,7 @@ mod tests {
 
     #[test]
     fn test_bandwidth_calculation() {
-        let mut tracker = PacketRateLimiter::new(Duration::from_secs(1), mpsc::channel(1).1);
+        let mut tracker = PacketRateLimiter::new(Duration::from_secs(1));
         tracker.add_packet(1500);
         tracker.add_packet(2500);
         verify_bandwidth_match(&tracker);
@@ -179,8 +311,56 @@ mod tests {
 
     #[test]
     fn test_immediate_send() {
-        let mut tracker = PacketRateLimiter::new(Duration::from_millis(10), mpsc::channel(1).1);
+        let mut tracker = PacketRateLimiter::new(Duration::from_millis(10));
         tracker.add_packet(3000);
         assert_eq!(tracker.can_send_packet(10000, 2000), None);
     }
+
+    fn queued(remote: u16, class: TrafficClass) -> OutboundPacket {
+        (
+            (std::net::Ipv4Addr::LOCALHOST, remote).into(),
+            vec![remote as u8].into(),
+            class,
+        )
+    }
+
+    #[test]
+    fn test_priority_classes() {
+        let mut queue = FairQueue::default();
+        queue.push(queued(1, TrafficClass::Bulk));
+        queue.push(queued(2, TrafficClass::Request));
+        queue.push(queued(3, TrafficClass::Control));
+        queue.push(queued(4, TrafficClass::Request));
+        let order: Vec<_> = std::iter::from_fn(|| queue.pop())
+            .map(|(addr, _)| addr.port())
+            .collect();
+        assert_eq!(order, vec![3, 2, 4, 1]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_round_robin_between_remotes() {
+        let mut queue = FairQueue::default();
+        for _ in 0..3 {
+            queue.push(queued(1, TrafficClass::Bulk));
+        }
+        queue.push(queued(2, TrafficClass::Bulk));
+        queue.push(queued(2, TrafficClass::Bulk));
+        let order: Vec<_> = std::iter::from_fn(|| queue.pop())
+            .map(|(addr, _)| addr.port())
+            .collect();
+        assert_eq!(order, vec![1, 2, 1, 2, 1]);
+    }
+
+    #[test]
+    fn downstream_limit() {
+        let limiter = DownstreamLimiter::new(Duration::from_secs(1), 3 * MAX_PACKET_SIZE);
+        limiter.add_packet(MAX_PACKET_SIZE);
+        assert!(!limiter.is_exceeded());
+        // shared by all the connections
+        limiter.clone().add_packet(MAX_PACKET_SIZE);
+        limiter.clone().add_packet(MAX_PACKET_SIZE);
+        assert!(limiter.is_exceeded());
+        assert!(!DownstreamLimiter::default().is_exceeded());
+    }
 }
@@ -71,6 +71,14 @@ impl<T: TimeSource> ReceivedPacketTracker<T> {
         }
     }
 
+    /// Acknowledges again a packet that was already received, in case the receipt was lost and
+    /// the remote is retransmitting it.
+    pub(super) fn report_duplicate_packet(&mut self, packet_id: PacketId) {
+        if !self.pending_receipts.contains(&packet_id) {
+            self.pending_receipts.push(packet_id);
+        }
+    }
+
     /// Returns a list of packets that have been received since the last call to this function.
     /// This should be called every time a packet is sent to ensure that receipts are sent
     /// promptly. Every `MAX_CONFIRMATION_DELAY` (50ms) this should be called and if the returned
@@ -0,0 +1,133 @@
+use crate::transport::PacketId;
+
+/// Number of packet ids behind the highest one received that are still tracked. Lost packets
+/// are retransmitted with their original id, so this must cover the packets sent while a
+/// retransmission is pending even at high throughput.
+const WINDOW_SIZE: u32 = 16_384;
+const WORDS: usize = WINDOW_SIZE as usize / 64;
+
+/// Sliding window over the ids of the packets received from a remote, used to drop duplicated
+/// or replayed packets before they are deserialized.
+///
+/// Packets older than the window are rejected, since we can't tell whether they were received.
+/// Packet ids wrap around once they reach `u32::MAX`, so ids are compared by their distance
+/// modulo 2^32: an id less than 2^31 ahead of the highest one is newer.
+pub(super) struct ReplayWindow {
+    highest: Option<PacketId>,
+    received: Box<[u64; WORDS]>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(super) enum ReplayCheck {
+    /// First time the packet is received, it has been recorded
+    New,
+    /// The packet was already received, it should be acknowledged again but otherwise ignored
+    Duplicate,
+    /// The packet is too old to be tracked
+    TooOld,
+}
+
+impl ReplayWindow {
+    pub(super) fn new() -> Self {
+        Self {
+            highest: None,
+            received: Box::new([0; WORDS]),
+        }
+    }
+
+    pub(super) fn check(&mut self, packet_id: PacketId) -> ReplayCheck {
+        let Some(highest) = self.highest else {
+            self.highest = Some(packet_id);
+            self.set(packet_id);
+            return ReplayCheck::New;
+        };
+        let ahead = packet_id.wrapping_sub(highest);
+        if ahead != 0 && ahead < 1 << 31 {
+            if ahead >= WINDOW_SIZE {
+                self.received.fill(0);
+            } else {
+                for offset in 1..ahead {
+                    self.clear(highest.wrapping_add(offset));
+                }
+            }
+            self.highest = Some(packet_id);
+            self.set(packet_id);
+            return ReplayCheck::New;
+        }
+        if highest.wrapping_sub(packet_id) >= WINDOW_SIZE {
+            return ReplayCheck::TooOld;
+        }
+        if self.is_set(packet_id) {
+            return ReplayCheck::Duplicate;
+        }
+        self.set(packet_id);
+        ReplayCheck::New
+    }
+
+    fn position(packet_id: PacketId) -> (usize, u64) {
+        let bit = packet_id % WINDOW_SIZE;
+        ((bit / 64) as usize, 1 << (bit % 64))
+    }
+
+    fn set(&mut self, packet_id: PacketId) {
+        let (word, mask) = Self::position(packet_id);
+        self.received[word] |= mask;
+    }
+
+    fn clear(&mut self, packet_id: PacketId) {
+        let (word, mask) = Self::position(packet_id);
+        self.received[word] &= !mask;
+    }
+
+    fn is_set(&self, packet_id: PacketId) -> bool {
+        let (word, mask) = Self::position(packet_id);
+        self.received[word] & mask != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_duplicates() {
+        let mut window = ReplayWindow::new();
+        assert_eq!(window.check(0), ReplayCheck::New);
+        assert_eq!(window.check(2), ReplayCheck::New);
+        assert_eq!(window.check(2), ReplayCheck::Duplicate);
+        // reordered packets are still accepted once
+        assert_eq!(window.check(1), ReplayCheck::New);
+        assert_eq!(window.check(1), ReplayCheck::Duplicate);
+        assert_eq!(window.check(0), ReplayCheck::Duplicate);
+    }
+
+    #[test]
+    fn slides_forward() {
+        let mut window = ReplayWindow::new();
+        assert_eq!(window.check(5), ReplayCheck::New);
+        assert_eq!(window.check(5 + WINDOW_SIZE - 1), ReplayCheck::New);
+        assert_eq!(window.check(5), ReplayCheck::Duplicate);
+        assert_eq!(window.check(5 + WINDOW_SIZE), ReplayCheck::New);
+        assert_eq!(window.check(5), ReplayCheck::TooOld);
+        // the bit of the old id is reused for a new one
+        assert_eq!(window.check(6), ReplayCheck::New);
+
+        assert_eq!(window.check(10 * WINDOW_SIZE), ReplayCheck::New);
+        assert_eq!(window.check(10 * WINDOW_SIZE - 1), ReplayCheck::New);
+        assert_eq!(window.check(9 * WINDOW_SIZE), ReplayCheck::TooOld);
+    }
+
+    #[test]
+    fn wraps_around() {
+        let mut window = ReplayWindow::new();
+        assert_eq!(window.check(u32::MAX - 1), ReplayCheck::New);
+        assert_eq!(window.check(1), ReplayCheck::New);
+        assert_eq!(window.check(u32::MAX), ReplayCheck::New);
+        assert_eq!(window.check(0), ReplayCheck::New);
+        assert_eq!(window.check(u32::MAX - 1), ReplayCheck::Duplicate);
+        assert_eq!(window.check(1), ReplayCheck::Duplicate);
+        assert_eq!(window.check(u32::MAX - WINDOW_SIZE), ReplayCheck::TooOld);
+        assert_eq!(window.check(WINDOW_SIZE), ReplayCheck::New);
+        assert_eq!(window.check(u32::MAX), ReplayCheck::TooOld);
+    }
+}
@@ -1,8 +1,10 @@
+use super::congestion_control::CongestionController;
 use super::PacketId;
 use crate::util::time_source::{InstantTimeSrc, TimeSource};
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
+use tokio::sync::Notify;
 
 const NETWORK_DELAY_ALLOWANCE: Duration = Duration::from_millis(500);
 
@@ -12,7 +14,8 @@ const MAX_CONFIRMATION_DELAY: Duration = Duration::from_millis(100);
 
 /// If we don't get a receipt for a message within 500ms, we assume the message was lost and
 /// resend it. This must be significantly higher than MAX_CONFIRMATION_DELAY (100ms) to
-/// account for network delay
+/// account for network delay. Once the RTT to the remote is known the timeout adapts to it,
+/// but it never goes below this value.
 pub(super) const MESSAGE_CONFIRMATION_TIMEOUT: Duration = {
     let millis: u128 = MAX_CONFIRMATION_DELAY.as_millis() + NETWORK_DELAY_ALLOWANCE.as_millis();
 
@@ -54,21 +57,38 @@ const PACKET_LOSS_DECAY_FACTOR: f64 = 1.0 / 1000.0;
 /// ```
 pub(super) struct SentPacketTracker<T: TimeSource> {
     /// The list of packets that have been sent but not yet acknowledged
-    pending_receipts: HashMap<PacketId, Arc<[u8]>>,
+    pending_receipts: HashMap<PacketId, SentPacket>,
 
     resend_queue: VecDeque<ResendQueueEntry>,
 
     packet_loss_proportion: f64,
 
+    /// Packets handed out by `get_resend`, their RTT can't be measured once they are sent again
+    resent: HashSet<PacketId>,
+
+    congestion: CongestionController,
+
+    /// Notified whenever receipts free room in the congestion window
+    window_opened: Arc<Notify>,
+
     pub(super) time_source: T,
 }
 
+struct SentPacket {
+    payload: Arc<[u8]>,
+    sent_at: Instant,
+    retransmitted: bool,
+}
+
 impl SentPacketTracker<InstantTimeSrc> {
     pub(super) fn new() -> Self {
         SentPacketTracker {
             pending_receipts: HashMap::new(),
             resend_queue: VecDeque::new(),
             packet_loss_proportion: 0.0,
+            resent: HashSet::new(),
+            congestion: CongestionController::new(),
+            window_opened: Arc::new(Notify::new()),
             time_source: InstantTimeSrc::new(),
         }
     }
@@ -76,21 +96,62 @@ impl SentPacketTracker<InstantTimeSrc> {
 
 impl<T: TimeSource> SentPacketTracker<T> {
     pub(super) fn report_sent_packet(&mut self, packet_id: PacketId, payload: Arc<[u8]>) {
-        self.pending_receipts.insert(packet_id, payload);
+        let now = self.time_source.now();
+        self.congestion.on_sent(payload.len());
+        let sent = SentPacket {
+            payload,
+            sent_at: now,
+            retransmitted: self.resent.remove(&packet_id),
+        };
+        if let Some(replaced) = self.pending_receipts.insert(packet_id, sent) {
+            self.congestion.on_ack(replaced.payload.len(), None, now);
+        }
         self.resend_queue.push_back(ResendQueueEntry {
-            timeout_at: self.time_source.now() + MESSAGE_CONFIRMATION_TIMEOUT,
+            timeout_at: now
+                + self
+                    .congestion
+                    .retransmission_timeout(MESSAGE_CONFIRMATION_TIMEOUT),
             packet_id,
         });
     }
 
     pub(super) fn report_received_receipts(&mut self, packet_ids: &[PacketId]) {
+        let now = self.time_source.now();
+        let mut acknowledged = 0;
+        let mut rtt: Option<Duration> = None;
         for packet_id in packet_ids {
             // This can be simplified but I'm leaving it like this for readability.
             self.packet_loss_proportion = self.packet_loss_proportion
                 * (1.0 - PACKET_LOSS_DECAY_FACTOR)
                 + (PACKET_LOSS_DECAY_FACTOR * 0.0);
-            self.pending_receipts.remove(packet_id);
+            if let Some(sent) = self.pending_receipts.remove(packet_id) {
+                acknowledged += sent.payload.len();
+                if !sent.retransmitted {
+                    let sample = now.saturating_duration_since(sent.sent_at);
+                    rtt = Some(rtt.map_or(sample, |rtt| rtt.min(sample)));
+                }
+            }
         }
+        if acknowledged > 0 {
+            // Receipts are delayed so they can be batched, the most recent packet of the batch
+            // gives the closest sample to the actual RTT.
+            self.congestion.on_ack(acknowledged, rtt, now);
+            self.window_opened.notify_waiters();
+        }
+    }
+
+    /// Whether the congestion window has room for a packet of the given size.
+    pub(super) fn can_send(&self, bytes: usize) -> bool {
+        self.congestion.can_send(bytes)
+    }
+
+    /// Notified when receipts free room in the congestion window.
+    pub(super) fn window_opened(&self) -> Arc<Notify> {
+        self.window_opened.clone()
+    }
+
+    pub(super) fn is_acknowledged(&self, packet_id: PacketId) -> bool {
+        !self.pending_receipts.contains_key(&packet_id)
     }
 
     /// Either get a packet that needs to be resent, or how long the caller should wait until
@@ -113,8 +174,10 @@ impl<T: TimeSource> SentPacketTracker<T> {
                 self.packet_loss_proportion = self.packet_loss_proportion
                     * (1.0 - PACKET_LOSS_DECAY_FACTOR)
                     + PACKET_LOSS_DECAY_FACTOR;
+                self.congestion.on_loss(packet.payload.len(), now);
+                self.resent.insert(entry.packet_id);
 
-                return ResendAction::Resend(entry.packet_id, packet);
+                return ResendAction::Resend(entry.packet_id, packet.payload);
             }
             // If the packet is no longer in pending_receipts, it means its receipt has been received.
             // No action needed, continue to check the next entry in the queue.
@@ -139,6 +202,7 @@ struct ResendQueueEntry {
 #[cfg(test)]
 pub(in crate::transport) mod tests {
     use super::*;
+    use crate::transport::packet_data::MAX_PACKET_SIZE;
     use crate::transport::MessagePayload;
     use crate::util::time_source::MockTimeSource;
 
@@ -149,6 +213,9 @@ pub(in crate::transport) mod tests {
             pending_receipts: HashMap::new(),
             resend_queue: VecDeque::new(),
             packet_loss_proportion: 0.0,
+            resent: HashSet::new(),
+            congestion: CongestionController::new(),
+            window_opened: Arc::new(Notify::new()),
             time_source,
         }
     }
@@ -218,6 +285,41 @@ pub(in crate::transport) mod tests {
         }
     }
 
+    #[test]
+    fn test_congestion_window() {
+        let mut tracker = mock_sent_packet_tracker();
+        let packet: Arc<[u8]> = vec![0; MAX_PACKET_SIZE].into();
+        let mut packet_id = 0;
+        while tracker.can_send(MAX_PACKET_SIZE) {
+            tracker.report_sent_packet(packet_id, packet.clone());
+            packet_id += 1;
+        }
+
+        // receipts free room in the window and let it grow
+        tracker.time_source.advance_time(Duration::from_millis(50));
+        tracker.report_received_receipts(&(0..packet_id).collect::<Vec<_>>());
+        let mut grown_window = 0;
+        while tracker.can_send(MAX_PACKET_SIZE) {
+            tracker.report_sent_packet(packet_id, packet.clone());
+            packet_id += 1;
+            grown_window += 1;
+        }
+        assert!(grown_window > packet_id - grown_window);
+
+        // losing the packets shrinks it
+        tracker
+            .time_source
+            .advance_time(MESSAGE_CONFIRMATION_TIMEOUT);
+        while let ResendAction::Resend(..) = tracker.get_resend() {}
+        let mut shrunk_window = 0;
+        while tracker.can_send(MAX_PACKET_SIZE) {
+            tracker.report_sent_packet(packet_id, packet.clone());
+            packet_id += 1;
+            shrunk_window += 1;
+        }
+        assert!(shrunk_window < grown_window);
+    }
+
     #[test]
     fn test_get_resend_with_pending_receipts() {
         let mut tracker = mock_sent_packet_tracker();
@@ -7,8 +7,8 @@ use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 
 use super::{
-    packet_data::PacketData, packet_data::MAX_DATA_SIZE, peer_connection::StreamId, MessagePayload,
-    PacketId,
+    crypto::TransportPublicKey, packet_data::PacketData, packet_data::MAX_DATA_SIZE,
+    peer_connection::StreamId, MessagePayload, PacketId,
 };
 
 #[serde_as]
@@ -28,6 +28,13 @@ impl SymmetricMessage {
         bincode::deserialize(bytes)
     }
 
+    /// Reads the id of a serialized message without deserializing the rest of it.
+    pub fn packet_id(bytes: &[u8]) -> Option<PacketId> {
+        bytes
+            .get(..std::mem::size_of::<PacketId>())
+            .and_then(|id| bincode::deserialize(id).ok())
+    }
+
     const ACK_ERROR_MSG: &str = concat!(
         "remote is using a different protocol version, expected version ",
         env!("CARGO_PKG_VERSION")
@@ -96,6 +103,7 @@ impl SymmetricMessage {
         outbound_sym_key: &Aes128Gcm,
         our_inbound_key: [u8; 16],
         remote_addr: SocketAddr,
+        extension: Option<&AckExtension>,
     ) -> Result<PacketData<SymmetricAES>, bincode::Error> {
         let message = Self {
             packet_id: Self::FIRST_PACKET_ID,
@@ -108,15 +116,33 @@ impl SymmetricMessage {
             },
         };
         let mut packet = [0u8; MAX_DATA_SIZE];
-        let size = bincode::serialized_size(&message)?;
-        debug_assert!(size <= MAX_DATA_SIZE as u64);
+        let mut size = bincode::serialized_size(&message)?;
         bincode::serialize_into(packet.as_mut_slice(), &message)?;
+        if let Some(extension) = extension {
+            let extension_size = bincode::serialized_size(extension)?;
+            bincode::serialize_into(&mut packet[size as usize..], extension)?;
+            size += extension_size;
+        }
+        debug_assert!(size <= MAX_DATA_SIZE as u64);
         let bytes = &packet[..size as usize];
 
         let packet = PacketData::from_buf_plain(bytes);
         Ok(packet.encrypt_symmetric(outbound_sym_key))
     }
 
+    /// Reads the extension trailing a connection ack, if the remote sent one.
+    ///
+    /// The extension is not part of the message so older peers can still decode the ack,
+    /// trailing bytes are ignored by [`Self::deser`].
+    pub fn ack_extension(bytes: &[u8]) -> Option<AckExtension> {
+        let message = Self::deser(bytes).ok()?;
+        let SymmetricMessagePayload::AckConnection { result: Ok(_) } = &message.payload else {
+            return None;
+        };
+        let size = bincode::serialized_size(&message).ok()? as usize;
+        bincode::deserialize(bytes.get(size..)?).ok()
+    }
+
     #[allow(clippy::type_complexity)]
     pub(super) fn try_serialize_msg_to_packet_data(
         packet_id: PacketId,
@@ -215,6 +241,55 @@ impl From<StreamFragment> for SymmetricMessagePayload {
     }
 }
 
+/// Fragments held by the receiver of a stream, and how far the sender may go. Cumulative, so a
+/// newer one always supersedes the previous ones.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct FragmentProgress {
+    /// All the fragments up to this one have been received
+    pub last_contiguous: u32,
+    /// Bitmap of the fragments received after `last_contiguous`, starting with the next one
+    pub received_after: Vec<u8>,
+    /// The receiver accepts fragments up to this one
+    pub window_end: u32,
+}
+
+impl FragmentProgress {
+    pub fn new(
+        last_contiguous: u32,
+        received: impl IntoIterator<Item = u32>,
+        window_end: u32,
+    ) -> Self {
+        let mut received_after = vec![];
+        for fragment_number in received {
+            let Some(bit) = fragment_number.checked_sub(last_contiguous + 1) else {
+                continue;
+            };
+            let (byte, bit) = (bit as usize / 8, bit % 8);
+            if received_after.len() <= byte {
+                received_after.resize(byte + 1, 0);
+            }
+            received_after[byte] |= 1 << bit;
+        }
+        Self {
+            last_contiguous,
+            received_after,
+            window_end,
+        }
+    }
+
+    /// The fragments received after `last_contiguous`.
+    pub fn received(&self) -> impl Iterator<Item = u32> + '_ {
+        self.received_after
+            .iter()
+            .enumerate()
+            .flat_map(move |(byte_idx, byte)| {
+                (0..8)
+                    .filter(move |bit| byte & (1 << bit) != 0)
+                    .map(move |bit| self.last_contiguous + 1 + (byte_idx * 8) as u32 + bit)
+            })
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 #[cfg_attr(test, derive(PartialEq, Debug, Clone))]
 pub(crate) struct OutboundConnection {
@@ -222,6 +297,27 @@ pub(crate) struct OutboundConnection {
     pub(super) remote_addr: SocketAddr,
 }
 
+/// Sent after a connection ack to remotes which advertised their capabilities in their intro
+/// packet or ack.
+#[derive(Serialize, Deserialize, Clone)]
+#[cfg_attr(test, derive(PartialEq, Debug))]
+pub(crate) struct AckExtension {
+    /// Capabilities of the sender, see `connection_handler::capabilities`.
+    pub(super) capabilities: u8,
+    /// Set when the X25519 handshake is used, in which case the key in the ack is only used
+    /// for the handshake and the session keys are derived from the ephemeral keys.
+    pub(super) auth: Option<HandshakeAuth>,
+}
+
+/// The ephemeral key of a peer, signed along with the remote ephemeral key by its static key.
+#[derive(Serialize, Deserialize, Clone)]
+#[cfg_attr(test, derive(PartialEq, Debug))]
+pub(crate) struct HandshakeAuth {
+    pub(super) ephemeral_key: [u8; 32],
+    pub(super) static_key: TransportPublicKey,
+    pub(super) signature: Vec<u8>,
+}
+
 #[derive(Serialize, Deserialize)]
 #[cfg_attr(test, derive(PartialEq, Debug, Clone))]
 pub(crate) enum SymmetricMessagePayload {
@@ -239,7 +335,21 @@ pub(crate) enum SymmetricMessagePayload {
         fragment_number: u32,
         payload: MessagePayload,
     },
+    /// Sent by the receiver of a stream to acknowledge fragments and open its window.
+    StreamProgress {
+        stream_id: StreamId,
+        progress: FragmentProgress,
+    },
+    /// Sent by the sender of a stream to learn which fragments the receiver still needs, when
+    /// the stream is resumed on a new connection or the receiver went quiet.
+    StreamResume {
+        stream_id: StreamId,
+        total_length_bytes: u64,
+    },
     NoOp,
+    /// The sender will encrypt the packets it sends with the next key of the ratchet once this
+    /// is acknowledged.
+    Rekey,
 }
 
 #[cfg(test)]
@@ -265,7 +375,19 @@ impl std::fmt::Display for SymmetricMessagePayload {
                 "StreamFragment: (stream id: {:?}, fragment no: {:?}) ",
                 stream_id, fragment_number
             ),
+            SymmetricMessagePayload::StreamProgress {
+                stream_id,
+                progress,
+            } => write!(
+                f,
+                "StreamProgress: (stream id: {:?}, last contiguous: {:?}) ",
+                stream_id, progress.last_contiguous
+            ),
+            SymmetricMessagePayload::StreamResume { stream_id, .. } => {
+                write!(f, "StreamResume: (stream id: {:?}) ", stream_id)
+            }
             SymmetricMessagePayload::NoOp => write!(f, "NoOp"),
+            SymmetricMessagePayload::Rekey => write!(f, "Rekey"),
         }
     }
 }
@@ -277,6 +399,7 @@ mod test {
     use aes_gcm::KeyInit;
 
     use super::*;
+    use crate::transport::crypto::{EphemeralKeypair, TransportKeypair};
 
     fn gen_key() -> Aes128Gcm {
         let key = rand::random::<[u8; 16]>();
@@ -322,7 +445,16 @@ mod test {
                     .map(|_| rand::random::<u8>())
                     .collect(),
             },
+            SymmetricMessagePayload::StreamProgress {
+                stream_id: StreamId::next(),
+                progress: FragmentProgress::new(10, [12, 20], 100),
+            },
+            SymmetricMessagePayload::StreamResume {
+                stream_id: StreamId::next(),
+                total_length_bytes: 100,
+            },
             SymmetricMessagePayload::NoOp,
+            SymmetricMessagePayload::Rekey,
         ];
         let key = gen_key();
 
@@ -332,6 +464,30 @@ mod test {
         }
     }
 
+    #[test]
+    fn fragment_progress_bitmap() {
+        let progress = FragmentProgress::new(10, [12, 14, 20, 21], 100);
+        assert_eq!(progress.received_after, vec![0b0000_1010, 0b0000_0110]);
+        assert_eq!(
+            progress.received().collect::<Vec<_>>(),
+            vec![12, 14, 20, 21]
+        );
+        assert_eq!(FragmentProgress::new(10, [], 100).received().count(), 0);
+    }
+
+    #[test]
+    fn read_packet_id() -> Result<(), Box<dyn std::error::Error>> {
+        let msg = SymmetricMessage {
+            packet_id: 0x0102_0304,
+            confirm_receipt: vec![5, 6],
+            payload: SymmetricMessagePayload::NoOp,
+        };
+        let bytes = bincode::serialize(&msg)?;
+        assert_eq!(SymmetricMessage::packet_id(&bytes), Some(0x0102_0304));
+        assert_eq!(SymmetricMessage::packet_id(&bytes[..3]), None);
+        Ok(())
+    }
+
     #[test]
     fn ack_error_msg() -> Result<(), Box<dyn std::error::Error>> {
         let key = gen_key();
@@ -360,13 +516,41 @@ mod test {
         let _dec: SymmetricMessage = bincode::deserialize(&enc)?;
 
         let key = gen_key();
-        let packet = SymmetricMessage::ack_ok(&key, [0; 16], (Ipv4Addr::LOCALHOST, 1234).into())?;
+        let packet =
+            SymmetricMessage::ack_ok(&key, [0; 16], (Ipv4Addr::LOCALHOST, 1234).into(), None)?;
+        let data = packet.decrypt(&key).unwrap();
+        let deser = SymmetricMessage::deser(data.data())?;
+        assert!(matches!(
+            deser.payload,
+            SymmetricMessagePayload::AckConnection { result: Ok(_) }
+        ));
+        assert!(SymmetricMessage::ack_extension(data.data()).is_none());
+
+        // the extension with the handshake auth must fit in the ack packet
+        let ephemeral = EphemeralKeypair::new();
+        let extension = AckExtension {
+            capabilities: u8::MAX,
+            auth: Some(
+                ephemeral.authenticate(&TransportKeypair::new(), EphemeralKeypair::new().public()),
+            ),
+        };
+        let packet = SymmetricMessage::ack_ok(
+            &key,
+            [0; 16],
+            (Ipv4Addr::LOCALHOST, 1234).into(),
+            Some(&extension),
+        )?;
         let data = packet.decrypt(&key).unwrap();
+        // peers without the X25519 handshake still decode the ack
         let deser = SymmetricMessage::deser(data.data())?;
         assert!(matches!(
             deser.payload,
             SymmetricMessagePayload::AckConnection { result: Ok(_) }
         ));
+        assert_eq!(
+            SymmetricMessage::ack_extension(data.data()),
+            Some(extension)
+        );
         Ok(())
     }
 
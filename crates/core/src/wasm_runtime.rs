@@ -3,21 +3,28 @@ mod contract_store;
 mod delegate;
 mod delegate_store;
 mod error;
+mod integrity;
 mod native_api;
 mod runtime;
+mod secrets_backup;
 mod secrets_store;
+mod snapshot;
 mod state_store;
 mod store;
 #[cfg(test)]
-mod tests;
+pub(crate) mod tests;
+mod tunables;
 
-pub(crate) use contract::ContractRuntimeInterface;
+pub use contract::ContractRuntimeInterface;
 pub use contract_store::ContractStore;
 pub(crate) use delegate::DelegateRuntimeInterface;
 pub use delegate_store::DelegateStore;
 pub(crate) use error::{ContractError, RuntimeInnerError, RuntimeResult};
+pub use integrity::{verify_stores, IntegrityReport, Issue, VerifyOptions};
 pub use runtime::{ContractExecError, Runtime};
+pub use secrets_backup::SecretsBackup;
 pub(crate) use secrets_store::SecretStoreError;
 pub use secrets_store::SecretsStore;
-pub use state_store::StateStore;
-pub(crate) use state_store::{StateStorage, StateStoreError};
+pub use snapshot::StoreSnapshot;
+pub(crate) use state_store::{StateBatch, StateStorage, StateStoreError};
+pub use state_store::{StateStore, StateVersion};
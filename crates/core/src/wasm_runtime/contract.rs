@@ -12,7 +12,7 @@ use wasmer::{Instance, Store, TypedFunction};
 
 type FfiReturnTy = i64;
 
-pub(crate) trait ContractRuntimeInterface {
+pub trait ContractRuntimeInterface {
     /// Verify that the state is valid, given the parameters. This will be used before a peer
     /// caches a new state.
     fn validate_state(
@@ -2,24 +2,30 @@ use std::{fs::File, io::Write, path::PathBuf, sync::Arc};
 
 use dashmap::DashMap;
 use freenet_stdlib::prelude::*;
+use parking_lot::Mutex;
 use stretto::Cache;
 
 use super::{
     error::RuntimeInnerError,
-    store::{SafeWriter, StoreFsManagement},
+    store::{self, SafeWriter, StoreFsManagement},
     RuntimeResult,
 };
 
 /// Handle contract blob storage on the file system.
+///
+/// Clones share the same index and in-memory cache.
+#[derive(Clone)]
 pub struct ContractStore {
     contracts_dir: PathBuf,
+    /// authenticates the compiled module artifacts persisted in the store
+    module_key: [u8; 32],
     key_file: PathBuf,
     contract_cache: Cache<CodeHash, Arc<ContractCode<'static>>>,
     key_to_code_part: Arc<DashMap<ContractInstanceId, (u64, CodeHash)>>,
-    index_file: SafeWriter<Self>,
+    /// bytes used by the compiled module artifacts persisted for each code
+    module_sizes: Arc<DashMap<CodeHash, u64>>,
+    index_file: Arc<Mutex<SafeWriter<Self>>>,
 }
-// TODO: add functionality to delete old contracts which have not been used for a while
-//       to keep the total space used under a configured threshold
 
 impl StoreFsManagement for ContractStore {
     type MemContainer = Arc<DashMap<ContractInstanceId, (u64, CodeHash)>>;
@@ -53,12 +59,16 @@ impl ContractStore {
         }
         Self::watch_changes(key_to_code_part.clone(), &key_file)?;
 
-        let index_file = SafeWriter::new(&key_file, false)?;
+        let index_file = Arc::new(Mutex::new(SafeWriter::new(&key_file, false)?));
+        let module_key = store::module_key(&contracts_dir)?;
+        let module_sizes = Arc::new(store::modules_sizes(&contracts_dir).into_iter().collect());
         Ok(Self {
+            module_key,
             contract_cache: Cache::new(100, max_size).expect(ERR),
             contracts_dir,
             key_file,
             key_to_code_part,
+            module_sizes,
             index_file,
         })
     }
@@ -147,20 +157,23 @@ impl ContractStore {
         let mut file = File::create(key_path)?;
         file.write_all(output.as_slice())?;
 
-        // Update index
-        let keys = self.key_to_code_part.entry(*key.id());
+        self.update_index(*key.id(), code_hash)
+    }
+
+    fn update_index(&mut self, id: ContractInstanceId, code_hash: &CodeHash) -> RuntimeResult<()> {
+        let keys = self.key_to_code_part.entry(id);
         match keys {
             dashmap::mapref::entry::Entry::Occupied(mut v) => {
                 let current_version_offset = v.get().0;
                 let prev_val = &mut v.get_mut().1;
                 // first mark the old entry (if it exists) as removed
                 Self::remove(&self.key_file, current_version_offset)?;
-                let new_offset = Self::insert(&mut self.index_file, *key.id(), code_hash)?;
+                let new_offset = Self::insert(&mut self.index_file.lock(), id, code_hash)?;
                 *prev_val = *code_hash;
                 v.get_mut().0 = new_offset;
             }
             dashmap::mapref::entry::Entry::Vacant(v) => {
-                let offset = Self::insert(&mut self.index_file, *key.id(), code_hash)?;
+                let offset = Self::insert(&mut self.index_file.lock(), id, code_hash)?;
                 v.insert((offset, *code_hash));
             }
         }
@@ -191,17 +204,108 @@ impl ContractStore {
         if let Some((_, (offset, _))) = self.key_to_code_part.remove(key.id()) {
             Self::remove(&self.key_file, offset)?;
         }
+        // the same code may be shared by other contracts with different parameters
+        if self
+            .key_to_code_part
+            .iter()
+            .any(|entry| entry.value().1 == contract_hash)
+        {
+            return Ok(());
+        }
+        self.contract_cache.remove(&contract_hash);
         let key_path = self
             .contracts_dir
             .join(contract_hash.encode())
             .with_extension("wasm");
         std::fs::remove_file(key_path)?;
+        store::remove_modules(&self.contracts_dir, &contract_hash, None)?;
+        self.module_sizes.remove(&contract_hash);
+        Ok(())
+    }
+
+    /// Returns the compiled module for the given contract code, if it was persisted
+    /// for an engine with the given tag.
+    pub(crate) fn fetch_module(&self, code_hash: &CodeHash, tag: &str) -> Option<Vec<u8>> {
+        store::load_module(&self.contracts_dir, &self.module_key, code_hash, tag)
+    }
+
+    /// Persists the compiled module for the given contract code, so it doesn't have to be
+    /// compiled again by an engine with the same tag.
+    pub(crate) fn store_module(
+        &self,
+        code_hash: &CodeHash,
+        tag: &str,
+        module: &[u8],
+    ) -> RuntimeResult<()> {
+        let size = store::save_module(
+            &self.contracts_dir,
+            &self.module_key,
+            code_hash,
+            tag,
+            module,
+        )?;
+        // saving a module replaces the ones compiled for other engines
+        self.module_sizes.insert(*code_hash, size);
         Ok(())
     }
 
     pub fn code_hash_from_key(&self, key: &ContractKey) -> Option<CodeHash> {
         self.key_to_code_part.get(key.id()).map(|r| r.value().1)
     }
+
+    /// Returns all the stored contracts along with the hash of their code.
+    pub(crate) fn contracts(&self) -> Vec<(ContractInstanceId, CodeHash)> {
+        self.key_to_code_part
+            .iter()
+            .map(|entry| (*entry.key(), entry.value().1))
+            .collect()
+    }
+
+    /// Returns the contract code as stored on disk, prefixed with its API version.
+    pub(crate) fn versioned_code(&self, code_hash: &CodeHash) -> RuntimeResult<Vec<u8>> {
+        let key_path = self
+            .contracts_dir
+            .join(code_hash.encode())
+            .with_extension("wasm");
+        Ok(std::fs::read(key_path)?)
+    }
+
+    /// Stores contract code previously returned by [`Self::versioned_code`] for the given
+    /// contract, checking it matches the code hash.
+    pub(crate) fn store_versioned_code(
+        &mut self,
+        id: ContractInstanceId,
+        code_hash: &CodeHash,
+        code: &[u8],
+    ) -> RuntimeResult<()> {
+        let key_path = self
+            .contracts_dir
+            .join(code_hash.encode())
+            .with_extension("wasm");
+        if !key_path.exists() {
+            std::fs::write(&key_path, code)?;
+            let matches = ContractCode::load_versioned_from_path(&key_path)
+                .is_ok_and(|(code, _)| code.hash() == code_hash);
+            if !matches {
+                std::fs::remove_file(&key_path)?;
+                return Err(
+                    anyhow::anyhow!("code doesn't match its hash {}", code_hash.encode()).into(),
+                );
+            }
+        }
+        self.update_index(id, code_hash)
+    }
+
+    /// Returns the size on disk of the given contract code, including its compiled modules.
+    pub(crate) fn code_size(&self, code_hash: &CodeHash) -> Option<u64> {
+        let key_path = self
+            .contracts_dir
+            .join(code_hash.encode())
+            .with_extension("wasm");
+        let code_size = std::fs::metadata(key_path).ok()?.len();
+        let modules_size = self.module_sizes.get(code_hash).map_or(0, |size| *size);
+        Some(code_size + modules_size)
+    }
 }
 
 #[cfg(test)]
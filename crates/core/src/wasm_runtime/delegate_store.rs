@@ -3,19 +3,24 @@ use freenet_stdlib::prelude::{
     APIVersion, CodeHash, Delegate, DelegateCode, DelegateContainer, DelegateKey,
     DelegateWasmAPIVersion, Parameters,
 };
+use parking_lot::Mutex;
 use std::{fs::File, io::Write, path::PathBuf, sync::Arc};
 use stretto::Cache;
 
 use crate::wasm_runtime::store::SafeWriter;
 
-use super::store::StoreFsManagement;
+use super::store::{self, StoreFsManagement};
 use super::RuntimeResult;
 
+/// Clones share the same index and in-memory cache.
+#[derive(Clone)]
 pub struct DelegateStore {
     delegates_dir: PathBuf,
+    /// authenticates the compiled module artifacts persisted in the store
+    module_key: [u8; 32],
     delegate_cache: Cache<CodeHash, DelegateCode<'static>>,
     key_to_code_part: Arc<DashMap<DelegateKey, (u64, CodeHash)>>,
-    index_file: SafeWriter<Self>,
+    index_file: Arc<Mutex<SafeWriter<Self>>>,
     key_file: PathBuf,
 }
 
@@ -51,8 +56,10 @@ impl DelegateStore {
         }
         Self::watch_changes(key_to_code_part.clone(), &key_file)?;
 
-        let index_file = SafeWriter::new(&key_file, false)?;
+        let index_file = Arc::new(Mutex::new(SafeWriter::new(&key_file, false)?));
+        let module_key = store::module_key(&delegates_dir)?;
         Ok(Self {
+            module_key,
             delegate_cache: Cache::new(100, max_size).expect(ERR),
             delegates_dir,
             key_to_code_part,
@@ -127,7 +134,10 @@ impl DelegateStore {
         let mut file = File::create(delegate_path)?;
         file.write_all(output.as_slice())?;
 
-        // Update index
+        self.update_index(key, code_hash)
+    }
+
+    fn update_index(&mut self, key: &DelegateKey, code_hash: &CodeHash) -> RuntimeResult<()> {
         let keys = self.key_to_code_part.entry(key.clone());
         match keys {
             dashmap::mapref::entry::Entry::Occupied(mut v) => {
@@ -135,12 +145,12 @@ impl DelegateStore {
                 let prev_val = &mut v.get_mut().1;
                 // first mark the old entry (if it exists) as removed
                 Self::remove(&self.key_file, current_version_offset)?;
-                let new_offset = Self::insert(&mut self.index_file, key.clone(), code_hash)?;
+                let new_offset = Self::insert(&mut self.index_file.lock(), key.clone(), code_hash)?;
                 *prev_val = *code_hash;
                 v.get_mut().0 = new_offset;
             }
             dashmap::mapref::entry::Entry::Vacant(v) => {
-                let offset = Self::insert(&mut self.index_file, key.clone(), code_hash)?;
+                let offset = Self::insert(&mut self.index_file.lock(), key.clone(), code_hash)?;
                 v.insert((offset, *code_hash));
             }
         }
@@ -169,6 +179,73 @@ impl DelegateStore {
     pub fn code_hash_from_key(&self, key: &DelegateKey) -> Option<CodeHash> {
         self.key_to_code_part.get(key).map(|r| r.value().1)
     }
+
+    /// Returns all the stored delegates along with the hash of their code.
+    pub(crate) fn delegates(&self) -> Vec<(DelegateKey, CodeHash)> {
+        self.key_to_code_part
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().1))
+            .collect()
+    }
+
+    /// Returns the delegate code as stored on disk, prefixed with its API version.
+    pub(crate) fn versioned_code(&self, code_hash: &CodeHash) -> RuntimeResult<Vec<u8>> {
+        let delegate_path = self
+            .delegates_dir
+            .join(code_hash.encode())
+            .with_extension("wasm");
+        Ok(std::fs::read(delegate_path)?)
+    }
+
+    /// Stores delegate code previously returned by [`Self::versioned_code`] for the given
+    /// delegate, checking it matches the code hash.
+    pub(crate) fn store_versioned_code(
+        &mut self,
+        key: &DelegateKey,
+        code: &[u8],
+    ) -> RuntimeResult<()> {
+        let code_hash = key.code_hash();
+        let delegate_path = self
+            .delegates_dir
+            .join(code_hash.encode())
+            .with_extension("wasm");
+        if !delegate_path.exists() {
+            std::fs::write(&delegate_path, code)?;
+            let matches = DelegateCode::load_versioned_from_path(&delegate_path)
+                .is_ok_and(|(code, _)| code.hash() == code_hash);
+            if !matches {
+                std::fs::remove_file(&delegate_path)?;
+                return Err(
+                    anyhow::anyhow!("code doesn't match its hash {}", code_hash.encode()).into(),
+                );
+            }
+        }
+        self.update_index(key, code_hash)
+    }
+
+    /// Returns the compiled module for the given delegate code, if it was persisted
+    /// for an engine with the given tag.
+    pub(crate) fn fetch_module(&self, code_hash: &CodeHash, tag: &str) -> Option<Vec<u8>> {
+        store::load_module(&self.delegates_dir, &self.module_key, code_hash, tag)
+    }
+
+    /// Persists the compiled module for the given delegate code, so it doesn't have to be
+    /// compiled again by an engine with the same tag.
+    pub(crate) fn store_module(
+        &self,
+        code_hash: &CodeHash,
+        tag: &str,
+        module: &[u8],
+    ) -> RuntimeResult<()> {
+        store::save_module(
+            &self.delegates_dir,
+            &self.module_key,
+            code_hash,
+            tag,
+            module,
+        )?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,478 @@
+//! Consistency checks for the contract, delegate and secret stores and the state database.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use freenet_stdlib::prelude::{
+    CodeHash, ContractCode, ContractInstanceId, ContractKey, DelegateCode, DelegateKey,
+};
+
+use super::{
+    store::{self, StoreFsManagement, StoreKey},
+    ContractStore, DelegateStore, SecretsStore, StateStorage, StateStore,
+};
+
+const KEY_DATA: &str = "KEY_DATA";
+
+/// What to do besides reporting the issues found.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VerifyOptions {
+    /// Check that all the stored code matches the hash it is stored under, which requires
+    /// reading all of it.
+    pub verify_hashes: bool,
+    /// Fix the issues found, discarding anything which can't be recovered.
+    pub repair: bool,
+    /// Rewrite the index files without the records of removed entries.
+    pub compact: bool,
+    /// Don't report lock files, since they may belong to another process using the stores.
+    pub skip_locks: bool,
+}
+
+/// An inconsistency found in the stores.
+#[derive(Debug)]
+pub enum Issue {
+    /// The index file ends with a partially written or malformed record.
+    TruncatedIndex {
+        index: PathBuf,
+        valid_len: u64,
+        len: u64,
+    },
+    /// A lock file left behind by an interrupted index compaction.
+    StaleLock(PathBuf),
+    /// An index record with a value which is not a code hash.
+    MalformedRecord { index: PathBuf, offset: u64 },
+    /// An index entry points at code which is not stored.
+    MissingCode { index: PathBuf, code_hash: CodeHash },
+    /// Stored code which no index entry points at.
+    OrphanedCode(PathBuf),
+    /// The stored code doesn't match the hash it is stored under.
+    HashMismatch { path: PathBuf, actual: CodeHash },
+    /// The stored code can't be read.
+    UnreadableCode { path: PathBuf, error: String },
+    /// Secrets stored for a delegate which is not in the secrets index.
+    OrphanedSecrets(PathBuf),
+    /// The secrets index has an entry for a delegate without any stored secrets.
+    MissingSecrets(DelegateKey),
+    /// The state of a contract is stored but not its code.
+    StateWithoutCode(ContractKey),
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Issue::TruncatedIndex {
+                index,
+                valid_len,
+                len,
+            } => write!(
+                f,
+                "index {index:?} has {} trailing bytes of incomplete records",
+                len - valid_len
+            ),
+            Issue::StaleLock(path) => write!(f, "stale lock file {path:?}"),
+            Issue::MalformedRecord { index, offset } => {
+                write!(f, "malformed record at offset {offset} of index {index:?}")
+            }
+            Issue::MissingCode { index, code_hash } => {
+                let code_hash = code_hash.encode();
+                write!(f, "index {index:?} points at missing code {code_hash}")
+            }
+            Issue::OrphanedCode(path) => write!(f, "code {path:?} is not in the index"),
+            Issue::HashMismatch { path, actual } => {
+                let actual = actual.encode();
+                write!(
+                    f,
+                    "code {path:?} doesn't match its hash, actual hash is {actual}"
+                )
+            }
+            Issue::UnreadableCode { path, error } => {
+                write!(f, "code {path:?} can't be read: {error}")
+            }
+            Issue::OrphanedSecrets(path) => write!(f, "secrets {path:?} are not in the index"),
+            Issue::MissingSecrets(delegate) => {
+                write!(f, "no secrets stored for indexed delegate {delegate}")
+            }
+            Issue::StateWithoutCode(key) => write!(f, "state of contract {key} has no code"),
+        }
+    }
+}
+
+/// Outcome of verifying the stores.
+#[derive(Debug, Default)]
+pub struct IntegrityReport {
+    pub issues: Vec<Issue>,
+    /// Number of removed records still taking space in the index files.
+    pub removed_records: usize,
+}
+
+impl IntegrityReport {
+    pub fn is_consistent(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Scans the stores in the given directories and the state database for inconsistencies,
+/// optionally repairing them.
+///
+/// The stores must not be in use while repairing or compacting them.
+pub async fn verify_stores<S>(
+    contracts_dir: &Path,
+    delegates_dir: &Path,
+    secrets_dir: &Path,
+    state_store: &mut StateStore<S>,
+    options: VerifyOptions,
+) -> anyhow::Result<IntegrityReport>
+where
+    S: StateStorage + Send + 'static,
+    <S as StateStorage>::Error: Into<anyhow::Error>,
+{
+    let mut report = IntegrityReport::default();
+    let contracts =
+        verify_code_store::<ContractStore>(contracts_dir, options, &mut report, |path| {
+            Ok(*ContractCode::load_versioned_from_path(path)?.0.hash())
+        })?;
+    verify_code_store::<DelegateStore>(delegates_dir, options, &mut report, |path| {
+        Ok(*DelegateCode::load_versioned_from_path(path)?.0.hash())
+    })?;
+    verify_secrets(secrets_dir, options, &mut report)?;
+
+    let contracts: HashSet<_> = contracts
+        .into_iter()
+        .filter_map(|key| match key {
+            StoreKey::ContractKey(id) => Some(ContractInstanceId::new(id)),
+            StoreKey::DelegateKey { .. } => None,
+        })
+        .collect();
+    for key in state_store.keys().await? {
+        if contracts.contains(key.id()) {
+            continue;
+        }
+        report.issues.push(Issue::StateWithoutCode(key));
+        if options.repair {
+            state_store.remove(&key).await?;
+        }
+    }
+    Ok(report)
+}
+
+/// Verifies a store of code files indexed by their code hash, returning the keys of the
+/// index entries whose code is stored.
+fn verify_code_store<S: StoreFsManagement>(
+    dir: &Path,
+    options: VerifyOptions,
+    report: &mut IntegrityReport,
+    code_hash_of: impl Fn(&Path) -> anyhow::Result<CodeHash>,
+) -> anyhow::Result<Vec<StoreKey>> {
+    let index = dir.join(KEY_DATA);
+    let Some(records) = scan_index(&index, options, report)? else {
+        return Ok(vec![]);
+    };
+
+    let mut stored = HashMap::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().map_or(true, |ext| ext != "wasm") {
+            continue;
+        }
+        let Some(code_hash) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(store::decode_code_hash)
+        else {
+            continue;
+        };
+        if options.verify_hashes {
+            let issue = match code_hash_of(&path) {
+                Ok(actual) if actual == code_hash => None,
+                Ok(actual) => Some(Issue::HashMismatch {
+                    path: path.clone(),
+                    actual,
+                }),
+                Err(err) => Some(Issue::UnreadableCode {
+                    path: path.clone(),
+                    error: err.to_string(),
+                }),
+            };
+            if let Some(issue) = issue {
+                report.issues.push(issue);
+                if options.repair {
+                    remove_code(dir, &path, &code_hash)?;
+                }
+                continue;
+            }
+        }
+        stored.insert(code_hash, path);
+    }
+
+    let mut live = vec![];
+    let mut referenced = HashSet::new();
+    for (offset, key, value) in records {
+        let Ok(code_hash) = <[u8; 32]>::try_from(value.as_slice()).map(CodeHash::new) else {
+            report.issues.push(Issue::MalformedRecord {
+                index: index.clone(),
+                offset,
+            });
+            if options.repair {
+                S::remove(&index, offset)?;
+            }
+            continue;
+        };
+        if stored.contains_key(&code_hash) {
+            referenced.insert(code_hash);
+            live.push(key);
+            continue;
+        }
+        report.issues.push(Issue::MissingCode {
+            index: index.clone(),
+            code_hash,
+        });
+        if options.repair {
+            S::remove(&index, offset)?;
+        }
+    }
+    for (code_hash, path) in stored {
+        if referenced.contains(&code_hash) {
+            continue;
+        }
+        report.issues.push(Issue::OrphanedCode(path.clone()));
+        if options.repair {
+            remove_code(dir, &path, &code_hash)?;
+        }
+    }
+
+    if options.compact {
+        store::compact_index_file::<S>(&index)?;
+    }
+    Ok(live)
+}
+
+fn verify_secrets(
+    dir: &Path,
+    options: VerifyOptions,
+    report: &mut IntegrityReport,
+) -> anyhow::Result<()> {
+    let index = dir.join(KEY_DATA);
+    let Some(records) = scan_index(&index, options, report)? else {
+        return Ok(());
+    };
+
+    let mut delegates = HashSet::new();
+    for (offset, key, _) in records {
+        if !matches!(key, StoreKey::DelegateKey { .. }) {
+            continue;
+        }
+        let delegate = DelegateKey::from(key);
+        let encoded = delegate.encode();
+        if dir.join(&encoded).is_dir() {
+            delegates.insert(encoded);
+            continue;
+        }
+        report.issues.push(Issue::MissingSecrets(delegate));
+        if options.repair {
+            SecretsStore::remove(&index, offset)?;
+        }
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let indexed = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| delegates.contains(name));
+        if !indexed {
+            report.issues.push(Issue::OrphanedSecrets(path.clone()));
+            if options.repair {
+                fs::remove_dir_all(&path)?;
+            }
+        }
+    }
+
+    if options.compact {
+        store::compact_index_file::<SecretsStore>(&index)?;
+    }
+    Ok(())
+}
+
+/// Checks the index file itself, returning the records which haven't been removed
+/// or none if there is no index.
+fn scan_index(
+    index: &Path,
+    options: VerifyOptions,
+    report: &mut IntegrityReport,
+) -> io::Result<Option<Vec<(u64, StoreKey, Vec<u8>)>>> {
+    if !index.exists() {
+        return Ok(None);
+    }
+    let lock = index.with_extension("lock");
+    if !options.skip_locks && lock.exists() {
+        report.issues.push(Issue::StaleLock(lock.clone()));
+        if options.repair {
+            fs::remove_file(&lock)?;
+        }
+    }
+    let scan = store::scan_index(index)?;
+    report.removed_records += scan.removed;
+    if scan.valid_len < scan.len {
+        report.issues.push(Issue::TruncatedIndex {
+            index: index.to_owned(),
+            valid_len: scan.valid_len,
+            len: scan.len,
+        });
+        if options.repair {
+            fs::OpenOptions::new()
+                .write(true)
+                .open(index)?
+                .set_len(scan.valid_len)?;
+        }
+    }
+    Ok(Some(scan.records))
+}
+
+fn remove_code(dir: &Path, path: &Path, code_hash: &CodeHash) -> io::Result<()> {
+    fs::remove_file(path)?;
+    store::remove_modules(dir, code_hash, None)
+}
+
+#[cfg(all(test, any(feature = "redb", feature = "sqlite")))]
+mod tests {
+    use freenet_stdlib::prelude::{Parameters, WrappedState};
+
+    use super::*;
+    use crate::wasm_runtime::store::SafeWriter;
+
+    #[cfg(feature = "redb")]
+    #[tokio::test]
+    async fn verify_and_repair_redb() -> anyhow::Result<()> {
+        let temp_dir = crate::util::tests::get_temp_dir();
+        let storage = crate::contract::storages::redb::ReDb::new(temp_dir.path()).await?;
+        verify_and_repair(temp_dir.path(), storage).await
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn verify_and_repair_sqlite() -> anyhow::Result<()> {
+        let temp_dir = crate::util::tests::get_temp_dir();
+        let storage = crate::contract::storages::sqlite::Pool::new(Some(temp_dir.path())).await?;
+        verify_and_repair(temp_dir.path(), storage).await
+    }
+
+    async fn verify_and_repair<S>(dir: &Path, storage: S) -> anyhow::Result<()>
+    where
+        S: StateStorage + Send + 'static,
+        <S as StateStorage>::Error: Into<anyhow::Error>,
+    {
+        let contracts_dir = dir.join("contracts");
+        let delegates_dir = dir.join("delegates");
+        let secrets_dir = dir.join("secrets");
+        for dir in [&contracts_dir, &delegates_dir, &secrets_dir] {
+            fs::create_dir_all(dir)?;
+        }
+        let stored_code = CodeHash::new([1; 32]);
+        let missing_code = CodeHash::new([2; 32]);
+        let orphaned_code = CodeHash::new([3; 32]);
+        for code_hash in [stored_code, orphaned_code] {
+            let path = contracts_dir
+                .join(code_hash.encode())
+                .with_extension("wasm");
+            fs::write(path, [0; 8])?;
+        }
+        let index = contracts_dir.join(KEY_DATA);
+        {
+            let mut file = SafeWriter::<ContractStore>::new(&index, false)?;
+            let stored = ContractInstanceId::new([1; 32]);
+            let missing = ContractInstanceId::new([2; 32]);
+            ContractStore::insert(&mut file, stored, &stored_code)?;
+            ContractStore::insert(&mut file, missing, &missing_code)?;
+        }
+        // a partially written record
+        let mut content = fs::read(&index)?;
+        content.extend_from_slice(&[0, 0, 7]);
+        fs::write(&index, content)?;
+
+        let mut state_store = StateStore::new(storage, 10_000)?;
+        let without_code = ContractKey::from(ContractInstanceId::new([2; 32]));
+        state_store
+            .store(
+                without_code,
+                WrappedState::new(vec![1]),
+                Parameters::from(vec![]),
+            )
+            .await?;
+
+        // left behind by an interrupted compaction, or held by another process
+        fs::write(index.with_extension("lock"), [])?;
+        let options = VerifyOptions {
+            skip_locks: true,
+            ..Default::default()
+        };
+        let report = verify_stores(
+            &contracts_dir,
+            &delegates_dir,
+            &secrets_dir,
+            &mut state_store,
+            options,
+        )
+        .await?;
+        assert_eq!(report.issues.len(), 4, "{:?}", report.issues);
+
+        let report = verify_stores(
+            &contracts_dir,
+            &delegates_dir,
+            &secrets_dir,
+            &mut state_store,
+            VerifyOptions::default(),
+        )
+        .await?;
+        assert_eq!(report.issues.len(), 5, "{:?}", report.issues);
+        assert!(report
+            .issues
+            .iter()
+            .any(|issue| matches!(issue, Issue::StaleLock(_))));
+        assert!(report
+            .issues
+            .iter()
+            .any(|issue| matches!(issue, Issue::TruncatedIndex { .. })));
+        assert!(report.issues.iter().any(
+            |issue| matches!(issue, Issue::MissingCode { code_hash, .. } if *code_hash == missing_code)
+        ));
+        assert!(report
+            .issues
+            .iter()
+            .any(|issue| matches!(issue, Issue::OrphanedCode(_))));
+        assert!(report
+            .issues
+            .iter()
+            .any(|issue| matches!(issue, Issue::StateWithoutCode(key) if *key == without_code)));
+
+        let options = VerifyOptions {
+            repair: true,
+            compact: true,
+            ..Default::default()
+        };
+        verify_stores(
+            &contracts_dir,
+            &delegates_dir,
+            &secrets_dir,
+            &mut state_store,
+            options,
+        )
+        .await?;
+        let report = verify_stores(
+            &contracts_dir,
+            &delegates_dir,
+            &secrets_dir,
+            &mut state_store,
+            VerifyOptions::default(),
+        )
+        .await?;
+        assert!(report.is_consistent(), "{:?}", report.issues);
+        assert_eq!(report.removed_records, 0);
+        Ok(())
+    }
+}
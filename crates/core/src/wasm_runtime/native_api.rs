@@ -2,7 +2,10 @@
 
 use dashmap::DashMap;
 use once_cell::sync::Lazy;
-use wasmer::{Function, Imports};
+use wasmer::{
+    AsStoreMut, Function, FunctionEnv, FunctionEnvMut, Global, Imports, Instance, Memory,
+    RuntimeError,
+};
 
 use super::runtime::InstanceInfo;
 
@@ -19,29 +22,454 @@ fn compute_ptr<T>(ptr: i64, start_ptr: i64) -> *mut T {
     (start_ptr + ptr) as _
 }
 
+/// Metering state of the running instance, so host functions can charge the computation
+/// they perform to the compute budget of the call, along with its memory so they can check
+/// the pointers they are passed.
+#[derive(Default)]
+pub(super) struct MeteringEnv {
+    remaining_points: Option<Global>,
+    points_exhausted: Option<Global>,
+    memory: Option<Memory>,
+}
+
+impl MeteringEnv {
+    /// Binds the metering globals and the memory of a new instance, must be called before
+    /// executing it.
+    pub fn bind(
+        env: &FunctionEnv<Self>,
+        store: &mut impl AsStoreMut,
+        instance: &Instance,
+        host_memory: Option<&Memory>,
+    ) {
+        let memory = host_memory
+            .or_else(|| instance.exports.get_memory("memory").ok())
+            .cloned();
+        let metering = env.as_mut(store);
+        metering.memory = memory;
+        metering.remaining_points = instance
+            .exports
+            .get_global("wasmer_metering_remaining_points")
+            .ok()
+            .cloned();
+        metering.points_exhausted = instance
+            .exports
+            .get_global("wasmer_metering_points_exhausted")
+            .ok()
+            .cloned();
+    }
+}
+
+/// Deducts the cost from the remaining points of the running instance, trapping (like the
+/// metering middleware does) if there aren't enough points left.
+fn charge(env: &mut FunctionEnvMut<MeteringEnv>, cost: u64) -> Result<(), RuntimeError> {
+    let (metering, mut store) = env.data_and_store_mut();
+    let Some(remaining_points) = &metering.remaining_points else {
+        return Ok(());
+    };
+    let remaining = remaining_points.get(&mut store).unwrap_i64() as u64;
+    if remaining < cost {
+        remaining_points.set(&mut store, 0i64.into())?;
+        if let Some(points_exhausted) = &metering.points_exhausted {
+            points_exhausted.set(&mut store, 1i32.into())?;
+        }
+        return Err(RuntimeError::new(
+            "not enough points remaining for host function",
+        ));
+    }
+    remaining_points.set(&mut store, ((remaining - cost) as i64).into())
+}
+
+/// Start and size of the linear memory of the running instance. Looked up on every call,
+/// since the memory may have been moved when it grew.
+#[derive(Clone, Copy)]
+struct GuestMemory {
+    start_ptr: i64,
+    size: u64,
+}
+
+fn guest_memory(env: &FunctionEnvMut<MeteringEnv>, id: i64) -> Result<GuestMemory, RuntimeError> {
+    if id == -1 {
+        return Err(RuntimeError::new("unset module id"));
+    }
+    let memory = env
+        .data()
+        .memory
+        .as_ref()
+        .ok_or_else(|| RuntimeError::new("instance memory not bound"))?;
+    let view = memory.view(env);
+    Ok(GuestMemory {
+        start_ptr: view.data_ptr() as i64,
+        size: view.data_size(),
+    })
+}
+
+/// Checks the `len` bytes at `ptr` lay within the memory of the instance.
+fn check_bounds(memory: GuestMemory, ptr: i64, len: u32) -> Result<(), RuntimeError> {
+    let end = u64::try_from(ptr)
+        .ok()
+        .and_then(|ptr| ptr.checked_add(len as u64));
+    match end {
+        Some(end) if end <= memory.size => Ok(()),
+        _ => Err(RuntimeError::new(format!(
+            "out of bounds memory access ({len} bytes at {ptr})"
+        ))),
+    }
+}
+
+/// # Safety
+/// The slice must not be used after the memory of the instance is grown or dropped.
+unsafe fn bytes<'a>(memory: GuestMemory, ptr: i64, len: u32) -> Result<&'a [u8], RuntimeError> {
+    check_bounds(memory, ptr, len)?;
+    Ok(std::slice::from_raw_parts(
+        compute_ptr::<u8>(ptr, memory.start_ptr),
+        len as usize,
+    ))
+}
+
+/// # Safety
+/// As for [`bytes`], and the slice must not overlap any other slice in use.
+unsafe fn bytes_mut<'a>(
+    memory: GuestMemory,
+    ptr: i64,
+    len: u32,
+) -> Result<&'a mut [u8], RuntimeError> {
+    check_bounds(memory, ptr, len)?;
+    Ok(std::slice::from_raw_parts_mut(
+        compute_ptr::<u8>(ptr, memory.start_ptr),
+        len as usize,
+    ))
+}
+
 pub(crate) mod log {
+    use std::{borrow::Cow, fmt, time::Instant};
+
+    use tracing::Level;
+
     use super::*;
 
-    pub(crate) fn prepare_export(store: &mut wasmer::Store, imports: &mut Imports) {
-        let utc_now = Function::new_typed(store, info);
+    /// Version of the structured logging API, bumped whenever the [`LogRecord`] encoding or
+    /// the signature of the exported functions change.
+    pub(crate) const LOG_API_VERSION: u32 = 1;
+
+    /// Records a single contract or delegate can log per second on average.
+    const RECORDS_PER_SEC: f64 = 50.0;
+
+    /// Records a single contract or delegate can log in a burst before being rate limited.
+    const MAX_BURST: f64 = 200.0;
+
+    /// Rate limits for the records logged by each contract or delegate, shared by all its
+    /// instances.
+    static RATE_LIMITS: Lazy<DashMap<String, RateLimit>> = Lazy::new(DashMap::default);
+
+    /// Rate limits kept before dropping the ones which are idle.
+    const MAX_TRACKED: usize = 1024;
+
+    pub(crate) fn prepare_export(
+        store: &mut wasmer::Store,
+        imports: &mut Imports,
+        env: &FunctionEnv<MeteringEnv>,
+    ) {
+        let info = Function::new_typed_with_env(store, env, info);
+        let version = Function::new_typed(store, version);
+        let log = Function::new_typed_with_env(store, env, log);
         imports.register_namespace(
             "freenet_log",
-            [("__frnt__logger__info".to_owned(), utc_now.into())],
+            [
+                ("__frnt__logger__info".to_owned(), info.into()),
+                ("__frnt__logger__version".to_owned(), version.into()),
+                ("__frnt__logger__log_v1".to_owned(), log.into()),
+            ],
         );
     }
 
-    // TODO: this API right now is just a patch, ideally we want to impl a tracing subscriber
-    // that can be used in wasm and that under the hood will just pass data to the host via
-    // functions like this in a structured way
-    fn info(id: i64, ptr: i64, len: i32) {
-        if id == -1 {
-            panic!("unset module id");
+    /// A structured record logged by a contract or delegate through `__frnt__logger__log_v1`.
+    ///
+    /// The record is passed bincode encoded, the level is passed as a separate argument
+    /// (0: trace, 1: debug, 2: info, 3: warn, 4: error).
+    #[derive(serde::Deserialize)]
+    struct LogRecord<'a> {
+        #[serde(borrow)]
+        message: Cow<'a, str>,
+        #[serde(borrow)]
+        fields: Vec<(Cow<'a, str>, Cow<'a, str>)>,
+    }
+
+    struct Fields<'a>(&'a [(Cow<'a, str>, Cow<'a, str>)]);
+
+    impl fmt::Display for Fields<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            for (i, (key, value)) in self.0.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(" ")?;
+                }
+                write!(f, "{key}={value:?}")?;
+            }
+            Ok(())
+        }
+    }
+
+    fn version() -> u32 {
+        LOG_API_VERSION
+    }
+
+    /// Unstructured, info level, logging used by modules built before the versioned API.
+    fn info(
+        env: FunctionEnvMut<MeteringEnv>,
+        id: i64,
+        ptr: i64,
+        len: i32,
+    ) -> Result<(), RuntimeError> {
+        let bytes = unsafe { record_bytes(&env, id, ptr, len)? };
+        let info = instance_info(id)?;
+        let record = LogRecord {
+            message: String::from_utf8_lossy(bytes),
+            fields: vec![],
+        };
+        emit(info.value(), Level::INFO, &record);
+        Ok(())
+    }
+
+    fn log(
+        env: FunctionEnvMut<MeteringEnv>,
+        id: i64,
+        level: i32,
+        ptr: i64,
+        len: i32,
+    ) -> Result<(), RuntimeError> {
+        let bytes = unsafe { record_bytes(&env, id, ptr, len)? };
+        let info = instance_info(id)?;
+        let level = match level {
+            0 => Level::TRACE,
+            1 => Level::DEBUG,
+            3 => Level::WARN,
+            4 => Level::ERROR,
+            _ => Level::INFO,
+        };
+        match bincode::deserialize::<LogRecord>(bytes) {
+            Ok(record) => emit(info.value(), level, &record),
+            Err(err) => {
+                let Some(_span) = acquire(info.value()) else {
+                    return Ok(());
+                };
+                tracing::warn!(target: "contract", "invalid log record: {err}")
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the `len` bytes logged at `ptr`, checking they lay within the memory of the
+    /// instance.
+    ///
+    /// # Safety
+    /// As for [`bytes`].
+    unsafe fn record_bytes<'a>(
+        env: &FunctionEnvMut<MeteringEnv>,
+        id: i64,
+        ptr: i64,
+        len: i32,
+    ) -> Result<&'a [u8], RuntimeError> {
+        let len = u32::try_from(len)
+            .map_err(|_| RuntimeError::new(format!("invalid log record length {len}")))?;
+        bytes(guest_memory(env, id)?, ptr, len)
+    }
+
+    fn instance_info(
+        id: i64,
+    ) -> Result<dashmap::mapref::one::Ref<'static, InstanceId, InstanceInfo>, RuntimeError> {
+        MEM_ADDR
+            .get(&id)
+            .ok_or_else(|| RuntimeError::new("instance mem space not recorded"))
+    }
+
+    /// Forgets the rate limit of a contract or delegate once one of its instances is unloaded,
+    /// if it has no records pending to be reported and its burst is fully refilled, since it
+    /// then behaves the same as a new one.
+    pub(in crate::wasm_runtime) fn release(info: &InstanceInfo) {
+        let now = Instant::now();
+        RATE_LIMITS.remove_if(&info.key(), |_, limit| limit.is_idle(now));
+    }
+
+    /// Acquires a record from the rate limit of the contract or delegate, returning the span
+    /// the record must be logged in if it's not rate limited.
+    fn acquire(info: &InstanceInfo) -> Option<tracing::span::EnteredSpan> {
+        let now = Instant::now();
+        if RATE_LIMITS.len() > MAX_TRACKED {
+            // the limits of instances unloaded while rate limited are eventually dropped
+            RATE_LIMITS.retain(|_, limit| !limit.is_idle(now));
+        }
+        let suppressed = {
+            let mut limit = RATE_LIMITS.entry(info.key()).or_insert_with(RateLimit::new);
+            limit.acquire(now)?
+        };
+        let span = info.span().entered();
+        if suppressed > 0 {
+            tracing::warn!(target: "contract", "rate limited, {suppressed} log records dropped");
+        }
+        Some(span)
+    }
+
+    fn emit(info: &InstanceInfo, level: Level, record: &LogRecord) {
+        let Some(_span) = acquire(info) else {
+            return;
+        };
+        let fields = Fields(&record.fields);
+        let message = &record.message;
+        match level {
+            Level::TRACE => tracing::trace!(target: "contract", %fields, "{message}"),
+            Level::DEBUG => tracing::debug!(target: "contract", %fields, "{message}"),
+            Level::INFO => tracing::info!(target: "contract", %fields, "{message}"),
+            Level::WARN => tracing::warn!(target: "contract", %fields, "{message}"),
+            _ => tracing::error!(target: "contract", %fields, "{message}"),
+        }
+    }
+
+    /// Token bucket limiting the rate at which records are logged.
+    struct RateLimit {
+        tokens: f64,
+        last_refill: Instant,
+        /// records dropped since the last one logged
+        suppressed: u64,
+    }
+
+    impl RateLimit {
+        fn new() -> Self {
+            Self {
+                tokens: MAX_BURST,
+                last_refill: Instant::now(),
+                suppressed: 0,
+            }
+        }
+
+        /// Returns the number of records previously dropped if this one can be logged.
+        fn acquire(&mut self, now: Instant) -> Option<u64> {
+            let elapsed = now
+                .saturating_duration_since(self.last_refill)
+                .as_secs_f64();
+            self.tokens = (self.tokens + elapsed * RECORDS_PER_SEC).min(MAX_BURST);
+            self.last_refill = now;
+            if self.tokens < 1.0 {
+                self.suppressed += 1;
+                return None;
+            }
+            self.tokens -= 1.0;
+            Some(std::mem::take(&mut self.suppressed))
+        }
+
+        /// Whether the limit would be the same as a new one by now.
+        fn is_idle(&self, now: Instant) -> bool {
+            let elapsed = now
+                .saturating_duration_since(self.last_refill)
+                .as_secs_f64();
+            self.suppressed == 0 && self.tokens + elapsed * RECORDS_PER_SEC >= MAX_BURST
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::time::Duration;
+
+        use super::*;
+
+        #[test]
+        fn rate_limit_drops_bursts() {
+            let mut limit = RateLimit::new();
+            let now = Instant::now();
+            for _ in 0..MAX_BURST as usize {
+                assert_eq!(limit.acquire(now), Some(0));
+            }
+            assert_eq!(limit.acquire(now), None);
+            assert_eq!(limit.acquire(now), None);
+
+            // refills over time, reporting the records dropped meanwhile
+            let later = now + Duration::from_secs_f64(1.0 / RECORDS_PER_SEC);
+            assert_eq!(limit.acquire(later), Some(2));
+            assert_eq!(limit.acquire(later), None);
+        }
+
+        #[test]
+        fn rate_limit_becomes_idle() {
+            let mut limit = RateLimit::new();
+            let now = Instant::now();
+            assert!(limit.is_idle(now));
+            for _ in 0..=MAX_BURST as usize {
+                limit.acquire(now);
+            }
+            // records dropped are still pending to be reported
+            let refilled = now + Duration::from_secs_f64(2.0 * MAX_BURST / RECORDS_PER_SEC);
+            assert!(!limit.is_idle(refilled));
+            assert_eq!(limit.acquire(refilled), Some(1));
+            assert!(!limit.is_idle(refilled));
+            let refilled = refilled + Duration::from_secs_f64(1.0 / RECORDS_PER_SEC);
+            assert!(limit.is_idle(refilled));
+        }
+
+        #[test]
+        fn out_of_bounds_record() {
+            // not assigned to any running instance
+            const ID: i64 = i64::MAX;
+            let mut store = wasmer::Store::new(wasmer_compiler_singlepass::Singlepass::default());
+            let memory = Memory::new(&mut store, wasmer::MemoryType::new(1, None, false)).unwrap();
+            let env = FunctionEnv::new(
+                &mut store,
+                MeteringEnv {
+                    memory: Some(memory),
+                    ..Default::default()
+                },
+            );
+            MEM_ADDR.insert(
+                ID,
+                InstanceInfo::contract(
+                    0,
+                    freenet_stdlib::prelude::ContractInstanceId::new([0; 32]),
+                ),
+            );
+            let info = Function::new_typed_with_env(&mut store, &env, info);
+            let log = Function::new_typed_with_env(&mut store, &env, log);
+            let mut call = |ptr: i64, len: i32| {
+                let log = log.call(
+                    &mut store,
+                    &[
+                        wasmer::Value::I64(ID),
+                        wasmer::Value::I32(2),
+                        wasmer::Value::I64(ptr),
+                        wasmer::Value::I32(len),
+                    ],
+                );
+                let info = info.call(
+                    &mut store,
+                    &[
+                        wasmer::Value::I64(ID),
+                        wasmer::Value::I64(ptr),
+                        wasmer::Value::I32(len),
+                    ],
+                );
+                assert_eq!(log.is_ok(), info.is_ok());
+                log
+            };
+            let size = wasmer::WASM_PAGE_SIZE as i64;
+            let in_bounds = call(size - 16, 16).is_ok();
+            let past_end = call(size - 8, 16).is_err();
+            let negative_len = call(0, -1).is_err();
+            let negative_ptr = call(-1, 1).is_err();
+            MEM_ADDR.remove(&ID);
+            assert!(in_bounds);
+            assert!(past_end);
+            assert!(negative_len);
+            assert!(negative_ptr);
+        }
+
+        #[test]
+        fn decode_record() {
+            let encoded =
+                bincode::serialize(&("updated state", vec![("size", "42"), ("peer", "a b")]))
+                    .unwrap();
+            let record: LogRecord = bincode::deserialize(&encoded).unwrap();
+            assert_eq!(record.message, "updated state");
+            assert_eq!(
+                Fields(&record.fields).to_string(),
+                r#"size="42" peer="a b""#
+            );
         }
-        let info = MEM_ADDR.get(&id).expect("instance mem space not recorded");
-        let ptr = compute_ptr::<u8>(ptr, info.start_ptr);
-        let msg =
-            unsafe { std::str::from_utf8_unchecked(std::slice::from_raw_parts(ptr, len as _)) };
-        tracing::info!(target: "contract", contract = %info.value().key(), "{msg}");
     }
 }
 
@@ -95,3 +523,375 @@ pub(crate) mod time {
         };
     }
 }
+
+pub(crate) mod crypto {
+    //! Cryptographic primitives, so contracts don't need to bundle (and execute) their own
+    //! implementation compiled to WASM.
+    //!
+    //! Verification functions return [`VALID`], [`INVALID`] or [`MALFORMED`] if the key
+    //! or signature can't be decoded. AEAD functions return [`SUCCESS`] or [`FAILURE`].
+
+    use chacha20poly1305::{
+        aead::{Aead, KeyInit, Payload},
+        XChaCha20Poly1305, XNonce,
+    };
+    use rsa::{pkcs8::DecodePublicKey, sha2::Sha256, signature::Verifier, RsaPublicKey};
+
+    use super::*;
+
+    pub(crate) const VALID: i32 = 1;
+    pub(crate) const INVALID: i32 = 0;
+    pub(crate) const MALFORMED: i32 = -1;
+    pub(crate) const SUCCESS: i32 = 0;
+    pub(crate) const FAILURE: i32 = -1;
+
+    /// Size of the authentication tag appended to the ciphertext.
+    pub(crate) const AEAD_TAG_SIZE: u32 = 16;
+
+    // Costs, in metering points, roughly matching the CPU cycles taken by each primitive.
+    const CALL_COST: u64 = 1_000;
+    const BLAKE3_BYTE_COST: u64 = 1;
+    const ED25519_VERIFY_COST: u64 = 150_000;
+    /// cost of verifying a signature with a 2048 bit key, scaled by the signature size
+    const RSA_VERIFY_COST: u64 = 200_000;
+    /// signatures made with keys larger than 4096 bits are rejected as malformed
+    const RSA_MAX_SIG_LEN: u32 = 512;
+    const AEAD_BYTE_COST: u64 = 2;
+
+    pub(crate) fn prepare_export(
+        store: &mut wasmer::Store,
+        imports: &mut Imports,
+        env: &FunctionEnv<MeteringEnv>,
+    ) {
+        let blake3_hash = Function::new_typed_with_env(store, env, blake3_hash);
+        let ed25519_verify = Function::new_typed_with_env(store, env, ed25519_verify);
+        let rsa_pss_verify = Function::new_typed_with_env(store, env, rsa_pss_verify);
+        let aead_encrypt = Function::new_typed_with_env(store, env, aead_encrypt);
+        let aead_decrypt = Function::new_typed_with_env(store, env, aead_decrypt);
+        imports.register_namespace(
+            "freenet_crypto",
+            [
+                ("__frnt__crypto__blake3_hash".to_owned(), blake3_hash.into()),
+                (
+                    "__frnt__crypto__ed25519_verify".to_owned(),
+                    ed25519_verify.into(),
+                ),
+                (
+                    "__frnt__crypto__rsa_pss_verify".to_owned(),
+                    rsa_pss_verify.into(),
+                ),
+                (
+                    "__frnt__crypto__aead_encrypt".to_owned(),
+                    aead_encrypt.into(),
+                ),
+                (
+                    "__frnt__crypto__aead_decrypt".to_owned(),
+                    aead_decrypt.into(),
+                ),
+            ],
+        );
+    }
+
+    /// Writes the 32 bytes hash of the data at `out_ptr`.
+    fn blake3_hash(
+        mut env: FunctionEnvMut<MeteringEnv>,
+        id: i64,
+        data_ptr: i64,
+        data_len: u32,
+        out_ptr: i64,
+    ) -> Result<(), RuntimeError> {
+        charge(&mut env, CALL_COST + data_len as u64 * BLAKE3_BYTE_COST)?;
+        let memory = guest_memory(&env, id)?;
+        let hash = blake3::hash(unsafe { bytes(memory, data_ptr, data_len)? });
+        let out = unsafe { bytes_mut(memory, out_ptr, blake3::OUT_LEN as u32)? };
+        out.copy_from_slice(hash.as_bytes());
+        Ok(())
+    }
+
+    /// Verifies a 64 bytes signature with a 32 bytes public key.
+    fn ed25519_verify(
+        mut env: FunctionEnvMut<MeteringEnv>,
+        id: i64,
+        key_ptr: i64,
+        msg_ptr: i64,
+        msg_len: u32,
+        sig_ptr: i64,
+    ) -> Result<i32, RuntimeError> {
+        charge(&mut env, ED25519_VERIFY_COST + msg_len as u64)?;
+        let memory = guest_memory(&env, id)?;
+        let key = unsafe { bytes(memory, key_ptr, ed25519_dalek::PUBLIC_KEY_LENGTH as u32)? };
+        let msg = unsafe { bytes(memory, msg_ptr, msg_len)? };
+        let sig = unsafe { bytes(memory, sig_ptr, ed25519_dalek::SIGNATURE_LENGTH as u32)? };
+        Ok(verify_ed25519(key, msg, sig))
+    }
+
+    /// Verifies a RSA-PSS (SHA-256) signature with a DER encoded (SPKI) public key.
+    #[allow(clippy::too_many_arguments)]
+    fn rsa_pss_verify(
+        mut env: FunctionEnvMut<MeteringEnv>,
+        id: i64,
+        key_ptr: i64,
+        key_len: u32,
+        msg_ptr: i64,
+        msg_len: u32,
+        sig_ptr: i64,
+        sig_len: u32,
+    ) -> Result<i32, RuntimeError> {
+        if sig_len > RSA_MAX_SIG_LEN {
+            charge(&mut env, CALL_COST)?;
+            return Ok(MALFORMED);
+        }
+        // the signature is as long as the key modulus
+        let key_factor = (sig_len as u64).div_ceil(256).max(1);
+        charge(
+            &mut env,
+            RSA_VERIFY_COST * key_factor * key_factor + msg_len as u64,
+        )?;
+        let memory = guest_memory(&env, id)?;
+        let key = unsafe { bytes(memory, key_ptr, key_len)? };
+        let msg = unsafe { bytes(memory, msg_ptr, msg_len)? };
+        let sig = unsafe { bytes(memory, sig_ptr, sig_len)? };
+        Ok(verify_rsa_pss(key, msg, sig))
+    }
+
+    /// Encrypts the data with XChaCha20-Poly1305, writing the ciphertext followed by the
+    /// authentication tag (`data_len + 16` bytes) at `out_ptr`.
+    #[allow(clippy::too_many_arguments)]
+    fn aead_encrypt(
+        mut env: FunctionEnvMut<MeteringEnv>,
+        id: i64,
+        key_ptr: i64,
+        nonce_ptr: i64,
+        aad_ptr: i64,
+        aad_len: u32,
+        data_ptr: i64,
+        data_len: u32,
+        out_ptr: i64,
+    ) -> Result<i32, RuntimeError> {
+        charge(
+            &mut env,
+            CALL_COST + (aad_len as u64 + data_len as u64) * AEAD_BYTE_COST,
+        )?;
+        let memory = guest_memory(&env, id)?;
+        let key = unsafe { bytes(memory, key_ptr, 32)? };
+        let nonce = unsafe { bytes(memory, nonce_ptr, 24)? };
+        let aad = unsafe { bytes(memory, aad_ptr, aad_len)? };
+        let data = unsafe { bytes(memory, data_ptr, data_len)? };
+        let Some(encrypted) = encrypt(key, nonce, aad, data) else {
+            return Ok(FAILURE);
+        };
+        let out_len = data_len
+            .checked_add(AEAD_TAG_SIZE)
+            .ok_or_else(|| RuntimeError::new("data too large to encrypt"))?;
+        let out = unsafe { bytes_mut(memory, out_ptr, out_len)? };
+        out.copy_from_slice(&encrypted);
+        Ok(SUCCESS)
+    }
+
+    /// Decrypts and authenticates data encrypted with [`aead_encrypt`], writing the
+    /// plaintext (`data_len - 16` bytes) at `out_ptr`.
+    #[allow(clippy::too_many_arguments)]
+    fn aead_decrypt(
+        mut env: FunctionEnvMut<MeteringEnv>,
+        id: i64,
+        key_ptr: i64,
+        nonce_ptr: i64,
+        aad_ptr: i64,
+        aad_len: u32,
+        data_ptr: i64,
+        data_len: u32,
+        out_ptr: i64,
+    ) -> Result<i32, RuntimeError> {
+        charge(
+            &mut env,
+            CALL_COST + (aad_len as u64 + data_len as u64) * AEAD_BYTE_COST,
+        )?;
+        if data_len < AEAD_TAG_SIZE {
+            return Ok(FAILURE);
+        }
+        let memory = guest_memory(&env, id)?;
+        let key = unsafe { bytes(memory, key_ptr, 32)? };
+        let nonce = unsafe { bytes(memory, nonce_ptr, 24)? };
+        let aad = unsafe { bytes(memory, aad_ptr, aad_len)? };
+        let data = unsafe { bytes(memory, data_ptr, data_len)? };
+        let Some(decrypted) = decrypt(key, nonce, aad, data) else {
+            return Ok(FAILURE);
+        };
+        let out = unsafe { bytes_mut(memory, out_ptr, data_len - AEAD_TAG_SIZE)? };
+        out.copy_from_slice(&decrypted);
+        Ok(SUCCESS)
+    }
+
+    fn verify_ed25519(key: &[u8], msg: &[u8], sig: &[u8]) -> i32 {
+        let Ok(key) = key.try_into() else {
+            return MALFORMED;
+        };
+        let Ok(key) = ed25519_dalek::VerifyingKey::from_bytes(key) else {
+            return MALFORMED;
+        };
+        let Ok(sig) = ed25519_dalek::Signature::from_slice(sig) else {
+            return MALFORMED;
+        };
+        match key.verify_strict(msg, &sig) {
+            Ok(()) => VALID,
+            Err(_) => INVALID,
+        }
+    }
+
+    fn verify_rsa_pss(key: &[u8], msg: &[u8], sig: &[u8]) -> i32 {
+        let Ok(key) = RsaPublicKey::from_public_key_der(key) else {
+            return MALFORMED;
+        };
+        let Ok(sig) = rsa::pss::Signature::try_from(sig) else {
+            return MALFORMED;
+        };
+        match rsa::pss::VerifyingKey::<Sha256>::new(key).verify(msg, &sig) {
+            Ok(()) => VALID,
+            Err(_) => INVALID,
+        }
+    }
+
+    fn encrypt(key: &[u8], nonce: &[u8], aad: &[u8], msg: &[u8]) -> Option<Vec<u8>> {
+        let cipher = XChaCha20Poly1305::new_from_slice(key).ok()?;
+        cipher
+            .encrypt(XNonce::from_slice(nonce), Payload { msg, aad })
+            .ok()
+    }
+
+    fn decrypt(key: &[u8], nonce: &[u8], aad: &[u8], msg: &[u8]) -> Option<Vec<u8>> {
+        let cipher = XChaCha20Poly1305::new_from_slice(key).ok()?;
+        cipher
+            .decrypt(XNonce::from_slice(nonce), Payload { msg, aad })
+            .ok()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use ed25519_dalek::Signer;
+        use rsa::{
+            pkcs8::EncodePublicKey,
+            signature::{RandomizedSigner, SignatureEncoding},
+            RsaPrivateKey,
+        };
+
+        use super::*;
+
+        #[test]
+        fn ed25519() {
+            let signing_key = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+            let key = signing_key.verifying_key().to_bytes();
+            let sig = signing_key.sign(b"message").to_bytes();
+            assert_eq!(verify_ed25519(&key, b"message", &sig), VALID);
+            assert_eq!(verify_ed25519(&key, b"other message", &sig), INVALID);
+            assert_eq!(verify_ed25519(&key[..16], b"message", &sig), MALFORMED);
+        }
+
+        #[test]
+        fn rsa_pss() {
+            let private_key = RsaPrivateKey::new(&mut ::rand::thread_rng(), 1024).unwrap();
+            let key = private_key.to_public_key().to_public_key_der().unwrap();
+            let signing_key = rsa::pss::SigningKey::<Sha256>::new(private_key);
+            let sig = signing_key
+                .sign_with_rng(&mut ::rand::thread_rng(), b"message")
+                .to_vec();
+            assert_eq!(verify_rsa_pss(key.as_bytes(), b"message", &sig), VALID);
+            assert_eq!(
+                verify_rsa_pss(key.as_bytes(), b"other message", &sig),
+                INVALID
+            );
+            assert_eq!(verify_rsa_pss(&[0; 8], b"message", &sig), MALFORMED);
+        }
+
+        #[test]
+        fn out_of_bounds_pointers() {
+            let mut store = wasmer::Store::new(wasmer_compiler_singlepass::Singlepass::default());
+            let memory = Memory::new(&mut store, wasmer::MemoryType::new(1, None, false)).unwrap();
+            let env = FunctionEnv::new(
+                &mut store,
+                MeteringEnv {
+                    memory: Some(memory),
+                    ..Default::default()
+                },
+            );
+            let hash = Function::new_typed_with_env(&mut store, &env, blake3_hash);
+            let mut call = |data_ptr: i64, data_len: u32, out_ptr: i64| {
+                hash.call(
+                    &mut store,
+                    &[
+                        wasmer::Value::I64(0),
+                        wasmer::Value::I64(data_ptr),
+                        wasmer::Value::I32(data_len as i32),
+                        wasmer::Value::I64(out_ptr),
+                    ],
+                )
+            };
+            let size = wasmer::WASM_PAGE_SIZE as i64;
+            assert!(call(0, 16, size - blake3::OUT_LEN as i64).is_ok());
+            assert!(call(size - 8, 16, 0).is_err());
+            assert!(call(0, 16, size - 16).is_err());
+            assert!(call(-1, 1, 0).is_err());
+            assert!(call(i64::MAX, u32::MAX, 0).is_err());
+        }
+
+        #[test]
+        fn rsa_pss_verify_cost() {
+            let mut store = wasmer::Store::new(wasmer_compiler_singlepass::Singlepass::default());
+            let memory = Memory::new(&mut store, wasmer::MemoryType::new(1, None, false)).unwrap();
+            let remaining_points =
+                Global::new_mut(&mut store, wasmer::Value::I64(RSA_VERIFY_COST as i64 * 3));
+            let points_exhausted = Global::new_mut(&mut store, wasmer::Value::I32(0));
+            let env = FunctionEnv::new(
+                &mut store,
+                MeteringEnv {
+                    remaining_points: Some(remaining_points.clone()),
+                    points_exhausted: Some(points_exhausted.clone()),
+                    memory: Some(memory),
+                },
+            );
+            let verify = Function::new_typed_with_env(&mut store, &env, rsa_pss_verify);
+            let call = |store: &mut wasmer::Store, sig_len: u32| {
+                verify.call(
+                    store,
+                    &[
+                        wasmer::Value::I64(0),
+                        wasmer::Value::I64(0),
+                        wasmer::Value::I32(8),
+                        wasmer::Value::I64(0),
+                        wasmer::Value::I32(8),
+                        wasmer::Value::I64(0),
+                        wasmer::Value::I32(sig_len as i32),
+                    ],
+                )
+            };
+
+            // oversized signatures are rejected without charging for the verification
+            for sig_len in [u32::MAX, RSA_MAX_SIG_LEN + 1] {
+                let result = call(&mut store, sig_len).unwrap();
+                assert_eq!(result[0].unwrap_i32(), MALFORMED);
+            }
+            assert_eq!(
+                remaining_points.get(&mut store).unwrap_i64(),
+                (RSA_VERIFY_COST * 3 - CALL_COST * 2) as i64
+            );
+            assert_eq!(points_exhausted.get(&mut store).unwrap_i32(), 0);
+
+            // verifying a signature from a 4096 bit key costs more than the budget left
+            assert!(call(&mut store, RSA_MAX_SIG_LEN).is_err());
+            assert_eq!(remaining_points.get(&mut store).unwrap_i64(), 0);
+            assert_eq!(points_exhausted.get(&mut store).unwrap_i32(), 1);
+        }
+
+        #[test]
+        fn aead() {
+            let (key, nonce) = ([1; 32], [2; 24]);
+            let encrypted = encrypt(&key, &nonce, b"aad", b"message").unwrap();
+            assert_eq!(encrypted.len(), b"message".len() + AEAD_TAG_SIZE as usize);
+            assert_eq!(
+                decrypt(&key, &nonce, b"aad", &encrypted).as_deref(),
+                Some(b"message".as_slice())
+            );
+            assert_eq!(decrypt(&key, &nonce, b"other aad", &encrypted), None);
+            assert_eq!(decrypt(&[3; 32], &nonce, b"aad", &encrypted), None);
+        }
+    }
+}
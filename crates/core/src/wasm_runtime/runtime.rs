@@ -1,7 +1,8 @@
 use super::{
     contract_store::ContractStore, delegate_store::DelegateStore, error::RuntimeInnerError,
-    native_api, secrets_store::SecretsStore, RuntimeResult,
+    native_api, secrets_store::SecretsStore, tunables::MemoryLimit, RuntimeResult,
 };
+use dashmap::DashMap;
 use freenet_stdlib::{
     memory::{
         buf::{BufferBuilder, BufferMut},
@@ -9,15 +10,26 @@ use freenet_stdlib::{
     },
     prelude::*,
 };
-use std::{collections::HashMap, sync::atomic::AtomicI64};
+use std::sync::{atomic::AtomicI64, Arc};
 use wasmer::{
-    imports, Bytes, CompilerConfig, Imports, Instance, Memory, MemoryType, Module, Store,
-    TypedFunction,
+    imports, BaseTunables, Bytes, CompilerConfig, Engine, FunctionEnv, Imports, Instance, Memory,
+    MemoryType, Module, NativeEngineExt, Pages, Store, Target, TypedFunction,
 };
-use wasmer_middlewares::metering::{get_remaining_points, MeteringPoints};
+use wasmer_middlewares::metering::{get_remaining_points, set_remaining_points, MeteringPoints};
 
 static INSTANCE_ID: AtomicI64 = AtomicI64::new(0);
 
+/// Custom section in which a contract declares the code hash of the contract it succeeds,
+/// whose state it can import when upgraded (see [`Runtime::contract_predecessor`]), followed
+/// by the signature authorizing the upgrade (see [`Runtime::upgrade_authorized`]).
+pub(crate) const PREDECESSOR_SECTION: &str = "freenet-predecessor";
+
+/// Custom section in which a contract declares the ed25519 key allowed to authorize its
+/// successors.
+pub(crate) const UPGRADE_KEY_SECTION: &str = "freenet-upgrade-key";
+
+const PREDECESSOR_DECLARATION_LENGTH: usize = 32 + ed25519_dalek::SIGNATURE_LENGTH;
+
 pub(super) struct RunningInstance {
     pub id: i64,
     pub instance: Instance,
@@ -25,7 +37,9 @@ pub(super) struct RunningInstance {
 
 impl Drop for RunningInstance {
     fn drop(&mut self) {
-        let _ = native_api::MEM_ADDR.remove(&self.id);
+        if let Some((_, info)) = native_api::MEM_ADDR.remove(&self.id) {
+            native_api::log::release(&info);
+        }
     }
 }
 
@@ -35,12 +49,32 @@ pub(super) struct InstanceInfo {
 }
 
 impl InstanceInfo {
+    #[cfg(test)]
+    pub(super) fn contract(start_ptr: i64, id: ContractInstanceId) -> Self {
+        Self {
+            start_ptr,
+            key: Key::Contract(id),
+        }
+    }
+
     pub fn key(&self) -> String {
         match &self.key {
             Key::Contract(k) => k.encode(),
             Key::Delegate(k) => k.encode(),
         }
     }
+
+    /// Span tagging the records logged by the instance with its contract or delegate key.
+    pub fn span(&self) -> tracing::Span {
+        match &self.key {
+            Key::Contract(k) => {
+                tracing::info_span!(target: "contract", "contract", contract = %k.encode())
+            }
+            Key::Delegate(k) => {
+                tracing::info_span!(target: "contract", "delegate", delegate = %k.encode())
+            }
+        }
+    }
 }
 
 enum Key {
@@ -96,6 +130,23 @@ pub enum ContractExecError {
 
     #[error("The operation exceeded the maximum allowed compute time")]
     MaxComputeTimeExceeded,
+
+    #[error("The operation exhausted its fuel, limited to {limit} instructions per call")]
+    FuelLimitExceeded { limit: u64 },
+
+    #[error("memory limit exceeded, the instance can't grow beyond {limit} pages")]
+    MemoryLimitExceeded { limit: u32 },
+
+    #[error(
+        "buffer limit exceeded, needed {req} bytes but only {limit} bytes are allowed per call"
+    )]
+    BufferLimitExceeded { req: usize, limit: usize },
+
+    #[error(
+        "invalid predecessor declaration, expected a 32 bytes code hash and a 64 bytes \
+         signature but got {0} bytes"
+    )]
+    InvalidPredecessor(usize),
 }
 
 pub struct RuntimeConfig {
@@ -105,6 +156,14 @@ pub struct RuntimeConfig {
     pub cpu_cycles_per_second: Option<u64>,
     /// Safety margin for CPU speed variations (0.0 to 1.0)
     pub safety_margin: f64,
+    /// Maximum number of instructions a single call can execute, the limit derived from
+    /// the maximum execution time applies if lower
+    pub max_fuel: Option<u64>,
+    /// Maximum number of pages (64 KiB each) the linear memory of an instance can grow to
+    pub max_memory_pages: Option<u32>,
+    /// Maximum number of bytes the host can allocate in the buffers of an instance during
+    /// a single call
+    pub max_buffer_bytes: Option<usize>,
 }
 
 impl Default for RuntimeConfig {
@@ -113,10 +172,23 @@ impl Default for RuntimeConfig {
             max_execution_seconds: 5.0,
             cpu_cycles_per_second: None,
             safety_margin: 0.2,
+            max_fuel: None,
+            // 256 MiB
+            max_memory_pages: Some(4096),
+            max_buffer_bytes: Some(64 * 1024 * 1024),
         }
     }
 }
 
+/// Limits enforced on every call to a contract or delegate, see [`RuntimeConfig`].
+#[derive(Clone, Copy)]
+struct CallLimits {
+    /// only set if lower than the limit derived from the maximum execution time
+    fuel: Option<u64>,
+    memory_pages: Option<u32>,
+    buffer_bytes: Option<usize>,
+}
+
 pub struct Runtime {
     /// Working memory store used by the inner engine
     pub(super) wasm_store: Option<Store>,
@@ -124,16 +196,26 @@ pub struct Runtime {
     pub(super) top_level_imports: Imports,
     /// assigned growable host memory
     pub(super) host_memory: Option<Memory>,
+    /// metering state of the running instance, used by the host functions
+    metering_env: FunctionEnv<native_api::MeteringEnv>,
+
+    /// engine used to compile the modules, shared with any forked runtimes
+    engine: Engine,
+    /// identifies the engine configuration for the compiled modules persisted in the stores
+    module_tag: String,
+    limits: CallLimits,
+    /// bytes allocated in the buffers of the running instance during the current call
+    buffer_bytes: usize,
 
     pub(super) secret_store: SecretsStore,
     pub(super) delegate_store: DelegateStore,
-    /// loaded delegate modules
-    pub(super) delegate_modules: HashMap<DelegateKey, Module>,
+    /// loaded delegate modules, shared with any forked runtimes
+    pub(super) delegate_modules: Arc<DashMap<DelegateKey, Module>>,
 
     /// Local contract storage.
     pub(crate) contract_store: ContractStore,
-    /// loaded contract modules
-    pub(super) contract_modules: HashMap<ContractKey, Module>,
+    /// loaded contract modules, shared with any forked runtimes
+    pub(super) contract_modules: Arc<DashMap<ContractKey, Module>>,
 }
 
 impl Runtime {
@@ -144,36 +226,108 @@ impl Runtime {
         host_mem: bool,
         config: RuntimeConfig,
     ) -> RuntimeResult<Self> {
-        let mut store = Self::instance_store_with_config(&config);
-        let (host_memory, mut top_level_imports) = if host_mem {
-            let mem = Self::instance_host_mem(&mut store)?;
-            let imports = imports! {
-                "env" => {
-                    "memory" =>  mem.clone(),
-                },
-            };
-            (Some(mem), imports)
-        } else {
-            (None, imports! {})
+        let max_cycles = Self::max_cycles(&config);
+        let engine = Self::engine_with_config(max_cycles, config.max_memory_pages);
+        let limits = CallLimits {
+            fuel: config.max_fuel.filter(|fuel| *fuel < max_cycles),
+            memory_pages: config.max_memory_pages,
+            buffer_bytes: config.max_buffer_bytes,
         };
-        native_api::log::prepare_export(&mut store, &mut top_level_imports);
-        native_api::rand::prepare_export(&mut store, &mut top_level_imports);
-        native_api::time::prepare_export(&mut store, &mut top_level_imports);
+        let mut store = Store::new(engine.clone());
+        let (host_memory, top_level_imports, metering_env) =
+            Self::prepare_imports(&mut store, host_mem)?;
 
         Ok(Self {
             wasm_store: Some(store),
             top_level_imports,
             host_memory,
+            metering_env,
+            engine,
+            module_tag: Self::module_tag(max_cycles, config.max_memory_pages),
+            limits,
+            buffer_bytes: 0,
 
             secret_store,
             delegate_store,
-            contract_modules: HashMap::new(),
+            contract_modules: Arc::new(DashMap::new()),
 
             contract_store,
-            delegate_modules: HashMap::new(),
+            delegate_modules: Arc::new(DashMap::new()),
+        })
+    }
+
+    /// Creates a new runtime with its own working memory, sharing the engine, the compiled
+    /// modules and the contract, delegate and secrets stores with this one.
+    ///
+    /// Each runtime can execute on a different thread, so this allows running several
+    /// contracts concurrently without having to compile their code more than once.
+    pub fn fork(&self) -> RuntimeResult<Self> {
+        let mut store = Store::new(self.engine.clone());
+        let (host_memory, top_level_imports, metering_env) =
+            Self::prepare_imports(&mut store, self.host_memory.is_some())?;
+        Ok(Self {
+            wasm_store: Some(store),
+            top_level_imports,
+            host_memory,
+            metering_env,
+            engine: self.engine.clone(),
+            module_tag: self.module_tag.clone(),
+            limits: self.limits,
+            buffer_bytes: 0,
+
+            secret_store: self.secret_store.clone(),
+            delegate_store: self.delegate_store.clone(),
+            contract_modules: self.contract_modules.clone(),
+
+            contract_store: self.contract_store.clone(),
+            delegate_modules: self.delegate_modules.clone(),
         })
     }
 
+    /// Rebuilds the working memory, while keeping the same engine, compiled modules and
+    /// contract, delegate and secrets stores.
+    ///
+    /// Used to recover from fatal errors which may have left the runtime in a broken state.
+    /// Compiled modules don't hold any execution state, so they are safe to keep around
+    /// (and may be in use by forked runtimes).
+    pub(crate) fn reset(&mut self) -> RuntimeResult<()> {
+        let mut store = Store::new(self.engine.clone());
+        let (host_memory, top_level_imports, metering_env) =
+            Self::prepare_imports(&mut store, self.host_memory.is_some())?;
+        self.host_memory = host_memory;
+        self.top_level_imports = top_level_imports;
+        self.metering_env = metering_env;
+        self.wasm_store = Some(store);
+        Ok(())
+    }
+
+    fn prepare_imports(
+        store: &mut Store,
+        host_mem: bool,
+    ) -> RuntimeResult<(
+        Option<Memory>,
+        Imports,
+        FunctionEnv<native_api::MeteringEnv>,
+    )> {
+        let (host_memory, mut top_level_imports) = if host_mem {
+            let mem = Self::instance_host_mem(store)?;
+            let imports = imports! {
+                "env" => {
+                    "memory" =>  mem.clone(),
+                },
+            };
+            (Some(mem), imports)
+        } else {
+            (None, imports! {})
+        };
+        let metering_env = FunctionEnv::new(store, native_api::MeteringEnv::default());
+        native_api::log::prepare_export(store, &mut top_level_imports, &metering_env);
+        native_api::rand::prepare_export(store, &mut top_level_imports);
+        native_api::time::prepare_export(store, &mut top_level_imports);
+        native_api::crypto::prepare_export(store, &mut top_level_imports, &metering_env);
+        Ok((host_memory, top_level_imports, metering_env))
+    }
+
     pub fn build(
         contract_store: ContractStore,
         delegate_store: DelegateStore,
@@ -194,6 +348,13 @@ impl Runtime {
         T: AsRef<[u8]>,
     {
         let data = data.as_ref();
+        let req = self.buffer_bytes + data.len();
+        if let Some(limit) = self.limits.buffer_bytes {
+            if req > limit {
+                return Err(ContractExecError::BufferLimitExceeded { req, limit }.into());
+            }
+        }
+        self.buffer_bytes = req;
         let wasm_store = self.wasm_store.as_mut().unwrap();
         let initiate_buffer: TypedFunction<u32, i64> = instance
             .exports
@@ -224,7 +385,97 @@ impl Runtime {
         parameters: &Parameters,
         req_bytes: usize,
     ) -> RuntimeResult<RunningInstance> {
-        let module = if let Some(module) = self.contract_modules.get(key) {
+        let module = self.contract_module(key, parameters)?;
+        let instance = self.prepare_instance(&module)?;
+        self.set_instance_mem(req_bytes, &instance)?;
+        self.buffer_bytes = 0;
+        RunningInstance::new(self, instance, Key::Contract(*key.id()))
+    }
+
+    /// Returns the code hash of the contract the given one succeeds, if declared in the
+    /// `freenet-predecessor` custom section of its module.
+    ///
+    /// The predecessor is the contract with that code and the same parameters.
+    pub(crate) fn contract_predecessor(
+        &mut self,
+        key: &ContractKey,
+        parameters: &Parameters,
+    ) -> RuntimeResult<Option<CodeHash>> {
+        let module = self.contract_module(key, parameters)?;
+        let Some(section) = module.custom_sections(PREDECESSOR_SECTION).next() else {
+            return Ok(None);
+        };
+        if section.len() != PREDECESSOR_DECLARATION_LENGTH {
+            return Err(ContractExecError::InvalidPredecessor(section.len()).into());
+        }
+        let mut code_hash = [0; 32];
+        code_hash.copy_from_slice(&section[..32]);
+        Ok(Some(CodeHash::new(code_hash)))
+    }
+
+    /// Whether the predecessor of a contract authorized it as its successor.
+    ///
+    /// The predecessor declares an ed25519 key in its `freenet-upgrade-key` custom section, and
+    /// the successor declaration must be signed with it. The signature covers the whole code
+    /// of the successor, with the signature bytes in its `freenet-predecessor` section zeroed.
+    pub(crate) fn upgrade_authorized(
+        &mut self,
+        predecessor: &ContractKey,
+        successor: &ContractKey,
+        parameters: &Parameters,
+    ) -> RuntimeResult<bool> {
+        if self
+            .contract_store
+            .fetch_contract(predecessor, parameters)
+            .is_none()
+        {
+            return Ok(false);
+        }
+        let module = self.contract_module(predecessor, parameters)?;
+        let Some(upgrade_key) = module.custom_sections(UPGRADE_KEY_SECTION).next() else {
+            return Ok(false);
+        };
+        let Some(upgrade_key) = <&[u8; 32]>::try_from(upgrade_key.as_ref())
+            .ok()
+            .and_then(|key| ed25519_dalek::VerifyingKey::from_bytes(key).ok())
+        else {
+            tracing::warn!(contract = %predecessor, "invalid upgrade key");
+            return Ok(false);
+        };
+
+        let module = self.contract_module(successor, parameters)?;
+        let Some(declaration) = module.custom_sections(PREDECESSOR_SECTION).next() else {
+            return Ok(false);
+        };
+        if declaration.len() != PREDECESSOR_DECLARATION_LENGTH {
+            return Err(ContractExecError::InvalidPredecessor(declaration.len()).into());
+        }
+        let signature = ed25519_dalek::Signature::from_slice(&declaration[32..])
+            .map_err(|_| ContractExecError::InvalidPredecessor(declaration.len()))?;
+        let Some(ContractContainer::Wasm(ContractWasmAPIVersion::V1(contract))) =
+            self.contract_store.fetch_contract(successor, parameters)
+        else {
+            return Ok(false);
+        };
+        let mut signed = contract.code().data().to_vec();
+        let Some(start) = signed
+            .windows(declaration.len())
+            .position(|bytes| bytes == declaration.as_ref())
+        else {
+            return Ok(false);
+        };
+        signed[start + 32..start + PREDECESSOR_DECLARATION_LENGTH].fill(0);
+        Ok(upgrade_key.verify_strict(&signed, &signature).is_ok())
+    }
+
+    fn contract_module(
+        &mut self,
+        key: &ContractKey,
+        parameters: &Parameters,
+    ) -> RuntimeResult<Module> {
+        // the map guard must be released before inserting, or the shard would deadlock
+        let loaded = self.contract_modules.get(key).map(|module| module.clone());
+        let module = if let Some(module) = loaded {
             module
         } else {
             let contract = self
@@ -233,17 +484,21 @@ impl Runtime {
                 .ok_or_else(|| RuntimeInnerError::ContractNotFound(*key))?;
             let module = match contract {
                 ContractContainer::Wasm(ContractWasmAPIVersion::V1(contract_v1)) => {
-                    Module::new(self.wasm_store.as_ref().unwrap(), contract_v1.code().data())?
+                    let code_hash = contract_v1.code().hash();
+                    let cached = self
+                        .contract_store
+                        .fetch_module(code_hash, &self.module_tag);
+                    self.load_or_compile_module(contract_v1.code().data(), cached, |artifact| {
+                        self.contract_store
+                            .store_module(code_hash, &self.module_tag, artifact)
+                    })?
                 }
                 _ => unimplemented!(),
             };
-            self.contract_modules.insert(*key, module);
-            self.contract_modules.get(key).unwrap()
-        }
-        .clone();
-        let instance = self.prepare_instance(&module)?;
-        self.set_instance_mem(req_bytes, &instance)?;
-        RunningInstance::new(self, instance, Key::Contract(*key.id()))
+            self.contract_modules.insert(*key, module.clone());
+            module
+        };
+        Ok(module)
     }
 
     pub(super) fn prepare_delegate_call(
@@ -252,20 +507,30 @@ impl Runtime {
         key: &DelegateKey,
         req_bytes: usize,
     ) -> RuntimeResult<RunningInstance> {
-        let module = if let Some(module) = self.delegate_modules.get(key) {
+        // the map guard must be released before inserting, or the shard would deadlock
+        let loaded = self.delegate_modules.get(key).map(|module| module.clone());
+        let module = if let Some(module) = loaded {
             module
         } else {
             let delegate = self
                 .delegate_store
                 .fetch_delegate(key, params)
                 .ok_or_else(|| RuntimeInnerError::DelegateNotFound(key.clone()))?;
-            let module = Module::new(self.wasm_store.as_ref().unwrap(), delegate.code().as_ref())?;
-            self.delegate_modules.insert(key.clone(), module);
-            self.delegate_modules.get(key).unwrap()
-        }
-        .clone();
+            let code_hash = delegate.code().hash();
+            let cached = self
+                .delegate_store
+                .fetch_module(code_hash, &self.module_tag);
+            let module =
+                self.load_or_compile_module(delegate.code().as_ref(), cached, |artifact| {
+                    self.delegate_store
+                        .store_module(code_hash, &self.module_tag, artifact)
+                })?;
+            self.delegate_modules.insert(key.clone(), module.clone());
+            module
+        };
         let instance = self.prepare_instance(&module)?;
         self.set_instance_mem(req_bytes, &instance)?;
+        self.buffer_bytes = 0;
         RunningInstance::new(self, instance, Key::Delegate(key.clone()))
     }
 
@@ -276,7 +541,12 @@ impl Runtime {
             .as_ref()
             .map(Ok)
             .unwrap_or_else(|| instance.exports.get_memory("memory"))?;
-        let req_pages: wasmer::Pages = Bytes::from(req_bytes).try_into().unwrap();
+        let req_pages: Pages = Bytes::from(req_bytes).try_into().unwrap();
+        if let Some(limit) = self.limits.memory_pages {
+            if req_pages.0 > limit {
+                return Err(ContractExecError::MemoryLimitExceeded { limit }.into());
+            }
+        }
         if memory.view(&*wasm_store).size() < req_pages {
             if let Err(err) = memory.grow(wasm_store, req_pages) {
                 tracing::error!("wasm runtime failed with memory error: {err}");
@@ -291,24 +561,42 @@ impl Runtime {
     }
 
     fn instance_host_mem(store: &mut Store) -> RuntimeResult<Memory> {
-        // todo: max memory assigned for this runtime
+        // the maximum is capped by the engine tunables if a memory limit is configured
         Ok(Memory::new(store, MemoryType::new(20u32, None, false))?)
     }
 
     fn prepare_instance(&mut self, module: &Module) -> RuntimeResult<Instance> {
-        Ok(Instance::new(
-            self.wasm_store.as_mut().unwrap(),
-            module,
-            &self.top_level_imports,
-        )?)
+        let wasm_store = self.wasm_store.as_mut().unwrap();
+        let instance = Instance::new(wasm_store, module, &self.top_level_imports)?;
+        if let Some(fuel) = self.limits.fuel {
+            // modules are compiled with the limit derived from the maximum execution time,
+            // a lower fuel budget is set on every new instance instead
+            set_remaining_points(wasm_store, &instance, fuel);
+        }
+        native_api::MeteringEnv::bind(
+            &self.metering_env,
+            wasm_store,
+            &instance,
+            self.host_memory.as_ref(),
+        );
+        Ok(instance)
     }
 
-    fn instance_store_with_config(config: &RuntimeConfig) -> Store {
-        use std::sync::Arc;
-        use wasmer::wasmparser::Operator;
-        use wasmer_compiler_singlepass::Singlepass;
-        use wasmer_middlewares::Metering;
+    /// Returns the memory limit if the memory of the instance can't grow any further.
+    fn memory_limit_reached(&self, instance: &Instance) -> Option<u32> {
+        let limit = self.limits.memory_pages?;
+        let memory = self
+            .host_memory
+            .as_ref()
+            .map(Ok)
+            .unwrap_or_else(|| instance.exports.get_memory("memory"))
+            .ok()?;
+        let size = memory.view(self.wasm_store.as_ref()?).size();
+        (size.0 >= limit).then_some(limit)
+    }
 
+    /// Maximum number of operations a single call can execute before running out of gas.
+    fn max_cycles(config: &RuntimeConfig) -> u64 {
         fn get_cpu_cycles_per_second() -> (u64, f64) {
             // Assumed CPU speed for cost calculations (3.0 GHz)
             const DEFAULT_CPU_CYCLES_PER_SECOND: u64 = 3_000_000_000;
@@ -330,9 +618,13 @@ impl Runtime {
         };
 
         // Calculate total allowed cycles including safety margin
-        let max_cycles: u64 = (config.max_execution_seconds
-            * cpu_cycles_per_sec as f64
-            * (1.0 + safety_margin)) as u64;
+        (config.max_execution_seconds * cpu_cycles_per_sec as f64 * (1.0 + safety_margin)) as u64
+    }
+
+    fn engine_with_config(max_cycles: u64, max_memory_pages: Option<u32>) -> Engine {
+        use wasmer::wasmparser::Operator;
+        use wasmer_compiler_singlepass::Singlepass;
+        use wasmer_middlewares::Metering;
 
         let operation_cost = |_operator: &Operator| -> u64 { 1 };
 
@@ -340,9 +632,64 @@ impl Runtime {
         let mut compiler_config = Singlepass::default();
         compiler_config.push_middleware(metering);
 
-        let engine = wasmer::EngineBuilder::new(compiler_config).engine();
+        let mut engine = wasmer::EngineBuilder::new(compiler_config).engine();
+        if let Some(pages) = max_memory_pages {
+            let base = BaseTunables::for_target(&Target::default());
+            engine.set_tunables(MemoryLimit::new(base, Pages(pages)));
+        }
+        engine
+    }
 
-        Store::new(&engine)
+    /// Identifies the configuration modules are compiled with, artifacts compiled with a
+    /// different wasmer version, compiler, metering, memory limit or for a different target
+    /// can't be reused.
+    fn module_tag(max_cycles: u64, max_memory_pages: Option<u32>) -> String {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(wasmer::VERSION.as_bytes());
+        hasher.update(b"singlepass");
+        hasher.update(&max_cycles.to_le_bytes());
+        if let Some(pages) = max_memory_pages {
+            hasher.update(&pages.to_le_bytes());
+        }
+        hasher.update(std::env::consts::ARCH.as_bytes());
+        hasher.update(std::env::consts::OS.as_bytes());
+        // the code is compiled for the features of the host CPU, which may not be
+        // available on the one a store is moved to
+        let target = Target::default();
+        for feature in target.cpu_features().iter() {
+            hasher.update(feature.to_string().as_bytes());
+        }
+        hasher.finalize().to_hex()[..16].to_owned()
+    }
+
+    /// Returns the compiled module for the given code, deserializing the artifact persisted
+    /// by a previous run if available, otherwise compiling it and persisting the artifact.
+    fn load_or_compile_module(
+        &self,
+        code: &[u8],
+        cached: Option<Vec<u8>>,
+        persist: impl FnOnce(&[u8]) -> RuntimeResult<()>,
+    ) -> RuntimeResult<Module> {
+        if let Some(artifact) = cached {
+            // SAFETY: the stores only return artifacts authenticated with their own key, which
+            // are written by this runtime after serializing a module compiled with the same
+            // configuration and for the same target (see `module_tag`), so they can't be
+            // truncated, modified or come from an incompatible engine
+            match unsafe { Module::deserialize(&self.engine, artifact) } {
+                Ok(module) => return Ok(module),
+                Err(err) => tracing::warn!("failed to load compiled module, recompiling: {err}"),
+            }
+        }
+        let module = Module::new(&self.engine, code)?;
+        match module.serialize() {
+            Ok(artifact) => {
+                if let Err(err) = persist(&artifact) {
+                    tracing::warn!("failed to persist compiled module: {err}");
+                }
+            }
+            Err(err) => tracing::warn!("failed to serialize compiled module: {err}"),
+        }
+        Ok(module)
     }
 
     pub(crate) fn handle_contract_error(
@@ -354,10 +701,28 @@ impl Runtime {
         let remaining_points = get_remaining_points(self.wasm_store.as_mut().unwrap(), instance);
         match remaining_points {
             MeteringPoints::Remaining(..) => {
+                // allocations failing after the memory reached its limit end up trapping
+                if let Some(limit) = self.memory_limit_reached(instance) {
+                    tracing::error!(
+                        "{} exceeded the memory limit of {} pages: {:?}",
+                        function_name,
+                        limit,
+                        error
+                    );
+                    return ContractExecError::MemoryLimitExceeded { limit }.into();
+                }
                 tracing::error!("Error while calling {}: {:?}", function_name, error);
                 error.into()
             }
             MeteringPoints::Exhausted => {
+                if let Some(limit) = self.limits.fuel {
+                    tracing::error!(
+                        "{} exhausted its fuel of {} instructions",
+                        function_name,
+                        limit
+                    );
+                    return ContractExecError::FuelLimitExceeded { limit }.into();
+                }
                 tracing::error!(
                     "{} ran out of gas, not enough points remaining",
                     function_name
@@ -0,0 +1,265 @@
+//! Passphrase protected backups of the secrets stored for a set of delegates, so they can be
+//! restored on another node or after the secrets directory is lost.
+
+use std::{fs, path::Path};
+
+use aes_gcm::KeyInit;
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, OsRng, Payload},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use freenet_stdlib::{
+    client_api::DelegateRequest,
+    prelude::{
+        CodeHash, Delegate, DelegateCode, DelegateContainer, DelegateKey, DelegateWasmAPIVersion,
+        Parameters,
+    },
+};
+use serde::{Deserialize, Serialize};
+
+use super::{DelegateStore, SecretStoreError, SecretsStore};
+
+/// Marks a secrets backup, followed by the passphrase salt and the nonce.
+const MAGIC: &[u8; 4] = b"FSB\x01";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize = MAGIC.len() + SALT_LEN + NONCE_LEN;
+const PBKDF2_ROUNDS: u32 = 600_000;
+
+/// The secrets of a set of delegates, along with the delegates code and parameters.
+///
+/// Secrets are decrypted from the store when exported, so a backup can be imported into a
+/// store using a different master key. Backups are only written to disk encrypted with a
+/// key derived from a passphrase.
+#[derive(Serialize, Deserialize)]
+pub struct SecretsBackup {
+    delegates: Vec<DelegateBackup>,
+    /// Secrets which couldn't be decrypted when exporting, by delegate and name.
+    #[serde(skip)]
+    skipped: Vec<(DelegateKey, String)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DelegateBackup {
+    key: [u8; 32],
+    code_hash: [u8; 32],
+    code: Vec<u8>,
+    params: Vec<u8>,
+    /// Secrets by the name of the file they are stored at.
+    secrets: Vec<(String, Vec<u8>)>,
+}
+
+impl SecretsBackup {
+    /// Exports the secrets of the given delegates, which must be stored along with their code.
+    ///
+    /// The store is not modified. Secrets stored in the legacy format for a delegate whose
+    /// cipher is not registered can't be decrypted, so they are skipped and reported by
+    /// [`Self::skipped`].
+    pub fn export(
+        secrets_store: &SecretsStore,
+        delegate_store: &DelegateStore,
+        delegates: &[(DelegateKey, Parameters<'static>)],
+    ) -> anyhow::Result<Self> {
+        let mut backups = Vec::with_capacity(delegates.len());
+        let mut skipped = vec![];
+        for (key, params) in delegates {
+            let delegate = delegate_store
+                .fetch_delegate(key, params)
+                .ok_or_else(|| anyhow::anyhow!("the code of delegate {key} is not stored"))?;
+            if delegate.key() != key {
+                anyhow::bail!("the parameters don't match delegate {key}");
+            }
+            let mut secrets = vec![];
+            for (name, plaintext) in secrets_store.named_secrets(key)? {
+                match plaintext {
+                    Ok(plaintext) => secrets.push((name, plaintext)),
+                    Err(SecretStoreError::MissingCipher) => skipped.push((key.clone(), name)),
+                    Err(err) => {
+                        return Err(anyhow::anyhow!(err)
+                            .context(format!("failed reading secret `{name}` of {key}")))
+                    }
+                }
+            }
+            if secrets.is_empty() && !skipped.iter().any(|(skipped, _)| skipped == key) {
+                anyhow::bail!("no secrets stored for delegate {key}");
+            }
+            backups.push(DelegateBackup {
+                key: **key,
+                code_hash: **key.code_hash(),
+                code: delegate.code().as_ref().to_vec(),
+                params: params.as_ref().to_vec(),
+                secrets,
+            });
+        }
+        Ok(Self {
+            delegates: backups,
+            skipped,
+        })
+    }
+
+    /// Registers the backed up delegates and stores their secrets, replacing any secrets with
+    /// the same ids. Returns the number of secrets stored.
+    pub fn import(
+        &self,
+        secrets_store: &mut SecretsStore,
+        delegate_store: &mut DelegateStore,
+    ) -> anyhow::Result<usize> {
+        let mut imported = 0;
+        for backup in &self.delegates {
+            let delegate = Delegate::from((
+                &DelegateCode::from(backup.code.clone()),
+                &Parameters::from(backup.params.clone()),
+            ));
+            let key = DelegateKey::new(backup.key, CodeHash::new(backup.code_hash));
+            if delegate.key() != &key {
+                anyhow::bail!("the backed up code and parameters don't match delegate {key}");
+            }
+            // secrets are stored encrypted under the master key, the cipher of the delegate is
+            // only used to read secrets stored in the legacy format
+            let cipher = XChaCha20Poly1305::new(Key::from_slice(&DelegateRequest::DEFAULT_CIPHER));
+            let nonce = *XNonce::from_slice(&DelegateRequest::DEFAULT_NONCE);
+            secrets_store.register_delegate(key.clone(), cipher, nonce)?;
+            delegate_store.store_delegate(DelegateContainer::Wasm(DelegateWasmAPIVersion::V1(
+                delegate,
+            )))?;
+            for (name, plaintext) in &backup.secrets {
+                secrets_store.store_secret_by_name(&key, name, plaintext)?;
+                imported += 1;
+            }
+        }
+        Ok(imported)
+    }
+
+    pub fn read(path: &Path, passphrase: &str) -> anyhow::Result<Self> {
+        let data = fs::read(path)?;
+        if data.len() < HEADER_LEN || !data.starts_with(MAGIC) {
+            anyhow::bail!("{path:?} is not a secrets backup");
+        }
+        let (header, ciphertext) = data.split_at(HEADER_LEN);
+        let salt = &header[MAGIC.len()..MAGIC.len() + SALT_LEN];
+        let nonce = XNonce::from_slice(&header[MAGIC.len() + SALT_LEN..]);
+        let payload = backup_cipher(passphrase, salt)
+            .decrypt(
+                nonce,
+                Payload {
+                    msg: ciphertext,
+                    aad: header,
+                },
+            )
+            .map_err(|_| anyhow::anyhow!("failed decrypting backup, check the passphrase"))?;
+        Ok(bincode::deserialize(&payload)?)
+    }
+
+    pub fn write(&self, path: &Path, passphrase: &str) -> anyhow::Result<()> {
+        let salt = rand::random::<[u8; SALT_LEN]>();
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut data = Vec::with_capacity(HEADER_LEN);
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&salt);
+        data.extend_from_slice(&nonce);
+        let payload = bincode::serialize(self)?;
+        let ciphertext = backup_cipher(passphrase, &salt)
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &payload,
+                    aad: &data,
+                },
+            )
+            .map_err(|err| anyhow::anyhow!("failed encrypting backup: {err}"))?;
+        data.extend_from_slice(&ciphertext);
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    pub fn delegates(&self) -> usize {
+        self.delegates.len()
+    }
+
+    pub fn secrets(&self) -> usize {
+        self.delegates.iter().map(|d| d.secrets.len()).sum()
+    }
+
+    /// Returns the secrets skipped when exporting, by delegate and name.
+    pub fn skipped(&self) -> &[(DelegateKey, String)] {
+        &self.skipped
+    }
+}
+
+fn backup_cipher(passphrase: &str, salt: &[u8]) -> XChaCha20Poly1305 {
+    let mut key = [0; 32];
+    pbkdf2::pbkdf2_hmac::<sha2::Sha256>(passphrase.as_bytes(), salt, PBKDF2_ROUNDS, &mut key);
+    XChaCha20Poly1305::new(Key::from_slice(&key))
+}
+
+#[cfg(test)]
+mod tests {
+    use freenet_stdlib::prelude::SecretsId;
+
+    use super::*;
+    use crate::config::Secrets;
+
+    #[test]
+    fn export_and_import() -> anyhow::Result<()> {
+        let temp_dir = crate::util::tests::get_temp_dir();
+        let source = temp_dir.path().join("source");
+        let mut secrets_store = SecretsStore::new(source.join("secrets"), Default::default())?;
+        let mut delegate_store = DelegateStore::new(source.join("delegates"), 10_000)?;
+        let params = Parameters::from(vec![6]);
+        let delegate = Delegate::from((&vec![0, 1, 2].into(), &params));
+        let key = delegate.key().clone();
+        delegate_store.store_delegate(DelegateContainer::Wasm(DelegateWasmAPIVersion::V1(
+            delegate,
+        )))?;
+        let secret_id = SecretsId::new(vec![3]);
+        secrets_store.store_secret(&key, &secret_id, vec![4, 5])?;
+
+        // a secret in the legacy format whose cipher isn't registered is skipped
+        let legacy_id = SecretsId::new(vec![7]);
+        secrets_store.store_secret(&key, &legacy_id, vec![])?;
+        let cipher = XChaCha20Poly1305::new(&XChaCha20Poly1305::generate_key(&mut OsRng));
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let legacy_path = source
+            .join("secrets")
+            .join(key.encode())
+            .join(legacy_id.encode());
+        let legacy = cipher.encrypt(&nonce, [8].as_slice()).unwrap();
+        fs::write(&legacy_path, &legacy)?;
+
+        let backup = SecretsBackup::export(
+            &secrets_store,
+            &delegate_store,
+            &[(key.clone(), params.clone())],
+        )?;
+        assert_eq!(backup.secrets(), 1);
+        assert_eq!(backup.skipped(), &[(key.clone(), legacy_id.encode())]);
+        // exporting doesn't modify the store
+        assert_eq!(fs::read(&legacy_path)?, legacy);
+        assert!(SecretsBackup::export(
+            &secrets_store,
+            &delegate_store,
+            &[(key.clone(), Parameters::from(vec![]))],
+        )
+        .is_err());
+
+        let path = temp_dir.path().join("backup");
+        backup.write(&path, "correct horse")?;
+        assert!(SecretsBackup::read(&path, "wrong horse").is_err());
+
+        // the target store encrypts secrets under a different master key
+        let target = temp_dir.path().join("target");
+        let secrets = Secrets {
+            passphrase: Some("battery staple".into()),
+            ..Default::default()
+        };
+        let mut secrets_store = SecretsStore::new(target.join("secrets"), secrets)?;
+        let mut delegate_store = DelegateStore::new(target.join("delegates"), 10_000)?;
+        let imported = SecretsBackup::read(&path, "correct horse")?
+            .import(&mut secrets_store, &mut delegate_store)?;
+        assert_eq!(imported, 1);
+        assert_eq!(secrets_store.get_secret(&key, &secret_id)?, vec![4, 5]);
+        assert_eq!(secrets_store.delegates(), vec![key.clone()]);
+        assert!(delegate_store.fetch_delegate(&key, &params).is_some());
+        Ok(())
+    }
+}
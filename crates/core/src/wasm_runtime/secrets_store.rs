@@ -1,16 +1,21 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashSet,
     fs::{self, File},
     io::Write,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::Arc,
 };
 
-use chacha20poly1305::{aead::Aead, Error as EncryptionError, XChaCha20Poly1305, XNonce};
+use aes_gcm::KeyInit;
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, OsRng, Payload},
+    Error as EncryptionError, Key, XChaCha20Poly1305, XNonce,
+};
 use dashmap::DashMap;
-use freenet_stdlib::prelude::*;
+use freenet_stdlib::{client_api::DelegateRequest, prelude::*};
+use parking_lot::Mutex;
 
-use crate::config::Secrets;
+use crate::config::{MasterKeySource, Secrets};
 
 use super::{
     store::{SafeWriter, StoreFsManagement},
@@ -19,6 +24,20 @@ use super::{
 
 type SecretKey = [u8; 32];
 
+/// Marks secrets encrypted with a key derived from the master key, followed by the master
+/// key fingerprint and the nonce. Secrets without it were encrypted with the delegate cipher.
+const MAGIC: &[u8; 4] = b"FSE\x01";
+const FINGERPRINT_LEN: usize = 8;
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize = MAGIC.len() + FINGERPRINT_LEN + NONCE_LEN;
+/// Fingerprint of the master key the stored secrets are encrypted under, followed by the salt
+/// it was derived with if derived from a passphrase.
+const MASTER_KEY_FILE: &str = "MASTER_KEY";
+/// Same as [`MASTER_KEY_FILE`] for the master key of a rotation in progress.
+const PENDING_MASTER_KEY_FILE: &str = "MASTER_KEY.pending";
+const SALT_LEN: usize = 16;
+const PBKDF2_ROUNDS: u32 = 600_000;
+
 #[derive(Debug, thiserror::Error)]
 pub enum SecretStoreError {
     #[error("encryption error: {0}")]
@@ -29,6 +48,8 @@ pub enum SecretStoreError {
     MissingCipher,
     #[error("missing secret: {0}")]
     MissingSecret(SecretsId),
+    #[error("secrets are encrypted under a different master key, check the passphrase or cipher")]
+    WrongMasterKey,
 }
 
 #[derive(Clone)]
@@ -37,15 +58,178 @@ struct Encryption {
     nonce: XNonce,
 }
 
+/// Node wide key the encryption keys of each delegate secrets are derived from.
+#[derive(Clone)]
+struct MasterKey {
+    key: [u8; 32],
+    fingerprint: [u8; FINGERPRINT_LEN],
+    /// Salt the key was derived with from a passphrase.
+    salt: Option<[u8; SALT_LEN]>,
+}
+
+impl MasterKey {
+    fn new(key: [u8; 32], salt: Option<[u8; SALT_LEN]>) -> Self {
+        let digest = blake3::derive_key("freenet secrets master key fingerprint", &key);
+        let mut fingerprint = [0; FINGERPRINT_LEN];
+        fingerprint.copy_from_slice(&digest[..FINGERPRINT_LEN]);
+        Self {
+            key,
+            fingerprint,
+            salt,
+        }
+    }
+
+    /// Derives the master key from the source, with the salt recorded in `record` if any or a
+    /// fresh one otherwise.
+    fn derive(record: &Path, source: &MasterKeySource) -> std::io::Result<Self> {
+        match source {
+            MasterKeySource::Cipher(key) => Ok(Self::new(*key, None)),
+            MasterKeySource::Passphrase(passphrase) => {
+                let salt = match fs::read(record) {
+                    Ok(record) => record
+                        .get(FINGERPRINT_LEN..)
+                        .and_then(|salt| salt.try_into().ok()),
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+                    Err(err) => return Err(err),
+                };
+                Ok(Self::from_passphrase(
+                    passphrase,
+                    salt.unwrap_or_else(rand::random),
+                ))
+            }
+        }
+    }
+
+    fn from_passphrase(passphrase: &str, salt: [u8; SALT_LEN]) -> Self {
+        let mut key = [0; 32];
+        pbkdf2::pbkdf2_hmac::<sha2::Sha256>(passphrase.as_bytes(), &salt, PBKDF2_ROUNDS, &mut key);
+        Self::new(key, Some(salt))
+    }
+
+    fn delegate_cipher(&self, delegate: &DelegateKey) -> XChaCha20Poly1305 {
+        let mut hasher = blake3::Hasher::new_derive_key("freenet secrets delegate key");
+        hasher.update(&self.key);
+        hasher.update(&**delegate);
+        hasher.update(&**delegate.code_hash());
+        XChaCha20Poly1305::new(Key::from_slice(hasher.finalize().as_bytes()))
+    }
+
+    /// The header and the secret name are authenticated, so a secret can't be passed off
+    /// as another one.
+    fn encrypt(
+        &self,
+        delegate: &DelegateKey,
+        name: &str,
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>, SecretStoreError> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut data = Vec::with_capacity(HEADER_LEN + plaintext.len() + 16);
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&self.fingerprint);
+        data.extend_from_slice(&nonce);
+        let aad = [data.as_slice(), name.as_bytes()].concat();
+        let ciphertext = self
+            .delegate_cipher(delegate)
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad: &aad,
+                },
+            )
+            .map_err(SecretStoreError::Encryption)?;
+        data.extend_from_slice(&ciphertext);
+        Ok(data)
+    }
+
+    fn decrypt(
+        &self,
+        delegate: &DelegateKey,
+        name: &str,
+        data: &[u8],
+    ) -> Result<Vec<u8>, SecretStoreError> {
+        let (header, ciphertext) = data.split_at(HEADER_LEN);
+        if header[MAGIC.len()..MAGIC.len() + FINGERPRINT_LEN] != self.fingerprint {
+            return Err(SecretStoreError::WrongMasterKey);
+        }
+        let nonce = XNonce::from_slice(&header[MAGIC.len() + FINGERPRINT_LEN..]);
+        let aad = [header, name.as_bytes()].concat();
+        self.delegate_cipher(delegate)
+            .decrypt(
+                nonce,
+                Payload {
+                    msg: ciphertext,
+                    aad: &aad,
+                },
+            )
+            .map_err(SecretStoreError::Encryption)
+    }
+
+    /// Checks the master key is the one the stored secrets are encrypted under, recording it
+    /// if no secrets were encrypted under a master key yet.
+    fn check(&self, secrets_dir: &Path) -> Result<(), SecretStoreError> {
+        match fs::read(secrets_dir.join(MASTER_KEY_FILE)) {
+            Ok(record) if record.starts_with(&self.fingerprint) => Ok(()),
+            Ok(_) => Err(SecretStoreError::WrongMasterKey),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                self.record(secrets_dir)?;
+                Ok(())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn record(&self, secrets_dir: &Path) -> std::io::Result<()> {
+        self.record_as(&secrets_dir.join(MASTER_KEY_FILE))
+    }
+
+    fn record_as(&self, path: &Path) -> std::io::Result<()> {
+        let mut record = self.fingerprint.to_vec();
+        record.extend(self.salt.iter().flatten());
+        write_atomically(path, &record)
+    }
+
+    /// Whether the key is the cipher every node uses by default, which doesn't protect the
+    /// secrets at all.
+    fn is_public(&self) -> bool {
+        self.salt.is_none() && self.key == DelegateRequest::DEFAULT_CIPHER
+    }
+}
+
+fn is_encrypted_under_master_key(data: &[u8]) -> bool {
+    data.len() >= HEADER_LEN && data.starts_with(MAGIC)
+}
+
+/// Replaces the file without leaving it partially written if interrupted.
+fn write_atomically(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(tmp_path, path)
+}
+
+/// Secrets are encrypted at rest with a key derived for each delegate from the node master
+/// key, see [`MasterKeySource`].
+///
+/// Unless a passphrase or a cipher other than the default one is configured the master key is
+/// public, so anyone with a copy of the secrets directory can decrypt them.
+///
+/// Secrets stored before are encrypted with the cipher registered by their delegate, or the
+/// node cipher, and are encrypted with the derived key once read or on a master key rotation.
+///
+/// Clones share the same index and registered ciphers.
+#[derive(Clone)]
 pub struct SecretsStore {
     base_path: PathBuf,
     #[allow(unused)]
     secrets: Secrets,
-    ciphers: HashMap<DelegateKey, Encryption>,
+    ciphers: Arc<DashMap<DelegateKey, Encryption>>,
     key_to_secret_part: Arc<DashMap<DelegateKey, (u64, HashSet<SecretKey>)>>,
-    index_file: SafeWriter<Self>,
+    index_file: Arc<Mutex<SafeWriter<Self>>>,
     key_file: PathBuf,
     default_encryption: Encryption,
+    master_key: MasterKey,
 }
 
 pub(super) struct ConcatenatedSecretKeys(Vec<u8>);
@@ -112,10 +296,21 @@ impl SecretsStore {
         }
         Self::watch_changes(key_to_secret_part.clone(), &key_file)?;
 
-        let index_file = SafeWriter::new(&key_file, false)?;
+        let index_file = Arc::new(Mutex::new(SafeWriter::new(&key_file, false)?));
+        let master_key = MasterKey::derive(
+            &secrets_dir.join(MASTER_KEY_FILE),
+            &secrets.master_key_source(),
+        )?;
+        master_key.check(&secrets_dir)?;
+        if master_key.is_public() {
+            tracing::warn!(
+                "No secrets passphrase or cipher configured, delegate secrets are not protected at rest"
+            );
+        }
         Ok(Self {
+            master_key,
             base_path: secrets_dir,
-            ciphers: HashMap::new(),
+            ciphers: Arc::new(DashMap::new()),
             key_to_secret_part,
             index_file,
             key_file,
@@ -146,24 +341,20 @@ impl SecretsStore {
         key: &SecretsId,
         plaintext: Vec<u8>,
     ) -> RuntimeResult<()> {
-        let delegate_path = self.base_path.join(delegate.encode());
-        let secret_file_path = delegate_path.join(key.encode());
-        let secret_key = *key.hash();
-        let encryption = self
-            .ciphers
-            .get(delegate)
-            .unwrap_or(&self.default_encryption);
+        self.store_named_secret(delegate, &key.encode(), *key.hash(), &plaintext)
+    }
 
-        let ciphertext = encryption
-            .cipher
-            .encrypt(&encryption.nonce, plaintext.as_ref())
-            .map_err(|err| {
-                if encryption.nonce == self.default_encryption.nonce {
-                    SecretStoreError::MissingCipher
-                } else {
-                    SecretStoreError::Encryption(err)
-                }
-            })?;
+    /// Stores a secret under the file name of its id, along with the hash of the id.
+    fn store_named_secret(
+        &mut self,
+        delegate: &DelegateKey,
+        name: &str,
+        secret_key: SecretKey,
+        plaintext: &[u8],
+    ) -> RuntimeResult<()> {
+        let delegate_path = self.base_path.join(delegate.encode());
+        let secret_file_path = delegate_path.join(name);
+        let ciphertext = self.master_key.encrypt(delegate, name, plaintext)?;
 
         // Update index
         let hashes = self.key_to_secret_part.entry(delegate.clone());
@@ -171,6 +362,7 @@ impl SecretsStore {
             dashmap::mapref::entry::Entry::Occupied(mut v) => {
                 let current_version_offset = v.get().0;
                 let secret_hashes = &mut v.get_mut().1;
+                secret_hashes.insert(secret_key);
                 let mut value = vec![];
                 for hash in &*secret_hashes {
                     value.extend_from_slice(hash);
@@ -178,16 +370,15 @@ impl SecretsStore {
                 // first mark the old entry (if it exists) as removed
                 Self::remove(&self.key_file, current_version_offset)?;
                 let new_offset = Self::insert(
-                    &mut self.index_file,
+                    &mut self.index_file.lock(),
                     delegate.clone(),
                     &ConcatenatedSecretKeys(value),
                 )?;
-                secret_hashes.insert(secret_key);
                 v.get_mut().0 = new_offset;
             }
             dashmap::mapref::entry::Entry::Vacant(v) => {
                 let offset = Self::insert(
-                    &mut self.index_file,
+                    &mut self.index_file.lock(),
                     delegate.clone(),
                     &ConcatenatedSecretKeys(secret_key.to_vec()),
                 )?;
@@ -196,9 +387,8 @@ impl SecretsStore {
         }
 
         fs::create_dir_all(&delegate_path)?;
-        tracing::debug!("storing secret `{key}` at {secret_file_path:?}");
-        let mut file = File::create(secret_file_path)?;
-        file.write_all(&ciphertext)?;
+        tracing::debug!("storing secret `{name}` at {secret_file_path:?}");
+        write_atomically(&secret_file_path, &ciphertext)?;
         Ok(())
     }
 
@@ -220,25 +410,172 @@ impl SecretsStore {
         delegate: &DelegateKey,
         key: &SecretsId,
     ) -> Result<Vec<u8>, SecretStoreError> {
-        let secret_path = self.base_path.join(delegate.encode()).join(key.encode());
+        self.get_named_secret(delegate, &key.encode())?
+            .ok_or_else(|| SecretStoreError::MissingSecret(key.clone()))
+    }
+
+    fn get_named_secret(
+        &self,
+        delegate: &DelegateKey,
+        name: &str,
+    ) -> Result<Option<Vec<u8>>, SecretStoreError> {
+        let secret_path = self.base_path.join(delegate.encode()).join(name);
+        let Ok(data) = fs::read(&secret_path) else {
+            return Ok(None);
+        };
+        if is_encrypted_under_master_key(&data) {
+            return self.master_key.decrypt(delegate, name, &data).map(Some);
+        }
+        let plaintext = self.decrypt_with_cipher(delegate, &data)?;
+        let migrated = self
+            .master_key
+            .encrypt(delegate, name, &plaintext)
+            .and_then(|ciphertext| {
+                write_atomically(&secret_path, &ciphertext).map_err(SecretStoreError::from)
+            });
+        if let Err(err) = migrated {
+            tracing::warn!("failed encrypting secret `{name}` under the master key: {err}");
+        }
+        Ok(Some(plaintext))
+    }
+
+    /// Returns the delegates with stored secrets.
+    pub fn delegates(&self) -> Vec<DelegateKey> {
+        self.key_to_secret_part
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    /// Returns all the secrets stored for the delegate, by the file name of their id.
+    ///
+    /// Unlike [`Self::get_secret`], secrets in the legacy format are not migrated, so the
+    /// store is left untouched. A secret which can't be decrypted is returned as an error
+    /// along with its name, so the rest can still be read.
+    pub(crate) fn named_secrets(
+        &self,
+        delegate: &DelegateKey,
+    ) -> Result<Vec<(String, Result<Vec<u8>, SecretStoreError>)>, SecretStoreError> {
+        let Ok(entries) = fs::read_dir(self.base_path.join(delegate.encode())) else {
+            return Ok(vec![]);
+        };
+        let mut secrets = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            // skip leftovers of interrupted writes
+            if path.extension().is_some() {
+                continue;
+            }
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let data = fs::read(&path)?;
+            let plaintext = if is_encrypted_under_master_key(&data) {
+                self.master_key.decrypt(delegate, name, &data)
+            } else {
+                self.decrypt_with_cipher(delegate, &data)
+            };
+            secrets.push((name.to_owned(), plaintext));
+        }
+        secrets.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(secrets)
+    }
+
+    /// Stores a secret returned by [`Self::named_secrets`].
+    pub(crate) fn store_secret_by_name(
+        &mut self,
+        delegate: &DelegateKey,
+        name: &str,
+        plaintext: &[u8],
+    ) -> RuntimeResult<()> {
+        // secret ids are stored under their base58 encoded hash
+        let mut secret_key = [0; 32];
+        let decoded = bs58::decode(name)
+            .with_alphabet(bs58::Alphabet::BITCOIN)
+            .onto(&mut secret_key);
+        if !matches!(decoded, Ok(32)) {
+            return Err(anyhow::anyhow!("invalid secret name `{name}`").into());
+        }
+        self.store_named_secret(delegate, name, secret_key, plaintext)
+    }
+
+    /// Decrypts a secret stored before secrets were encrypted under the master key.
+    fn decrypt_with_cipher(
+        &self,
+        delegate: &DelegateKey,
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>, SecretStoreError> {
         let encryption = self
             .ciphers
             .get(delegate)
-            .unwrap_or(&self.default_encryption);
-
-        let ciphertext =
-            fs::read(secret_path).map_err(|_| SecretStoreError::MissingSecret(key.clone()))?;
-        let plaintext = encryption
+            .map(|encryption| encryption.clone())
+            .unwrap_or_else(|| self.default_encryption.clone());
+        encryption
             .cipher
-            .decrypt(&encryption.nonce, ciphertext.as_ref())
+            .decrypt(&encryption.nonce, ciphertext)
             .map_err(|err| {
                 if encryption.nonce == self.default_encryption.nonce {
                     SecretStoreError::MissingCipher
                 } else {
                     SecretStoreError::Encryption(err)
                 }
-            })?;
-        Ok(plaintext)
+            })
+    }
+
+    /// Re-encrypts all the stored secrets under the master key derived from `new`, returning
+    /// how many were re-encrypted.
+    ///
+    /// A new master key derived from a passphrase uses a fresh salt. The new master key is only
+    /// recorded once all the secrets are re-encrypted, so an interrupted rotation is completed
+    /// by running it again with the same keys. Secrets stored with the cipher of a delegate
+    /// which isn't registered are left as they are.
+    pub fn rotate_master_key(&mut self, new: &MasterKeySource) -> Result<usize, SecretStoreError> {
+        let pending = self.base_path.join(PENDING_MASTER_KEY_FILE);
+        // a rotation interrupted before completing is resumed with the same salt
+        let new_key = MasterKey::derive(&pending, new)?;
+        new_key.record_as(&pending)?;
+        let mut rotated = 0;
+        for delegate in self.delegates() {
+            let Ok(entries) = fs::read_dir(self.base_path.join(delegate.encode())) else {
+                continue;
+            };
+            for entry in entries {
+                let path = entry?.path();
+                // skip leftovers of interrupted writes
+                if path.extension().is_some() {
+                    continue;
+                }
+                let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                    continue;
+                };
+                let data = fs::read(&path)?;
+                let plaintext = if is_encrypted_under_master_key(&data) {
+                    match new_key.decrypt(&delegate, name, &data) {
+                        Ok(_) => continue,
+                        Err(SecretStoreError::WrongMasterKey) => {
+                            self.master_key.decrypt(&delegate, name, &data)?
+                        }
+                        Err(err) => return Err(err),
+                    }
+                } else {
+                    match self.decrypt_with_cipher(&delegate, &data) {
+                        Ok(plaintext) => plaintext,
+                        Err(err) => {
+                            tracing::warn!(
+                                "skipping secret {path:?} of delegate `{delegate}`: {err}"
+                            );
+                            continue;
+                        }
+                    }
+                };
+                write_atomically(&path, &new_key.encrypt(&delegate, name, &plaintext)?)?;
+                rotated += 1;
+            }
+        }
+        new_key.record(&self.base_path)?;
+        fs::remove_file(pending)?;
+        self.master_key = new_key;
+        Ok(rotated)
     }
 }
 
@@ -271,4 +608,96 @@ mod test {
         assert!(f.is_ok());
         Ok(())
     }
+
+    #[test]
+    fn rotate_master_key() -> Result<(), Box<dyn std::error::Error>> {
+        let secrets_dir = crate::util::tests::get_temp_dir();
+        let mut store = SecretsStore::new(secrets_dir.path().into(), Default::default())?;
+        let delegate = Delegate::from((&vec![0, 1, 2].into(), &vec![].into()));
+        let secret_id = SecretsId::new(vec![0, 1, 2]);
+        store.store_secret(delegate.key(), &secret_id, vec![3, 4, 5])?;
+
+        let passphrase = MasterKeySource::Passphrase("correct horse".into());
+        assert_eq!(store.rotate_master_key(&passphrase)?, 1);
+        assert_eq!(store.get_secret(delegate.key(), &secret_id)?, vec![3, 4, 5]);
+        // every rotation derives the key with a fresh salt
+        let record = fs::read(secrets_dir.path().join(MASTER_KEY_FILE))?;
+        assert_eq!(store.rotate_master_key(&passphrase)?, 1);
+        assert_ne!(fs::read(secrets_dir.path().join(MASTER_KEY_FILE))?, record);
+        assert!(!secrets_dir.path().join(PENDING_MASTER_KEY_FILE).exists());
+
+        // the stored secrets can't be read with the previous master key anymore
+        assert!(matches!(
+            SecretsStore::new(secrets_dir.path().into(), Default::default()),
+            Err(err) if err.to_string().contains("different master key")
+        ));
+        let secrets = Secrets {
+            passphrase: Some("correct horse".into()),
+            ..Default::default()
+        };
+        let store = SecretsStore::new(secrets_dir.path().into(), secrets)?;
+        assert_eq!(store.get_secret(delegate.key(), &secret_id)?, vec![3, 4, 5]);
+        Ok(())
+    }
+
+    #[test]
+    fn wrong_passphrase() -> Result<(), Box<dyn std::error::Error>> {
+        let secrets_dir = crate::util::tests::get_temp_dir();
+        let with_passphrase = |passphrase: &str| Secrets {
+            passphrase: Some(passphrase.into()),
+            ..Default::default()
+        };
+        let mut store =
+            SecretsStore::new(secrets_dir.path().into(), with_passphrase("correct horse"))?;
+        let delegate = Delegate::from((&vec![0, 1, 2].into(), &vec![].into()));
+        let secret_id = SecretsId::new(vec![0, 1, 2]);
+        store.store_secret(delegate.key(), &secret_id, vec![3, 4, 5])?;
+
+        assert!(matches!(
+            SecretsStore::new(secrets_dir.path().into(), with_passphrase("battery staple")),
+            Err(err) if err.to_string().contains("different master key")
+        ));
+        // the secrets can't be decrypted with a different key even if the fingerprint is lost
+        fs::remove_file(secrets_dir.path().join(MASTER_KEY_FILE))?;
+        let store =
+            SecretsStore::new(secrets_dir.path().into(), with_passphrase("battery staple"))?;
+        assert!(matches!(
+            store.get_secret(delegate.key(), &secret_id),
+            Err(SecretStoreError::WrongMasterKey)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn migrate_legacy_secret() -> Result<(), Box<dyn std::error::Error>> {
+        let secrets_dir = crate::util::tests::get_temp_dir();
+        let secrets = Secrets {
+            passphrase: Some("correct horse".into()),
+            ..Default::default()
+        };
+        let mut store = SecretsStore::new(secrets_dir.path().into(), secrets)?;
+        let delegate = Delegate::from((&vec![0, 1, 2].into(), &vec![].into()));
+        let secret_id = SecretsId::new(vec![0, 1, 2]);
+        store.store_secret(delegate.key(), &secret_id, vec![])?;
+
+        // secrets stored before were encrypted with the cipher of their delegate
+        let cipher = XChaCha20Poly1305::new(&XChaCha20Poly1305::generate_key(&mut OsRng));
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let path = secrets_dir
+            .path()
+            .join(delegate.key().encode())
+            .join(secret_id.encode());
+        fs::write(&path, cipher.encrypt(&nonce, [3, 4, 5].as_slice()).unwrap())?;
+        assert!(matches!(
+            store.get_secret(delegate.key(), &secret_id),
+            Err(SecretStoreError::MissingCipher)
+        ));
+
+        store.register_delegate(delegate.key().clone(), cipher, nonce)?;
+        assert_eq!(store.get_secret(delegate.key(), &secret_id)?, vec![3, 4, 5]);
+        // and are encrypted under the master key once read
+        assert!(is_encrypted_under_master_key(&fs::read(&path)?));
+        assert_eq!(store.get_secret(delegate.key(), &secret_id)?, vec![3, 4, 5]);
+        Ok(())
+    }
 }
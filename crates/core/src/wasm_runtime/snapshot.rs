@@ -0,0 +1,266 @@
+//! Snapshots of everything a node stores for contracts and delegates, so other nodes can be
+//! seeded from them or a node can be moved to a different storage backend.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use freenet_stdlib::prelude::{
+    CodeHash, ContractInstanceId, ContractKey, DelegateKey, Parameters, WrappedState,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{ContractStore, DelegateStore, StateStorage, StateStore, StateStoreError};
+
+const FORMAT_VERSION: u32 = 1;
+
+/// The code, parameters and state of the stored contracts along with the stored delegates.
+///
+/// Secrets and state histories are not included. Entries are sorted by key, so exporting
+/// the same stores always produces the same snapshot.
+#[derive(Serialize, Deserialize)]
+pub struct StoreSnapshot {
+    format: u32,
+    contracts: Vec<ContractEntry>,
+    delegates: Vec<DelegateEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ContractEntry {
+    id: [u8; 32],
+    /// Code hash and code as stored on disk, missing if only the state is stored.
+    code: Option<([u8; 32], Vec<u8>)>,
+    params: Option<Vec<u8>>,
+    state: Option<Vec<u8>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DelegateEntry {
+    key: [u8; 32],
+    code_hash: [u8; 32],
+    code: Vec<u8>,
+}
+
+impl StoreSnapshot {
+    pub async fn export<S>(
+        contract_store: &ContractStore,
+        delegate_store: &DelegateStore,
+        state_store: &StateStore<S>,
+    ) -> anyhow::Result<Self>
+    where
+        S: StateStorage + Send + 'static,
+        <S as StateStorage>::Error: Into<anyhow::Error>,
+    {
+        let mut ids: Vec<_> = contract_store
+            .contracts()
+            .into_iter()
+            .map(|(id, code_hash)| (id, Some(code_hash)))
+            .collect();
+        for key in state_store.keys().await? {
+            if !ids.iter().any(|(id, _)| id == key.id()) {
+                ids.push((*key.id(), None));
+            }
+        }
+        ids.sort_unstable_by_key(|(id, _)| **id);
+
+        let mut contracts = Vec::with_capacity(ids.len());
+        for (id, code_hash) in ids {
+            let key = ContractKey::from(id);
+            let code = match code_hash {
+                Some(code_hash) => Some((*code_hash, contract_store.versioned_code(&code_hash)?)),
+                None => None,
+            };
+            let params = state_store.get_params(&key).await?;
+            let state = match state_store.get(&key).await {
+                Ok(state) => Some(state),
+                Err(StateStoreError::MissingContract(_)) => None,
+                Err(StateStoreError::Any(err)) => return Err(err),
+            };
+            contracts.push(ContractEntry {
+                id: *id,
+                code,
+                params: params.map(|params| params.as_ref().to_vec()),
+                state: state.map(|state| state.as_ref().to_vec()),
+            });
+        }
+
+        let mut delegates = delegate_store.delegates();
+        delegates.sort_unstable_by_key(|(key, _)| (**key, **key.code_hash()));
+        let delegates = delegates
+            .into_iter()
+            .map(|(key, code_hash)| {
+                Ok(DelegateEntry {
+                    key: *key,
+                    code_hash: *key.code_hash(),
+                    code: delegate_store.versioned_code(&code_hash)?,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            format: FORMAT_VERSION,
+            contracts,
+            delegates,
+        })
+    }
+
+    /// Writes the snapshot to the stores, replacing any entries with the same keys.
+    ///
+    /// Returns the contracts whose state couldn't be imported because the snapshot lacks
+    /// their parameters.
+    pub async fn import<S>(
+        &self,
+        contract_store: &mut ContractStore,
+        delegate_store: &mut DelegateStore,
+        state_store: &mut StateStore<S>,
+    ) -> anyhow::Result<Vec<ContractInstanceId>>
+    where
+        S: StateStorage + Send + 'static,
+        <S as StateStorage>::Error: Into<anyhow::Error>,
+    {
+        let mut missing_params = vec![];
+        for contract in &self.contracts {
+            let id = ContractInstanceId::new(contract.id);
+            if let Some((code_hash, code)) = &contract.code {
+                contract_store.store_versioned_code(id, &CodeHash::new(*code_hash), code)?;
+            }
+            let Some(state) = &contract.state else {
+                continue;
+            };
+            let Some(params) = &contract.params else {
+                missing_params.push(id);
+                continue;
+            };
+            state_store
+                .store(
+                    ContractKey::from(id),
+                    WrappedState::new(state.clone()),
+                    Parameters::from(params.clone()),
+                )
+                .await?;
+        }
+        for delegate in &self.delegates {
+            let key = DelegateKey::new(delegate.key, CodeHash::new(delegate.code_hash));
+            delegate_store.store_versioned_code(&key, &delegate.code)?;
+        }
+        Ok(missing_params)
+    }
+
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let snapshot: Self = bincode::deserialize_from(BufReader::new(File::open(path)?))?;
+        if snapshot.format != FORMAT_VERSION {
+            anyhow::bail!(
+                "unsupported snapshot format {}, expected {FORMAT_VERSION}",
+                snapshot.format
+            );
+        }
+        Ok(snapshot)
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        bincode::serialize_into(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+
+    pub fn contracts(&self) -> usize {
+        self.contracts.len()
+    }
+
+    pub fn delegates(&self) -> usize {
+        self.delegates.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use freenet_stdlib::prelude::{
+        ContractCode, ContractContainer, ContractWasmAPIVersion, WrappedContract,
+    };
+
+    use super::*;
+    use crate::contract::storages::MemoryStorage;
+
+    #[tokio::test]
+    async fn export_and_import() -> anyhow::Result<()> {
+        let temp_dir = crate::util::tests::get_temp_dir();
+        let source = temp_dir.path().join("source");
+        let mut contract_store = ContractStore::new(source.join("contracts"), 10_000)?;
+        let delegate_store = DelegateStore::new(source.join("delegates"), 10_000)?;
+        let mut state_store = StateStore::new(MemoryStorage::default(), 10_000)?;
+
+        let params = Parameters::from(vec![4]);
+        let contract =
+            WrappedContract::new(Arc::new(ContractCode::from(vec![1, 2, 3])), params.clone());
+        let key = *contract.key();
+        contract_store.store_contract(ContractContainer::Wasm(ContractWasmAPIVersion::V1(
+            contract,
+        )))?;
+        state_store
+            .store(key, WrappedState::new(vec![5]), params.clone())
+            .await?;
+        // a state whose contract code isn't stored
+        let without_code = ContractKey::from(ContractInstanceId::new([9; 32]));
+        state_store
+            .store(
+                without_code,
+                WrappedState::new(vec![6]),
+                Parameters::from(vec![]),
+            )
+            .await?;
+
+        let snapshot =
+            StoreSnapshot::export(&contract_store, &delegate_store, &state_store).await?;
+        assert_eq!(snapshot.contracts(), 2);
+        let path = temp_dir.path().join("snapshot");
+        snapshot.write(&path)?;
+
+        let target = temp_dir.path().join("target");
+        let mut contract_store = ContractStore::new(target.join("contracts"), 10_000)?;
+        let mut delegate_store = DelegateStore::new(target.join("delegates"), 10_000)?;
+        let mut state_store = StateStore::new(MemoryStorage::default(), 10_000)?;
+        let missing_params = StoreSnapshot::read(&path)?
+            .import(&mut contract_store, &mut delegate_store, &mut state_store)
+            .await?;
+        assert!(missing_params.is_empty());
+        assert!(contract_store.fetch_contract(&key, &params).is_some());
+        assert_eq!(state_store.get(&key).await?.as_ref(), &[5]);
+        assert_eq!(state_store.get(&without_code).await?.as_ref(), &[6]);
+
+        // exporting the imported stores yields the same snapshot
+        let reexported =
+            StoreSnapshot::export(&contract_store, &delegate_store, &state_store).await?;
+        assert_eq!(
+            bincode::serialize(&reexported)?,
+            bincode::serialize(&snapshot)?
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn import_reports_missing_params() -> anyhow::Result<()> {
+        let temp_dir = crate::util::tests::get_temp_dir();
+        let snapshot = StoreSnapshot {
+            format: FORMAT_VERSION,
+            contracts: vec![ContractEntry {
+                id: [1; 32],
+                code: None,
+                params: None,
+                state: Some(vec![2]),
+            }],
+            delegates: vec![],
+        };
+        let mut contract_store = ContractStore::new(temp_dir.path().join("contracts"), 10_000)?;
+        let mut delegate_store = DelegateStore::new(temp_dir.path().join("delegates"), 10_000)?;
+        let mut state_store = StateStore::new(MemoryStorage::default(), 10_000)?;
+        let missing_params = snapshot
+            .import(&mut contract_store, &mut delegate_store, &mut state_store)
+            .await?;
+        assert_eq!(missing_params, vec![ContractInstanceId::new([1; 32])]);
+        assert!(state_store.keys().await?.is_empty());
+        Ok(())
+    }
+}
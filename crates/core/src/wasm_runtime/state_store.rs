@@ -1,5 +1,9 @@
+use chrono::{DateTime, Utc};
 use core::future::Future;
+use dashmap::DashMap;
 use freenet_stdlib::prelude::*;
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
 use stretto::AsyncCache;
 
 #[derive(thiserror::Error, Debug)]
@@ -23,6 +27,99 @@ impl From<StateStoreError> for crate::wasm_runtime::ContractError {
     }
 }
 
+/// A prior state of a contract, kept in its state history.
+#[derive(Debug, Clone)]
+pub struct StateVersion {
+    /// Increases with every state added to the history of the contract.
+    pub version: u64,
+    /// When this state was replaced by a newer one.
+    pub replaced_at: DateTime<Utc>,
+    pub state: WrappedState,
+}
+
+/// State writes for one or more contracts which must be persisted together,
+/// see [`StateStore::commit`].
+#[derive(Default)]
+pub struct StateBatch {
+    writes: Vec<(ContractKey, WrappedState, Option<Parameters<'static>>)>,
+    /// States replaced by the writes, appended to the history of their contract along with them.
+    replaced: Vec<(ContractKey, DateTime<Utc>, WrappedState)>,
+    /// Contracts removed along with the writes.
+    removed: Vec<ContractKey>,
+    /// Number of replaced states kept for each contract.
+    history_len: usize,
+}
+
+impl StateBatch {
+    /// Stores the state and parameters of a new or existing contract.
+    pub fn store(&mut self, key: ContractKey, state: WrappedState, params: Parameters<'static>) {
+        self.writes.push((key, state, Some(params)));
+    }
+
+    /// Replaces the state of an existing contract.
+    pub fn update(&mut self, key: ContractKey, state: WrappedState) {
+        self.writes.push((key, state, None));
+    }
+
+    /// Removes the state, the parameters and the state history of a contract, once any
+    /// writes in the batch have been applied.
+    pub fn remove(&mut self, key: ContractKey) {
+        self.removed.push(key);
+    }
+
+    /// Returns the latest state written for the contract in this batch, if any.
+    pub fn get(&self, key: &ContractKey) -> Option<&WrappedState> {
+        self.writes
+            .iter()
+            .rev()
+            .find_map(|(k, state, _)| (k == key).then_some(state))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty() && self.removed.is_empty()
+    }
+
+    /// Adds a state replaced by the writes to the history of the contract, keeping only the
+    /// last `history_len` states.
+    fn archive(
+        &mut self,
+        key: ContractKey,
+        replaced_at: DateTime<Utc>,
+        state: WrappedState,
+        history_len: usize,
+    ) {
+        self.replaced.push((key, replaced_at, state));
+        self.history_len = history_len;
+    }
+
+    /// Returns the replaced states to append to the history of their contract, in the same
+    /// transaction as the writes.
+    pub fn replaced(&self) -> impl Iterator<Item = (&ContractKey, &DateTime<Utc>, &WrappedState)> {
+        self.replaced
+            .iter()
+            .map(|(key, replaced_at, state)| (key, replaced_at, state))
+    }
+
+    /// Number of replaced states to keep for each contract, see [`StateStorage::push_history`].
+    pub fn history_len(&self) -> usize {
+        self.history_len
+    }
+
+    /// Returns the contracts removed by the batch.
+    pub fn removed(&self) -> impl Iterator<Item = &ContractKey> {
+        self.removed.iter()
+    }
+
+    /// Returns the writes in the order they were added, later writes take precedence.
+    pub fn writes(
+        &self,
+    ) -> impl Iterator<Item = (&ContractKey, &WrappedState, Option<&Parameters<'static>>)> {
+        self.writes
+            .iter()
+            .map(|(key, state, params)| (key, state, params.as_ref()))
+    }
+}
+
 pub trait StateStorage {
     type Error;
     fn store(
@@ -43,12 +140,55 @@ pub trait StateStorage {
         &'a self,
         key: &'a ContractKey,
     ) -> impl Future<Output = Result<Option<Parameters<'static>>, Self::Error>> + Send + 'a;
+    /// Returns the keys of all the contracts with a stored state.
+    fn keys(&self) -> impl Future<Output = Result<Vec<ContractKey>, Self::Error>> + Send;
+    /// Removes the state, the parameters and the state history of the contract.
+    fn remove(&mut self, key: &ContractKey)
+        -> impl Future<Output = Result<(), Self::Error>> + Send;
+    /// Persists all the writes in the batch, appends the states they replace to the history
+    /// and removes the contracts removed by it, in a single transaction so either all or none
+    /// of them are applied.
+    fn commit(
+        &mut self,
+        batch: &StateBatch,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+    /// Appends a replaced state to the history of the contract, dropping the oldest ones
+    /// so only the last `max_versions` are kept.
+    fn push_history(
+        &mut self,
+        key: ContractKey,
+        replaced_at: DateTime<Utc>,
+        state: WrappedState,
+        max_versions: usize,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+    /// Returns the state history of the contract, oldest first.
+    fn history(
+        &self,
+        key: &ContractKey,
+    ) -> impl Future<Output = Result<Vec<StateVersion>, Self::Error>> + Send;
 }
 
 pub struct StateStore<S: StateStorage> {
     state_mem_cache: AsyncCache<ContractKey, WrappedState>,
     // params_mem_cache: AsyncCache<ContractKey, Parameters<'static>>,
     store: S,
+    /// Number of replaced states kept for each contract, disabled if 0.
+    history_len: usize,
+    /// Sizes of the states in the history of each contract, oldest first, loaded the first
+    /// time the stored bytes of the contract are requested and kept up to date on commit.
+    history_sizes: Arc<DashMap<ContractKey, VecDeque<u64>>>,
+}
+
+/// Clones share the same in-memory cache and underlying storage.
+impl<S: StateStorage + Clone> Clone for StateStore<S> {
+    fn clone(&self) -> Self {
+        Self {
+            state_mem_cache: self.state_mem_cache.clone(),
+            store: self.store.clone(),
+            history_len: self.history_len,
+            history_sizes: self.history_sizes.clone(),
+        }
+    }
 }
 
 impl<S> StateStore<S>
@@ -68,29 +208,25 @@ where
             // params_mem_cache: AsyncCache::new(counters, max_size as i64)
             //     .map_err(|err| StateStoreError::Any(Box::new(err)))?,
             store,
+            history_len: 0,
+            history_sizes: Arc::new(DashMap::new()),
         })
     }
 
+    /// Keeps the last `versions` states replaced for each contract, so they can be restored.
+    pub fn with_history(mut self, versions: usize) -> Self {
+        self.history_len = versions;
+        self
+    }
+
     pub async fn update(
         &mut self,
         key: &ContractKey,
         state: WrappedState,
     ) -> Result<(), StateStoreError> {
-        // only allow updates for existing contracts
-        if self.state_mem_cache.get(key).await.is_none() {
-            self.store
-                .get(key)
-                .await
-                .map_err(Into::into)?
-                .ok_or_else(|| StateStoreError::MissingContract(*key))?;
-        }
-        self.store
-            .store(*key, state.clone())
-            .await
-            .map_err(Into::into)?;
-        let cost = state.size() as i64;
-        self.state_mem_cache.insert(*key, state, cost).await;
-        Ok(())
+        let mut batch = StateBatch::default();
+        batch.update(*key, state);
+        self.commit(batch).await
     }
 
     pub async fn store(
@@ -99,18 +235,70 @@ where
         state: WrappedState,
         params: Parameters<'static>,
     ) -> Result<(), StateStoreError> {
-        self.store
-            .store(key, state.clone())
-            .await
-            .map_err(Into::into)?;
-        let cost = state.size() as i64;
-        self.state_mem_cache.insert(key, state, cost).await;
-        self.store
-            .store_params(key, params.clone())
-            .await
-            .map_err(Into::into)?;
-        // let cost = params.size();
-        // self.params_mem_cache.insert(key, params, cost as i64).await;
+        let mut batch = StateBatch::default();
+        batch.store(key, state, params);
+        self.commit(batch).await
+    }
+
+    /// Persists the writes of the batch atomically, along with the states they replace in the
+    /// state history.
+    ///
+    /// Fails without writing anything if the batch updates a contract which doesn't exist.
+    pub async fn commit(&mut self, batch: StateBatch) -> Result<(), StateStoreError> {
+        self.commit_with_history(batch, self.history_len).await
+    }
+
+    /// Commits the batch keeping the last `history_len` replaced states of each contract.
+    async fn commit_with_history(
+        &mut self,
+        mut batch: StateBatch,
+        history_len: usize,
+    ) -> Result<(), StateStoreError> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let mut written = HashSet::new();
+        let mut replaced = Vec::new();
+        for (key, _, params) in batch.writes() {
+            if !written.insert(*key) {
+                continue;
+            }
+            let previous = match self.state_mem_cache.get(key).await {
+                Some(v) => Some(v.value().clone()),
+                None => self.store.get(key).await.map_err(Into::into)?,
+            };
+            match previous {
+                Some(previous) => replaced.push((*key, previous)),
+                // only allow updates for existing contracts
+                None if params.is_none() => {
+                    return Err(StateStoreError::MissingContract(*key));
+                }
+                None => {}
+            }
+        }
+        if history_len > 0 {
+            let replaced_at = Utc::now();
+            for (key, previous) in replaced {
+                batch.archive(key, replaced_at, previous, history_len);
+            }
+        }
+        self.store.commit(&batch).await.map_err(Into::into)?;
+        for (key, state, _) in batch.writes() {
+            let cost = state.size() as i64;
+            self.state_mem_cache.insert(*key, state.clone(), cost).await;
+        }
+        for (key, _, state) in batch.replaced() {
+            if let Some(mut sizes) = self.history_sizes.get_mut(key) {
+                sizes.push_back(state.size() as u64);
+                while sizes.len() > history_len {
+                    sizes.pop_front();
+                }
+            }
+        }
+        for key in batch.removed() {
+            self.state_mem_cache.remove(key).await;
+            self.history_sizes.remove(key);
+        }
         Ok(())
     }
 
@@ -132,4 +320,81 @@ where
         let r = self.store.get_params(key).await.map_err(Into::into)?;
         Ok(r)
     }
+
+    pub async fn remove(&mut self, key: &ContractKey) -> Result<(), StateStoreError> {
+        self.store.remove(key).await.map_err(Into::into)?;
+        self.state_mem_cache.remove(key).await;
+        self.history_sizes.remove(key);
+        Ok(())
+    }
+
+    /// Returns the bytes stored for the contract: its state, parameters and state history.
+    pub async fn stored_bytes(&self, key: &ContractKey) -> u64 {
+        let state = self.get(key).await.map_or(0, |state| state.size());
+        let params = self
+            .get_params(key)
+            .await
+            .ok()
+            .flatten()
+            .map_or(0, |params| params.size());
+        (state + params) as u64 + self.history_bytes(key).await
+    }
+
+    /// Returns the bytes used by the state history of the contract, only reading the history
+    /// from the storage the first time.
+    async fn history_bytes(&self, key: &ContractKey) -> u64 {
+        let cached = self
+            .history_sizes
+            .get(key)
+            .map(|sizes| sizes.iter().sum::<u64>());
+        if let Some(bytes) = cached {
+            return bytes;
+        }
+        let Ok(history) = self.history(key).await else {
+            return 0;
+        };
+        let sizes: VecDeque<u64> = history
+            .iter()
+            .map(|version| version.state.size() as u64)
+            .collect();
+        let bytes = sizes.iter().sum();
+        self.history_sizes.entry(*key).or_insert(sizes);
+        bytes
+    }
+
+    /// Returns the keys of all the contracts with a stored state.
+    pub async fn keys(&self) -> Result<Vec<ContractKey>, StateStoreError> {
+        let r = self.store.keys().await.map_err(Into::into)?;
+        Ok(r)
+    }
+
+    /// Returns the prior states kept for the contract, oldest first.
+    pub async fn history(&self, key: &ContractKey) -> Result<Vec<StateVersion>, StateStoreError> {
+        let r = self.store.history(key).await.map_err(Into::into)?;
+        Ok(r)
+    }
+
+    /// Replaces the current state of the contract with a prior one from its history.
+    ///
+    /// The current state is added to the history, so the rollback itself can be undone.
+    /// The restored state is not validated again by the contract.
+    pub async fn restore(
+        &mut self,
+        key: &ContractKey,
+        version: u64,
+    ) -> Result<WrappedState, StateStoreError> {
+        let history = self.history(key).await?;
+        let Some(prior) = history.iter().find(|v| v.version == version) else {
+            return Err(StateStoreError::Any(anyhow::anyhow!(
+                "version {version} of contract {key} not found in the state history"
+            )));
+        };
+        let restored = prior.state.clone();
+        // keep the whole history even if it is disabled or shorter in this store
+        let history_len = self.history_len.max(history.len() + 1);
+        let mut batch = StateBatch::default();
+        batch.update(*key, restored.clone());
+        self.commit_with_history(batch, history_len).await?;
+        Ok(restored)
+    }
 }
@@ -2,6 +2,7 @@ use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use either::Either;
 use freenet_stdlib::prelude::{CodeHash, ContractInstanceId, DelegateKey};
 use notify::Watcher;
+use std::collections::HashMap;
 use std::fs::{self, OpenOptions};
 use std::io::{self, BufReader, BufWriter, Seek, Write};
 use std::path::{Path, PathBuf};
@@ -226,6 +227,171 @@ pub(super) trait StoreFsManagement: Sized {
     }
 }
 
+/// Extension of the compiled module artifacts, stored next to the code they were compiled
+/// from as `<code hash>.<engine tag>.module`.
+const MODULE_EXTENSION: &str = "module";
+
+/// File holding the key the module artifacts of a store are authenticated with.
+const MODULE_KEY_FILE: &str = "MODULE_KEY";
+
+const MODULE_MAC_LEN: usize = blake3::OUT_LEN;
+
+fn module_path(dir: &Path, code_hash: &CodeHash, tag: &str) -> PathBuf {
+    dir.join(format!("{}.{tag}.{MODULE_EXTENSION}", code_hash.encode()))
+}
+
+/// Returns the key used to authenticate the module artifacts in the given directory,
+/// generating it the first time.
+pub(super) fn module_key(dir: &Path) -> io::Result<[u8; 32]> {
+    let path = dir.join(MODULE_KEY_FILE);
+    loop {
+        match fs::read(&path) {
+            Ok(key) => {
+                if let Ok(key) = key.try_into() {
+                    return Ok(key);
+                }
+                // a truncated key can't have authenticated any artifact, replace it
+                fs::remove_file(&path)?;
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        match options.open(&path) {
+            Ok(mut file) => {
+                let key = rand::random::<[u8; 32]>();
+                file.write_all(&key)?;
+                file.sync_all()?;
+                return Ok(key);
+            }
+            // created concurrently by another store, read it instead
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn module_mac(key: &[u8; 32], code_hash: &CodeHash, tag: &str, artifact: &[u8]) -> blake3::Hash {
+    let mut hasher = blake3::Hasher::new_keyed(key);
+    hasher.update(code_hash.encode().as_bytes());
+    hasher.update(tag.as_bytes());
+    hasher.update(artifact);
+    hasher.finalize()
+}
+
+/// Returns the compiled module artifact for the given code and engine tag, if any.
+///
+/// Artifacts which were not written with the given key, or were modified since, are ignored.
+pub(super) fn load_module(
+    dir: &Path,
+    key: &[u8; 32],
+    code_hash: &CodeHash,
+    tag: &str,
+) -> Option<Vec<u8>> {
+    let path = module_path(dir, code_hash, tag);
+    let mut data = fs::read(&path).ok()?;
+    if data.len() < MODULE_MAC_LEN {
+        tracing::warn!(path = %path.display(), "ignoring truncated module artifact");
+        return None;
+    }
+    let artifact = data.split_off(MODULE_MAC_LEN);
+    let mac: [u8; MODULE_MAC_LEN] = data.try_into().ok()?;
+    // the comparison between hashes is constant time
+    if blake3::Hash::from(mac) != module_mac(key, code_hash, tag, &artifact) {
+        tracing::warn!(path = %path.display(), "ignoring module artifact failing verification");
+        return None;
+    }
+    Some(artifact)
+}
+
+/// Persists a compiled module artifact authenticated with the given key, replacing any
+/// artifacts for the same code compiled with a different engine.
+///
+/// Returns the bytes used by the persisted artifact.
+pub(super) fn save_module(
+    dir: &Path,
+    key: &[u8; 32],
+    code_hash: &CodeHash,
+    tag: &str,
+    artifact: &[u8],
+) -> io::Result<u64> {
+    let path = module_path(dir, code_hash, tag);
+    remove_modules(dir, code_hash, Some(&path))?;
+    let mac = module_mac(key, code_hash, tag, artifact);
+    // write to a temporary file first, so a partially written artifact is never loaded
+    let tmp_path = path.with_extension(format!("{}.tmp", rand::random::<u32>()));
+    {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(mac.as_bytes())?;
+        file.write_all(artifact)?;
+    }
+    fs::rename(tmp_path, path)?;
+    Ok((MODULE_MAC_LEN + artifact.len()) as u64)
+}
+
+/// Returns the bytes used by the compiled module artifacts of each code in the directory.
+pub(super) fn modules_sizes(dir: &Path) -> HashMap<CodeHash, u64> {
+    let mut sizes = HashMap::new();
+    let Ok(entries) = fs::read_dir(dir) else {
+        return sizes;
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if !path.extension().is_some_and(|ext| ext == MODULE_EXTENSION) {
+            continue;
+        }
+        let code_hash = path
+            .file_name()
+            .and_then(|name| name.to_str()?.split('.').next())
+            .and_then(decode_code_hash);
+        let (Some(code_hash), Ok(metadata)) = (code_hash, entry.metadata()) else {
+            continue;
+        };
+        *sizes.entry(code_hash).or_default() += metadata.len();
+    }
+    sizes
+}
+
+/// Decodes a code hash from its encoded form, as used in the names of the stored files.
+pub(super) fn decode_code_hash(encoded: &str) -> Option<CodeHash> {
+    let mut code_hash = [0; 32];
+    let len = bs58::decode(encoded)
+        .with_alphabet(bs58::Alphabet::BITCOIN)
+        .onto(&mut code_hash)
+        .ok()?;
+    (len == 32).then(|| CodeHash::new(code_hash))
+}
+
+/// Removes the compiled module artifacts for the given code, except for the one at `keep`.
+pub(super) fn remove_modules(
+    dir: &Path,
+    code_hash: &CodeHash,
+    keep: Option<&Path>,
+) -> io::Result<()> {
+    let prefix = format!("{}.", code_hash.encode());
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_module = path.extension().is_some_and(|ext| ext == MODULE_EXTENSION)
+            && path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with(&prefix));
+        if is_module && keep != Some(path.as_path()) {
+            if let Err(err) = fs::remove_file(&path) {
+                if err.kind() != io::ErrorKind::NotFound {
+                    return Err(err);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 #[allow(clippy::type_complexity)]
 fn process_record<T>(
     reader: &mut BufReader<T>,
@@ -240,7 +406,12 @@ where
     let key_type = match key_part[1] {
         0 => KeyType::Contract,
         1 => KeyType::Delegate,
-        _ => unreachable!(),
+        other => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown key type {other}"),
+            ))
+        }
     };
 
     if !deleted {
@@ -292,7 +463,61 @@ where
     }
 }
 
-fn compact_index_file<S: StoreFsManagement>(key_file_path: &Path) -> std::io::Result<()> {
+/// Records read from an index file, see [`scan_index`].
+pub(super) struct IndexScan {
+    /// Offset, key and value of the records which haven't been removed.
+    pub records: Vec<(u64, StoreKey, Vec<u8>)>,
+    /// Number of removed records still in the file.
+    pub removed: usize,
+    /// Length of the file up to the end of the last complete record.
+    pub valid_len: u64,
+    pub len: u64,
+}
+
+/// Reads all the records of an index file, stopping at the first incomplete or malformed one.
+pub(super) fn scan_index(key_file_path: &Path) -> io::Result<IndexScan> {
+    let file = File::open(key_file_path)?;
+    let len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let mut scan = IndexScan {
+        records: vec![],
+        removed: 0,
+        valid_len: 0,
+        len,
+    };
+    loop {
+        let record = match process_record(&mut reader) {
+            Ok(record) => record,
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidData
+                ) =>
+            {
+                break
+            }
+            Err(err) => return Err(err),
+        };
+        // skipping a removed record may go past the end of a truncated file
+        let end = reader.stream_position()?;
+        if end > len {
+            break;
+        }
+        match record {
+            Some((key, Either::Left(value))) => {
+                scan.records.push((scan.valid_len, key, value.to_vec()))
+            }
+            Some((key, Either::Right(value))) => scan.records.push((scan.valid_len, key, value)),
+            None => scan.removed += 1,
+        }
+        scan.valid_len = end;
+    }
+    Ok(scan)
+}
+
+pub(super) fn compact_index_file<S: StoreFsManagement>(
+    key_file_path: &Path,
+) -> std::io::Result<()> {
     // Define the path to the lock file
     let lock_file_path = key_file_path.with_extension("lock");
 
@@ -499,6 +724,70 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_module_artifacts() {
+        let temp_dir = get_temp_dir();
+        let dir = temp_dir.path();
+        let key = module_key(dir).expect("failed to create key");
+        assert_eq!(module_key(dir).expect("failed to read key"), key);
+        let code_hash = CodeHash::new([1; 32]);
+        let other_code_hash = CodeHash::new([2; 32]);
+
+        save_module(dir, &key, &code_hash, "tag1", &[1, 2, 3]).expect("failed to save");
+        save_module(dir, &key, &other_code_hash, "tag1", &[4, 5, 6]).expect("failed to save");
+        assert_eq!(
+            load_module(dir, &key, &code_hash, "tag1"),
+            Some(vec![1, 2, 3])
+        );
+        assert_eq!(load_module(dir, &key, &code_hash, "tag2"), None);
+
+        // artifacts compiled with a different engine are replaced
+        let size = save_module(dir, &key, &code_hash, "tag2", &[7, 8]).expect("failed to save");
+        assert_eq!(size, (MODULE_MAC_LEN + 2) as u64);
+        assert_eq!(load_module(dir, &key, &code_hash, "tag1"), None);
+        assert_eq!(load_module(dir, &key, &code_hash, "tag2"), Some(vec![7, 8]));
+        assert_eq!(
+            load_module(dir, &key, &other_code_hash, "tag1"),
+            Some(vec![4, 5, 6])
+        );
+        assert_eq!(modules_sizes(dir).get(&code_hash), Some(&size));
+
+        remove_modules(dir, &code_hash, None).expect("failed to remove");
+        assert_eq!(load_module(dir, &key, &code_hash, "tag2"), None);
+        assert_eq!(modules_sizes(dir).get(&code_hash), None);
+        assert_eq!(
+            load_module(dir, &key, &other_code_hash, "tag1"),
+            Some(vec![4, 5, 6])
+        );
+    }
+
+    #[test]
+    fn test_module_artifacts_verification() {
+        let temp_dir = get_temp_dir();
+        let dir = temp_dir.path();
+        let key = module_key(dir).expect("failed to create key");
+        let code_hash = CodeHash::new([1; 32]);
+        save_module(dir, &key, &code_hash, "tag", &[1, 2, 3]).expect("failed to save");
+
+        // artifacts written by another node are not loaded
+        assert_eq!(load_module(dir, &[0; 32], &code_hash, "tag"), None);
+
+        // neither are modified or truncated artifacts
+        let path = module_path(dir, &code_hash, "tag");
+        let mut data = fs::read(&path).unwrap();
+        *data.last_mut().unwrap() ^= 1;
+        fs::write(&path, &data).unwrap();
+        assert_eq!(load_module(dir, &key, &code_hash, "tag"), None);
+        fs::write(&path, &data[..MODULE_MAC_LEN - 1]).unwrap();
+        assert_eq!(load_module(dir, &key, &code_hash, "tag"), None);
+
+        // nor artifacts renamed to other code
+        save_module(dir, &key, &code_hash, "tag", &[1, 2, 3]).expect("failed to save");
+        let other_code_hash = CodeHash::new([2; 32]);
+        fs::rename(&path, module_path(dir, &other_code_hash, "tag")).unwrap();
+        assert_eq!(load_module(dir, &key, &other_code_hash, "tag"), None);
+    }
+
     #[test]
     fn test_concurrent_updates() {
         const NUM_THREADS: usize = 4;
@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use freenet_stdlib::prelude::*;
 
 use crate::wasm_runtime::tests::TestSetup;
@@ -105,3 +107,91 @@ fn get_state_delta() -> Result<(), Box<dyn std::error::Error>> {
     std::mem::drop(temp_dir);
     Ok(())
 }
+
+#[test]
+fn contract_predecessor() -> Result<(), Box<dyn std::error::Error>> {
+    let TestSetup {
+        contract_store,
+        delegate_store,
+        secrets_store,
+        contract_key,
+        temp_dir,
+    } = super::setup_test_contract(TEST_CONTRACT_1)?;
+    let mut runtime = Runtime::build(contract_store, delegate_store, secrets_store, false).unwrap();
+    let params = Parameters::from([].as_ref());
+    assert!(runtime
+        .contract_predecessor(&contract_key, &params)?
+        .is_none());
+
+    // same code declaring the original contract as its predecessor
+    let predecessor = *contract_key.code_hash().unwrap();
+    let upgrade_key = ed25519_dalek::SigningKey::from_bytes(&[1; 32]);
+    let code = super::with_predecessor(
+        super::get_test_module(TEST_CONTRACT_1)?,
+        &predecessor,
+        &upgrade_key,
+    );
+    let successor = ContractContainer::Wasm(ContractWasmAPIVersion::V1(WrappedContract::new(
+        Arc::new(ContractCode::from(code)),
+        params.clone(),
+    )));
+    let successor_key = successor.key();
+    runtime.contract_store.store_contract(successor)?;
+
+    assert_eq!(
+        runtime.contract_predecessor(&successor_key, &params)?,
+        Some(predecessor)
+    );
+    std::mem::drop(temp_dir);
+    Ok(())
+}
+
+#[test]
+fn upgrade_authorized() -> Result<(), Box<dyn std::error::Error>> {
+    let TestSetup {
+        contract_store,
+        delegate_store,
+        secrets_store,
+        temp_dir,
+        ..
+    } = super::setup_test_contract(TEST_CONTRACT_1)?;
+    let mut runtime = Runtime::build(contract_store, delegate_store, secrets_store, false).unwrap();
+    let params = Parameters::from([].as_ref());
+    let mut store = |code: Vec<u8>| {
+        let contract = ContractContainer::Wasm(ContractWasmAPIVersion::V1(WrappedContract::new(
+            Arc::new(ContractCode::from(code)),
+            params.clone(),
+        )));
+        let key = contract.key();
+        runtime.contract_store.store_contract(contract).map(|_| key)
+    };
+
+    let upgrade_key = ed25519_dalek::SigningKey::from_bytes(&[1; 32]);
+    let code = super::get_test_module(TEST_CONTRACT_1)?;
+    let predecessor = store(super::with_upgrade_key(code.clone(), &upgrade_key))?;
+    let predecessor_hash = predecessor.code_hash().unwrap();
+    let successor = store(super::with_predecessor(
+        code.clone(),
+        predecessor_hash,
+        &upgrade_key,
+    ))?;
+    let other_key = ed25519_dalek::SigningKey::from_bytes(&[2; 32]);
+    let impostor = store(super::with_predecessor(
+        code.clone(),
+        predecessor_hash,
+        &other_key,
+    ))?;
+    // the original contract doesn't declare any upgrade key
+    let original = store(code.clone())?;
+    let original_successor = store(super::with_predecessor(
+        code,
+        original.code_hash().unwrap(),
+        &upgrade_key,
+    ))?;
+
+    assert!(runtime.upgrade_authorized(&predecessor, &successor, &params)?);
+    assert!(!runtime.upgrade_authorized(&predecessor, &impostor, &params)?);
+    assert!(!runtime.upgrade_authorized(&original, &original_successor, &params)?);
+    std::mem::drop(temp_dir);
+    Ok(())
+}
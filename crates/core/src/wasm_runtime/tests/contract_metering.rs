@@ -15,6 +15,7 @@ const TIMEOUT_ITERATIONS: u64 = 100_000_000_000;
 #[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
 struct TestConditions {
     pub iterations: u64,
+    pub allocations: usize,
 }
 
 #[test]
@@ -31,6 +32,7 @@ fn validate_state_metering() -> Result<(), Box<dyn std::error::Error>> {
         max_execution_seconds: 5.0,
         cpu_cycles_per_second: Some(1_000_000), // Lower limit to force gas error
         safety_margin: 0.1,
+        ..Default::default()
     };
 
     let mut runtime =
@@ -39,6 +41,7 @@ fn validate_state_metering() -> Result<(), Box<dyn std::error::Error>> {
 
     let test_conditions = TestConditions {
         iterations: HIGH_ITERATIONS,
+        ..Default::default()
     };
     let state = WrappedState::new(serde_json::to_vec(&test_conditions)?);
     let time = Instant::now();
@@ -81,6 +84,7 @@ fn test_update_state_metering() -> Result<(), Box<dyn std::error::Error>> {
         max_execution_seconds: 5.0,
         cpu_cycles_per_second: Some(2_000_000),
         safety_margin: 0.1,
+        ..Default::default()
     };
 
     let mut runtime =
@@ -89,6 +93,7 @@ fn test_update_state_metering() -> Result<(), Box<dyn std::error::Error>> {
 
     let test_conditions = TestConditions {
         iterations: HIGH_ITERATIONS,
+        ..Default::default()
     };
     let state = WrappedState::new(serde_json::to_vec(&test_conditions)?);
     let time = Instant::now();
@@ -132,6 +137,7 @@ fn test_summarize_state_metering() -> Result<(), Box<dyn std::error::Error>> {
         max_execution_seconds: 5.0,
         cpu_cycles_per_second: Some(3_000_000),
         safety_margin: 0.1,
+        ..Default::default()
     };
 
     let mut runtime =
@@ -140,6 +146,7 @@ fn test_summarize_state_metering() -> Result<(), Box<dyn std::error::Error>> {
 
     let test_conditions = TestConditions {
         iterations: HIGH_ITERATIONS,
+        ..Default::default()
     };
     let state = WrappedState::new(serde_json::to_vec(&test_conditions)?);
     let time = Instant::now();
@@ -178,6 +185,7 @@ fn test_get_state_delta_metering() -> Result<(), Box<dyn std::error::Error>> {
         max_execution_seconds: 5.0,
         cpu_cycles_per_second: Some(4_000_000),
         safety_margin: 0.1,
+        ..Default::default()
     };
 
     let mut runtime =
@@ -186,6 +194,7 @@ fn test_get_state_delta_metering() -> Result<(), Box<dyn std::error::Error>> {
 
     let test_conditions = TestConditions {
         iterations: HIGH_ITERATIONS,
+        ..Default::default()
     };
     let state = WrappedState::new(serde_json::to_vec(&test_conditions)?);
     let time = Instant::now();
@@ -229,6 +238,7 @@ fn test_timeout_metering() -> Result<(), Box<dyn std::error::Error>> {
         max_execution_seconds: 5.0,
         cpu_cycles_per_second: Some(u64::MAX),
         safety_margin: 0.1,
+        ..Default::default()
     };
 
     let mut runtime =
@@ -237,6 +247,7 @@ fn test_timeout_metering() -> Result<(), Box<dyn std::error::Error>> {
 
     let test_conditions = TestConditions {
         iterations: TIMEOUT_ITERATIONS,
+        ..Default::default()
     };
     let state = WrappedState::new(serde_json::to_vec(&test_conditions)?);
     let time = Instant::now();
@@ -268,3 +279,173 @@ fn test_timeout_metering() -> Result<(), Box<dyn std::error::Error>> {
     std::mem::drop(temp_dir);
     Ok(())
 }
+
+#[test]
+fn validate_state_fuel_limit() -> Result<(), Box<dyn std::error::Error>> {
+    let TestSetup {
+        contract_store,
+        delegate_store,
+        secrets_store,
+        contract_key,
+        temp_dir,
+    } = super::setup_test_contract(TEST_CONTRACT_METERING)?;
+
+    let config = RuntimeConfig {
+        max_fuel: Some(1_000_000),
+        ..Default::default()
+    };
+
+    let mut runtime =
+        Runtime::build_with_config(contract_store, delegate_store, secrets_store, false, config)
+            .unwrap();
+
+    let test_conditions = TestConditions {
+        iterations: HIGH_ITERATIONS,
+        ..Default::default()
+    };
+    let state = WrappedState::new(serde_json::to_vec(&test_conditions)?);
+
+    let result = runtime.validate_state(
+        &contract_key,
+        &Parameters::from([].as_ref()),
+        &state,
+        &Default::default(),
+    );
+
+    assert!(
+        matches!(
+            result.as_ref().err().map(|e| e.deref()),
+            Some(RuntimeInnerError::ContractExecError(
+                ContractExecError::FuelLimitExceeded { limit: 1_000_000 }
+            ))
+        ),
+        "Should fail with fuel error"
+    );
+
+    std::mem::drop(temp_dir);
+    Ok(())
+}
+
+#[test]
+fn validate_state_memory_limit() -> Result<(), Box<dyn std::error::Error>> {
+    let TestSetup {
+        contract_store,
+        delegate_store,
+        secrets_store,
+        contract_key,
+        temp_dir,
+    } = super::setup_test_contract(TEST_CONTRACT_METERING)?;
+
+    // 4 MiB
+    const MAX_PAGES: u32 = 64;
+    let config = RuntimeConfig {
+        max_memory_pages: Some(MAX_PAGES),
+        ..Default::default()
+    };
+
+    let mut runtime =
+        Runtime::build_with_config(contract_store, delegate_store, secrets_store, false, config)
+            .unwrap();
+
+    // 256 KiB fit within the limit
+    let test_conditions = TestConditions {
+        allocations: 16,
+        ..Default::default()
+    };
+    let state = WrappedState::new(serde_json::to_vec(&test_conditions)?);
+    let result = runtime.validate_state(
+        &contract_key,
+        &Parameters::from([].as_ref()),
+        &state,
+        &Default::default(),
+    )?;
+    assert_eq!(result, ValidateResult::Valid);
+
+    // 16 MiB don't
+    let test_conditions = TestConditions {
+        allocations: 1024,
+        ..Default::default()
+    };
+    let state = WrappedState::new(serde_json::to_vec(&test_conditions)?);
+    let result = runtime.validate_state(
+        &contract_key,
+        &Parameters::from([].as_ref()),
+        &state,
+        &Default::default(),
+    );
+
+    assert!(
+        matches!(
+            result.as_ref().err().map(|e| e.deref()),
+            Some(RuntimeInnerError::ContractExecError(
+                ContractExecError::MemoryLimitExceeded { limit: MAX_PAGES }
+            ))
+        ),
+        "Should fail with memory error"
+    );
+
+    // the host can't request more memory than allowed either
+    let state = WrappedState::new(vec![0; MAX_PAGES as usize * wasmer::WASM_PAGE_SIZE + 1]);
+    let result = runtime.validate_state(
+        &contract_key,
+        &Parameters::from([].as_ref()),
+        &state,
+        &Default::default(),
+    );
+
+    assert!(
+        matches!(
+            result.as_ref().err().map(|e| e.deref()),
+            Some(RuntimeInnerError::ContractExecError(
+                ContractExecError::MemoryLimitExceeded { limit: MAX_PAGES }
+            ))
+        ),
+        "Should fail with memory error"
+    );
+
+    std::mem::drop(temp_dir);
+    Ok(())
+}
+
+#[test]
+fn validate_state_buffer_limit() -> Result<(), Box<dyn std::error::Error>> {
+    let TestSetup {
+        contract_store,
+        delegate_store,
+        secrets_store,
+        contract_key,
+        temp_dir,
+    } = super::setup_test_contract(TEST_CONTRACT_METERING)?;
+
+    const MAX_BUFFER_BYTES: usize = 1024;
+    let config = RuntimeConfig {
+        max_buffer_bytes: Some(MAX_BUFFER_BYTES),
+        ..Default::default()
+    };
+
+    let mut runtime =
+        Runtime::build_with_config(contract_store, delegate_store, secrets_store, false, config)
+            .unwrap();
+
+    let state = WrappedState::new(serde_json::to_vec(&TestConditions::default())?);
+    let parameters = vec![0; MAX_BUFFER_BYTES];
+    let result = runtime.validate_state(
+        &contract_key,
+        &Parameters::from(parameters.as_slice()),
+        &state,
+        &Default::default(),
+    );
+
+    assert!(
+        matches!(
+            result.as_ref().err().map(|e| e.deref()),
+            Some(RuntimeInnerError::ContractExecError(
+                ContractExecError::BufferLimitExceeded { req, limit: MAX_BUFFER_BYTES }
+            )) if *req > MAX_BUFFER_BYTES
+        ),
+        "Should fail with buffer error"
+    );
+
+    std::mem::drop(temp_dir);
+    Ok(())
+}
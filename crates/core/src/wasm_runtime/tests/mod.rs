@@ -4,16 +4,19 @@ use std::{
     sync::Arc,
 };
 
+use ed25519_dalek::Signer;
 use freenet_stdlib::prelude::{
-    ContractCode, ContractContainer, ContractKey, ContractWasmAPIVersion, WrappedContract,
+    CodeHash, ContractCode, ContractContainer, ContractKey, ContractWasmAPIVersion, WrappedContract,
 };
 
 use crate::util::tests::get_temp_dir;
 
+use super::runtime::{PREDECESSOR_SECTION, UPGRADE_KEY_SECTION};
 use super::{ContractStore, DelegateStore, SecretsStore};
 
 mod contract;
 mod contract_metering;
+mod runtime_pool;
 mod time;
 
 pub(crate) fn get_test_module(name: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
@@ -80,3 +83,38 @@ pub(crate) fn setup_test_contract(name: &str) -> Result<TestSetup, Box<dyn std::
         contract_key,
     })
 }
+
+/// Appends a custom section to a module, small enough for its size to fit a single byte.
+pub(crate) fn with_custom_section(mut code: Vec<u8>, name: &str, content: &[u8]) -> Vec<u8> {
+    code.push(0); // custom section id
+    code.push((1 + name.len() + content.len()) as u8); // section size as LEB128
+    code.push(name.len() as u8);
+    code.extend_from_slice(name.as_bytes());
+    code.extend_from_slice(content);
+    code
+}
+
+/// Declares the key allowed to authorize the successors of a module.
+pub(crate) fn with_upgrade_key(code: Vec<u8>, upgrade_key: &ed25519_dalek::SigningKey) -> Vec<u8> {
+    with_custom_section(
+        code,
+        UPGRADE_KEY_SECTION,
+        upgrade_key.verifying_key().as_bytes(),
+    )
+}
+
+/// Declares the contract with the given code hash as the predecessor of a module, signing
+/// the declaration with the upgrade key of the predecessor.
+pub(crate) fn with_predecessor(
+    code: Vec<u8>,
+    predecessor: &CodeHash,
+    upgrade_key: &ed25519_dalek::SigningKey,
+) -> Vec<u8> {
+    let mut declaration = predecessor.to_vec();
+    declaration.extend([0; ed25519_dalek::SIGNATURE_LENGTH]);
+    let mut code = with_custom_section(code, PREDECESSOR_SECTION, &declaration);
+    let signature = upgrade_key.sign(&code);
+    let start = code.len() - ed25519_dalek::SIGNATURE_LENGTH;
+    code[start..].copy_from_slice(&signature.to_bytes());
+    code
+}
@@ -0,0 +1,74 @@
+use super::super::contract::*;
+use super::super::Runtime;
+use crate::wasm_runtime::tests::TestSetup;
+use freenet_stdlib::prelude::*;
+use std::sync::Arc;
+
+const TEST_CONTRACT_METERING: &str = "test_contract_metering";
+
+const ITERATIONS: u64 = 100_000;
+
+const CALLS: usize = 32;
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+struct TestConditions {
+    pub iterations: u64,
+}
+
+fn validate(runtime: &mut Runtime, key: &ContractKey, state: &WrappedState, calls: usize) {
+    for _ in 0..calls {
+        let result = runtime
+            .validate_state(
+                key,
+                &Parameters::from([].as_ref()),
+                state,
+                &Default::default(),
+            )
+            .unwrap();
+        assert_eq!(result, ValidateResult::Valid);
+    }
+}
+
+/// Forked runtimes can run calls concurrently on separate threads, sharing the modules
+/// compiled by the runtime they were forked from.
+///
+/// The throughput gained is measured by the `runtime_pool` benchmark.
+#[test]
+fn forked_runtimes_run_concurrently() -> Result<(), Box<dyn std::error::Error>> {
+    let TestSetup {
+        contract_store,
+        delegate_store,
+        secrets_store,
+        contract_key,
+        temp_dir,
+    } = super::setup_test_contract(TEST_CONTRACT_METERING)?;
+    let mut runtime = Runtime::build(contract_store, delegate_store, secrets_store, false).unwrap();
+    let state = WrappedState::new(serde_json::to_vec(&TestConditions {
+        iterations: ITERATIONS,
+    })?);
+    validate(&mut runtime, &contract_key, &state, 1);
+
+    let workers = std::thread::available_parallelism()?.get().clamp(2, 8);
+    let mut pool = (0..workers)
+        .map(|_| runtime.fork())
+        .collect::<Result<Vec<_>, _>>()?;
+    let (key, state) = (&contract_key, &state);
+    std::thread::scope(|s| {
+        for runtime in &mut pool {
+            s.spawn(move || validate(runtime, key, state, CALLS / workers));
+        }
+    });
+
+    // the module compiled by the first runtime is used by the forked ones
+    assert_eq!(runtime.contract_modules.len(), 1);
+    assert!(pool
+        .iter()
+        .all(|rt| Arc::ptr_eq(&rt.contract_modules, &runtime.contract_modules)));
+
+    // and the runtime keeps working after its forks are dropped
+    drop(pool);
+    validate(&mut runtime, key, state, 1);
+
+    std::mem::drop(temp_dir);
+    Ok(())
+}
@@ -46,6 +46,11 @@ pub enum SubCommand {
     Query {},
     WasmRuntime(ExecutorConfig),
     Execute(RunCliConfig),
+    StateHistory(crate::state_history::StateHistoryConfig),
+    VerifyStores(crate::verify::VerifyConfig),
+    Snapshot(crate::snapshot::SnapshotConfig),
+    MigrateStorage(crate::migrate::MigrateConfig),
+    Secrets(crate::secrets::SecretsConfig),
     Test(crate::testing::TestConfig),
     NetworkMetricsServer(crate::network_metrics_server::ServerConfig),
 }
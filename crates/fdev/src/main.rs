@@ -7,11 +7,16 @@ mod build;
 mod commands;
 mod config;
 mod inspect;
+mod migrate;
 pub(crate) mod network_metrics_server;
 mod new_package;
 mod query;
+mod secrets;
+mod snapshot;
+mod state_history;
 mod testing;
 mod util;
+mod verify;
 mod wasm_runtime;
 
 use crate::{
@@ -19,7 +24,12 @@ use crate::{
     commands::{put, update},
     config::{Config, SubCommand},
     inspect::inspect,
+    migrate::migrate,
     new_package::create_new_package,
+    secrets::secrets,
+    snapshot::snapshot,
+    state_history::state_history,
+    verify::verify,
     wasm_runtime::run_local_executor,
 };
 
@@ -58,6 +68,19 @@ fn main() -> anyhow::Result<()> {
                     update(update_config, config.additional).await
                 }
             },
+            SubCommand::StateHistory(history_config) => {
+                state_history(history_config, config.additional).await
+            }
+            SubCommand::VerifyStores(verify_config) => {
+                verify(verify_config, config.additional).await
+            }
+            SubCommand::Snapshot(snapshot_config) => {
+                snapshot(snapshot_config, config.additional).await
+            }
+            SubCommand::MigrateStorage(migrate_config) => {
+                migrate(migrate_config, config.additional).await
+            }
+            SubCommand::Secrets(secrets_config) => secrets(secrets_config, config.additional).await,
             SubCommand::Test(test_config) => testing::test_framework(test_config).await,
             SubCommand::NetworkMetricsServer(server_config) => {
                 let (server, _) = crate::network_metrics_server::start_server(&server_config).await;
@@ -0,0 +1,32 @@
+use std::path::PathBuf;
+
+use freenet::dev_tool::{migrate_state_db, StorageBackend};
+
+use crate::config::BaseConfig;
+
+/// Copy the state database of the local node from one storage backend to the other.
+///
+/// Every contract state is copied along with its parameters and state history, and read
+/// back to verify it. The node must not be running while migrating its database.
+#[derive(clap::Parser, Clone)]
+pub struct MigrateConfig {
+    /// Backend the node has been using so far.
+    #[arg(long, value_enum)]
+    pub(crate) from: StorageBackend,
+    /// Directory where the migrated database will be written, the node database directory
+    /// by default. Each backend uses its own file, so the source database is kept.
+    #[arg(long)]
+    pub(crate) target_dir: Option<PathBuf>,
+}
+
+pub async fn migrate(config: MigrateConfig, other: BaseConfig) -> anyhow::Result<()> {
+    let db_dir = other.paths.build(None)?.db_dir(other.mode);
+    let target_dir = config.target_dir.unwrap_or_else(|| db_dir.clone());
+    std::fs::create_dir_all(&target_dir)?;
+    let report = migrate_state_db(config.from, &db_dir, &target_dir).await?;
+    println!(
+        "Migrated {} contracts and {} prior states to {target_dir:?}",
+        report.contracts, report.history_versions
+    );
+    Ok(())
+}
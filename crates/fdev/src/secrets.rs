@@ -0,0 +1,182 @@
+use std::{
+    io::{IsTerminal, Write},
+    path::PathBuf,
+};
+
+use freenet::{
+    config::{MasterKeySource, SecretArgs},
+    dev_tool::{DelegateStore, SecretsBackup, SecretsStore},
+};
+use freenet_stdlib::prelude::{DelegateKey, Parameters};
+
+use crate::config::BaseConfig;
+
+/// Manage the delegate secrets stored by the local node.
+///
+/// The node must not be running, since its stores can't be modified concurrently.
+#[derive(clap::Parser, Clone)]
+pub struct SecretsConfig {
+    /// The same secrets options the node is started with.
+    #[clap(flatten)]
+    pub(crate) secrets: SecretArgs,
+    #[clap(subcommand)]
+    pub(crate) command: SecretsCommand,
+}
+
+#[derive(clap::Subcommand, Clone)]
+pub(crate) enum SecretsCommand {
+    /// Re-encrypts all the stored secrets under a new master key.
+    ///
+    /// Unless a new cipher is given, the new passphrase to derive the master key from is read
+    /// from the `NEW_SECRETS_PASSPHRASE` environment variable, or prompted for.
+    ///
+    /// The node must be started with the new cipher or passphrase afterwards. If interrupted,
+    /// run it again with the same options to complete the rotation.
+    Rotate {
+        /// Path to the new cipher file, containing the 32 bytes of the new master key.
+        #[arg(long)]
+        new_cipher: Option<PathBuf>,
+    },
+    /// Lists the delegates with stored secrets.
+    List,
+    /// Writes the secrets of the given delegates, and their code, to a passphrase protected file.
+    ///
+    /// The passphrase is read from the `BACKUP_PASSPHRASE` environment variable, or prompted for.
+    Export {
+        /// Key of a delegate whose secrets are exported, as listed by `list`. If secrets are
+        /// stored for several versions of the delegate, the code hash must be given as well,
+        /// as `<KEY>:<CODE_HASH>`.
+        #[arg(long = "delegate", required = true)]
+        delegates: Vec<String>,
+        /// A path to the parameters of each delegate, in the same order. If not specified,
+        /// the delegates are assumed to have empty parameters.
+        #[arg(long = "parameters")]
+        parameters: Vec<PathBuf>,
+        /// A path to the file where the backup will be written.
+        #[arg(long)]
+        output: PathBuf,
+    },
+    /// Registers the delegates of a backup and stores their secrets, replacing any secrets
+    /// with the same ids.
+    ///
+    /// The passphrase is read from the `BACKUP_PASSPHRASE` environment variable, or prompted for.
+    Import {
+        /// A path to the backup file.
+        #[arg(long)]
+        input: PathBuf,
+    },
+}
+
+const MAX_SIZE: i64 = 10 * 1024 * 1024;
+const PASSPHRASE_ENV: &str = "BACKUP_PASSPHRASE";
+
+pub async fn secrets(config: SecretsConfig, other: BaseConfig) -> anyhow::Result<()> {
+    let paths = other.paths.build(None)?;
+    let mut store = SecretsStore::new(paths.secrets_dir(other.mode), config.secrets.build()?)?;
+    match config.command {
+        SecretsCommand::Rotate { new_cipher } => {
+            let new_key = match new_cipher {
+                Some(path) => {
+                    let cipher = std::fs::read(&path)?.try_into().map_err(|_| {
+                        anyhow::anyhow!("the cipher file {path:?} must contain 32 bytes")
+                    })?;
+                    MasterKeySource::Cipher(cipher)
+                }
+                None => MasterKeySource::Passphrase(read_passphrase(
+                    "NEW_SECRETS_PASSPHRASE",
+                    "New secrets passphrase",
+                )?),
+            };
+            let rotated = store.rotate_master_key(&new_key)?;
+            println!("Re-encrypted {rotated} secrets under the new master key");
+        }
+        SecretsCommand::List => {
+            for delegate in store.delegates() {
+                println!("{delegate} (code hash: {})", delegate.code_hash().encode());
+            }
+        }
+        SecretsCommand::Export {
+            delegates,
+            parameters,
+            output,
+        } => {
+            if !parameters.is_empty() && parameters.len() != delegates.len() {
+                anyhow::bail!("parameters must be given for each of the delegates");
+            }
+            let delegate_store = DelegateStore::new(paths.delegates_dir(other.mode), MAX_SIZE)?;
+            let stored = store.delegates();
+            let mut exported = Vec::with_capacity(delegates.len());
+            for (i, encoded) in delegates.iter().enumerate() {
+                let params = match parameters.get(i) {
+                    Some(path) => Parameters::from(std::fs::read(path)?),
+                    None => Parameters::from(vec![]),
+                };
+                exported.push((find_delegate(&stored, encoded)?, params));
+            }
+            let passphrase = read_passphrase(PASSPHRASE_ENV, "Backup passphrase")?;
+            let backup = SecretsBackup::export(&store, &delegate_store, &exported)?;
+            backup.write(&output, &passphrase)?;
+            for (delegate, name) in backup.skipped() {
+                eprintln!(
+                    "Skipped secret `{name}` of {delegate}, its delegate cipher is not registered"
+                );
+            }
+            println!(
+                "Exported {} secrets of {} delegates to {output:?}",
+                backup.secrets(),
+                backup.delegates()
+            );
+        }
+        SecretsCommand::Import { input } => {
+            let mut delegate_store = DelegateStore::new(paths.delegates_dir(other.mode), MAX_SIZE)?;
+            let passphrase = read_passphrase(PASSPHRASE_ENV, "Backup passphrase")?;
+            let backup = SecretsBackup::read(&input, &passphrase)?;
+            let imported = backup.import(&mut store, &mut delegate_store)?;
+            println!(
+                "Imported {imported} secrets of {} delegates from {input:?}",
+                backup.delegates()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Finds a stored delegate by its key, optionally followed by `:` and its code hash.
+fn find_delegate(stored: &[DelegateKey], encoded: &str) -> anyhow::Result<DelegateKey> {
+    let (encoded_key, code_hash) = match encoded.split_once(':') {
+        Some((key, code_hash)) => (key, Some(code_hash)),
+        None => (encoded, None),
+    };
+    let mut matching = stored.iter().filter(|key| {
+        key.encode() == encoded_key
+            && code_hash.map_or(true, |code_hash| key.code_hash().encode() == code_hash)
+    });
+    match (matching.next(), matching.next()) {
+        (Some(key), None) => Ok(key.clone()),
+        (None, _) => anyhow::bail!("no secrets stored for delegate {encoded}"),
+        (Some(_), Some(_)) => anyhow::bail!(
+            "secrets are stored for several versions of delegate {encoded}, \
+            specify the code hash as `<KEY>:<CODE_HASH>`"
+        ),
+    }
+}
+
+/// Reads a passphrase from the given environment variable, or prompts for it, so it doesn't
+/// show up in the process list.
+fn read_passphrase(env: &str, prompt: &str) -> anyhow::Result<String> {
+    if let Ok(passphrase) = std::env::var(env) {
+        return Ok(passphrase);
+    }
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!("no passphrase given, set the {env} environment variable");
+    }
+    eprint!("{prompt}: ");
+    std::io::stderr().flush()?;
+    let mut passphrase = String::new();
+    std::io::stdin().read_line(&mut passphrase)?;
+    let passphrase = passphrase.trim_end_matches(['\r', '\n']);
+    if passphrase.is_empty() {
+        anyhow::bail!("the passphrase can't be empty");
+    }
+    Ok(passphrase.to_owned())
+}
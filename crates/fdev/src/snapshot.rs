@@ -0,0 +1,70 @@
+use std::path::PathBuf;
+
+use freenet::dev_tool::{ContractStore, DelegateStore, StateStore, Storage, StoreSnapshot};
+
+use crate::config::BaseConfig;
+
+const MAX_MEM_CACHE: u32 = 10_000_000;
+const MAX_SIZE: i64 = 10 * 1024 * 1024;
+
+/// Export or import a snapshot of the contracts and delegates stored by the local node.
+///
+/// A snapshot can seed other nodes (see `--load-snapshot`) or move a node between storage
+/// backends. The node must not be running, since its stores can't be modified concurrently.
+#[derive(clap::Parser, Clone)]
+pub struct SnapshotConfig {
+    #[clap(subcommand)]
+    pub(crate) command: SnapshotCommand,
+}
+
+#[derive(clap::Subcommand, Clone)]
+pub(crate) enum SnapshotCommand {
+    /// Writes the code, parameters and state of all the stored contracts and the stored
+    /// delegates to a file.
+    Export {
+        /// A path to the file where the snapshot will be written.
+        #[arg(long)]
+        output: PathBuf,
+    },
+    /// Writes the contents of a snapshot to the stores, replacing any entries with the same keys.
+    Import {
+        /// A path to the snapshot file.
+        #[arg(long)]
+        input: PathBuf,
+    },
+}
+
+pub async fn snapshot(config: SnapshotConfig, other: BaseConfig) -> anyhow::Result<()> {
+    let paths = other.paths.build(None)?;
+    let db_dir = paths.db_dir(other.mode);
+    let mut state_store = StateStore::new(Storage::new(&db_dir).await?, MAX_MEM_CACHE)?;
+    let mut contract_store = ContractStore::new(paths.contracts_dir(other.mode), MAX_SIZE)?;
+    let mut delegate_store = DelegateStore::new(paths.delegates_dir(other.mode), MAX_SIZE)?;
+    match config.command {
+        SnapshotCommand::Export { output } => {
+            let snapshot =
+                StoreSnapshot::export(&contract_store, &delegate_store, &state_store).await?;
+            snapshot.write(&output)?;
+            println!(
+                "Exported {} contracts and {} delegates to {output:?}",
+                snapshot.contracts(),
+                snapshot.delegates()
+            );
+        }
+        SnapshotCommand::Import { input } => {
+            let snapshot = StoreSnapshot::read(&input)?;
+            let missing_params = snapshot
+                .import(&mut contract_store, &mut delegate_store, &mut state_store)
+                .await?;
+            for id in &missing_params {
+                eprintln!("Skipped the state of {id}, its parameters are missing");
+            }
+            println!(
+                "Imported {} contracts and {} delegates from {input:?}",
+                snapshot.contracts(),
+                snapshot.delegates()
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use std::path::PathBuf;
+
+use freenet::dev_tool::{StateStore, Storage};
+use freenet_stdlib::prelude::{ContractInstanceId, ContractKey};
+
+use crate::config::BaseConfig;
+
+const MAX_MEM_CACHE: u32 = 10_000_000;
+
+/// Inspect and roll back the prior states kept for a contract by the local node.
+///
+/// The node must be configured to keep a state history (`--state-history`) and must
+/// not be running, since its state database can't be opened concurrently.
+#[derive(clap::Parser, Clone)]
+pub struct StateHistoryConfig {
+    /// Contract id of the contract in Base58 format.
+    pub(crate) key: String,
+    #[clap(subcommand)]
+    pub(crate) command: HistoryCommand,
+}
+
+#[derive(clap::Subcommand, Clone)]
+pub(crate) enum HistoryCommand {
+    /// Lists the prior states kept for the contract.
+    List,
+    /// Writes a prior state of the contract to a file.
+    Get {
+        version: u64,
+        /// A path to the file where the state will be written.
+        #[arg(long)]
+        output: PathBuf,
+    },
+    /// Replaces the current state of the contract with a prior one.
+    ///
+    /// The current state is kept in the history, so the rollback can be undone.
+    Restore { version: u64 },
+}
+
+pub async fn state_history(config: StateHistoryConfig, other: BaseConfig) -> anyhow::Result<()> {
+    let key: ContractKey = ContractInstanceId::try_from(config.key)?.into();
+    let db_dir = other.paths.build(None)?.db_dir(other.mode);
+    let mut state_store = StateStore::new(Storage::new(&db_dir).await?, MAX_MEM_CACHE)?;
+    match config.command {
+        HistoryCommand::List => {
+            let history = state_store.history(&key).await?;
+            if history.is_empty() {
+                println!("No prior states kept for contract {key}");
+            }
+            for prior in history {
+                println!(
+                    "version {}: replaced at {}, {} bytes",
+                    prior.version,
+                    prior.replaced_at,
+                    prior.state.size()
+                );
+            }
+        }
+        HistoryCommand::Get { version, output } => {
+            let history = state_store.history(&key).await?;
+            let Some(prior) = history.into_iter().find(|v| v.version == version) else {
+                anyhow::bail!("version {version} of contract {key} not found");
+            };
+            std::fs::write(&output, prior.state.as_ref())?;
+            println!("Wrote version {version} of contract {key} to {output:?}");
+        }
+        HistoryCommand::Restore { version } => {
+            state_store.restore(&key, version).await?;
+            println!("Restored version {version} of contract {key}");
+        }
+    }
+    Ok(())
+}
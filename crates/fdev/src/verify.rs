@@ -0,0 +1,62 @@
+use freenet::dev_tool::{verify_stores, StateStore, Storage, VerifyOptions};
+
+use crate::config::BaseConfig;
+
+const MAX_MEM_CACHE: u32 = 10_000_000;
+
+/// Check the contract, delegate and secret stores and the state database of the local
+/// node for inconsistencies, optionally repairing them.
+///
+/// The node must not be running, since its stores can't be modified concurrently.
+#[derive(clap::Parser, Clone)]
+pub struct VerifyConfig {
+    /// Check that all the stored code matches its hash, which requires reading all of it.
+    #[arg(long)]
+    pub(crate) verify_hashes: bool,
+    /// Fix the issues found, discarding any entries which can't be recovered.
+    #[arg(long)]
+    pub(crate) repair: bool,
+    /// Rewrite the index files without the records of removed entries.
+    #[arg(long)]
+    pub(crate) compact: bool,
+}
+
+pub async fn verify(config: VerifyConfig, other: BaseConfig) -> anyhow::Result<()> {
+    let paths = other.paths.build(None)?;
+    let db_dir = paths.db_dir(other.mode);
+    let mut state_store = StateStore::new(Storage::new(&db_dir).await?, MAX_MEM_CACHE)?;
+    let options = VerifyOptions {
+        verify_hashes: config.verify_hashes,
+        repair: config.repair,
+        compact: config.compact,
+        skip_locks: false,
+    };
+    let report = verify_stores(
+        &paths.contracts_dir(other.mode),
+        &paths.delegates_dir(other.mode),
+        &paths.secrets_dir(other.mode),
+        &mut state_store,
+        options,
+    )
+    .await?;
+    for issue in &report.issues {
+        println!("{issue}");
+    }
+    if report.is_consistent() {
+        println!("No inconsistencies found");
+    } else if config.repair {
+        println!("Repaired {} inconsistencies", report.issues.len());
+    } else {
+        println!(
+            "Found {} inconsistencies, run with `--repair` to fix them",
+            report.issues.len()
+        );
+    }
+    if report.removed_records > 0 && !config.compact {
+        println!(
+            "{} removed records are taking space in the index files, run with `--compact` to drop them",
+            report.removed_records
+        );
+    }
+    Ok(())
+}
@@ -28,14 +28,17 @@ impl ContractInterface for Contract {
         state: State<'static>,
         mut data: Vec<UpdateData<'static>>,
     ) -> Result<UpdateModification<'static>, ContractError> {
-        if let Some(UpdateData::Delta(delta)) = data.pop() {
-            if delta.as_ref() == [4] && state.as_ref() == [5, 2, 3] {
-                Ok(UpdateModification::valid(State::from(vec![5, 2, 3, 4])))
-            } else {
-                Err(ContractError::InvalidUpdate)
+        match data.pop() {
+            Some(UpdateData::Delta(delta)) => {
+                if delta.as_ref() == [4] && state.as_ref() == [5, 2, 3] {
+                    Ok(UpdateModification::valid(State::from(vec![5, 2, 3, 4])))
+                } else {
+                    Err(ContractError::InvalidUpdate)
+                }
             }
-        } else {
-            Err(ContractError::InvalidUpdate)
+            // adopts the state of the contract it was upgraded from
+            Some(UpdateData::RelatedState { state, .. }) => Ok(UpdateModification::valid(state)),
+            _ => Err(ContractError::InvalidUpdate),
         }
     }
 
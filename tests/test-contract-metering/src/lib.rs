@@ -3,6 +3,9 @@ use freenet_stdlib::prelude::*;
 #[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
 struct TestConditions {
     pub iterations: u64,
+    /// Number of 16 KiB chunks to allocate while validating the state
+    #[serde(default)]
+    pub allocations: usize,
 }
 
 struct Contract;
@@ -16,12 +19,18 @@ impl ContractInterface for Contract {
     ) -> Result<ValidateResult, ContractError> {
         let test_conditions: TestConditions = serde_json::from_slice(state.as_ref())
             .map_err(|e| ContractError::Deser(e.to_string()))?;
-        
+
         let mut _counter = 0;
         for _ in 0..test_conditions.iterations {
             _counter += 1;
         }
 
+        // grow the linear memory a page at a time
+        let chunks: Vec<Vec<u8>> = (0..test_conditions.allocations)
+            .map(|i| vec![i as u8; 16 * 1024])
+            .collect();
+        std::hint::black_box(chunks);
+
         Ok(ValidateResult::Valid)
     }
 